@@ -12,6 +12,8 @@ or distributed except according to those terms.
 
 The invocation is simple: it expects *at least* one argument, which is passed to `cargo-script` as the name of a script file to execute.  All other arguments are passed *after* a `--` argument to ensure they are interpreted as arguments to the *script* as opposed to this program, `cargo` or `cargo-script`.
 
+The one exception is `--pause`, which can come before the script's path.  Unless the script succeeds, this waits for Enter before exiting, so that the console window a double-clicked script gets doesn't disappear before anyone can read why it failed.  `cargo script file-association install --pause` sets this up.
+
 # Why a separate program?
 
 Because `cargo-script` isn't suitable for use as a hashbang target.  There are *two* problems:
@@ -21,17 +23,22 @@ Because `cargo-script` isn't suitable for use as a hashbang target.  There are *
 
 In addition, making it a program instead of a shell script means it doesn't have to worry about things like what shell the user has, or what happens on Windows.
 */
+use std::io::Write;
 use std::process::Command;
 
 fn main() {
     let mut args = std::env::args();
     let exe = args.next().unwrap_or_else(|| "run-cargo-script".into());
-    let path = match args.next() {
+    let mut path = args.next();
+    let pause = path.as_ref().map_or(false, |p| p == "--pause");
+    if pause {
+        path = args.next();
+    }
+    let path = match path {
         Some(v) => v,
         None => {
-            use std::io::Write;
             let stderr = &mut std::io::stderr();
-            let _ = writeln!(stderr, "Usage: {} PATH", exe);
+            let _ = writeln!(stderr, "Usage: {} [--pause] PATH", exe);
             std::process::exit(1);
         }
     };
@@ -51,5 +58,10 @@ fn main() {
         Some(c) => c,
         None => !0,
     };
+    if pause && exit_status != 0 {
+        let stderr = &mut std::io::stderr();
+        let _ = write!(stderr, "Press Enter to close.").and_then(|_| stderr.flush());
+        let _ = std::io::stdin().read_line(&mut String::new());
+    }
     std::process::exit(exit_status);
 }
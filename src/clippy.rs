@@ -0,0 +1,201 @@
+/*
+Copyright ⓒ 2017 cargo-script contributors.
+
+Licensed under the MIT license (see LICENSE or <http://opensource.org
+/licenses/MIT>) or the Apache License, Version 2.0 (see LICENSE of
+<http://www.apache.org/licenses/LICENSE-2.0>), at your option. All
+files in the project carrying such notice may not be copied, modified,
+or distributed except according to those terms.
+*/
+/*!
+This module contains code related to linting scripts with Clippy.
+*/
+use crate::error::{Blame, MainError, Result};
+use crate::manifest;
+use crate::platform;
+use crate::util::write_file_atomic;
+use log::info;
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Debug)]
+pub struct Args {
+    pub script: OsString,
+    pub args: Vec<OsString>,
+}
+
+impl Args {
+    pub fn subcommand() -> clap::App<'static, 'static> {
+        use clap::{AppSettings, Arg, SubCommand};
+
+        SubCommand::with_name("clippy")
+            .about("Lint a script with `cargo clippy`, without building it.")
+            .setting(AppSettings::AllowInvalidUtf8)
+            .arg(
+                Arg::with_name("script")
+                    .help("Script file to lint.")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("args")
+                    .help("Flags to pass on to Clippy, such as `-W clippy::pedantic`.  These must come after `--`.")
+                    .multiple(true)
+                    .last(true),
+            )
+    }
+
+    pub fn parse(m: &clap::ArgMatches<'_>) -> Self {
+        Args {
+            script: m.value_of_os("script").unwrap().to_owned(),
+            args: m
+                .values_of_os("args")
+                .map(|vs| vs.map(Into::into).collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+pub fn try_main(args: Args) -> Result<i32> {
+    let script = crate::load_script(&args.script, None)?;
+    let input = script.input();
+
+    let deps_from = crate::env_deps_from()?;
+    let split = manifest::split_input(
+        &input,
+        &manifest::SplitOptions {
+            deps_from: deps_from.as_ref(),
+            ..Default::default()
+        },
+    )?;
+    for warning in &split.warnings {
+        warning!("{}", warning);
+    }
+
+    /*
+    Rather than linting the source in the generated package, we point the package at the script itself.  rustc is happy to skip over the hashbang, and the embedded manifest is only a comment, so this is the same code; the difference is that Clippy now reports the script's own path and line numbers.
+
+    That needs a different manifest, so it gets a package of its own next to the script's.  It can't be done for scripts which pull in other files with `//# include`, or have `.crs` modules, since rustc wouldn't see the script the way it's built; those are linted as generated.
+    */
+    let cache_path = crate::get_script_cache_path()?;
+    let run_pkg_path = crate::script_pkg_path(&input, deps_from.as_ref())?;
+    let pkg_path = cache_path.join({
+        let mut id = run_pkg_path.file_name().unwrap_or_default().to_owned();
+        id.push("-clippy");
+        id
+    });
+    info!("linting package in {:?}", pkg_path);
+    fs::create_dir_all(&pkg_path)?;
+    crate::mark_cache_dir(&cache_path);
+
+    let as_generated = !split.includes.is_empty()
+        || split
+            .modules
+            .iter()
+            .any(|(from, _)| from.extension().map_or(false, |ext| ext == "crs"));
+    let lint_path = match as_generated {
+        true => {
+            let lint_path = pkg_path.join(format!("{}.rs", input.safe_name()));
+            write_file_atomic(&lint_path, split.script.as_bytes())?;
+            lint_path
+        }
+        false => script.path.clone(),
+    };
+
+    let (mut mani, mut files) = (split.manifest, split.files);
+    crate::add_bundle_files(&input, script.bundle.as_deref(), &mut mani, &mut files)?;
+    let mani_path = pkg_path.join("Cargo.toml");
+    write_file_atomic(
+        &mani_path,
+        clippy_manifest(&mani, &split.bin_name, &lint_path)?.as_bytes(),
+    )?;
+    crate::write_pkg_files(&pkg_path, &files, &[])?;
+
+    // Lint against the same versions of the dependencies the script runs with, if it has been run.
+    let lock_path = run_pkg_path.join("Cargo.lock");
+    if lock_path.is_file() {
+        info!("copying {:?}", lock_path);
+        fs::copy(&lock_path, pkg_path.join("Cargo.lock"))?;
+    }
+
+    // Share the binary cache, so that dependencies already built for the script can be reused.
+    let bincache_path = crate::get_binary_cache_path()?;
+    fs::create_dir_all(&bincache_path)?;
+    crate::mark_cache_dir(&bincache_path);
+
+    let mut cmd = Command::new("cargo");
+    cmd.arg("clippy")
+        .arg("--manifest-path")
+        .arg(&mani_path)
+        .env("CARGO_TARGET_DIR", &bincache_path);
+    if platform::force_cargo_color() {
+        cmd.arg("--color").arg("always");
+    }
+    if !args.args.is_empty() {
+        cmd.arg("--").args(&args.args);
+    }
+    info!("running {:?}", cmd);
+    crate::logging::trace_command(&cmd);
+    Ok(cmd.status()?.code().unwrap_or(1))
+}
+
+/**
+Turns the manifest of a generated package into one which builds the script file directly, as the binary target `bin_name`.
+*/
+fn clippy_manifest(mani: &str, bin_name: &str, script_path: &Path) -> Result<String> {
+    use toml::Value;
+
+    let mut mani: toml::value::Table =
+        toml::from_str(mani).map_err(|e| MainError::Other(Blame::Internal, Box::new(e)))?;
+
+    let script_path = script_path
+        .to_str()
+        .ok_or((Blame::Human, "script path is not valid UTF-8"))?;
+    if let Some(Value::Array(bins)) = mani.get_mut("bin") {
+        for bin in bins.iter_mut().filter_map(Value::as_table_mut) {
+            if bin.get("name").and_then(Value::as_str) == Some(bin_name) {
+                bin.insert("path".into(), Value::String(script_path.into()));
+            }
+        }
+    }
+
+    Ok(format!("{}", Value::Table(mani)))
+}
+
+#[test]
+fn test_clippy_manifest() {
+    let mani = clippy_manifest(
+        r#"[[bin]]
+name = "helper"
+path = "helper.rs"
+
+[[bin]]
+name = "n"
+path = "n.rs"
+
+[package]
+name = "n"
+version = "0.1.0"
+"#,
+        "n",
+        Path::new("/scripts/n.rs"),
+    )
+    .unwrap();
+    assert_eq!(
+        mani,
+        r#"[[bin]]
+name = "helper"
+path = "helper.rs"
+
+[[bin]]
+name = "n"
+path = "/scripts/n.rs"
+
+[package]
+name = "n"
+version = "0.1.0"
+"#
+    );
+}
@@ -12,16 +12,14 @@ This module just contains any big string literals I don't want cluttering up the
 */
 
 /**
-The message output when the user invokes `cargo script` with no further arguments.  We need to do this ourselves because `clap` doesn't provide any way to generate this message manually.
+Examples of valid dependency specs, shown when the user gives us an invalid one.
 */
-pub const NO_ARGS_MESSAGE: &'static str = "\
-The following required arguments were not supplied:
-\t'<script>'
-
-USAGE:
-\tcargo script [FLAGS OPTIONS] [--] <script> <args>...
-
-For more information try --help";
+pub const DEP_SPEC_EXAMPLES: &'static str = "\
+expected a dependency spec such as:
+\t--dep regex
+\t--dep regex=1.3
+\t--dep 'regex={ version = \"1.3\", default-features = false }'
+\t--dep 'internal={ version = \"1\", registry = \"company\" }'";
 
 /*
 What follows are the templates used to wrap script input.
@@ -33,6 +31,18 @@ pub const SCRIPT_BODY_SUB: &'static str = "script";
 /// Substitution for the script prelude.
 pub const SCRIPT_PRELUDE_SUB: &'static str = "prelude";
 
+/// Substitution for the type of an expression's value, from `--expr-as`.  Without it, this is `_`.
+pub const EXPR_TYPE_SUB: &'static str = "expr_type";
+
+/// Substitution for the initial value of a `--fold` accumulator.
+pub const LOOP_INIT_SUB: &'static str = "init";
+
+/// Substitution for the closure `--fold` calls once the input runs out.
+pub const LOOP_FINISH_SUB: &'static str = "finish";
+
+/// What `--fold` does once the input runs out, when not given `--finish`: shows the accumulator itself.
+pub const LOOP_DEFAULT_FINISH: &'static str = "|acc| acc";
+
 /// The template used for script file inputs.
 pub const FILE_TEMPLATE: &'static str = r#"#{script}"#;
 
@@ -44,7 +54,11 @@ fn main() {
         Ok(()) => None,
         Err(e) => {
             use std::io::{self, Write};
-            let _ = writeln!(io::stderr(), "Error: {}", e);
+            // This is set by `--error-format`.
+            let _ = match std::env::var_os("CARGO_SCRIPT_ERROR_FORMAT") {
+                Some(ref format) if format == "plain" => writeln!(io::stderr(), "{}", e),
+                _ => writeln!(io::stderr(), "Error: {}", e),
+            };
             Some(1)
         },
     };
@@ -53,11 +67,67 @@ fn main() {
     }
 }
 
-fn try_main() -> Result<(), Box<std::error::Error>> {
-    match {#{script}} {
-        __cargo_script_expr => println!("{:?}", __cargo_script_expr)
+fn try_main() -> Result<(), Box<dyn std::error::Error>> {
+    // The expression goes on lines of its own, so that a trailing comment can't swallow the end of the block.  This is a `match` rather than a `let` so that any temporaries in the expression live long enough to be shown.
+    match __cargo_script_as::<#{expr_type}>({
+#{script}
+    }) {
+        __cargo_script_expr => (&&&&__CargoScriptExpr(__cargo_script_expr)).__cargo_script_show()
+    }
+}
+
+// Gives the expression's value the type asked for with `--expr-as`, if there was one.
+fn __cargo_script_as<T>(value: T) -> T {
+    value
+}
+
+// These pick how to show the expression's value, most specific first.  `()` isn't shown at all, a `Result` fails with its error, and anything else is printed with `{:?}`.
+struct __CargoScriptExpr<T>(T);
+
+trait __CargoScriptShowUnit {
+    fn __cargo_script_show(&self) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+impl<'a, 'b, 'c> __CargoScriptShowUnit for &'a &'b &'c __CargoScriptExpr<()> {
+    fn __cargo_script_show(&self) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+}
+
+trait __CargoScriptShowResult {
+    fn __cargo_script_show(&self) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+impl<'a, 'b, T: std::fmt::Debug, E: std::fmt::Display> __CargoScriptShowResult for &'a &'b __CargoScriptExpr<Result<T, E>> {
+    fn __cargo_script_show(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.0 {
+            Ok(ref v) => Ok(println!("{:?}", v)),
+            Err(ref e) => Err(e.to_string().into()),
+        }
+    }
+}
+
+trait __CargoScriptShowDebugResult {
+    fn __cargo_script_show(&self) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+impl<'a, T: std::fmt::Debug, E: std::fmt::Debug> __CargoScriptShowDebugResult for &'a __CargoScriptExpr<Result<T, E>> {
+    fn __cargo_script_show(&self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.0 {
+            Ok(ref v) => Ok(println!("{:?}", v)),
+            Err(ref e) => Err(format!("{:?}", e).into()),
+        }
+    }
+}
+
+trait __CargoScriptShowDebug {
+    fn __cargo_script_show(&self) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+impl<T: std::fmt::Debug> __CargoScriptShowDebug for __CargoScriptExpr<T> {
+    fn __cargo_script_show(&self) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(println!("{:?}", self.0))
     }
-    Ok(())
 }
 "#;
 
@@ -105,7 +175,7 @@ where F: FnMut(&str) -> T, T: 'static {
 
 /// The template used for `--count --loop` input.
 pub const LOOP_COUNT_TEMPLATE: &'static str = r#"
-%p
+#{prelude}
 use std::any::Any;
 use std::io::prelude::*;
 
@@ -140,9 +210,150 @@ where F: FnMut(&str, usize) -> T, T: 'static {
 }
 "#;
 
-/// Substitution for the identifier-safe name of the script.
+/// The template used for `--filter --loop` input.
+pub const LOOP_FILTER_TEMPLATE: &'static str = r#"
+#{prelude}
+use std::io::prelude::*;
+
+fn main() {
+    let mut closure = enforce_closure(
+{#{script}}
+    );
+    let mut line_buffer = String::new();
+    let mut stdin = std::io::stdin();
+    loop {
+        line_buffer.clear();
+        let read_res = stdin.read_line(&mut line_buffer).unwrap_or(0);
+        if read_res == 0 { break }
+        if closure(&line_buffer) {
+            print!("{}", line_buffer);
+        }
+    }
+}
+
+// This is what makes a closure which doesn't return a `bool` an error, and says what it should be.
+fn enforce_closure<F>(closure: F) -> F
+where F: FnMut(&str) -> bool {
+    closure
+}
+"#;
+
+/// The template used for `--fold --loop` input.
+pub const LOOP_FOLD_TEMPLATE: &'static str = r#"
+#{prelude}
+use std::any::Any;
+use std::io::prelude::*;
+
+fn main() {
+    let mut acc = {#{init}};
+    let mut closure = enforce_closure(&acc,
+{#{script}}
+    );
+    let finish = enforce_finish(&acc,
+{#{finish}}
+    );
+    let mut line_buffer = String::new();
+    let mut stdin = std::io::stdin();
+    loop {
+        line_buffer.clear();
+        let read_res = stdin.read_line(&mut line_buffer).unwrap_or(0);
+        if read_res == 0 { break }
+        let output = closure(&mut acc, &line_buffer);
+        display(&output);
+    }
+    let output = finish(acc);
+    display(&output);
+}
+
+fn display<T: std::fmt::Debug + 'static>(output: &T) {
+    let output_any: &dyn Any = output;
+    if !output_any.is::<()>() {
+        println!("{:?}", output);
+    }
+}
+
+fn enforce_closure<A, F, T>(_: &A, closure: F) -> F
+where F: FnMut(&mut A, &str) -> T, T: 'static {
+    closure
+}
+
+fn enforce_finish<A, F, T>(_: &A, finish: F) -> F
+where F: FnOnce(A) -> T, T: 'static {
+    finish
+}
+"#;
+
+/**
+Appended to scripts run with `--run-as-test-module` which don't have a `main` function of their own, so that the binary target is still complete.
+*/
+pub const TEST_MODULE_MAIN: &'static str = r#"
+
+#[cfg(not(test))]
+fn main() {}
+"#;
+
+/**
+The template used by `cargo-script new` for new scripts.
+*/
+pub const NEW_SCRIPT_TEMPLATE: &'static str = r#"#!/usr/bin/env run-cargo-script
+//! #{name}
+//!
+//! ```cargo
+//! [dependencies]
+//! ```
+
+fn main() {
+    println!("Hello, World!");
+}
+"#;
+
+/**
+The template used by `cargo-script new --expr` for new scripts which evaluate and display an expression.
+*/
+pub const NEW_EXPR_SCRIPT_TEMPLATE: &'static str = r#"#!/usr/bin/env run-cargo-script
+//! #{name}
+//!
+//! ```cargo
+//! [dependencies]
+//! ```
+
+fn main() {
+    let result = {
+        1 + 1
+    };
+    println!("{:?}", result);
+}
+"#;
+
+/**
+The template used by `cargo-script new --loop` for new scripts which process standard input line by line.
+*/
+pub const NEW_LOOP_SCRIPT_TEMPLATE: &'static str = r#"#!/usr/bin/env run-cargo-script
+//! #{name}
+//!
+//! ```cargo
+//! [dependencies]
+//! ```
+use std::io::BufRead;
+
+fn main() {
+    let stdin = std::io::stdin();
+    for (i, line) in stdin.lock().lines().enumerate() {
+        let line = line.expect("could not read line from stdin");
+        println!("{}: {}", i + 1, line);
+    }
+}
+"#;
+
+/// Substitution for the name of a new script.
+pub const NEW_NAME_SUB: &'static str = "name";
+
+/// Substitution for the name of the generated package.
 pub const MANI_NAME_SUB: &'static str = "name";
 
+/// Substitution for the identifier-safe name of the script.
+pub const MANI_BIN_NAME_SUB: &'static str = "bin_name";
+
 /// Substitution for the filesystem-safe name of the script.
 pub const MANI_FILE_SUB: &'static str = "file";
 
@@ -156,20 +367,97 @@ version = "0.1.0"
 authors = ["Anonymous"]
 
 [[bin]]
-name = "#{name}"
+name = "#{bin_name}"
 path = "#{file}.rs"
+
+# Keep the package out of any workspace that happens to enclose it.
+[workspace]
 "##;
 
+/**
+The editions Cargo has to be told about, and the first release of Cargo (as major and minor versions) to support each.  `2015` is the default, so any Cargo will do.
+*/
+pub const EDITION_CARGO_VERSIONS: &'static [(&'static str, (u64, u64))] =
+    &[("2018", (1, 31)), ("2021", (1, 56)), ("2024", (1, 85))];
+
+/**
+Dependency keys which say which registry a dependency comes from.  These are kept when a dependency is overridden with `--dep`.
+*/
+pub const DEP_REGISTRY_KEYS: &'static [&'static str] = &["registry", "registry-index"];
+
+/**
+Crate names which a generated package must not use, beyond those of its own dependencies.
+*/
+pub const RESERVED_PKG_NAMES: &'static [&'static str] =
+    &["alloc", "core", "proc_macro", "std", "test"];
+
+/**
+Suffix appended to a generated package's name when it would otherwise collide with a dependency or reserved name.
+*/
+pub const PKG_NAME_COLLISION_SUFFIX: &'static str = "-script";
+
+/**
+The name a script's build script is given in the generated package.
+*/
+pub const BUILD_SCRIPT_NAME: &'static str = "build.rs";
+
+/**
+The name of the empty library given to packages built for Criterion benchmarks, since Cargo won't have a package with nothing but a benchmark in it.
+*/
+pub const BENCH_LIB_NAME: &'static str = "cargo-script-bench-lib.rs";
+
+/**
+The language token of the code block a build script can be embedded in, alongside the manifest.
+*/
+pub const BUILD_SCRIPT_LANG: &'static str = "build-script";
+
 /**
 The name of the package metadata file.
 */
 pub const METADATA_FILE: &'static str = "metadata.json";
 
+/**
+The contents of the `.gitignore` written into each cache directory, so that version control (and tools which respect it) skip the cache.
+*/
+pub const CACHE_GITIGNORE: &'static str = "\
+# This directory is managed by cargo-script.
+*
+";
+
+/**
+The contents of the `CACHEDIR.TAG` written into each cache directory.  See <https://bford.info/cachedir/>.
+*/
+pub const CACHEDIR_TAG: &'static str = "\
+Signature: 8a477f597d28d172789f06886806bc55
+# This file is a cache directory tag created by cargo-script.
+# For information about cache directory tags see https://bford.info/cachedir/
+";
+
 /**
 Extensions to check when trying to find script input by name.
 */
 pub const SEARCH_EXTS: &'static [&'static str] = &["crs", "rs"];
 
+/**
+The environment variable listing other directories to look for scripts in, separated as in `PATH`.
+*/
+pub const SCRIPT_PATH_DIRS_ENV: &'static str = "CARGO_SCRIPT_PATH_DIRS";
+
+/**
+The environment variable giving the manifest for `--deps-from` to use when it isn't given.
+*/
+pub const DEPS_FROM_ENV: &'static str = "CARGO_SCRIPT_DEPS_FROM";
+
+/**
+The environment variable which, when set to anything, has the same effect as `--allow-wildcard-deps`.
+*/
+pub const ALLOW_WILDCARD_DEPS_ENV: &'static str = "CARGO_SCRIPT_ALLOW_WILDCARD_DEPS";
+
+/**
+The maximum length of a dependency's package name, as enforced by crates.io.
+*/
+pub const DEP_NAME_LEN_MAX: usize = 64;
+
 /**
 When generating a package's unique ID, how many hex nibbles of the digest should be used *at most*?
 
@@ -185,3 +473,28 @@ Measured in milliseconds.
 // It's been *one week* since you looked at me,
 // cocked your head to the side and said "I'm angry."
 pub const MAX_CACHE_AGE_MS: u64 = 1 * 7 * 24 * 60 * 60 * 1000;
+
+/**
+The phases reported by `--timings`, in the order they're listed.
+*/
+pub const TIMING_PHASES: &'static [&'static str] =
+    &["args", "read", "cache", "manifest", "cargo", "run", "gc"];
+
+/**
+The exit code for when something cargo-script ran took too long and was killed, such as a build which went past `--build-timeout`.  This is the same as the `timeout` command's.
+*/
+pub const TIMED_OUT_EXIT_CODE: i32 = 124;
+
+/**
+How long to wait before the first retry of a build which failed for want of the network, with `--build-retries`.  Each retry after that waits twice as long as the one before, up to `BUILD_RETRY_DELAY_MAX_MS`.
+
+Measured in milliseconds.
+*/
+pub const BUILD_RETRY_DELAY_MS: u64 = 1_000;
+
+/**
+The longest wait between retries of a build.
+
+Measured in milliseconds.
+*/
+pub const BUILD_RETRY_DELAY_MAX_MS: u64 = 30_000;
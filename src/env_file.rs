@@ -0,0 +1,192 @@
+/*
+Copyright ⓒ 2017 cargo-script contributors.
+
+Licensed under the MIT license (see LICENSE or <http://opensource.org
+/licenses/MIT>) or the Apache License, Version 2.0 (see LICENSE of
+<http://www.apache.org/licenses/LICENSE-2.0>), at your option. All
+files in the project carrying such notice may not be copied, modified,
+or distributed except according to those terms.
+*/
+/*!
+This module deals with loading environment variables for the script from "dotenv"-style files.
+*/
+use crate::error::{Blame, MainError, Result, ResultExt};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+/**
+Loads and parses the environment file at the given path.
+*/
+pub fn load<P>(path: P) -> Result<Vec<(String, String)>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let mut content = String::new();
+    fs::File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut content))
+        .map_err(MainError::from)
+        .err_tag(format!(
+            "could not read environment file `{}`",
+            path.display()
+        ))
+        .shift_blame(Blame::Human)?;
+
+    parse(&content).err_tag(format!("in environment file `{}`", path.display()))
+}
+
+/**
+Parses the contents of an environment file.
+
+Each non-blank line which isn't a `#` comment must be of the form `KEY=VALUE`, optionally preceded by `export`.  Values may be wrapped in single quotes (taken literally) or double quotes (which understand `\n`, `\t`, `\"` and `\\` escapes).  Unquoted values run until the end of the line, or a ` #` comment.
+*/
+pub fn parse(content: &str) -> Result<Vec<(String, String)>> {
+    let mut vars = vec![];
+
+    for (i, line) in content.lines().enumerate() {
+        let line_num = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = match line.strip_prefix("export ") {
+            Some(rest) => rest.trim_start(),
+            None => line,
+        };
+
+        let (key, value) = match line.find('=') {
+            Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+            None => {
+                return Err((
+                    Blame::Human,
+                    format!("line {}: expected `KEY=VALUE`, got {:?}", line_num, line),
+                )
+                    .into());
+            }
+        };
+
+        if !is_valid_key(key) {
+            return Err((
+                Blame::Human,
+                format!("line {}: invalid variable name {:?}", line_num, key),
+            )
+                .into());
+        }
+
+        let value = parse_value(value).map_err(|msg| {
+            MainError::OtherOwned(Blame::Human, format!("line {}: {}", line_num, msg))
+        })?;
+
+        vars.push((key.into(), value));
+    }
+
+    Ok(vars)
+}
+
+fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    match chars.next() {
+        Some(c) if c == '_' || c.is_ascii_alphabetic() => (),
+        _ => return false,
+    }
+    chars.all(|c| c == '_' || c == '.' || c.is_ascii_alphanumeric())
+}
+
+fn parse_value(value: &str) -> ::std::result::Result<String, &'static str> {
+    if let Some(rest) = value.strip_prefix('\'') {
+        let end = rest.find('\'').ok_or("unterminated single-quoted value")?;
+        check_trailing(&rest[end + 1..])?;
+        Ok(rest[..end].into())
+    } else if let Some(rest) = value.strip_prefix('"') {
+        let mut r = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    check_trailing(&rest[i + 1..])?;
+                    return Ok(r);
+                }
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => r.push('\n'),
+                    Some((_, 'r')) => r.push('\r'),
+                    Some((_, 't')) => r.push('\t'),
+                    Some((_, c)) => r.push(c),
+                    None => break,
+                },
+                c => r.push(c),
+            }
+        }
+        Err("unterminated double-quoted value")
+    } else {
+        let value = match value.find(" #") {
+            Some(i) => &value[..i],
+            None => value,
+        };
+        Ok(value.trim_end().into())
+    }
+}
+
+fn check_trailing(rest: &str) -> ::std::result::Result<(), &'static str> {
+    let rest = rest.trim();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        Err("unexpected characters after quoted value")
+    }
+}
+
+#[test]
+fn test_parse() {
+    macro_rules! p {
+        ($s:expr) => {
+            parse($s).map_err(|e| e.to_string())
+        };
+    }
+
+    macro_rules! vars {
+        ($($k:expr => $v:expr),* $(,)*) => {
+            Ok(vec![$(($k.to_string(), $v.to_string())),*])
+        };
+    }
+
+    assert_eq!(p!(""), vars![]);
+    assert_eq!(p!("A=1"), vars!["A" => "1"]);
+    assert_eq!(
+        p!("
+# A comment.
+A=1
+
+  B = two words  # trailing comment
+export C=3
+"),
+        vars!["A" => "1", "B" => "two words", "C" => "3"]
+    );
+    assert_eq!(p!("A="), vars!["A" => ""]);
+    assert_eq!(p!("A=x#y"), vars!["A" => "x#y"]);
+    assert_eq!(p!("A='a \\n # b'"), vars!["A" => "a \\n # b"]);
+    assert_eq!(p!("A=\"a\\n\\\"b\\\"\" # c"), vars!["A" => "a\n\"b\""]);
+    assert_eq!(p!("A=\"x=y\""), vars!["A" => "x=y"]);
+
+    assert_eq!(
+        p!("A=1\nnope"),
+        Err("line 2: expected `KEY=VALUE`, got \"nope\"".into())
+    );
+    assert_eq!(
+        p!("1A=1"),
+        Err("line 1: invalid variable name \"1A\"".into())
+    );
+    assert_eq!(
+        p!("A='oops"),
+        Err("line 1: unterminated single-quoted value".into())
+    );
+    assert_eq!(
+        p!("A=\"oops"),
+        Err("line 1: unterminated double-quoted value".into())
+    );
+    assert_eq!(
+        p!("A=\"x\" y"),
+        Err("line 1: unexpected characters after quoted value".into())
+    );
+}
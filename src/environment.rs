@@ -0,0 +1,200 @@
+/*
+Copyright ⓒ 2017 cargo-script contributors.
+
+Licensed under the MIT license (see LICENSE or <http://opensource.org
+/licenses/MIT>) or the Apache License, Version 2.0 (see LICENSE of
+<http://www.apache.org/licenses/LICENSE-2.0>), at your option. All
+files in the project carrying such notice may not be copied, modified,
+or distributed except according to those terms.
+*/
+/*!
+This module contains code for the `env` subcommand, which shows where cargo-script keeps things, which toolchain it will use, and which environment variables are changing its behaviour.
+*/
+use crate::consts;
+use crate::error::Result;
+use crate::platform;
+use crate::templates;
+use crate::util::find_on_path;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+/// Environment variables which change what cargo-script (or the Cargo it runs) does, other than its own `CARGO_SCRIPT_*` ones.
+const RELEVANT_VARS: &[&str] = &[
+    "CARGO",
+    "CARGO_ENCODED_RUSTFLAGS",
+    "CARGO_HOME",
+    "CARGO_NET_OFFLINE",
+    "CARGO_TARGET_DIR",
+    "RUSTC",
+    "RUSTC_WRAPPER",
+    "RUSTFLAGS",
+    "RUSTUP_TOOLCHAIN",
+    "XDG_CACHE_HOME",
+    "XDG_CONFIG_HOME",
+];
+
+#[derive(Debug)]
+pub struct Args {
+    pub json: bool,
+}
+
+impl Args {
+    pub fn subcommand() -> clap::App<'static, 'static> {
+        use clap::{Arg, SubCommand};
+
+        SubCommand::with_name("env")
+            .about("Show where cargo-script keeps its cache and templates, which toolchain it uses, and which environment variables affect it.")
+            .arg(
+                Arg::with_name("json")
+                    .help("Show it all as a JSON object.")
+                    .long("json"),
+            )
+    }
+
+    pub fn parse(m: &clap::ArgMatches<'_>) -> Self {
+        Args {
+            json: m.is_present("json"),
+        }
+    }
+}
+
+#[derive(RustcEncodable)]
+struct Report {
+    cache: String,
+    script_cache: String,
+    binary_cache: String,
+    script_data: String,
+    config: String,
+    templates: String,
+    templates_exist: bool,
+    edition: String,
+    toolchain: Option<String>,
+    cargo: Option<String>,
+    cargo_version: Option<String>,
+    rustc: Option<String>,
+    rustc_version: Option<String>,
+    env: BTreeMap<String, String>,
+}
+
+pub fn try_main(args: Args) -> Result<i32> {
+    let display = |p: &Path| p.display().to_string();
+    let manifest_edition = manifest_edition();
+    let templates = templates::get_template_path()?;
+
+    // Cargo and rustc are found the same way a run finds them, which is just on the `PATH`.
+    let report = Report {
+        cache: display(&platform::get_cache_dir()?),
+        script_cache: display(&crate::get_script_cache_path()?),
+        binary_cache: display(&crate::get_binary_cache_path()?),
+        script_data: display(&crate::get_script_data_path()?),
+        config: display(&platform::get_config_dir()?),
+        templates_exist: templates.is_dir(),
+        templates: display(&templates),
+        edition: manifest_edition.clone().unwrap_or_else(|| "2015".into()),
+        toolchain: active_toolchain(),
+        cargo: find_on_path("cargo").map(|p| display(&p)),
+        cargo_version: crate::tool_version("cargo").ok(),
+        rustc: find_on_path("rustc").map(|p| display(&p)),
+        rustc_version: crate::tool_version("rustc").ok(),
+        env: relevant_vars(env::vars()),
+    };
+
+    if args.json {
+        let json = rustc_serialize::json::encode(&report).map_err(|err| err.to_string())?;
+        println!("{}", json);
+        return Ok(0);
+    }
+
+    let tool = |path: &Option<String>, version: &Option<String>| match (path, version) {
+        (Some(path), Some(version)) => format!("{} ({})", path, version),
+        (Some(path), None) => format!("{} (could not get its version)", path),
+        (None, _) => "not found".into(),
+    };
+    println!("cache: {}", report.cache);
+    println!("script cache: {}", report.script_cache);
+    println!("binary cache: {}", report.binary_cache);
+    println!("script data: {}", report.script_data);
+    println!("config: {}", report.config);
+    println!(
+        "templates: {} ({})",
+        report.templates,
+        match report.templates_exist {
+            true => "exists",
+            false => "missing",
+        }
+    );
+    match manifest_edition {
+        Some(_) => println!("edition: {}", report.edition),
+        None => println!(
+            "edition: {} (Cargo's default, unless the script sets `package.edition`)",
+            report.edition
+        ),
+    }
+    if let Some(ref toolchain) = report.toolchain {
+        println!("toolchain: {}", toolchain);
+    }
+    println!("cargo: {}", tool(&report.cargo, &report.cargo_version));
+    println!("rustc: {}", tool(&report.rustc, &report.rustc_version));
+    for (k, v) in &report.env {
+        println!("env: {}={}", k, v);
+    }
+    Ok(0)
+}
+
+/**
+Works out which edition the default manifest gives scripts which don't ask for one.  If it doesn't give one, they get Cargo's default.
+*/
+fn manifest_edition() -> Option<String> {
+    crate::package_edition(consts::DEFAULT_MANIFEST)
+}
+
+/**
+Asks rustup which toolchain it will use here, and why.  This is `None` without rustup.
+*/
+fn active_toolchain() -> Option<String> {
+    let mut cmd = Command::new("rustup");
+    cmd.args(["show", "active-toolchain"]);
+    crate::logging::trace_command(&cmd);
+    let out = cmd.output().ok()?;
+    match out.status.success() {
+        true => Some(String::from_utf8_lossy(&out.stdout).trim().into()),
+        false => None,
+    }
+}
+
+/**
+Picks out the environment variables that have a say in what cargo-script does.
+*/
+fn relevant_vars<I>(vars: I) -> BTreeMap<String, String>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    vars.into_iter()
+        .filter(|(k, _)| k.starts_with("CARGO_SCRIPT_") || RELEVANT_VARS.contains(&&**k))
+        .collect()
+}
+
+#[test]
+fn test_relevant_vars() {
+    let vars = |vs: &[(&str, &str)]| {
+        relevant_vars(vs.iter().map(|&(k, v)| (k.to_owned(), v.to_owned())))
+            .into_iter()
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        vars(&[
+            ("RUSTFLAGS", "-g"),
+            ("PATH", "/bin"),
+            ("CARGO_SCRIPT_SANDBOX", "firejail"),
+            ("CARGO_HOME", "/c"),
+            ("CARGO_PKG_NAME", "x"),
+        ]),
+        vec![
+            ("CARGO_HOME".to_owned(), "/c".to_owned()),
+            ("CARGO_SCRIPT_SANDBOX".to_owned(), "firejail".to_owned()),
+            ("RUSTFLAGS".to_owned(), "-g".to_owned()),
+        ]
+    );
+}
@@ -32,6 +32,8 @@ pub enum MainError {
     Other(Blame, Box<dyn Error>),
     OtherOwned(Blame, String),
     OtherBorrowed(Blame, &'static str),
+    /// Something took longer than it was allowed to, and was killed.  Unlike other errors, this has an exit code of its own.
+    TimedOut(Blame, String),
 }
 
 /**
@@ -53,7 +55,8 @@ impl MainError {
             | Tag(blame, _, _)
             | Other(blame, _)
             | OtherOwned(blame, _)
-            | OtherBorrowed(blame, _) => blame,
+            | OtherBorrowed(blame, _)
+            | TimedOut(blame, _) => blame,
         }
     }
 
@@ -68,7 +71,20 @@ impl MainError {
             | Tag(ref mut cur_blame, _, _)
             | Other(ref mut cur_blame, _)
             | OtherOwned(ref mut cur_blame, _)
-            | OtherBorrowed(ref mut cur_blame, _) => *cur_blame = blame,
+            | OtherBorrowed(ref mut cur_blame, _)
+            | TimedOut(ref mut cur_blame, _) => *cur_blame = blame,
+        }
+    }
+
+    /**
+    The exit code cargo-script should finish with because of this error.
+    */
+    pub fn exit_code(&self) -> i32 {
+        use self::MainError::*;
+        match *self {
+            Tag(_, _, ref err) => err.exit_code(),
+            TimedOut(..) => crate::consts::TIMED_OUT_EXIT_CODE,
+            _ => 1,
         }
     }
 }
@@ -83,6 +99,7 @@ impl fmt::Display for MainError {
             Other(_, ref err) => Display::fmt(err, fmt),
             OtherOwned(_, ref err) => Display::fmt(err, fmt),
             OtherBorrowed(_, ref err) => Display::fmt(err, fmt),
+            TimedOut(_, ref err) => Display::fmt(err, fmt),
         }
     }
 }
@@ -96,6 +113,7 @@ impl Error for MainError {
             Other(_, ref err) => err.description(),
             OtherOwned(_, ref err) => err,
             OtherBorrowed(_, ref err) => err,
+            TimedOut(_, ref err) => err,
         }
     }
 }
@@ -0,0 +1,220 @@
+/*
+Copyright ⓒ 2017 cargo-script contributors.
+
+Licensed under the MIT license (see LICENSE or <http://opensource.org
+/licenses/MIT>) or the Apache License, Version 2.0 (see LICENSE of
+<http://www.apache.org/licenses/LICENSE-2.0>), at your option. All
+files in the project carrying such notice may not be copied, modified,
+or distributed except according to those terms.
+*/
+/*!
+This module contains code related to exporting a script as a regular Cargo package.
+*/
+use crate::error::{Blame, MainError, Result, ResultExt};
+use crate::manifest;
+use log::info;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct Args {
+    pub script: OsString,
+    pub to: Option<PathBuf>,
+    pub force: bool,
+}
+
+impl Args {
+    pub fn subcommand() -> clap::App<'static, 'static> {
+        use clap::{AppSettings, Arg, SubCommand};
+
+        SubCommand::with_name("export")
+            .about("Export a script as a standalone Cargo package.")
+            .setting(AppSettings::AllowInvalidUtf8)
+            .arg(
+                Arg::with_name("script")
+                    .help("Script file to export.")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("to")
+                    .help("Directory to create the package in.  Defaults to a directory named after the script, in the current directory.")
+                    .long("to")
+                    .takes_value(true)
+                    .value_name("DIR"),
+            )
+            .arg(
+                Arg::with_name("force")
+                    .help("Export into the directory even if it isn't empty, overwriting any files in the way.")
+                    .long("force"),
+            )
+    }
+
+    pub fn parse(m: &clap::ArgMatches<'_>) -> Self {
+        Args {
+            script: m.value_of_os("script").unwrap().to_owned(),
+            to: m.value_of_os("to").map(Into::into),
+            force: m.is_present("force"),
+        }
+    }
+}
+
+pub fn try_main(args: Args) -> Result<i32> {
+    let script = crate::load_script(&args.script, None)?;
+    let input = script.input();
+
+    let dest = args.to.unwrap_or_else(|| PathBuf::from(&script.name));
+    if !args.force && !is_empty_dir(&dest)? {
+        return Err(MainError::OtherOwned(
+            Blame::Human,
+            format!(
+                "`{}` already exists and isn't empty; use `--force` to export into it anyway",
+                dest.display()
+            ),
+        ));
+    }
+
+    let deps_from = crate::env_deps_from()?;
+    let split = manifest::split_input(
+        &input,
+        &manifest::SplitOptions {
+            deps_from: deps_from.as_ref(),
+            ..Default::default()
+        },
+    )?;
+    for warning in &split.warnings {
+        warning!("{}", warning);
+    }
+    let (mut mani, mut files) = (split.manifest, split.files);
+    let own_files = files.len();
+    crate::add_bundle_files(&input, script.bundle.as_deref(), &mut mani, &mut files)?;
+    let mani = export_manifest(&mani, &split.bin_name)?;
+    let source = manifest::strip_embedded_manifest(&script.content);
+    let source = match split.includes.is_empty() {
+        true => source,
+        false => manifest::expand_includes(&source, &script.path)?.0,
+    };
+
+    let write = |rel_path: &str, content: &[u8]| -> Result<()> {
+        let path = dest.join(rel_path);
+        fs::create_dir_all(path.parent().unwrap())
+            .and_then(|_| fs::write(&path, content))
+            .map_err(MainError::from)
+            .err_tag(format!("could not write `{}`", path.display()))
+            .shift_blame(Blame::Human)
+    };
+    write("Cargo.toml", mani.as_bytes())?;
+    write("src/main.rs", source.as_bytes())?;
+
+    // Build scripts, extra binaries and the like go in the same places they would in the generated package.  Modules, and anything else from a bundle, have to go next to `main.rs`.
+    for (i, file) in files.iter().enumerate() {
+        match i >= own_files || split.modules.iter().any(|(_, path)| *path == file.path) {
+            true => write(&format!("src/{}", file.path), &file.content)?,
+            false => write(&file.path, &file.content)?,
+        }
+    }
+
+    // If the script has been run before, build against the same versions of its dependencies.
+    let pkg_path = crate::script_pkg_path(&input, deps_from.as_ref())?;
+    let lock_path = pkg_path.join("Cargo.lock");
+    if lock_path.is_file() {
+        info!("copying {:?}", lock_path);
+        fs::copy(&lock_path, dest.join("Cargo.lock"))?;
+    }
+
+    println!(
+        "Exported `{}` to `{}`.",
+        script.found.display(),
+        dest.display()
+    );
+    Ok(0)
+}
+
+/**
+Turns the manifest of a generated package into one for the exported package.
+
+The script's binary target (`bin_name`) is pointed at the usual `src/main.rs`, and the edition is written out explicitly, since the package would otherwise be at the mercy of Cargo's default.  Relative paths in the script's manifest have already been made absolute, so they keep working wherever the package ends up.
+*/
+fn export_manifest(mani: &str, bin_name: &str) -> Result<String> {
+    use toml::Value;
+
+    let mut mani: toml::value::Table =
+        toml::from_str(mani).map_err(|e| MainError::Other(Blame::Internal, Box::new(e)))?;
+
+    if let Some(Value::Array(bins)) = mani.get_mut("bin") {
+        for bin in bins.iter_mut().filter_map(Value::as_table_mut) {
+            if bin.get("name").and_then(Value::as_str) == Some(bin_name) {
+                bin.insert("path".into(), Value::String("src/main.rs".into()));
+            }
+        }
+    }
+    if let Some(Value::Table(pkg)) = mani.get_mut("package") {
+        pkg.entry("edition")
+            .or_insert_with(|| Value::String("2015".into()));
+    }
+
+    Ok(format!("{}", Value::Table(mani)))
+}
+
+fn is_empty_dir(path: &Path) -> Result<bool> {
+    if !path.exists() {
+        return Ok(true);
+    }
+    Ok(path.is_dir() && fs::read_dir(path)?.next().is_none())
+}
+
+#[test]
+fn test_export_manifest() {
+    let mani = export_manifest(
+        r#"[[bin]]
+name = "n"
+path = "n.rs"
+
+[dependencies]
+time = "0.1.25"
+
+[package]
+authors = ["Anonymous"]
+name = "n"
+version = "0.1.0"
+
+[workspace]
+"#,
+        "n",
+    )
+    .unwrap();
+    assert_eq!(
+        mani,
+        r#"[[bin]]
+name = "n"
+path = "src/main.rs"
+
+[dependencies]
+time = "0.1.25"
+
+[package]
+authors = ["Anonymous"]
+edition = "2015"
+name = "n"
+version = "0.1.0"
+
+[workspace]
+"#
+    );
+
+    let mani = export_manifest(
+        r#"[[bin]]
+name = "n"
+path = "n.rs"
+
+[package]
+edition = "2018"
+name = "n"
+version = "0.1.0"
+"#,
+        "n",
+    )
+    .unwrap();
+    assert!(mani.contains("edition = \"2018\""));
+}
@@ -15,13 +15,15 @@ Since this only makes sense on Windows, this entire module is Windows-only.
 #![cfg(windows)]
 
 use crate::error::{Blame, Result};
+use crate::platform;
 use itertools::Itertools;
 use std::io;
 
 #[derive(Debug)]
 pub enum Args {
-    Install { amend_pathext: bool },
+    Install { amend_pathext: bool, pause: bool },
     Uninstall,
+    Doctor { fix: bool, amend_pathext: bool },
 }
 
 impl Args {
@@ -37,18 +39,38 @@ impl Args {
                     .help("Add script extension to PATHEXT.  This allows scripts to be executed without typing the file extension.")
                     .long("amend-pathext")
                 )
+                .arg(Arg::with_name("pause")
+                    .help("When a script that was double-clicked fails, keep its console window open until Enter is pressed, so there's time to read why.")
+                    .long("pause")
+                )
             )
             .subcommand(SubCommand::with_name("uninstall")
                 .about("Uninstall file associations.")
             )
+            .subcommand(SubCommand::with_name("doctor")
+                .about("Check that the file associations are installed, and point at this copy of cargo-script.")
+                .arg(Arg::with_name("fix")
+                    .help("Install the file associations again if there's anything wrong with them.")
+                    .long("fix")
+                )
+                .arg(Arg::with_name("amend_pathext")
+                    .help("Also check that the script extension is in PATHEXT (and add it, with `--fix`).")
+                    .long("amend-pathext")
+                )
+            )
     }
 
     pub fn parse(m: &clap::ArgMatches<'_>) -> Self {
         match m.subcommand() {
             ("install", Some(m)) => Args::Install {
                 amend_pathext: m.is_present("amend_pathext"),
+                pause: m.is_present("pause"),
             },
             ("uninstall", _) => Args::Uninstall,
+            ("doctor", Some(m)) => Args::Doctor {
+                fix: m.is_present("fix"),
+                amend_pathext: m.is_present("amend_pathext"),
+            },
             (name, _) => panic!("bad subcommand: {:?}", name),
         }
     }
@@ -56,19 +78,23 @@ impl Args {
 
 pub fn try_main(args: Args) -> Result<i32> {
     match args {
-        Args::Install { amend_pathext } => install(amend_pathext)?,
+        Args::Install {
+            amend_pathext,
+            pause,
+        } => install(amend_pathext, pause)?,
         Args::Uninstall => uninstall()?,
+        Args::Doctor { fix, amend_pathext } => return doctor(fix, amend_pathext),
     }
 
     Ok(0)
 }
 
-fn install(amend_pathext: bool) -> Result<()> {
+/**
+The path to `run-cargo-script.exe`, which should be installed next to this executable, in the form the registry wants it.
+*/
+fn handler_path() -> Result<String> {
     use std::env;
-    use winreg::enums as wre;
-    use winreg::RegKey;
 
-    // Set up file association.
     let cs_path = env::current_exe()?;
     let cs_path = cs_path.canonicalize()?;
     let rcs_path = cs_path.with_file_name("run-cargo-script.exe");
@@ -78,12 +104,26 @@ fn install(amend_pathext: bool) -> Result<()> {
     }
 
     // We have to remove the `\\?\` prefix because, if we don't, the shell freaks out.
-    let rcs_path = rcs_path.to_string_lossy();
-    let rcs_path = if rcs_path.starts_with(r#"\\?\"#) {
-        &rcs_path[4..]
-    } else {
-        &rcs_path[..]
-    };
+    let rcs_path = platform::simplify_path(&rcs_path);
+    Ok(rcs_path.to_string_lossy().into_owned())
+}
+
+/**
+The command the registry runs to open a script.  With `pause`, `run-cargo-script` keeps the console open if the script fails.
+*/
+fn handler_command(rcs_path: &str, pause: bool) -> String {
+    match pause {
+        true => format!(r#""{}" --pause "%1" %*"#, rcs_path),
+        false => format!(r#""{}" "%1" %*"#, rcs_path),
+    }
+}
+
+fn install(amend_pathext: bool, pause: bool) -> Result<()> {
+    use winreg::enums as wre;
+    use winreg::RegKey;
+
+    // Set up file association.
+    let rcs_path = handler_path()?;
 
     let res = (|| -> io::Result<()> {
         let hlcr = RegKey::predef(wre::HKEY_CLASSES_ROOT);
@@ -94,7 +134,7 @@ fn install(amend_pathext: bool) -> Result<()> {
         cs_crs.set_value("", &"Cargo Script")?;
 
         let (sh_o_c, _) = cs_crs.create_subkey(r#"shell\open\command"#)?;
-        sh_o_c.set_value("", &format!(r#""{}" "%1" %*"#, rcs_path))?;
+        sh_o_c.set_value("", &handler_command(&rcs_path, pause))?;
         Ok(())
     })();
 
@@ -114,6 +154,9 @@ fn install(amend_pathext: bool) -> Result<()> {
 
     println!("Created run-cargo-script registry entry.");
     println!("- Handler set to: {}", rcs_path);
+    if pause {
+        println!("- Consoles stay open when a script fails.");
+    }
 
     // Amend PATHEXT.
     if amend_pathext {
@@ -178,6 +221,96 @@ fn uninstall() -> Result<()> {
     Ok(())
 }
 
+/**
+Checks the file associations, and reinstalls them if asked to and there's anything wrong.
+
+Returns 1 if there's a problem left over, so this can be used from scripts.
+*/
+fn doctor(fix: bool, amend_pathext: bool) -> Result<i32> {
+    let problems = diagnose(amend_pathext)?;
+    if problems.is_empty() {
+        println!("File associations are installed, and point at this copy of cargo-script.");
+        return Ok(0);
+    }
+    for problem in &problems {
+        println!("- {}", problem);
+    }
+    if !fix {
+        println!("Run `cargo script file-association doctor --fix` to install them again.");
+        return Ok(1);
+    }
+
+    // If only PATHEXT was missing, this just puts back the same handler.  Either way, it pauses if it did before.
+    let pause = default_value(r#"CargoScript.Crs\shell\open\command"#)?
+        .map_or(false, |command| command.contains(r#"" --pause "#));
+    install(amend_pathext, pause)?;
+    match diagnose(amend_pathext)?.is_empty() {
+        true => Ok(0),
+        false => Err((
+            Blame::Human,
+            "file associations are still broken after reinstalling them",
+        )
+            .into()),
+    }
+}
+
+/**
+Describes everything that's wrong with the file associations, as `install` would have set them up.  PATHEXT is only checked if `amend_pathext` is set, since putting the extension in it is optional.
+*/
+fn diagnose(amend_pathext: bool) -> Result<Vec<String>> {
+    use winreg::enums as wre;
+    use winreg::RegKey;
+
+    let mut problems = vec![];
+    match default_value(".crs")? {
+        Some(ref class) if class == "CargoScript.Crs" => {
+            let rcs_path = handler_path()?;
+            let expected = handler_command(&rcs_path, false);
+            match default_value(r#"CargoScript.Crs\shell\open\command"#)? {
+                Some(ref command)
+                    if *command == expected || *command == handler_command(&rcs_path, true) => {}
+                Some(command) => problems.push(format!(
+                    "the handler for `.crs` files is out of date: it runs `{}`, rather than `{}`",
+                    command, expected
+                )),
+                None => problems.push("there is no handler for `.crs` files".into()),
+            }
+        }
+        Some(class) => problems.push(format!(
+            "`.crs` files are associated with `{}`, rather than cargo-script",
+            class
+        )),
+        None => problems.push("there is no file association for `.crs` files".into()),
+    }
+
+    if amend_pathext {
+        let hklm = RegKey::predef(wre::HKEY_LOCAL_MACHINE);
+        let env =
+            hklm.open_subkey(r#"SYSTEM\CurrentControlSet\Control\Session Manager\Environment"#)?;
+        let pathext: String = env.get_value("PATHEXT")?;
+        if !pathext.split(";").any(|e| e.eq_ignore_ascii_case(".crs")) {
+            problems.push("`.crs` is not in PATHEXT".into());
+        }
+    }
+
+    Ok(problems)
+}
+
+/**
+Reads the default value of a key under `HKEY_CLASSES_ROOT`, if it's there.
+*/
+fn default_value(path: &str) -> io::Result<Option<String>> {
+    use winreg::enums as wre;
+    use winreg::RegKey;
+
+    let hlcr = RegKey::predef(wre::HKEY_CLASSES_ROOT);
+    match hlcr.open_subkey(path).and_then(|key| key.get_value("")) {
+        Ok(value) => Ok(Some(value)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
 trait IgnoreMissing {
     fn ignore_missing_and<F>(self, f: F) -> Self
     where
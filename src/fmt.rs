@@ -0,0 +1,152 @@
+/*
+Copyright ⓒ 2017 cargo-script contributors.
+
+Licensed under the MIT license (see LICENSE or <http://opensource.org
+/licenses/MIT>) or the Apache License, Version 2.0 (see LICENSE of
+<http://www.apache.org/licenses/LICENSE-2.0>), at your option. All
+files in the project carrying such notice may not be copied, modified,
+or distributed except according to those terms.
+*/
+/*!
+This module contains code related to formatting scripts with rustfmt.
+*/
+use crate::error::{Blame, MainError, Result, ResultExt};
+use crate::manifest;
+use lazy_static::lazy_static;
+use log::info;
+use regex::{Captures, Regex};
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+lazy_static! {
+    static ref RE_DIFF_HEADER: Regex =
+        Regex::new(r"(?m)^Diff in <stdin>(:| at line )(\d+):").unwrap();
+}
+
+#[derive(Debug)]
+pub struct Args {
+    pub script: OsString,
+    pub check: bool,
+}
+
+impl Args {
+    pub fn subcommand() -> clap::App<'static, 'static> {
+        use clap::{AppSettings, Arg, SubCommand};
+
+        SubCommand::with_name("fmt")
+            .about("Format a script with rustfmt, leaving its hashbang and embedded manifest alone.")
+            .setting(AppSettings::AllowInvalidUtf8)
+            .arg(
+                Arg::with_name("script")
+                    .help("Script file to format.")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("check")
+                    .help("Don't change the script; instead, show a diff of what would be changed, and fail if there is anything.")
+                    .long("check"),
+            )
+    }
+
+    pub fn parse(m: &clap::ArgMatches<'_>) -> Self {
+        Args {
+            script: m.value_of_os("script").unwrap().to_owned(),
+            check: m.is_present("check"),
+        }
+    }
+}
+
+pub fn try_main(args: Args) -> Result<i32> {
+    let script = crate::load_script(&args.script, None)?;
+    let (path, content) = (&script.found, &script.content);
+
+    // rustfmt needs to know the edition, which might only come from the script's defaults.
+    let split = manifest::split_input(&script.input(), &manifest::SplitOptions::default())?;
+    let edition = script_edition(&split.manifest)?;
+
+    let (header, body) = manifest::split_script_header(content);
+    if body.is_empty() {
+        return Ok(0);
+    }
+
+    // Run from the script's directory, so rustfmt finds any `rustfmt.toml` the script would.
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    let formatted = rustfmt(dir, &edition, body, false)?;
+    if formatted == body {
+        return Ok(0);
+    }
+
+    if args.check {
+        // Point the diff at the script itself, rather than at rustfmt's stdin.
+        let diff = rustfmt(dir, &edition, body, true)?;
+        let offset = header.matches('\n').count();
+        let diff = RE_DIFF_HEADER.replace_all(&diff, |caps: &Captures<'_>| {
+            let line: usize = caps[2].parse().unwrap_or(0);
+            format!("Diff in {}{}{}:", path.display(), &caps[1], line + offset)
+        });
+        print!("{}", diff);
+        return Ok(1);
+    }
+
+    fs::write(path, format!("{}{}", header, formatted))
+        .map_err(MainError::from)
+        .err_tag(format!("could not write `{}`", path.display()))
+        .shift_blame(Blame::Human)?;
+    Ok(0)
+}
+
+/**
+Works out the edition of a script from its complete manifest, using Cargo's default if it doesn't give one.
+*/
+fn script_edition(mani: &str) -> Result<String> {
+    let mani: toml::Value =
+        toml::from_str(mani).map_err(|e| MainError::Other(Blame::Internal, Box::new(e)))?;
+    Ok(mani
+        .get("package")
+        .and_then(|pkg| pkg.get("edition"))
+        .and_then(|ed| ed.as_str())
+        .unwrap_or("2015")
+        .into())
+}
+
+/**
+Runs rustfmt over `source`, returning either the formatted source or, with `check`, the diff rustfmt shows.
+*/
+fn rustfmt(dir: &Path, edition: &str, source: &str, check: bool) -> Result<String> {
+    let mut cmd = Command::new("rustfmt");
+    cmd.current_dir(dir).args(["--edition", edition]);
+    if check {
+        cmd.args(["--check", "--color", "never"]);
+    }
+    cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
+    info!("running {:?}", cmd);
+    crate::logging::trace_command(&cmd);
+
+    let mut child = cmd
+        .spawn()
+        .map_err(MainError::from)
+        .err_tag("could not run `rustfmt`; is it installed?")
+        .shift_blame(Blame::Human)?;
+    child.stdin.take().unwrap().write_all(source.as_bytes())?;
+    let output = child.wait_with_output()?;
+
+    // `--check` fails when there's a diff, which is exactly when we ask for one.
+    if !output.status.success() && (!check || output.stdout.is_empty()) {
+        return Err((Blame::Human, "rustfmt could not format the script").into());
+    }
+    String::from_utf8(output.stdout).map_err(|e| MainError::Other(Blame::Internal, Box::new(e)))
+}
+
+#[test]
+fn test_script_edition() {
+    let se = |s| script_edition(s).unwrap();
+    assert_eq!(se("[package]\nname = \"n\"\n"), "2015");
+    assert_eq!(se("[package]\nname = \"n\"\nedition = \"2021\"\n"), "2021");
+}
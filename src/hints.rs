@@ -0,0 +1,270 @@
+/*
+Copyright ⓒ 2017 cargo-script contributors.
+
+Licensed under the MIT license (see LICENSE or <http://opensource.org
+/licenses/MIT>) or the Apache License, Version 2.0 (see LICENSE of
+<http://www.apache.org/licenses/LICENSE-2.0>), at your option. All
+files in the project carrying such notice may not be copied, modified,
+or distributed except according to those terms.
+*/
+/*!
+This module contains the hints shown when a build fails in a way that's both common and easy to recognise, such as a dependency needing a system library that isn't installed.
+*/
+use crate::error::Result;
+use lazy_static::lazy_static;
+use log::info;
+use regex::Regex;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// What to suggest when there's no C toolchain, whether it's wanted for linking or for a build script.
+const C_TOOLCHAIN: &str = "install a C toolchain: `build-essential` on Debian or Ubuntu, the Xcode command line tools on macOS, or the Visual Studio Build Tools on Windows";
+
+lazy_static! {
+    /**
+    The known failures, and what to do about them.  `${1}` in a hint is replaced with whatever the pattern captured.
+
+    Only add failures here which can be recognised without any doubt, and come with advice that applies to everyone; a wrong hint is worse than none.
+    */
+    static ref SIGNATURES: Vec<(Regex, String)> = vec![
+        (
+            Regex::new(r"Could not find directory of OpenSSL installation").unwrap(),
+            "install OpenSSL's development files (`libssl-dev` on Debian or Ubuntu, or `openssl-devel` on Fedora) and `pkg-config`, or add `features = [\"vendored\"]` to the `openssl` dependency to build OpenSSL from source".into(),
+        ),
+        (
+            Regex::new(r"The pkg-config command could not be found").unwrap(),
+            "install `pkg-config`, which build scripts use to find system libraries".into(),
+        ),
+        (
+            Regex::new(r"The system library `([^`\s]+)` required by crate").unwrap(),
+            "install the development files for the `${1}` system library; on Debian or Ubuntu, these are usually in a package called something like `lib${1}-dev`".into(),
+        ),
+        (
+            Regex::new(r"Package (\S+) was not found in the pkg-config search path").unwrap(),
+            "install the development files for the `${1}` system library; on Debian or Ubuntu, these are usually in a package called something like `lib${1}-dev`".into(),
+        ),
+        (
+            Regex::new(r"linker `([^`\s]+)` not found").unwrap(),
+            format!("`${{1}}` is needed to link the script; {}", C_TOOLCHAIN),
+        ),
+        (
+            Regex::new(r"Failed to find tool\. Is `([^`\s]+)` installed\?").unwrap(),
+            format!("`${{1}}` is needed to build C code for a dependency; {}", C_TOOLCHAIN),
+        ),
+        (
+            Regex::new(r"(?:cannot|unable to) find (?:library )?-l([\w.+-]+)").unwrap(),
+            "install the development files for the `${1}` library, which the script is linked against; on Debian or Ubuntu, these are usually in a package called something like `lib${1}-dev`".into(),
+        ),
+        (
+            Regex::new(r"(?:is not permitted in Rust 2015|(?:is|are) only allowed in Rust 2018 or later)").unwrap(),
+            "scripts are built as Rust 2015 unless they say otherwise; add `edition = \"2021\"` to the `[package]` section of the script's manifest, or pass `--manifest-key package.edition=2021`".into(),
+        ),
+        (
+            Regex::new(r"(?:feature `edition(\d+)` is required|this version of Cargo is older than the `(\d+)` edition)").unwrap(),
+            "this toolchain is too old for edition ${1}${2}; update it (try `rustup update`), or use a newer one (such as `cargo +stable script`)".into(),
+        ),
+        (
+            Regex::new(r"(?:you're using offline mode \(--offline\)|but --offline was specified)").unwrap(),
+            "Cargo was told to stay off the network (by `--no-net`, or `CARGO_NET_OFFLINE`), but some of the script's dependencies haven't been downloaded yet; run the script once without it to fetch them".into(),
+        ),
+    ];
+
+    static ref RE_ANSI_ESCAPE: Regex = Regex::new(r"\x1b\[[0-9;]*[A-Za-z]").unwrap();
+}
+
+/**
+Works out which hints apply to what a failed build printed.  Each hint is only given once, however many times its failure turns up.
+*/
+pub fn hints_for(output: &str) -> Vec<String> {
+    let output = strip_colours(output);
+    let mut hints: Vec<String> = vec![];
+    for (pattern, hint) in SIGNATURES.iter() {
+        for caps in pattern.captures_iter(&output) {
+            let mut expanded = String::new();
+            caps.expand(hint, &mut expanded);
+            if !hints.contains(&expanded) {
+                hints.push(expanded);
+            }
+        }
+    }
+    hints
+}
+
+/**
+Takes the colours out of what Cargo printed, for somewhere other than a terminal.
+*/
+pub fn strip_colours(output: &str) -> std::borrow::Cow<'_, str> {
+    RE_ANSI_ESCAPE.replace_all(output, "")
+}
+
+/**
+Shows the hints for what a failed build printed, if there are any.
+*/
+pub fn show_hints(output: &str) {
+    for hint in hints_for(output) {
+        hint!("{}", hint);
+    }
+}
+
+/**
+Runs a Cargo command, passing on everything it writes to stderr, and follows it with any hints if it fails.
+
+If `quiet_for` is given, Cargo's output is held back for that long, and thrown away if it succeeds in that time; this is the same as `util::suppress_child_output`.
+*/
+pub fn run_with_hints(cmd: &mut Command, quiet_for: Option<Duration>) -> Result<ExitStatus> {
+    let (status, output) = run_teed(cmd, quiet_for, None)?;
+    if !status.success() {
+        show_hints(&output);
+    }
+    Ok(status)
+}
+
+/**
+Runs a Cargo command as `run_with_hints` does, but leaves the hints to the caller: it gets back everything the command wrote to stderr, instead.
+
+With a `timeout`, the command (and whatever it started) is killed once it's up, as with `watchdog::spawn`.
+*/
+pub fn run_teed(
+    cmd: &mut Command,
+    quiet_for: Option<Duration>,
+    timeout: Option<Duration>,
+) -> Result<(ExitStatus, String)> {
+    info!("running with hints {:?}", cmd);
+    crate::logging::trace_command(cmd);
+    let mut watched = crate::watchdog::spawn(cmd.stderr(Stdio::piped()), timeout)?;
+
+    let tee = Arc::new(Mutex::new(Tee {
+        output: vec![],
+        shown: quiet_for.is_none(),
+        done: false,
+    }));
+    if let Some(quiet_for) = quiet_for {
+        let tee = tee.clone();
+        thread::spawn(move || {
+            thread::sleep(quiet_for);
+            let mut tee = tee.lock().unwrap();
+            if !tee.done {
+                tee.show();
+            }
+        });
+    }
+
+    {
+        let mut stderr_in = BufReader::new(watched.child.stderr.take().unwrap());
+        let mut line = vec![];
+        loop {
+            line.clear();
+            if stderr_in.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            let mut tee = tee.lock().unwrap();
+            tee.output.extend_from_slice(&line);
+            if tee.shown {
+                let stderr = io::stderr();
+                let mut stderr = stderr.lock();
+                let _ = stderr.write_all(&line).and_then(|_| stderr.flush());
+            }
+        }
+    }
+    let status = watched.wait();
+
+    let mut tee = tee.lock().unwrap();
+    tee.done = true;
+    // Whatever was said before a timeout could well explain it, so that's shown too.
+    match status {
+        Ok(ref status) if status.success() => (),
+        _ => tee.show(),
+    }
+    Ok((status?, String::from_utf8_lossy(&tee.output).into_owned()))
+}
+
+/**
+Everything a child process has written to stderr, and whether it's being passed on yet.
+*/
+struct Tee {
+    output: Vec<u8>,
+    shown: bool,
+    done: bool,
+}
+
+impl Tee {
+    /// Writes out everything held back so far, and passes on the rest as it comes.
+    fn show(&mut self) {
+        if !self.shown {
+            self.shown = true;
+            let _ = io::stderr().write_all(&self.output);
+        }
+    }
+}
+
+#[test]
+fn test_hints_for() {
+    macro_rules! fixture {
+        ($name:expr) => {
+            hints_for(include_str!(concat!(
+                "../tests/data/build-failures/",
+                $name,
+                ".txt"
+            )))
+        };
+    }
+
+    let hints = fixture!("openssl");
+    assert_eq!(hints.len(), 1, "{:?}", hints);
+    assert!(hints[0].contains("`libssl-dev`"));
+    assert!(hints[0].contains("`features = [\"vendored\"]`"));
+
+    assert_eq!(
+        fixture!("pkg-config"),
+        vec!["install `pkg-config`, which build scripts use to find system libraries"]
+    );
+
+    let hints = fixture!("pkg-config-package");
+    assert_eq!(hints.len(), 1, "{:?}", hints);
+    assert!(hints[0].starts_with("install the development files for the `alsa` system library"));
+    assert!(hints[0].contains("`libalsa-dev`"));
+
+    let hints = fixture!("linker");
+    assert_eq!(hints.len(), 1, "{:?}", hints);
+    assert!(hints[0].starts_with("`cc` is needed to link the script; install a C toolchain"));
+
+    let hints = fixture!("c-compiler");
+    assert_eq!(hints.len(), 1, "{:?}", hints);
+    assert!(hints[0].starts_with("`cc` is needed to build C code for a dependency"));
+
+    let hints = fixture!("missing-library");
+    assert_eq!(hints.len(), 1, "{:?}", hints);
+    assert!(hints[0].starts_with("install the development files for the `zmq` library"));
+
+    // Both errors are down to the edition, so there should only be one hint.
+    let hints = fixture!("edition-2015");
+    assert_eq!(hints.len(), 1, "{:?}", hints);
+    assert!(hints[0].contains("--manifest-key package.edition=2021"));
+
+    assert_eq!(
+        fixture!("edition-unstable"),
+        vec!["this toolchain is too old for edition 2024; update it (try `rustup update`), or use a newer one (such as `cargo +stable script`)"]
+    );
+
+    let hints = fixture!("edition-unknown");
+    assert_eq!(hints.len(), 1, "{:?}", hints);
+    assert!(hints[0].starts_with("this toolchain is too old for edition 2027;"));
+
+    let hints = fixture!("offline");
+    assert_eq!(hints.len(), 1, "{:?}", hints);
+    assert!(hints[0].contains("`--no-net`"));
+
+    assert_eq!(fixture!("type-error"), Vec::<String>::new());
+}
+
+#[test]
+fn test_hints_for_colored() {
+    let hints = hints_for(
+        "\x1b[0m\x1b[1m\x1b[38;5;9merror\x1b[0m\x1b[0m\x1b[1m: linker `\x1b[0m\x1b[0m\x1b[1mclang\x1b[0m\x1b[0m\x1b[1m` not found\x1b[0m\n",
+    );
+    assert_eq!(hints.len(), 1, "{:?}", hints);
+    assert!(hints[0].starts_with("`clang` is needed"));
+}
@@ -0,0 +1,95 @@
+/*
+Copyright ⓒ 2017 cargo-script contributors.
+
+Licensed under the MIT license (see LICENSE or <http://opensource.org
+/licenses/MIT>) or the Apache License, Version 2.0 (see LICENSE of
+<http://www.apache.org/licenses/LICENSE-2.0>), at your option. All
+files in the project carrying such notice may not be copied, modified,
+or distributed except according to those terms.
+*/
+/*!
+This module contains the logging set-up, which everything cargo-script has to say (other than errors and what it was asked for) goes through.
+
+There are two kinds of messages.  Notes, warnings, hints and status messages are for the user, and are shown as they always have been, unless `--quiet` is given.  Everything else is diagnostic: it's only shown with `--trace`, or whatever `RUST_LOG` asks for, and is timestamped.  Either way, it all goes to stderr, a whole message at a time, so it never ends up mixed into the script's output.
+*/
+use log::{trace, LevelFilter};
+use std::io::Write;
+use std::process::Command;
+
+/**
+The targets used for messages meant for the user, rather than for debugging.
+*/
+const USER_TARGETS: &[&str] = &["note", "warning", "hint", "status"];
+
+/// Tells the user something worth knowing, as `note: ...`.
+macro_rules! note {
+    ($($args:tt)*) => { log::info!(target: "note", $($args)*) };
+}
+
+/// Warns the user, as `warning: ...`.
+macro_rules! warning {
+    ($($args:tt)*) => { log::warn!(target: "warning", $($args)*) };
+}
+
+/// Suggests a fix to the user, as `hint: ...`.
+macro_rules! hint {
+    ($($args:tt)*) => { log::info!(target: "hint", $($args)*) };
+}
+
+/// Tells the user how things went, as-is.
+macro_rules! status {
+    ($($args:tt)*) => { log::info!(target: "status", $($args)*) };
+}
+
+/**
+Sets up logging.  With `trace`, everything cargo-script logs is shown, on top of whatever `RUST_LOG` asks for.  With `quiet`, messages for the user aren't.
+*/
+pub fn init(trace: bool, quiet: bool) {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(LevelFilter::Error);
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    if trace {
+        builder.filter_module("cargo_script", LevelFilter::Trace);
+    }
+    for &target in USER_TARGETS {
+        builder.filter_module(
+            target,
+            if quiet {
+                LevelFilter::Off
+            } else {
+                LevelFilter::Info
+            },
+        );
+    }
+    builder.format(|buf, record| match record.target() {
+        "status" => writeln!(buf, "{}", record.args()),
+        target if USER_TARGETS.contains(&target) => writeln!(buf, "{}: {}", target, record.args()),
+        target => writeln!(
+            buf,
+            "[{} {:<5} {}] {}",
+            buf.timestamp_millis(),
+            record.level(),
+            target,
+            record.args()
+        ),
+    });
+    builder.init();
+}
+
+/**
+Logs a command that's about to be run, with everything it's given, and where it's run.
+*/
+pub fn trace_command(cmd: &Command) {
+    let cwd = match cmd.get_current_dir() {
+        Some(dir) => Some(dir.to_owned()),
+        None => std::env::current_dir().ok(),
+    };
+    trace!(
+        "running {:?} with arguments {:?} in {:?}",
+        cmd.get_program(),
+        cmd.get_args().collect::<Vec<_>>(),
+        cwd
+    );
+}
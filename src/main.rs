@@ -49,12 +49,34 @@ macro_rules! if_windows {
     ($($tts:tt)*) => {{}};
 }
 
+// This comes first, so that its macros can be used everywhere else.
+#[macro_use]
+mod logging;
+
+mod clippy;
 mod consts;
+mod env_file;
+mod environment;
 mod error;
+mod export;
+mod fmt;
+mod hints;
 mod manifest;
+mod new;
+mod panics;
+mod pin;
 mod platform;
+mod prebuild;
+mod quote;
+mod report;
+mod sandbox;
+mod script_config;
+mod self_update;
 mod templates;
+mod time_spec;
+mod update;
 mod util;
+mod watchdog;
 
 #[cfg(windows)]
 mod file_assoc;
@@ -63,9 +85,7 @@ mod file_assoc;
 mod file_assoc {}
 
 use semver::Version;
-use std::borrow::Cow;
-use std::error::Error;
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
@@ -73,40 +93,124 @@ use std::process::{self, Command};
 
 use crate::error::{Blame, MainError, Result, ResultExt};
 use crate::platform::MigrationKind;
-use crate::util::{ChainMap, Defer, PathExt};
+use crate::sandbox::Sandbox;
+use crate::util::{make_temp_dir, write_file_atomic, ChainMap, Defer, PathExt, Timings};
 
 #[derive(Debug)]
 enum SubCommand {
     Script(Args),
+    New(new::Args),
+    Export(export::Args),
+    Clippy(clippy::Args),
+    Env(environment::Args),
+    Fmt(fmt::Args),
+    Pin(pin::Args),
+    Prebuild(prebuild::Args),
+    Quote(quote::Args),
     Templates(templates::Args),
+    Update(update::Args),
+    SelfUpdate(self_update::Args),
     #[cfg(windows)]
     FileAssoc(file_assoc::Args),
 }
 
 #[derive(Debug)]
 struct Args {
-    script: Option<String>,
-    args: Vec<String>,
+    script: Option<OsString>,
+    args: Vec<OsString>,
     features: Option<String>,
+    cargo_config: Vec<String>,
+    jobs: Option<i32>,
+    build_limits: watchdog::Limits,
+    input_encoding: Option<InputEncoding>,
+    target: Option<String>,
+    rustflags: Option<String>,
+    /// `--allow`, `--warn` and `--deny`, as the flag rustc takes and the lint, in the order they were given.
+    lint_levels: Vec<(&'static str, String)>,
+    panic: Option<String>,
+    strip: Option<String>,
+    gui: bool,
+    runner: Option<String>,
+    before: Option<String>,
+    after: Option<String>,
+    sandbox: bool,
+    sandbox_command: Option<String>,
+    clean_panics: bool,
+    color_diagnostics_to_file: Option<PathBuf>,
+    force_child_color: bool,
+    no_hints: bool,
+    verbose: bool,
+    quiet: bool,
+    trace: bool,
+    env: Vec<String>,
+    env_file: Vec<PathBuf>,
 
     expr: bool,
+    expr_file: bool,
+    /// The fragments from any `--expr` after the first, whose fragment is `script`.
+    expr_fragments: Vec<OsString>,
     loop_: bool,
     count: bool,
-
-    pkg_path: Option<String>,
+    fold: Option<String>,
+    finish: Option<String>,
+    filter: bool,
+    output: Option<PathBuf>,
+    append: bool,
+
+    pkg_path: Option<PathBuf>,
+    no_cache: bool,
+    require_cache: bool,
+    no_net: bool,
     gen_pkg_only: bool,
+    dry_run: bool,
+    hash_only: bool,
+    keep_build_dir: bool,
     build_only: bool,
     clear_cache: bool,
+    list_cache: bool,
+    clear_data: bool,
+    list_data: bool,
+    list_templates: bool,
+    list_tests: bool,
+    since: Option<String>,
+    until: Option<String>,
     debug: bool,
+    release: bool,
+    deny_warnings: bool,
+    allow_wildcard_deps: bool,
+    deny_wildcard_deps: bool,
     dep: Vec<String>,
+    manifest_key: Vec<String>,
+    manifest_from_stdin: bool,
+    deps_from: Option<PathBuf>,
     dep_extern: Vec<String>,
     extern_: Vec<String>,
+    prelude_crate: Vec<String>,
     force: bool,
+    name: Option<String>,
+    bin: Option<String>,
     unstable_features: Vec<String>,
     use_bincache: Option<bool>,
     migrate_data: Option<MigrationKind>,
     build_kind: BuildKind,
+    doc_deps: bool,
+    run_as_test_module: bool,
     template: Option<String>,
+    expr_as: Option<String>,
+    error_format: Option<String>,
+    timings: bool,
+    report: Option<PathBuf>,
+    append_report: bool,
+    message_format: MessageFormat,
+    content_addressed: bool,
+    which: Option<MessageFormat>,
+    which_all: bool,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum MessageFormat {
+    Human,
+    Json,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -114,13 +218,20 @@ enum BuildKind {
     Normal,
     Test,
     Bench,
+    Check,
+    Tree,
+    Doc,
 }
 
 impl BuildKind {
     fn can_exec_directly(&self) -> bool {
         match *self {
             BuildKind::Normal => true,
-            BuildKind::Test | BuildKind::Bench => false,
+            BuildKind::Test
+            | BuildKind::Bench
+            | BuildKind::Check
+            | BuildKind::Tree
+            | BuildKind::Doc => false,
         }
     }
 
@@ -129,20 +240,37 @@ impl BuildKind {
             BuildKind::Normal => panic!("asked for exec command for normal build"),
             BuildKind::Test => "test",
             BuildKind::Bench => "bench",
+            BuildKind::Check => "check",
+            BuildKind::Tree => "tree",
+            BuildKind::Doc => "doc",
         }
     }
 
-    fn from_flags(test: bool, bench: bool) -> Self {
-        match (test, bench) {
-            (false, false) => BuildKind::Normal,
-            (true, false) => BuildKind::Test,
-            (false, true) => BuildKind::Bench,
-            _ => panic!("got both test and bench"),
+    fn from_flags(test: bool, bench: bool, check: bool, tree: bool, doc: bool) -> Self {
+        match (test, bench, check, tree, doc) {
+            (false, false, false, false, false) => BuildKind::Normal,
+            (true, false, false, false, false) => BuildKind::Test,
+            (false, true, false, false, false) => BuildKind::Bench,
+            (false, false, true, false, false) => BuildKind::Check,
+            (false, false, false, true, false) => BuildKind::Tree,
+            (false, false, false, false, true) => BuildKind::Doc,
+            _ => panic!("got more than one of test, bench, check, tree, and doc"),
         }
     }
 }
 
 fn parse_args() -> SubCommand {
+    parse_args_from(std::env::args_os())
+}
+
+/**
+Parses `args` as if they were our command line, starting with the name we were run as.
+*/
+fn parse_args_from<I, T>(args: I) -> SubCommand
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
     use clap::{App, AppSettings, Arg, ArgGroup, SubCommand};
     let version = option_env!("CARGO_PKG_VERSION").unwrap_or("unknown");
     let about = r#"Compiles and runs "Cargoified Rust scripts"."#;
@@ -157,159 +285,651 @@ fn parse_args() -> SubCommand {
         }
     }
 
+    let script = SubCommand::with_name("script")
+        .version(version)
+        .about(about)
+        .setting(AppSettings::AllowInvalidUtf8)
+        .setting(AppSettings::TrailingVarArg)
+
+        /*
+        Major script modes.
+        */
+        .arg(Arg::with_name("script")
+            .help("Script file (with or without extension) to execute, or a directory to run as a script bundle.")
+            .index(1)
+            .required_unless_one(csas!["clear_cache", "list_cache", "clear_data", "list_data", "list_templates", "migrate_data"])
+        )
+        .arg(Arg::with_name("args")
+            .help("Additional arguments passed to the script.  Everything from the first of these on is passed exactly as given, including any `--`.")
+            .index(2)
+            .multiple(true)
+        )
+        .arg(Arg::with_name("expr")
+            .help("Execute <script> as a literal expression and display the result.  Give `--expr` again for each extra fragment of code (such as `-e 'let x = 2;' -e 'x * 3'`): they're put together a line at a time, and the last is the expression displayed.")
+            .long("expr")
+            .short("e")
+            .multiple(true)
+            .requires("script")
+        )
+        .arg(Arg::with_name("expr_file")
+            .help("Like `--expr`, but <script> is a file to read the expression from, or `-` to read it from stdin.  This saves quoting long expressions for the shell.")
+            .long("expr-file")
+            .short("E")
+            .requires("script")
+        )
+        .arg(Arg::with_name("loop")
+            .help("Execute <script> as a literal closure once for each line from stdin.")
+            .long("loop")
+            .short("l")
+            .requires("script")
+        )
+        .group(ArgGroup::with_name("expr_or_loop")
+            .args(&["expr", "expr_file", "loop"])
+        )
+        .group(ArgGroup::with_name("any_expr")
+            .args(&["expr", "expr_file"])
+        )
+
+        /*
+        Options that impact the script being executed.
+        */
+        .arg(Arg::with_name("count")
+            .help("Invoke the loop closure with two arguments: line, and line number.")
+            .long("count")
+            .requires("loop")
+        )
+        .arg(Arg::with_name("fold")
+            .help("Fold the input into an accumulator, starting with the value of EXPR.  The loop closure is called with the accumulator (by `&mut`) and each line; once the input runs out, the accumulator is printed.")
+            .long("fold")
+            .takes_value(true)
+            .value_name("EXPR")
+            .requires("loop")
+            .conflicts_with("count")
+        )
+        .arg(Arg::with_name("finish")
+            .help("With `--fold`, call CLOSURE with the accumulator once the input runs out, and print what it returns (unless that's `()`) instead of the accumulator.")
+            .long("finish")
+            .takes_value(true)
+            .value_name("CLOSURE")
+            .requires("fold")
+        )
+        .arg(Arg::with_name("filter")
+            .help("Treat the loop closure as a predicate, which has to return a `bool`: each line it returns `true` for is printed as it is.")
+            .long("filter")
+            .requires("loop")
+            .conflicts_with_all(csas!["count", "fold"])
+        )
+        .arg(Arg::with_name("output")
+            .help("Write what the expression or loop prints to FILE, rather than stdout.  Any missing parent directories are created, and FILE is replaced unless `--append` is given.")
+            .long("output")
+            .short("o")
+            .takes_value(true)
+            .value_name("FILE")
+            .requires("expr_or_loop")
+        )
+        .arg(Arg::with_name("append")
+            .help("With `--output`, add to the end of FILE, rather than replacing it.")
+            .long("append")
+            .requires("output")
+        )
+        .arg(Arg::with_name("debug")
+            .help("Build a debug executable, not an optimised one.")
+            .long("debug")
+            .requires("script")
+        )
+        .arg(Arg::with_name("release")
+            .help("Build an optimised executable.  This is the default, unless the script's manifest says otherwise.")
+            .long("release")
+            .requires("script")
+            .conflicts_with_all(csas!["debug"])
+        )
+        .arg(Arg::with_name("dep")
+            .help("Add an additional Cargo dependency.  Each SPEC can be either just the package name (which will assume the latest version) or a full `name=version` spec.")
+            .long("dep")
+            .short("d")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .requires("script")
+        )
+        .arg(Arg::with_name("manifest_key")
+            .help("Set a key in the generated manifest, over anything from the script or `--dep`.  KEY is a dotted path, like `package.edition` or `profile.release.lto`; VALUE is TOML, or else a plain string.")
+            .long("manifest-key")
+            .takes_value(true)
+            .value_name("KEY=VALUE")
+            .multiple(true)
+            .number_of_values(1)
+            .requires("script")
+        )
+        .arg(Arg::with_name("manifest_from_stdin")
+            .help("Read a manifest from stdin, and merge it over the script's embedded manifest and any `--dep`s.  `--manifest-key` still wins over it.  The script itself gets nothing on stdin.")
+            .long("manifest-from-stdin")
+            .requires("script")
+            .conflicts_with("loop")
+        )
+        .arg(Arg::with_name("deps_from")
+            .help("Take the versions of dependencies which don't give one (such as `// cargo-deps: serde`, or `--dep serde`) from an existing Cargo project: its `[dependencies]`, or else its workspace's `[workspace.dependencies]`.  Dependencies it doesn't have can be any version, as usual.  Defaults to `CARGO_SCRIPT_DEPS_FROM`.")
+            .long("deps-from")
+            .takes_value(true)
+            .value_name("MANIFEST")
+            .requires("script")
+        )
+        .arg(Arg::with_name("dep_extern")
+            .help("Like `dep`, except that it *also* adds a `#[macro_use] extern crate name;` item for expression and loop scripts.  Note that this only works if the name of the dependency and the name of the library it generates are exactly the same.")
+            .long("dep-extern")
+            .short("D")
+            .takes_value(true)
+            .multiple(true)
+            .requires("expr_or_loop")
+        )
+        .arg(Arg::with_name("prelude_crate")
+            .help("Like `dep-extern`, except that it *also* adds a `use name::prelude::*;` item, for crates which provide a `prelude` module.")
+            .long("prelude-crate")
+            .takes_value(true)
+            .value_name("SPEC")
+            .multiple(true)
+            .number_of_values(1)
+            .requires("expr_or_loop")
+        )
+        .arg(Arg::with_name("extern")
+            .help("Adds an `#[macro_use] extern crate name;` item for expressions and loop scripts.")
+            .long("extern")
+            .short("x")
+            .takes_value(true)
+            .multiple(true)
+            .requires("expr_or_loop")
+        )
+        .arg(Arg::with_name("env")
+            .help("Set an environment variable for the script.  Each SPEC should be of the form `KEY=VALUE`.  These take precedence over any variables loaded with `--env-file`.")
+            .long("env")
+            .takes_value(true)
+            .value_name("SPEC")
+            .multiple(true)
+            .number_of_values(1)
+            .requires("script")
+        )
+        .arg(Arg::with_name("env_file")
+            .help("Load environment variables for the script from a dotenv-style file containing `KEY=VALUE` lines.  Can be given more than once; later files take precedence.")
+            .long("env-file")
+            .takes_value(true)
+            .value_name("PATH")
+            .multiple(true)
+            .number_of_values(1)
+            .requires("script")
+        )
+        .arg(Arg::with_name("features")
+             .help("Cargo features to pass when building and running.")
+             .long("features")
+             .takes_value(true)
+        )
+        .arg(Arg::with_name("target")
+            .help("Build for the given target triple, such as `wasm32-wasi`.  Executables for other targets usually need `--runner` to run them.")
+            .long("target")
+            .takes_value(true)
+            .value_name("TRIPLE")
+            .requires("script")
+        )
+        .arg(Arg::with_name("gui")
+            .help("Build the script as a Windows GUI program, so that running it doesn't open a console window.  This only makes a difference when building for Windows.")
+            .long("gui")
+            .requires("script")
+        )
+        .arg(Arg::with_name("rustflags")
+            .help("Build the script with FLAGS as `RUSTFLAGS`, in place of whatever is in the environment.  Each set of flags gets its own cache entry and target directory, so switching between them doesn't rebuild everything.")
+            .long("rustflags")
+            .takes_value(true)
+            .allow_hyphen_values(true)
+            .value_name("FLAGS")
+            .requires("script")
+        )
+        .arg(Arg::with_name("panic")
+            .help("Build the script with the given panic strategy.  `abort` makes for a smaller executable, and a quicker build, but panics can no longer be caught, and `Drop` impls don't run on the way out.  Each strategy gets its own cache entry.")
+            .long("panic")
+            .takes_value(true)
+            .value_name("STRATEGY")
+            .possible_values(csas!["unwind", "abort"])
+            .requires("script")
+        )
+        .arg(Arg::with_name("strip")
+            .help("Strip the given things from the script's executable: `debuginfo`, or `symbols` (which includes debuginfo).  This is mostly worth it for release builds.  Each setting gets its own cache entry.")
+            .long("strip")
+            .takes_value(true)
+            .value_name("WHAT")
+            .possible_values(csas!["none", "debuginfo", "symbols"])
+            .requires("script")
+        )
+        .arg(Arg::with_name("allow")
+            .help("Build the script with the given lint allowed, as with `-A LINT` in `RUSTFLAGS`.  Can be given more than once.")
+            .long("allow")
+            .takes_value(true)
+            .value_name("LINT")
+            .multiple(true)
+            .number_of_values(1)
+            .requires("script")
+        )
+        .arg(Arg::with_name("warn")
+            .help("Build the script with the given lint as a warning, as with `-W LINT` in `RUSTFLAGS`.  Can be given more than once.")
+            .long("warn")
+            .takes_value(true)
+            .value_name("LINT")
+            .multiple(true)
+            .number_of_values(1)
+            .requires("script")
+        )
+        .arg(Arg::with_name("deny")
+            .help("Build the script with the given lint as an error, as with `-D LINT` in `RUSTFLAGS` (such as `--deny unsafe_code`).  Can be given more than once.  Not to be confused with `--deny-warnings`, which is about cargo script's own warnings.")
+            .long("deny")
+            .takes_value(true)
+            .value_name("LINT")
+            .multiple(true)
+            .number_of_values(1)
+            .requires("script")
+        )
+        .arg(Arg::with_name("runner")
+            .help("Run the compiled executable with COMMAND, as `COMMAND <exe> [args]...`.  COMMAND is split on whitespace, so it can have arguments of its own.  With `wasmtime`, the script's environment variables are passed into the module.")
+            .long("runner")
+            .takes_value(true)
+            .value_name("COMMAND")
+            .requires("script")
+            .conflicts_with_all(csas!["bench", "check", "test", "run_as_test_module"])
+        )
+        .arg(Arg::with_name("before")
+            .help("Run COMMAND with the shell before running the script, with the same environment variables as the script.  If it fails, the script isn't run.")
+            .long("before")
+            .takes_value(true)
+            .value_name("COMMAND")
+            .requires("script")
+        )
+        .arg(Arg::with_name("after")
+            .help("Run COMMAND with the shell after running the script, even if the script failed, with the same environment variables as the script.")
+            .long("after")
+            .takes_value(true)
+            .value_name("COMMAND")
+            .requires("script")
+        )
+        .arg(Arg::with_name("sandbox")
+            .help("Run the compiled executable in a sandbox, with no network access, and unable to write anywhere but a temporary directory of its own (which it's given as `TMPDIR`).  On Linux, this uses bubblewrap (`bwrap`) unless COMMAND or `CARGO_SCRIPT_SANDBOX` gives a sandbox command; elsewhere, one of them has to.  The command is run as `COMMAND <exe> [args]...`.  Only running the script is sandboxed, not building it.")
+            .long("sandbox")
+            .takes_value(true)
+            .value_name("COMMAND")
+            .min_values(0)
+            .require_equals(true)
+            .requires("script")
+            .conflicts_with_all(csas!["bench", "check", "test", "run_as_test_module", "runner", "doc", "tree"])
+        )
+        .arg(Arg::with_name("clean_panics")
+            .help("If the script dies from a panic, replace Rust's panic report with a one-line summary pointing at the script.  This means reading what the script writes to stderr, so its stderr is no longer a terminal.")
+            .long("clean-panics")
+            .requires("script")
+            .conflicts_with_all(csas!["bench", "check", "test", "run_as_test_module"])
+        )
+        .arg(Arg::with_name("no_hints")
+            .help("Don't follow a failed build with hints for failures cargo script recognises, such as a missing system library.")
+            .long("no-hints")
+            .requires("script")
+        )
+        .arg(Arg::with_name("color_diagnostics_to_file")
+            .help("Write everything Cargo prints while building the script (coloured, as it would be in a terminal) to FILE, instead of stderr.  The script's own output is left alone.  FILE is emptied on every run, even if nothing has to be built.")
+            .long("color-diagnostics-to-file")
+            .takes_value(true)
+            .value_name("FILE")
+            .requires("script")
+            .conflicts_with_all(csas!["bench", "test", "run_as_test_module", "dry_run"])
+        )
+        .arg(Arg::with_name("force_child_color")
+            .help("Set `CLICOLOR_FORCE=1` for the script, so that it keeps its colours even when its output is piped into a file or a pager.  This is for the script's own output; it has nothing to do with Cargo's.")
+            .long("force-child-color")
+            .requires("script")
+        )
+        .arg(Arg::with_name("verbose")
+            .help("Say why the script has to be rebuilt, if it does, and how big its executable is, and show everything the script prints as-is, even with `--clean-panics`.")
+            .short("v")
+            .long("verbose")
+            .requires("script")
+        )
+        .arg(Arg::with_name("quiet")
+            .help("Don't show cargo-script's own notes, warnings, hints, and status messages.  Errors are still shown, as is anything Cargo or the script prints.")
+            .short("q")
+            .long("quiet")
+            .conflicts_with("verbose")
+        )
+        .arg(Arg::with_name("trace")
+            .help("Log everything cargo-script does to stderr, with timestamps: each command it runs (with its arguments and working directory), each cache file it reads or writes, each digest it computes, and why it does or doesn't rebuild the script.")
+            .long("trace")
+        )
+        .arg(Arg::with_name("cargo_config")
+            .help("Pass a configuration value or file on to Cargo, exactly as with Cargo's own `--config`.  Useful for settings such as alternative registries which aren't in your Cargo configuration.")
+            .long("config")
+            .takes_value(true)
+            .value_name("KEY=VALUE|PATH")
+            .multiple(true)
+            .number_of_values(1)
+            .requires("script")
+        )
+        .arg(Arg::with_name("jobs")
+            .help("Build with at most N jobs at once, as with Cargo's own `--jobs`.  Without this, Cargo goes by `CARGO_BUILD_JOBS`, or else the number of CPUs.  Running the script isn't affected.")
+            .long("jobs")
+            .short("j")
+            .takes_value(true)
+            .value_name("N")
+            .validator(|v| match v.parse::<i32>() {
+                Ok(0) | Err(_) => Err(format!("expected a non-zero number of jobs, not {:?}", v)),
+                Ok(_) => Ok(()),
+            })
+            .requires("script")
+        )
+        .arg(Arg::with_name("build_timeout")
+            .help("Kill Cargo, along with everything it has started, if building the script takes longer than DURATION (such as `90s` or `10m`; a number on its own is in seconds).  cargo script then fails with exit code 124.  With `--build-retries`, this applies to each attempt.  Running the script isn't affected.  On Unix, Cargo is put in a process group of its own for this, and Ctrl+C is passed on to the whole group.")
+            .long("build-timeout")
+            .takes_value(true)
+            .value_name("DURATION")
+            .validator(|v| time_spec::parse_duration(&v).map(|_| ()).map_err(|err| err.to_string()))
+            .requires("script")
+        )
+        .arg(Arg::with_name("build_retries")
+            .help("If building the script fails in a way that looks like Cargo couldn't reach the network, such as a registry timing out, try again, up to N times.  The first retry waits a second, and each one after that waits twice as long as the last.")
+            .long("build-retries")
+            .takes_value(true)
+            .value_name("N")
+            .validator(|v| match v.parse::<u32>() {
+                Ok(_) => Ok(()),
+                Err(_) => Err(format!("expected a number of retries, not {:?}", v)),
+            })
+            .requires("script")
+        )
+        .arg(Arg::with_name("input_encoding")
+            .help("Read the script as being in this encoding, rather than UTF-8.  This can be utf-8, utf-16le, utf-16be, latin1 (or iso-8859-1), or windows-1252 (or cp1252).")
+            .long("input-encoding")
+            .takes_value(true)
+            .value_name("ENCODING")
+            .validator(|v| match InputEncoding::from_name(&v) {
+                Some(_) => Ok(()),
+                None => Err(format!("unknown encoding {:?}; expected utf-8, utf-16le, utf-16be, latin1 or windows-1252", v)),
+            })
+            .requires("script")
+            .conflicts_with_all(csas!["expr", "loop"])
+        )
+        .arg(Arg::with_name("name")
+            .help("Name to use for the generated Cargo package.  By default, the package is named after the script, unless that would collide with one of its dependencies.")
+            .long("name")
+            .takes_value(true)
+            .requires("script")
+        )
+        .arg(Arg::with_name("bin")
+            .help("Run the binary target called NAME, rather than the one built from the script.  Other binary targets can be added in the script's manifest with `[[bin]]` sections, pointing at files next to the script.")
+            .long("bin")
+            .takes_value(true)
+            .value_name("NAME")
+            .requires("script")
+            .conflicts_with_all(csas!["expr", "expr_file", "loop"])
+        )
+        .arg(Arg::with_name("unstable_features")
+            .help("Add a #![feature] declaration to the crate.")
+            .long("unstable-feature")
+            .short("u")
+            .takes_value(true)
+            .multiple(true)
+            .requires("expr_or_loop")
+        )
+
+        /*
+        Options that change how cargo script itself behaves, and don't alter what the script will do.
+        */
+        .arg(Arg::with_name("build_only")
+            .help("Build the script, but don't run it.")
+            .long("build-only")
+            .requires("script")
+            .conflicts_with_all(csas!["args"])
+        )
+        .arg(Arg::with_name("check")
+            .help("Check the script for errors with `cargo check`, but don't build a binary or run it.  Unlike `--build-only`, this does not produce an executable.")
+            .long("check")
+            .requires("script")
+            .conflicts_with_all(csas!["args", "build_only", "test", "bench", "run_as_test_module"])
+        )
+        .arg(Arg::with_name("tree")
+            .help("Show the script's dependency tree with `cargo tree`, without building it.  Anything after a `--` following the script is passed on to `cargo tree`, such as `--duplicates`, `--invert CRATE`, or `--depth N`.")
+            .long("tree")
+            .requires("script")
+            .conflicts_with_all(csas!["build_only", "gen_pkg_only", "check", "test", "bench", "run_as_test_module", "runner"])
+        )
+        .arg(Arg::with_name("doc")
+            .help("Build the script's documentation with `cargo doc`, and open it in a browser, without running the script.  Only the script itself is documented, unless you use `--doc=deps` to include its dependencies.  Anything after a `--` following the script is passed on to `cargo doc`.")
+            .long("doc")
+            .takes_value(true)
+            .min_values(0)
+            .require_equals(true)
+            .possible_values(csas!["deps"])
+            .requires("script")
+            .conflicts_with_all(csas!["build_only", "gen_pkg_only", "check", "test", "bench", "run_as_test_module", "runner", "tree"])
+        )
+        .arg(Arg::with_name("clear_cache")
+            .help("Clears out the script cache.")
+            .long("clear-cache")
+        )
+        .arg(Arg::with_name("list_cache")
+            .help("Lists the scripts in the cache, along with when they were last built.")
+            .long("list-cache")
+            .conflicts_with_all(csas!["script", "clear_cache"])
+        )
+        .arg(Arg::with_name("clear_data")
+            .help("Clears out the scripts' data directories, the ones given to them as `CARGO_SCRIPT_DATA_DIR`.  Clearing the cache leaves these alone.")
+            .long("clear-data")
+        )
+        .arg(Arg::with_name("list_data")
+            .help("Lists the scripts which have data directories, and where they are.")
+            .long("list-data")
+            .conflicts_with_all(csas!["script", "clear_cache", "list_cache", "clear_data"])
+        )
+        .arg(Arg::with_name("list_templates")
+            .help("Lists the templates which can be used with `--template`, and where each one comes from.  This is the same as `cargo script templates list`.")
+            .long("list-templates")
+            .conflicts_with_all(csas!["script", "clear_cache", "list_cache", "list_data"])
+        )
+        .arg(Arg::with_name("since")
+            .help("Only list cached scripts built at or after TIME.  TIME can be a duration ago (like `12h`, `3d`, or `2w`) or a UTC date (like `2017-06-01` or `2017-06-01 14:30`).")
+            .long("since")
+            .takes_value(true)
+            .value_name("TIME")
+            .requires("list_cache")
+        )
+        .arg(Arg::with_name("until")
+            .help("Only list cached scripts built at or before TIME.  Accepts the same forms as `--since`.")
+            .long("until")
+            .takes_value(true)
+            .value_name("TIME")
+            .requires("list_cache")
+        )
+        .arg(Arg::with_name("timings")
+            .help("Once the script has finished, print how long each phase of the run took to stderr.")
+            .long("timings")
+            .requires("script")
+        )
+        .arg(Arg::with_name("report")
+            .help("Once the run is over, write a summary of it to FILE as JSON: the script, whether it was already built, how long building and running it took, the exit code, the toolchain, the edition, and the size of the executable for each profile it has been built with.  FILE is replaced unless `--append-report` is given.")
+            .long("report")
+            .takes_value(true)
+            .value_name("FILE")
+            .requires("script")
+        )
+        .arg(Arg::with_name("append_report")
+            .help("With `--report`, add the summary to the end of FILE, on a line of its own, rather than replacing it.")
+            .long("append-report")
+            .requires("report")
+        )
+        .arg(Arg::with_name("message_format")
+            .help("Format for the messages cargo script prints about the run.  Currently, this only affects `--timings` and `--dry-run`.")
+            .long("message-format")
+            .takes_value(true)
+            .possible_values(csas!["human", "json"])
+        )
+        .arg(Arg::with_name("deny_warnings")
+            .help("Treat warnings from cargo script itself as errors.")
+            .long("deny-warnings")
+        )
+        .arg(Arg::with_name("allow_wildcard_deps")
+            .help("Don't warn about dependencies which can be any version, such as from `--dep serde` or `// cargo-deps: rand`.  Setting `CARGO_SCRIPT_ALLOW_WILDCARD_DEPS` does the same.")
+            .long("allow-wildcard-deps")
+            .requires("script")
+        )
+        .arg(Arg::with_name("deny_wildcard_deps")
+            .help("Refuse to build a script with dependencies which can be any version, listing them.  This wins over `CARGO_SCRIPT_ALLOW_WILDCARD_DEPS`.")
+            .long("deny-wildcard-deps")
+            .requires("script")
+            .conflicts_with("allow_wildcard_deps")
+        )
+        .arg(Arg::with_name("force")
+            .help("Force the script to be rebuilt, overwriting any changes made by hand to its generated package.")
+            .long("force")
+            .requires("script")
+        )
+        .arg(Arg::with_name("gen_pkg_only")
+            .help("Generate the Cargo package, but don't compile or run it.")
+            .long("gen-pkg-only")
+            .requires("script")
+            .conflicts_with_all(csas!["args", "build_only", "check", "debug", "release", "test", "bench", "run_as_test_module"])
+        )
+        .arg(Arg::with_name("hash_only")
+            .help("Print the ID of the script's cache entry, and exit without building anything or looking in the cache.  This is the ID a real run with the same options would use, so other tools can key their own caches off it.  With `--content-addressed`, it also changes with the script's dependencies, edition, toolchain and build flags.")
+            .long("hash-only")
+            .requires("script")
+            .conflicts_with_all(csas!["pkg_path", "no_cache", "dry_run", "gen_pkg_only", "clear_cache", "list_cache", "clear_data", "migrate_data"])
+        )
+        .arg(Arg::with_name("dry_run")
+            .help("Work out what would be done, and whether the cache is up to date, and say so without changing anything or running Cargo.")
+            .long("dry-run")
+            .requires("script")
+            .conflicts_with_all(csas!["clear_cache", "list_cache", "migrate_data"])
+        )
+        .arg(Arg::with_name("keep_build_dir")
+            .help("Keep the generated package around, even if the build fails, and say where it is once the run is over.  Also stops old packages from being cleaned out of the cache this time.")
+            .long("keep-build-dir")
+            .visible_alias("keep-pkg")
+            .requires("script")
+        )
+        .arg(Arg::with_name("content_addressed")
+            .help("Key the cache entry on the generated package, toolchain and build flags alone, rather than on where the script lives.  Scripts which generate identical packages will share a single cache entry.")
+            .long("content-addressed")
+            .requires("script")
+            .conflicts_with_all(csas!["pkg_path"])
+        )
+        .arg(Arg::with_name("pkg_path")
+            .help("Specify where to place the generated Cargo package.  Paths in the generated manifest are made relative to it.")
+            .long("pkg-path")
+            .takes_value(true)
+            .requires("script")
+            .conflicts_with_all(csas!["clear_cache"])
+        )
+        .arg(Arg::with_name("no_cache")
+            .help("Build the script in a temporary package, with a target directory of its own, and remove it once the run is over.  Nothing is taken from the cache, left in it, or cleaned out of it.  This only changes where cargo script builds things: it does nothing to stop the script itself from writing wherever it likes (see `--sandbox` for that).")
+            .long("no-cache")
+            .visible_alias("ephemeral")
+            .requires("script")
+            .conflicts_with_all(csas!["pkg_path", "content_addressed", "keep_build_dir", "clear_cache", "use_bincache", "gen_pkg_only"])
+        )
+        .arg(Arg::with_name("require_cache")
+            .help("Fail if the cache can't be written to, rather than building the script in a temporary directory instead.")
+            .long("require-cache")
+            .requires("script")
+            .conflicts_with("no_cache")
+        )
+        .arg(Arg::with_name("no_net")
+            .help("Keep Cargo off the network, as with its `--offline`: if the script's dependencies aren't already downloaded, the build fails rather than fetching them.  This only restricts cargo script's own build: it does nothing to stop the script itself from using the network (see `--sandbox` for that).")
+            .long("no-net")
+            .requires("script")
+        )
+        .arg(Arg::with_name("use_bincache")
+            .help("Override whether or not the shared binary cache will be used for compilation.")
+            .long("use-shared-binary-cache")
+            .takes_value(true)
+            .possible_values(csas!["no", "yes"])
+        )
+        .arg(Arg::with_name("migrate_data")
+            .help("Migrate data from older versions.")
+            .long("migrate-data")
+            .takes_value(true)
+            .possible_values(csas!["dry-run", "for-real"])
+        )
+        .arg(Arg::with_name("test")
+            .help("Compile and run tests.  Arguments after the script go to `cargo test`, so they can filter which tests run, and anything after a `--` among them goes to the test harness (such as `--nocapture`).  Harness options straight after the script need two: `<script> -- -- --ignored`.  Tests are built in debug mode, unless `--release` is given.")
+            .long("test")
+            .conflicts_with_all(csas!["bench", "debug", "force"])
+        )
+        .arg(Arg::with_name("list_tests")
+            .help("With `--test` or `--run-as-test-module`, list the script's tests instead of running them.")
+            .long("list")
+            .requires("script")
+        )
+        .arg(Arg::with_name("bench")
+            .help("Compile and run benchmarks.  Scripts which use Criterion (with `criterion_main!`) are built as a Criterion benchmark; otherwise, this runs `#[bench]` functions, and requires a nightly toolchain.  Arguments after the script are passed on as with `--test`.")
+            .long("bench")
+            .conflicts_with_all(csas!["test", "debug", "release", "force"])
+        )
+        .arg(Arg::with_name("run_as_test_module")
+            .help("Treat the script as a module of tests: compile and run its tests, adding an empty `main` if it doesn't have one.")
+            .long("run-as-test-module")
+            .requires("script")
+            .conflicts_with_all(csas!["bench", "debug", "force", "test", "expr", "expr_file", "loop"])
+        )
+        .arg(Arg::with_name("template")
+            .help("Specify a template to use for expression scripts.")
+            .long("template")
+            .short("t")
+            .takes_value(true)
+            .requires("any_expr")
+        )
+        .arg(Arg::with_name("expr_as")
+            .help("Give the expression's value this type, for when it can't be inferred (such as `--expr-as i64` for `1 << 40`).")
+            .long("expr-as")
+            .takes_value(true)
+            .value_name("TYPE")
+            .requires("any_expr")
+            .validator(|v| match plausible_type(&v) {
+                true => Ok(()),
+                false => Err(format!("`{}` doesn't look like a type", v)),
+            })
+        )
+        .arg(Arg::with_name("error_format")
+            .help("How the expression shows an error it fails with: `prefixed` (the default) prints `Error: ` before it, `plain` prints just the error, which is tidier for other programs to read.  This doesn't need a rebuild.")
+            .long("error-format")
+            .takes_value(true)
+            .value_name("FORMAT")
+            .possible_values(csas!["prefixed", "plain"])
+            .requires("any_expr")
+        );
+
     // We have to kinda lie about who we are for the output to look right...
     let m = App::new("cargo")
         .bin_name("cargo")
         .version(version)
         .about(about)
         .setting(AppSettings::SubcommandRequiredElseHelp)
-        .subcommand(SubCommand::with_name("script")
-            .version(version)
-            .about(about)
-            .usage("cargo script [FLAGS OPTIONS] [--] <script> <args>...")
-
-            /*
-            Major script modes.
-            */
-            .arg(Arg::with_name("script")
-                .help("Script file (with or without extension) to execute.")
-                .index(1)
-            )
-            .arg(Arg::with_name("args")
-                .help("Additional arguments passed to the script.")
-                .index(2)
-                .multiple(true)
-            )
-            .arg(Arg::with_name("expr")
-                .help("Execute <script> as a literal expression and display the result.")
-                .long("expr")
-                .short("e")
-                .requires("script")
-            )
-            .arg(Arg::with_name("loop")
-                .help("Execute <script> as a literal closure once for each line from stdin.")
-                .long("loop")
-                .short("l")
-                .requires("script")
-            )
-            .group(ArgGroup::with_name("expr_or_loop")
-                .args(&["expr", "loop"])
-            )
-
-            /*
-            Options that impact the script being executed.
-            */
-            .arg(Arg::with_name("count")
-                .help("Invoke the loop closure with two arguments: line, and line number.")
-                .long("count")
-                .requires("loop")
-            )
-            .arg(Arg::with_name("debug")
-                .help("Build a debug executable, not an optimised one.")
-                .long("debug")
-                .requires("script")
-            )
-            .arg(Arg::with_name("dep")
-                .help("Add an additional Cargo dependency.  Each SPEC can be either just the package name (which will assume the latest version) or a full `name=version` spec.")
-                .long("dep")
-                .short("d")
-                .takes_value(true)
-                .multiple(true)
-                .number_of_values(1)
-                .requires("script")
+        .subcommand(script.clone())
+        .subcommand(script
+            .name("which")
+            .about("Show where a script's package and executable are, without building anything.  This takes the same options as `script`.")
+            .arg(Arg::with_name("json")
+                .help("Print the locations as a JSON object.")
+                .long("json")
             )
-            .arg(Arg::with_name("dep_extern")
-                .help("Like `dep`, except that it *also* adds a `#[macro_use] extern crate name;` item for expression and loop scripts.  Note that this only works if the name of the dependency and the name of the library it generates are exactly the same.")
-                .long("dep-extern")
-                .short("D")
-                .takes_value(true)
-                .multiple(true)
-                .requires("expr_or_loop")
-            )
-            .arg(Arg::with_name("extern")
-                .help("Adds an `#[macro_use] extern crate name;` item for expressions and loop scripts.")
-                .long("extern")
-                .short("x")
-                .takes_value(true)
-                .multiple(true)
-                .requires("expr_or_loop")
-            )
-            .arg(Arg::with_name("features")
-                 .help("Cargo features to pass when building and running.")
-                 .long("features")
-                 .takes_value(true)
-            )
-            .arg(Arg::with_name("unstable_features")
-                .help("Add a #![feature] declaration to the crate.")
-                .long("unstable-feature")
-                .short("u")
-                .takes_value(true)
-                .multiple(true)
-                .requires("expr_or_loop")
-            )
-
-            /*
-            Options that change how cargo script itself behaves, and don't alter what the script will do.
-            */
-            .arg(Arg::with_name("build_only")
-                .help("Build the script, but don't run it.")
-                .long("build-only")
-                .requires("script")
-                .conflicts_with_all(csas!["args"])
-            )
-            .arg(Arg::with_name("clear_cache")
-                .help("Clears out the script cache.")
-                .long("clear-cache")
-            )
-            .arg(Arg::with_name("force")
-                .help("Force the script to be rebuilt.")
-                .long("force")
-                .requires("script")
-            )
-            .arg(Arg::with_name("gen_pkg_only")
-                .help("Generate the Cargo package, but don't compile or run it.")
-                .long("gen-pkg-only")
-                .requires("script")
-                .conflicts_with_all(csas!["args", "build_only", "debug", "force", "test", "bench"])
-            )
-            .arg(Arg::with_name("pkg_path")
-                .help("Specify where to place the generated Cargo package.")
-                .long("pkg-path")
-                .takes_value(true)
-                .requires("script")
-                .conflicts_with_all(csas!["clear_cache", "force"])
-            )
-            .arg(Arg::with_name("use_bincache")
-                .help("Override whether or not the shared binary cache will be used for compilation.")
-                .long("use-shared-binary-cache")
-                .takes_value(true)
-                .possible_values(csas!["no", "yes"])
-            )
-            .arg(Arg::with_name("migrate_data")
-                .help("Migrate data from older versions.")
-                .long("migrate-data")
-                .takes_value(true)
-                .possible_values(csas!["dry-run", "for-real"])
-            )
-            .arg(Arg::with_name("test")
-                .help("Compile and run tests.")
-                .long("test")
-                .conflicts_with_all(csas!["bench", "debug", "args", "force"])
-            )
-            .arg(Arg::with_name("bench")
-                .help("Compile and run benchmarks.  Requires a nightly toolchain.")
-                .long("bench")
-                .conflicts_with_all(csas!["test", "debug", "args", "force"])
-            )
-            .arg(Arg::with_name("template")
-                .help("Specify a template to use for expression scripts.")
-                .long("template")
-                .short("t")
-                .takes_value(true)
-                .requires("expr")
+            .arg(Arg::with_name("all")
+                .help("Print the absolute path of every file <script> could refer to, one per line, starting with the one which would be run.  This includes any in the directories listed in `CARGO_SCRIPT_PATH_DIRS`.")
+                .long("all")
+                .conflicts_with_all(csas!["json", "expr_or_loop"])
             )
         )
+        .subcommand(new::Args::subcommand())
+        .subcommand(export::Args::subcommand())
+        .subcommand(fmt::Args::subcommand())
+        .subcommand(clippy::Args::subcommand())
+        .subcommand(environment::Args::subcommand())
+        .subcommand(pin::Args::subcommand())
+        .subcommand(pin::Args::unpin_subcommand())
+        .subcommand(prebuild::Args::subcommand())
+        .subcommand(quote::Args::subcommand())
         .subcommand(templates::Args::subcommand())
+        .subcommand(update::Args::subcommand())
+        .subcommand(self_update::Args::subcommand())
         .chain_map(|mut app| {
             drop(&mut app); // avoid warning
             if_windows! {
@@ -317,25 +937,125 @@ fn parse_args() -> SubCommand {
             }
             app
         })
-        .get_matches();
+        .get_matches_from_safe(args)
+        .unwrap_or_else(|err| {
+            // By the time we see this, clap has already printed the version line itself, minus the line break.
+            if err.kind == clap::ErrorKind::VersionDisplayed {
+                println!();
+                print_version_details();
+                process::exit(0);
+            }
+            err.exit()
+        });
+
+    if let Some(m) = m.subcommand_matches("new") {
+        return crate::SubCommand::New(new::Args::parse(m));
+    }
+
+    if let Some(m) = m.subcommand_matches("export") {
+        return crate::SubCommand::Export(export::Args::parse(m));
+    }
+
+    if let Some(m) = m.subcommand_matches("fmt") {
+        return crate::SubCommand::Fmt(fmt::Args::parse(m));
+    }
+
+    if let Some(m) = m.subcommand_matches("clippy") {
+        return crate::SubCommand::Clippy(clippy::Args::parse(m));
+    }
+
+    if let Some(m) = m.subcommand_matches("env") {
+        return crate::SubCommand::Env(environment::Args::parse(m));
+    }
+
+    if let Some(m) = m.subcommand_matches("pin") {
+        return crate::SubCommand::Pin(pin::Args::parse(m, true));
+    }
+
+    if let Some(m) = m.subcommand_matches("unpin") {
+        return crate::SubCommand::Pin(pin::Args::parse(m, false));
+    }
+
+    if let Some(m) = m.subcommand_matches("prebuild") {
+        return crate::SubCommand::Prebuild(prebuild::Args::parse(m));
+    }
+
+    if let Some(m) = m.subcommand_matches("quote") {
+        return crate::SubCommand::Quote(quote::Args::parse(m));
+    }
 
     if let Some(m) = m.subcommand_matches("templates") {
         return crate::SubCommand::Templates(templates::Args::parse(m));
     }
 
+    if let Some(m) = m.subcommand_matches("update") {
+        return crate::SubCommand::Update(update::Args::parse(m));
+    }
+
+    if let Some(m) = m.subcommand_matches("self") {
+        return crate::SubCommand::SelfUpdate(self_update::Args::parse(m));
+    }
+
     if_windows! {
         if let Some(m) = m.subcommand_matches("file-association") {
             return crate::SubCommand::FileAssoc(file_assoc::Args::parse(m));
         }
     }
 
-    let m = m.subcommand_matches("script").unwrap();
+    // `which` is `script` in all but name, so it's parsed the same way.
+    let (m, which) = match m.subcommand_matches("which") {
+        Some(m) if m.is_present("json") => (m, Some(MessageFormat::Json)),
+        Some(m) => (m, Some(MessageFormat::Human)),
+        None => (m.subcommand_matches("script").unwrap(), None),
+    };
 
-    fn owned_vec_string<'a, I>(v: Option<I>) -> Vec<String>
-    where
-        I: ::std::iter::Iterator<Item = &'a str>,
-    {
-        v.map(|itr| itr.map(Into::into).collect()).unwrap_or(vec![])
+    /*
+    Paths and the script's own arguments are passed through untouched, but everything else has to be UTF-8.  Having allowed invalid UTF-8 in general, we can't use `value_of` and `values_of`, since they panic on it.
+    */
+    fn require_utf8<'a>(name: &str, v: &'a std::ffi::OsStr) -> &'a str {
+        v.to_str().unwrap_or_else(|| {
+            clap::Error::with_description(
+                &format!(
+                    "Invalid UTF-8 was detected in the value for `--{}`",
+                    name.replace('_', "-")
+                ),
+                clap::ErrorKind::InvalidUtf8,
+            )
+            .exit()
+        })
+    }
+
+    fn value_of<'a>(m: &'a clap::ArgMatches<'_>, name: &str) -> Option<&'a str> {
+        m.value_of_os(name).map(|v| require_utf8(name, v))
+    }
+
+    fn owned_vec_string(m: &clap::ArgMatches<'_>, name: &str) -> Vec<String> {
+        m.values_of_os(name)
+            .map(|itr| itr.map(|v| require_utf8(name, v).into()).collect())
+            .unwrap_or(vec![])
+    }
+
+    fn owned_vec_os<T: From<OsString>>(m: &clap::ArgMatches<'_>, name: &str) -> Vec<T> {
+        m.values_of_os(name)
+            .map(|itr| itr.map(|v| v.to_owned().into()).collect())
+            .unwrap_or_default()
+    }
+
+    /**
+    Puts `--allow`, `--warn` and `--deny` back in the order they were given, since it's the last one for a lint that counts.
+    */
+    fn lint_levels(m: &clap::ArgMatches<'_>) -> Vec<(&'static str, String)> {
+        let mut levels = vec![];
+        for &(name, flag) in &[("allow", "-A"), ("warn", "-W"), ("deny", "-D")] {
+            let indices = m.indices_of(name).into_iter().flatten();
+            let lints = owned_vec_string(m, name);
+            levels.extend(indices.zip(lints).map(|(i, lint)| (i, flag, lint)));
+        }
+        levels.sort_by_key(|&(i, _, _)| i);
+        levels
+            .into_iter()
+            .map(|(_, flag, lint)| (flag, lint))
+            .collect()
     }
 
     fn yes_or_no(v: Option<&str>) -> Option<bool> {
@@ -354,93 +1074,604 @@ fn parse_args() -> SubCommand {
         })
     }
 
+    fn message_format(v: Option<&str>) -> MessageFormat {
+        match v {
+            None | Some("human") => MessageFormat::Human,
+            Some("json") => MessageFormat::Json,
+            _ => unreachable!(),
+        }
+    }
+
+    let (expr_fragments, script_args) =
+        split_expr_fragments(m.occurrences_of("expr"), owned_vec_os(m, "args"));
+
+    let test_mode = m.is_present("test") || m.is_present("run_as_test_module");
+    if m.is_present("list_tests") && !test_mode {
+        clap::Error::with_description(
+            "`--list` can only be used with `--test` or `--run-as-test-module`",
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit()
+    }
+
     crate::SubCommand::Script(Args {
-        script: m.value_of("script").map(Into::into),
-        args: owned_vec_string(m.values_of("args")),
-        features: m.value_of("features").map(Into::into),
+        script: m.value_of_os("script").map(Into::into),
+        args: script_args,
+        features: value_of(m, "features").map(Into::into),
+        cargo_config: owned_vec_string(m, "cargo_config"),
+        jobs: value_of(m, "jobs").map(|v| v.parse().unwrap()),
+        build_limits: watchdog::Limits {
+            timeout: value_of(m, "build_timeout").map(|v| time_spec::parse_duration(v).unwrap()),
+            retries: value_of(m, "build_retries").map_or(0, |v| v.parse().unwrap()),
+        },
+        input_encoding: value_of(m, "input_encoding").map(|v| InputEncoding::from_name(v).unwrap()),
+        target: value_of(m, "target").map(Into::into),
+        rustflags: value_of(m, "rustflags").map(Into::into),
+        lint_levels: lint_levels(m),
+        panic: value_of(m, "panic").map(Into::into),
+        strip: value_of(m, "strip").map(Into::into),
+        gui: m.is_present("gui"),
+        runner: value_of(m, "runner").map(Into::into),
+        before: value_of(m, "before").map(Into::into),
+        after: value_of(m, "after").map(Into::into),
+        sandbox: m.is_present("sandbox"),
+        sandbox_command: value_of(m, "sandbox").map(Into::into),
+        clean_panics: m.is_present("clean_panics"),
+        no_hints: m.is_present("no_hints"),
+        color_diagnostics_to_file: m.value_of_os("color_diagnostics_to_file").map(Into::into),
+        force_child_color: m.is_present("force_child_color"),
+        verbose: m.is_present("verbose"),
+        quiet: m.is_present("quiet"),
+        trace: m.is_present("trace"),
+        env: owned_vec_string(m, "env"),
+        env_file: owned_vec_os(m, "env_file"),
 
         expr: m.is_present("expr"),
+        expr_file: m.is_present("expr_file"),
+        expr_fragments,
         loop_: m.is_present("loop"),
         count: m.is_present("count"),
-
-        pkg_path: m.value_of("pkg_path").map(Into::into),
+        fold: value_of(m, "fold").map(Into::into),
+        finish: value_of(m, "finish").map(Into::into),
+        filter: m.is_present("filter"),
+        output: m.value_of_os("output").map(Into::into),
+        append: m.is_present("append"),
+
+        pkg_path: m.value_of_os("pkg_path").map(Into::into),
+        no_cache: m.is_present("no_cache"),
+        require_cache: m.is_present("require_cache"),
+        no_net: m.is_present("no_net"),
         gen_pkg_only: m.is_present("gen_pkg_only"),
+        dry_run: m.is_present("dry_run"),
+        hash_only: m.is_present("hash_only"),
+        keep_build_dir: m.is_present("keep_build_dir"),
         build_only: m.is_present("build_only"),
         clear_cache: m.is_present("clear_cache"),
+        list_cache: m.is_present("list_cache"),
+        clear_data: m.is_present("clear_data"),
+        list_data: m.is_present("list_data"),
+        list_templates: m.is_present("list_templates"),
+        list_tests: m.is_present("list_tests"),
+        since: value_of(m, "since").map(Into::into),
+        until: value_of(m, "until").map(Into::into),
         debug: m.is_present("debug"),
-        dep: owned_vec_string(m.values_of("dep")),
-        dep_extern: owned_vec_string(m.values_of("dep_extern")),
-        extern_: owned_vec_string(m.values_of("extern")),
+        release: m.is_present("release"),
+        deny_warnings: m.is_present("deny_warnings"),
+        allow_wildcard_deps: m.is_present("allow_wildcard_deps")
+            || (!m.is_present("deny_wildcard_deps")
+                && std::env::var_os(consts::ALLOW_WILDCARD_DEPS_ENV)
+                    .map_or(false, |v| !v.is_empty())),
+        deny_wildcard_deps: m.is_present("deny_wildcard_deps"),
+        dep: owned_vec_string(m, "dep"),
+        manifest_key: owned_vec_string(m, "manifest_key"),
+        manifest_from_stdin: m.is_present("manifest_from_stdin"),
+        deps_from: m.value_of_os("deps_from").map(Into::into).or_else(|| {
+            std::env::var_os(consts::DEPS_FROM_ENV)
+                .filter(|p| !p.is_empty())
+                .map(Into::into)
+        }),
+        dep_extern: owned_vec_string(m, "dep_extern"),
+        extern_: owned_vec_string(m, "extern"),
+        prelude_crate: owned_vec_string(m, "prelude_crate"),
         force: m.is_present("force"),
-        unstable_features: owned_vec_string(m.values_of("unstable_features")),
-        use_bincache: yes_or_no(m.value_of("use_bincache")),
-        migrate_data: run_kind(m.value_of("migrate_data")),
-        build_kind: BuildKind::from_flags(m.is_present("test"), m.is_present("bench")),
-        template: m.value_of("template").map(Into::into),
+        name: value_of(m, "name").map(Into::into),
+        bin: value_of(m, "bin").map(Into::into),
+        unstable_features: owned_vec_string(m, "unstable_features"),
+        use_bincache: yes_or_no(value_of(m, "use_bincache")),
+        migrate_data: run_kind(value_of(m, "migrate_data")),
+        build_kind: BuildKind::from_flags(
+            test_mode,
+            m.is_present("bench"),
+            m.is_present("check"),
+            m.is_present("tree"),
+            m.is_present("doc"),
+        ),
+        doc_deps: value_of(m, "doc") == Some("deps"),
+        run_as_test_module: m.is_present("run_as_test_module"),
+        template: value_of(m, "template").map(Into::into),
+        expr_as: value_of(m, "expr_as").map(Into::into),
+        error_format: value_of(m, "error_format").map(Into::into),
+        timings: m.is_present("timings"),
+        report: m.value_of_os("report").map(Into::into),
+        append_report: m.is_present("append_report"),
+        message_format: message_format(value_of(m, "message_format")),
+        content_addressed: m.is_present("content_addressed"),
+        which,
+        which_all: m.is_present("all"),
     })
 }
 
-fn main() {
-    env_logger::init();
-    info!("starting");
-    info!("args: {:?}", std::env::args().collect::<Vec<_>>());
-    let stderr = &mut std::io::stderr();
-    match try_main() {
-        Ok(0) => (),
-        Ok(code) => {
-            std::process::exit(code);
-        }
-        Err(ref err) if err.is_human() => {
-            writeln!(stderr, "error: {}", err).unwrap();
-            std::process::exit(1);
-        }
-        Err(ref err) => {
-            writeln!(stderr, "internal error: {}", err).unwrap();
-            std::process::exit(1);
+/**
+Picks out the extra fragments of an expression given with more than one `--expr`, from the arguments that would otherwise go to the script.
+
+clap only sees the `--expr`s up to the first argument; after that, they all end up in `args` as-is.  So the first `occurrences - 1` arguments are fragments, and so is the argument after each `--expr` which comes straight after them.
+*/
+fn split_expr_fragments(occurrences: u64, args: Vec<OsString>) -> (Vec<OsString>, Vec<OsString>) {
+    let mut args = args.into_iter().peekable();
+    let mut fragments: Vec<OsString> = args
+        .by_ref()
+        .take(occurrences.saturating_sub(1) as usize)
+        .collect();
+    if occurrences > 0 {
+        while args
+            .peek()
+            .map_or(false, |arg| arg == "-e" || arg == "--expr")
+        {
+            args.next();
+            match args.next() {
+                Some(fragment) => fragments.push(fragment),
+                None => break,
+            }
         }
     }
+    (fragments, args.collect())
 }
 
-fn try_main() -> Result<i32> {
-    let args = parse_args();
-    info!("Arguments: {:?}", args);
-
-    let args = match args {
-        SubCommand::Script(args) => args,
-        SubCommand::Templates(args) => return templates::try_main(args),
-        #[cfg(windows)]
-        SubCommand::FileAssoc(args) => return file_assoc::try_main(args),
+#[test]
+fn test_split_expr_fragments() {
+    let split = |occurrences, args: &[&str]| {
+        let (fragments, args) =
+            split_expr_fragments(occurrences, args.iter().map(Into::into).collect());
+        let strs = |v: Vec<OsString>| -> Vec<String> {
+            v.into_iter().map(|s| s.into_string().unwrap()).collect()
+        };
+        (strs(fragments), strs(args))
     };
+    let v = |strs: &[&str]| -> Vec<String> { strs.iter().map(|&s| s.into()).collect() };
+
+    assert_eq!(split(0, &["a", "-e", "b"]), (v(&[]), v(&["a", "-e", "b"])));
+    assert_eq!(split(1, &["a", "b"]), (v(&[]), v(&["a", "b"])));
+    assert_eq!(split(2, &["b", "a"]), (v(&["b"]), v(&["a"])));
+    assert_eq!(
+        split(2, &["b", "-e", "c", "--expr", "d", "a", "-e"]),
+        (v(&["b", "c", "d"]), v(&["a", "-e"]))
+    );
+    assert_eq!(split(1, &["-e"]), (v(&[]), v(&[])));
+}
 
-    /*
-    Do data migration before anything else, since it can cause the location of stuff to change.
-    */
-    if let Some(run_kind) = args.migrate_data {
-        println!("Migrating data...");
-        let (log, res) = platform::migrate_old_data(run_kind);
-        match (log.len(), res) {
-            (0, Ok(())) => {
-                println!("Nothing to do.");
-                return Ok(0);
-            }
-            (_, Ok(())) => {
-                for entry in log {
-                    println!("- {}", entry);
-                }
-                return Ok(0);
-            }
-            (_, Err(err)) => {
-                for entry in log {
-                    println!("- {}", entry);
-                }
-                return Err(err);
+/**
+Puts together the fragments of an expression given with more than one `--expr`.  Each goes on its own line; all but the last are statements, so they get a `;` if they don't already end with one (or with a block).  The last is the expression whose value is displayed.
+*/
+fn join_expr_fragments(fragments: &[String]) -> Result<String> {
+    let mut content = String::new();
+    for (i, fragment) in fragments.iter().enumerate() {
+        let fragment = fragment.trim();
+        if fragment.is_empty() {
+            return Err(MainError::OtherOwned(
+                Blame::Human,
+                format!("`--expr` fragment {} is empty", i + 1),
+            ));
+        }
+        content.push_str(fragment);
+        if i + 1 < fragments.len() {
+            if !fragment.ends_with(';') && !fragment.ends_with('}') {
+                content.push(';');
             }
+            content.push('\n');
         }
     }
+    Ok(content)
+}
 
-    if log_enabled!(log::Level::Debug) {
-        let scp = get_script_cache_path()?;
-        let bcp = get_binary_cache_path()?;
+#[test]
+fn test_join_expr_fragments() {
+    let join = |fragments: &[&str]| {
+        let fragments: Vec<String> = fragments.iter().map(|&s| s.into()).collect();
+        join_expr_fragments(&fragments).map_err(|e| e.to_string())
+    };
+
+    assert_eq!(join(&["1 + 1"]), Ok("1 + 1".into()));
+    assert_eq!(
+        join(&["let x = 2", "fn f() {}", "let y = 3; ", "f(); x * y"]),
+        Ok("let x = 2;\nfn f() {}\nlet y = 3;\nf(); x * y".into())
+    );
+    assert_eq!(
+        join(&["let x = 2;", " ", "x"]),
+        Err("`--expr` fragment 2 is empty".into())
+    );
+}
+
+/**
+Could this be a type, for `--expr-as`?  This only rules out things which would break out of the template, such as braces, quotes and comments; whether it actually *is* a type is for rustc to say.
+*/
+fn plausible_type(ty: &str) -> bool {
+    !ty.trim().is_empty()
+        && !ty.contains("//")
+        && ty
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == ' ' || "_:<>,&'[];()*+-=".contains(c))
+}
+
+#[test]
+fn test_plausible_type() {
+    for ty in &[
+        "i64",
+        "std::collections::HashMap<String, Vec<u8>>",
+        "&'static str",
+        "[u8; 4]",
+        "(i32, f64)",
+        "Box<dyn Fn(i32) -> i32 + Send>",
+        "*const u8",
+    ] {
+        assert!(plausible_type(ty), "{:?}", ty);
+    }
+    for ty in &[
+        "",
+        " ",
+        "i64 {",
+        "i64>({0})",
+        "\"x\"",
+        "u8 // comment",
+        "i64\n",
+        "#[x]",
+    ] {
+        assert!(!plausible_type(ty), "{:?}", ty);
+    }
+}
+
+/**
+Reads the manifest for `--manifest-from-stdin`.  It's only parsed later, along with everything else that goes into the manifest.
+*/
+fn read_stdin_manifest(args: &Args) -> Result<String> {
+    if args.expr_file && args.script.as_deref() == Some("-".as_ref()) {
+        return Err((
+            Blame::Human,
+            "`--manifest-from-stdin` and `--expr-file -` can't both read stdin",
+        )
+            .into());
+    }
+    let mut bytes = vec![];
+    std::io::stdin().read_to_end(&mut bytes)?;
+    String::from_utf8(bytes)
+        .map_err(|_| (Blame::Human, "the manifest from stdin is not valid UTF-8").into())
+}
+
+/**
+Reads the expression for `--expr-file`: from the given file, or from stdin for `-`.
+
+Windows PowerShell writes files as UTF-16 unless told otherwise, so that's understood too, as long as it starts with a byte order mark.  Anything else needs `--input-encoding`, given as `encoding`.
+*/
+fn read_expr_file(path: &std::ffi::OsStr, encoding: Option<InputEncoding>) -> Result<String> {
+    let bytes = match path == "-" {
+        true => {
+            let mut bytes = vec![];
+            std::io::stdin().read_to_end(&mut bytes)?;
+            bytes
+        }
+        false => fs::read(path).map_err(|err| {
+            MainError::OtherOwned(
+                Blame::Human,
+                format!(
+                    "could not read expression file `{}`: {}",
+                    Path::new(path).display(),
+                    err
+                ),
+            )
+        })?,
+    };
+    let text = match encoding {
+        None => decode_text(&bytes).ok_or((
+            Blame::Human,
+            "expression file is neither UTF-8 nor UTF-16 with a byte order mark",
+        ))?,
+        Some(encoding) => encoding.decode(&bytes).ok_or_else(|| {
+            MainError::OtherOwned(
+                Blame::Human,
+                format!("expression file is not valid {}", encoding.name()),
+            )
+        })?,
+    };
+    match text.trim() {
+        "" => Err((Blame::Human, "expression file is empty").into()),
+        text => Ok(text.into()),
+    }
+}
+
+/**
+Decodes text which is either UTF-8, with or without a byte order mark, or UTF-16 with one.
+*/
+fn decode_text(bytes: &[u8]) -> Option<String> {
+    match bytes {
+        [0xef, 0xbb, 0xbf, rest @ ..] => String::from_utf8(rest.to_vec()).ok(),
+        [0xff, 0xfe, rest @ ..] => decode_utf16(rest, u16::from_le_bytes),
+        [0xfe, 0xff, rest @ ..] => decode_utf16(rest, u16::from_be_bytes),
+        _ => String::from_utf8(bytes.to_vec()).ok(),
+    }
+}
+
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> Option<String> {
+    if bytes.len() % 2 != 0 {
+        return None;
+    }
+    let units: Vec<u16> = bytes.chunks(2).map(|c| unit([c[0], c[1]])).collect();
+    String::from_utf16(&units).ok()
+}
+
+/**
+The encodings a script can be read in with `--input-encoding`.
+*/
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum InputEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+    Windows1252,
+}
+
+impl InputEncoding {
+    /**
+    Looks an encoding up by name.  Case, hyphens and underscores don't matter, so `UTF-16LE` and `utf16le` are the same.
+    */
+    fn from_name(name: &str) -> Option<Self> {
+        use self::InputEncoding::*;
+        let name: String = name
+            .chars()
+            .filter(|&c| c != '-' && c != '_')
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        match &*name {
+            "utf8" => Some(Utf8),
+            "utf16le" => Some(Utf16Le),
+            "utf16be" => Some(Utf16Be),
+            "latin1" | "iso88591" => Some(Latin1),
+            "windows1252" | "cp1252" => Some(Windows1252),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        use self::InputEncoding::*;
+        match self {
+            Utf8 => "UTF-8",
+            Utf16Le => "UTF-16LE",
+            Utf16Be => "UTF-16BE",
+            Latin1 => "Latin-1",
+            Windows1252 => "Windows-1252",
+        }
+    }
+
+    /**
+    Decodes `bytes`, dropping a byte order mark if there is one.  Only the Unicode encodings can fail.
+    */
+    fn decode(self, bytes: &[u8]) -> Option<String> {
+        use self::InputEncoding::*;
+
+        // The characters Windows-1252 puts in place of Latin-1's C1 controls.  The five it leaves undefined are kept as controls, as browsers do.
+        const WINDOWS_1252: [char; 32] = [
+            '\u{20ac}', '\u{81}', '\u{201a}', '\u{192}', '\u{201e}', '\u{2026}', '\u{2020}',
+            '\u{2021}', '\u{2c6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8d}',
+            '\u{17d}', '\u{8f}', '\u{90}', '\u{2018}', '\u{2019}', '\u{201c}', '\u{201d}',
+            '\u{2022}', '\u{2013}', '\u{2014}', '\u{2dc}', '\u{2122}', '\u{161}', '\u{203a}',
+            '\u{153}', '\u{9d}', '\u{17e}', '\u{178}',
+        ];
+
+        match (self, bytes) {
+            (Utf8, [0xef, 0xbb, 0xbf, rest @ ..]) | (Utf8, rest) => {
+                String::from_utf8(rest.to_vec()).ok()
+            }
+            (Utf16Le, [0xff, 0xfe, rest @ ..]) | (Utf16Le, rest) => {
+                decode_utf16(rest, u16::from_le_bytes)
+            }
+            (Utf16Be, [0xfe, 0xff, rest @ ..]) | (Utf16Be, rest) => {
+                decode_utf16(rest, u16::from_be_bytes)
+            }
+            (Latin1, _) => Some(bytes.iter().map(|&b| b as char).collect()),
+            (Windows1252, _) => Some(
+                bytes
+                    .iter()
+                    .map(|&b| match b {
+                        0x80..=0x9f => WINDOWS_1252[(b - 0x80) as usize],
+                        _ => b as char,
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+#[test]
+fn test_input_encoding() {
+    let decode = |name: &str, bytes: &[u8]| InputEncoding::from_name(name).unwrap().decode(bytes);
+
+    assert_eq!(
+        InputEncoding::from_name("UTF-16LE"),
+        Some(InputEncoding::Utf16Le)
+    );
+    assert_eq!(
+        InputEncoding::from_name("iso_8859_1"),
+        Some(InputEncoding::Latin1)
+    );
+    assert_eq!(InputEncoding::from_name("ebcdic"), None);
+
+    assert_eq!(
+        decode("utf-8", b"\xef\xbb\xbf\xc3\xa9"),
+        Some("\u{e9}".into())
+    );
+    assert_eq!(decode("utf-8", b"\xe9"), None);
+    assert_eq!(
+        decode("latin1", b"caf\xe9 \x80"),
+        Some("caf\u{e9} \u{80}".into())
+    );
+    assert_eq!(
+        decode("cp1252", b"caf\xe9 \x80\x81"),
+        Some("caf\u{e9} \u{20ac}\u{81}".into())
+    );
+    assert_eq!(decode("utf-16le", b"\xff\xfe\xe9\0"), Some("\u{e9}".into()));
+    assert_eq!(decode("utf-16be", b"\0\xe9"), Some("\u{e9}".into()));
+    assert_eq!(decode("utf-16be", b"\0"), None);
+}
+
+#[test]
+fn test_decode_text() {
+    assert_eq!(decode_text(b"1 + 1\r\n"), Some("1 + 1\r\n".into()));
+    assert_eq!(decode_text(b"\xef\xbb\xbf\"x\""), Some("\"x\"".into()));
+    assert_eq!(
+        decode_text(b"\xff\xfe1\0 \0+\0 \x001\0"),
+        Some("1 + 1".into())
+    );
+    assert_eq!(
+        decode_text(b"\xfe\xff\0'\0\xe9\0'"),
+        Some("'\u{e9}'".into())
+    );
+    assert_eq!(decode_text(b"\xff\xfe1"), None);
+    assert_eq!(decode_text(b"\xff\xff"), None);
+}
+
+fn main() {
+    let stderr = &mut std::io::stderr();
+    match try_main() {
+        Ok(0) => (),
+        Ok(code) => {
+            std::process::exit(code);
+        }
+        Err(ref err) if err.is_human() => {
+            writeln!(stderr, "error: {}", err).unwrap();
+            std::process::exit(err.exit_code());
+        }
+        Err(ref err) => {
+            writeln!(stderr, "internal error: {}", err).unwrap();
+            std::process::exit(1);
+        }
+    }
+}
+
+fn try_main() -> Result<i32> {
+    let start = std::time::Instant::now();
+    let args = parse_args();
+    let args_time = start.elapsed();
+
+    // Logging can't be set up until we know whether to trace, or keep quiet.
+    match args {
+        SubCommand::Script(ref args) => logging::init(args.trace, args.quiet),
+        _ => logging::init(false, false),
+    }
+    info!("starting");
+    info!("args: {:?}", std::env::args_os().collect::<Vec<_>>());
+    info!("Arguments: {:?}", args);
+
+    let args = match args {
+        SubCommand::Script(args) => args,
+        SubCommand::New(args) => return new::try_main(args),
+        SubCommand::Export(args) => return export::try_main(args),
+        SubCommand::Fmt(args) => return fmt::try_main(args),
+        SubCommand::Clippy(args) => return clippy::try_main(args),
+        SubCommand::Pin(args) => return pin::try_main(args),
+        SubCommand::Prebuild(args) => return prebuild::try_main(args),
+        SubCommand::Quote(args) => return quote::try_main(args),
+        SubCommand::Env(args) => return environment::try_main(args),
+        SubCommand::Templates(args) => return templates::try_main(args),
+        SubCommand::Update(args) => return update::try_main(args),
+        SubCommand::SelfUpdate(args) => return self_update::try_main(args),
+        #[cfg(windows)]
+        SubCommand::FileAssoc(args) => return file_assoc::try_main(args),
+    };
+
+    // The check for updates goes to the network, and keeps its notes in the cache.
+    if args.which.is_none() && !args.hash_only && !args.no_net && !args.no_cache {
+        self_update::notify_if_outdated();
+    }
+
+    run_script(args, start, args_time, &mut false)
+}
+
+/**
+Does everything the `script` subcommand (or `which`) does with `args`, returning the exit code rather than exiting with it.  This is also how `prebuild` builds each of its scripts, so `compiled` is set to whether the script's package had to be built.
+
+`start` is when we started, and `args_time` how long parsing the arguments took, for `--timings`.
+*/
+fn run_script(
+    mut args: Args,
+    start: std::time::Instant,
+    args_time: std::time::Duration,
+    compiled: &mut bool,
+) -> Result<i32> {
+    /*
+    The summary is printed by a `Defer` so that it still happens however we leave, and since this is declared first, it runs *after* the cache cleanup at the end.
+    */
+    // `--report` needs to know how long things took, whether or not they're shown.
+    let timings = Timings::new(args.timings || args.report.is_some(), consts::TIMING_PHASES);
+    timings.record("args", args_time);
+    let (message_format, show_timings) = (args.message_format, args.timings);
+    let _report_timings = Defer::<_, MainError>::defer(|| {
+        if show_timings {
+            let total = start.elapsed();
+            match message_format {
+                MessageFormat::Human => eprintln!("timings: {}", timings.summary(total)),
+                MessageFormat::Json => eprintln!("{}", timings.to_json(total)),
+            }
+        }
+        Ok(())
+    });
+
+    // `which` only looks; it mustn't go changing the cache out from under whoever asked.
+    if args.which.is_some()
+        && (args.clear_cache
+            || args.list_cache
+            || args.clear_data
+            || args.list_data
+            || args.migrate_data.is_some())
+    {
+        return Err((
+            Blame::Human,
+            "`which` can't be used with `--clear-cache`, `--list-cache`, `--clear-data`, `--list-data`, or `--migrate-data`",
+        )
+            .into());
+    }
+
+    if args.which_all {
+        return show_script_candidates(args.script.as_ref().unwrap());
+    }
+
+    /*
+    Do data migration before anything else, since it can cause the location of stuff to change.
+    */
+    if let Some(run_kind) = args.migrate_data {
+        println!("Migrating data...");
+        let (log, res) = platform::migrate_old_data(run_kind);
+        match (log.len(), res) {
+            (0, Ok(())) => {
+                println!("Nothing to do.");
+                return Ok(0);
+            }
+            (_, Ok(())) => {
+                for entry in log {
+                    println!("- {}", entry);
+                }
+                return Ok(0);
+            }
+            (_, Err(err)) => {
+                for entry in log {
+                    println!("- {}", entry);
+                }
+                return Err(err);
+            }
+        }
+    }
+
+    if log_enabled!(log::Level::Debug) {
+        let scp = get_script_cache_path()?;
+        let bcp = get_binary_cache_path()?;
         debug!("script-cache path: {:?}", scp);
         debug!("binary-cache path: {:?}", bcp);
     }
@@ -457,45 +1688,143 @@ fn try_main() -> Result<i32> {
         // If we *did not* get a `<script>` argument, that's OK.
         if args.script.is_none() {
             // Just let the user know that we did *actually* run.
-            println!("cargo script cache cleared.");
+            status!("cargo script cache cleared.");
             return Ok(0);
         }
     }
 
-    // Take the arguments and work out what our input is going to be.  Primarily, this gives us the content, a user-friendly name, and a cache-friendly ID.
-    // These three are just storage for the borrows we'll actually use.
-    let script_name: String;
-    let script_path: PathBuf;
-    let content: String;
+    // Data directories are nothing to do with the cache, so they're only cleared when asked for by name.
+    if args.clear_data {
+        clear_data()?;
+        if args.script.is_none() {
+            status!("cargo script data cleared.");
+            return Ok(0);
+        }
+    }
+
+    if args.list_data {
+        list_data()?;
+        return Ok(0);
+    }
 
-    let input = match (args.script, args.expr, args.loop_) {
-        (Some(script), false, false) => {
-            let (path, mut file) = find_script(script).ok_or("could not find script")?;
+    if args.list_cache {
+        let now = platform::current_time();
+        let since = args.since.map(|s| time_spec::parse(&s, now)).transpose()?;
+        let until = args.until.map(|s| time_spec::parse(&s, now)).transpose()?;
+        list_cache(since, until)?;
+        return Ok(0);
+    }
 
-            script_name = path
-                .file_stem()
-                .map(|os| os.to_string_lossy().into_owned())
-                .unwrap_or("unknown".into());
+    if args.list_templates {
+        templates::list()?;
+        return Ok(0);
+    }
 
-            let mut body = String::new();
-            file.read_to_string(&mut body)?;
+    /*
+    In a read-only container, the cache is no use to us, but that's no reason not to run the script: it can be built somewhere temporary instead, as with `--no-cache`.  That isn't an option for things that only make sense *in* the cache, though, and looking doesn't need to write anything.
+    */
+    let uses_cache = !(args.no_cache || args.dry_run || args.hash_only || args.which.is_some())
+        && args.pkg_path.is_none();
+    if uses_cache {
+        if let Err((path, err)) = check_cache_writable() {
+            let needs_cache = args.require_cache
+                || args.gen_pkg_only
+                || args.keep_build_dir
+                || args.content_addressed
+                || args.use_bincache == Some(true);
+            if needs_cache {
+                return Err(MainError::OtherOwned(
+                    Blame::Human,
+                    format!(
+                        "the cache directory `{}` can't be written to: {}; {}",
+                        path.display(),
+                        err,
+                        if cfg!(windows) {
+                            "pass `--no-cache` to build without it"
+                        } else {
+                            "set `CARGO_HOME` to move it somewhere writable, or pass `--no-cache` to build without it"
+                        }
+                    ),
+                ));
+            }
+            warning!(
+                "the cache directory `{}` can't be written to ({}), so the script will be built in a temporary directory, and nothing will be cached",
+                path.display(),
+                err
+            );
+            args.no_cache = true;
+        }
+    }
 
-            let mtime = platform::file_last_modified(&file);
+    // Catch this now, rather than after a potentially lengthy build.
+    if let (Some(target), None) = (&args.target, &args.runner) {
+        let runs = !(args.gen_pkg_only || args.build_only);
+        if runs && is_wasm_target(target) && args.build_kind.can_exec_directly() {
+            return Err(MainError::OtherOwned(
+                Blame::Human,
+                format!(
+                    "`{}` executables can't be run directly; use `--runner` to choose a runtime, such as `--runner wasmtime`",
+                    target
+                ),
+            ));
+        }
+    }
 
-            script_path = std::env::current_dir()?.join(path);
-            content = body;
+    let manifest_override = match args.manifest_from_stdin {
+        true => Some(read_stdin_manifest(&args)?),
+        false => None,
+    };
+    let deps_from = match args.deps_from {
+        Some(ref path) => Some(manifest::DepsFrom::load(path)?),
+        None => None,
+    };
 
-            Input::File(&script_name, &script_path, &content, mtime)
+    // Take the arguments and work out what our input is going to be.  Primarily, this gives us the content, a user-friendly name, and a cache-friendly ID.
+    // These are just storage for the borrows we'll actually use.
+    let script: LoadedScript;
+    let content: String;
+    let mut bundle = None;
+
+    let input = match (args.script.clone(), args.expr || args.expr_file, args.loop_) {
+        (Some(path), false, false) => {
+            let _t = timings.scope("read");
+            script = load_script(&path, args.input_encoding)?;
+            bundle = script.bundle.clone();
+            script.input()
+        }
+        (Some(path), true, false) if args.expr_file => {
+            let _t = timings.scope("read");
+            content = read_expr_file(&path, args.input_encoding)?;
+            Input::Expr(&content, args.template.as_deref(), args.expr_as.as_deref())
         }
         (Some(expr), true, false) => {
-            content = expr;
-            Input::Expr(&content, args.template.as_ref().map(|s| &**s))
+            let fragments = std::iter::once(expr)
+                .chain(args.expr_fragments.iter().cloned())
+                .map(|fragment| {
+                    fragment
+                        .into_string()
+                        .map_err(|_| (Blame::Human, "expression is not valid UTF-8"))
+                })
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            content = join_expr_fragments(&fragments)?;
+            Input::Expr(
+                &content,
+                args.template.as_ref().map(|s| &**s),
+                args.expr_as.as_deref(),
+            )
         }
         (Some(loop_), false, true) => {
-            content = loop_;
-            Input::Loop(&content, args.count)
+            content = loop_
+                .into_string()
+                .map_err(|_| (Blame::Human, "loop closure is not valid UTF-8"))?;
+            let finish = args
+                .finish
+                .as_deref()
+                .unwrap_or(consts::LOOP_DEFAULT_FINISH);
+            let fold = args.fold.as_deref().map(|init| (init, finish));
+            Input::Loop(&content, args.count, fold, args.filter)
         }
-        (None, _, _) => Err((Blame::Human, consts::NO_ARGS_MESSAGE))?,
+        (None, _, _) => unreachable!("clap should have required <script>"),
         _ => Err((Blame::Human, "cannot specify both --expr and --loop"))?,
     };
     info!("input: {:?}", input);
@@ -512,32 +1841,17 @@ fn try_main() -> Result<i32> {
         use std::collections::HashMap;
 
         let mut deps: HashMap<String, String> = HashMap::new();
-        for dep in args.dep.iter().chain(args.dep_extern.iter()).cloned() {
-            // Append '=*' if it needs it.
-            let dep = match dep.find('=') {
-                Some(_) => dep,
-                None => dep + "=*",
-            };
-
-            let mut parts = dep.splitn(2, '=');
-            let name = parts.next().expect("dependency is missing name");
-            let version = parts.next().expect("dependency is missing version");
-            assert!(
-                parts.next().is_none(),
-                "dependency somehow has three parts?!"
-            );
-
-            if name == "" {
-                Err((Blame::Human, "cannot have empty dependency package name"))?;
-            }
-
-            if version == "" {
-                Err((Blame::Human, "cannot have empty dependency version"))?;
-            }
+        for dep in args
+            .dep
+            .iter()
+            .chain(args.dep_extern.iter())
+            .chain(args.prelude_crate.iter())
+        {
+            let (name, version) = manifest::parse_dep_spec(dep)?;
 
-            match deps.entry(name.into()) {
+            match deps.entry(name.clone()) {
                 Vacant(ve) => {
-                    ve.insert(version.into());
+                    ve.insert(version);
                 }
                 Occupied(oe) => {
                     // This is *only* a problem if the versions don't match.  We won't try to do anything clever in terms of upgrading or resolving or anything... exact match or go home.
@@ -563,25 +1877,29 @@ fn try_main() -> Result<i32> {
     info!("deps: {:?}", deps);
 
     /*
-    Generate the prelude items, if we need any.  Again, ensure consistent and *valid* sorting.
+    Generate the prelude items, if we need any.  Again, ensure consistent and *valid* sorting.  A crate can be asked for more than once (*e.g.* with both `--dep-extern` and `--prelude-crate`), so duplicates are also removed.
     */
     let prelude_items = {
         let unstable_features = args
             .unstable_features
             .iter()
             .map(|uf| format!("#![feature({})]", uf));
+        let crate_name = |d: &str| {
+            let d = match d.find('=') {
+                Some(i) => &d[..i],
+                None => d,
+            };
+            d.replace("-", "_")
+        };
         let dep_externs = args
             .dep_extern
             .iter()
-            .map(|d| match d.find('=') {
-                Some(i) => &d[..i],
-                None => &d[..],
-            })
-            .map(|d| match d.contains('-') {
-                true => Cow::from(d.replace("-", "_")),
-                false => Cow::from(d),
-            })
-            .map(|d| format!("#[macro_use] extern crate {};", d));
+            .chain(args.prelude_crate.iter())
+            .map(|d| format!("#[macro_use] extern crate {};", crate_name(d)));
+        let prelude_uses = args
+            .prelude_crate
+            .iter()
+            .map(|d| format!("use {}::prelude::*;", crate_name(d)));
 
         let externs = args
             .extern_
@@ -591,111 +1909,1062 @@ fn try_main() -> Result<i32> {
         let mut items: Vec<_> = unstable_features
             .chain(dep_externs)
             .chain(externs)
+            .chain(prelude_uses)
             .collect();
         items.sort();
+        items.dedup();
         items
     };
     info!("prelude_items: {:?}", prelude_items);
 
-    // Work out what to do.
-    let action = decide_action_for(
-        &input,
-        deps,
-        prelude_items,
-        args.debug,
-        args.pkg_path,
-        args.gen_pkg_only,
-        args.build_only,
-        args.force,
-        args.features,
-        args.use_bincache,
-        args.build_kind,
-    )?;
-    info!("action: {:?}", action);
-
-    gen_pkg_and_compile(&input, &action)?;
+    /*
+    Work out the extra environment for the script.  Do this *before* compiling so that a bad file or spec doesn't cost the user a build.  Later sources override earlier ones, so explicit `--env` flags win over anything from `--env-file`, which wins over `--force-child-color`.
+    */
+    let script_env = {
+        let mut script_env = vec![];
+        if args.force_child_color {
+            script_env.push(("CLICOLOR_FORCE".into(), "1".into()));
+        }
+        for path in &args.env_file {
+            script_env.extend(env_file::load(path)?);
+        }
+        for spec in &args.env {
+            match spec.find('=') {
+                Some(i) if i > 0 => script_env.push((spec[..i].into(), spec[i + 1..].into())),
+                _ => Err((
+                    Blame::Human,
+                    format!("invalid --env spec {:?}: expected `KEY=VALUE`", spec),
+                ))?,
+            }
+        }
+        script_env
+    };
+    info!("script_env: {:?}", script_env);
 
-    // Once we're done, clean out old packages from the cache.  There's no point if we've already done a full clear, though.
-    let _defer_clear = {
-        // To get around partially moved args problems.
-        let cc = args.clear_cache;
+    // With `--no-cache`, the package gets a directory of its own, which goes once we're done.
+    let no_cache_path = match args.no_cache && !args.dry_run {
+        true => Some(make_temp_dir("cargo-script-pkg")?),
+        false => None,
+    };
+    let _defer_no_cache = {
+        let path = no_cache_path.clone();
         Defer::<_, MainError>::defer(move || {
-            if !cc {
-                clean_cache(consts::MAX_CACHE_AGE_MS)?;
+            if let Some(path) = path {
+                info!("removing {:?}", path);
+                fs::remove_dir_all(path)?;
             }
             Ok(())
         })
     };
+    let pkg_path = match (args.no_cache, no_cache_path) {
+        (_, Some(path)) => Some(path),
+        (true, None) => Some(std::env::temp_dir().join("cargo-script-pkg")),
+        (false, None) => args.pkg_path.clone(),
+    };
 
-    // Run it!
-    if action.execute {
-        fn hint<F: FnOnce(&mut Command) -> &mut Command>(f: F) -> F {
-            f
+    // Work out what to do.
+    let profile: Vec<(&str, &str)> = [("panic", &args.panic), ("strip", &args.strip)]
+        .iter()
+        .filter_map(|&(key, value)| value.as_deref().map(|value| (key, value)))
+        .collect();
+    let manifest_keys = with_profile_settings(&args.manifest_key, &profile, args.build_kind)?;
+    let options = ActionOptions {
+        deps,
+        manifest_keys: &manifest_keys,
+        manifest_override: manifest_override.as_deref(),
+        deps_from: deps_from.as_ref(),
+        bundle: bundle.as_deref(),
+        prelude: prelude_items,
+        debug: match (args.debug, args.release) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            (false, false) => None,
+        },
+        pkg_path,
+        gen_pkg_only: args.gen_pkg_only || args.which.is_some() || args.hash_only,
+        look_only: args.which.is_some() || args.hash_only,
+        build_only: args.build_only,
+        force: args.force,
+        features: args.features.clone(),
+        cargo_config: args.cargo_config.clone(),
+        jobs: args.jobs,
+        no_net: args.no_net,
+        target: args.target.clone(),
+        rustflags: with_lint_levels(args.rustflags.clone(), &args.lint_levels)?,
+        profile: &profile,
+        gui: args.gui,
+        use_bincache: args.use_bincache,
+        build_kind: args.build_kind,
+        test_module: args.run_as_test_module,
+        pkg_name: args.name.as_deref(),
+        bin: args.bin.as_deref(),
+        content_addressed: args.content_addressed,
+        dry_run: args.dry_run,
+    };
+    let mut action = decide_action_for(&input, options, &timings)?;
+    info!("action: {:?}", action);
+    *compiled = action.compile;
+
+    if !args.allow_wildcard_deps {
+        let wildcards = manifest::wildcard_deps(&action.manifest)?;
+        if args.deny_wildcard_deps && !wildcards.is_empty() {
+            return Err(MainError::OtherOwned(
+                Blame::Human,
+                format!(
+                    "dependencies which can be any version (--deny-wildcard-deps): {}; give each one a version with `name=version`",
+                    wildcards
+                        .iter()
+                        .map(|name| format!("`{}`", name))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            ));
         }
-        let add_env = hint(move |cmd| {
-            cmd.env(
-                "CARGO_SCRIPT_SCRIPT_PATH",
-                input.path().unwrap_or(Path::new("")),
-            );
-            cmd.env("CARGO_SCRIPT_SAFE_NAME", input.safe_name());
-            cmd.env("CARGO_SCRIPT_PKG_NAME", input.package_name());
-            cmd.env("CARGO_SCRIPT_BASE_PATH", input.base_path());
-            cmd
-        });
+        action
+            .warnings
+            .extend(wildcard_dep_warnings(&wildcards, &action.pkg_path));
+    }
 
-        if action.build_kind.can_exec_directly() {
-            let exe_path = get_exe_path(action.build_kind, &action.pkg_path)?;
-            info!("executing {:?}", exe_path);
-            match Command::new(exe_path)
-                .args(&args.args)
-                .chain_map(add_env)
-                .status()
-                .map(|st| st.code().unwrap_or(1))?
-            {
-                0 => (),
-                n => return Ok(n),
-            }
-        } else {
-            let cmd_name = action.build_kind.exec_command();
-            info!("running `cargo {}`", cmd_name);
-            let mut cmd = action.cargo(cmd_name)?;
-            add_env(&mut cmd);
-            match cmd.status().map(|st| st.code().unwrap_or(1))? {
-                0 => (),
-                n => return Ok(n),
-            }
+    for warning in &action.warnings {
+        warning!("{}", warning);
+    }
+    if args.deny_warnings && !action.warnings.is_empty() {
+        return Err((Blame::Human, "aborting due to warnings (--deny-warnings)").into());
+    }
+    if args.verbose {
+        for file in &action.script_config {
+            note!("using Cargo configuration from `{}`", file.path.display());
+        }
+        for config in &action.metadata.cargo_config {
+            note!("passing `--config {}` on to Cargo", config);
+        }
+        if let Some(ref deps_from) = deps_from {
+            note!(
+                "taking dependency versions from `{}`",
+                deps_from.path.display()
+            );
         }
     }
 
-    // If nothing else failed, I suppose we succeeded.
-    Ok(0)
-}
-
-/**
-Clean up the cache folder.
+    if let Some(format) = args.which {
+        return show_which(&input, &action, format);
+    }
 
-Looks for all folders whose metadata says they were created at least `max_age` in the past and kills them dead.
-*/
-fn clean_cache(max_age: u64) -> Result<()> {
-    info!("cleaning cache with max_age: {:?}", max_age);
+    if args.hash_only {
+        let id = action.pkg_path.file_name().ok_or("no ID for the package")?;
+        println!("{}", id.to_string_lossy());
+        return Ok(0);
+    }
 
-    if max_age == 0 {
-        info!("max_age is 0, clearing binary cache...");
-        let cache_dir = get_binary_cache_path()?;
-        if ALLOW_AUTO_REMOVE {
-            if let Err(err) = fs::remove_dir_all(&cache_dir) {
-                error!("failed to remove binary cache {:?}: {}", cache_dir, err);
-            }
-        }
+    // Cargo would give anything it ran the configuration's `[env]`, but the script isn't run by Cargo.
+    let config_env =
+        script_config::env_vars(&action.script_config, |k| std::env::var_os(k).is_some())?;
+    let mut run_env: Vec<(OsString, OsString)> = config_env
+        .into_iter()
+        .chain(script_env)
+        .map(|(k, v)| (k.into(), v.into()))
+        .collect();
+    run_env.push((
+        "CARGO_SCRIPT_SCRIPT_PATH".into(),
+        input.path().unwrap_or(Path::new("")).into(),
+    ));
+    run_env.push(("CARGO_SCRIPT_SAFE_NAME".into(), input.safe_name().into()));
+    run_env.push((
+        "CARGO_SCRIPT_PKG_NAME".into(),
+        action.pkg_name.clone().into(),
+    ));
+    run_env.push(("CARGO_SCRIPT_BASE_PATH".into(), input.base_path().into()));
+    run_env.push((
+        "CARGO_SCRIPT_CACHE_PATH".into(),
+        std::env::current_dir()?.join(&action.pkg_path).into(),
+    ));
+    /*
+    Only scripts get a data directory; there's nothing to tie an expression's data to from one run to the next.  A run which doesn't use the cache doesn't get to keep anything either, so its script's data goes in the package directory, and goes with it.
+    */
+    let data_dir = match input.data_id()? {
+        Some(_) if args.no_cache => Some(action.pkg_path.join("script-data")),
+        Some(id) => Some(get_script_data_path()?.join(id)),
+        None => None,
+    };
+    if let Some(ref dir) = data_dir {
+        run_env.push(("CARGO_SCRIPT_DATA_DIR".into(), dir.clone().into()));
+    }
+    // The expression template checks this when it fails, so the same executable does for either format.
+    if let Some(ref format) = args.error_format {
+        run_env.push(("CARGO_SCRIPT_ERROR_FORMAT".into(), format.into()));
     }
 
-    let cutoff = platform::current_time() - max_age;
+    let sandbox = match args.sandbox {
+        true => Some(Sandbox::new(args.sandbox_command.clone(), !args.dry_run)?),
+        false => None,
+    };
+
+    let exec_args = match args.list_tests {
+        true => with_harness_arg(&args.args, "--list"),
+        false => args.args.clone(),
+    };
+
+    if args.dry_run {
+        let run_cmd = match action.execute {
+            true => Some(exec_command(
+                &action,
+                args.runner.as_deref(),
+                sandbox.as_ref(),
+                args.doc_deps,
+                &exec_args,
+                &run_env,
+                true,
+            )?),
+            false => None,
+        };
+        return show_dry_run(&input, &action, run_cmd.as_ref(), message_format);
+    }
+
+    // Everything from here on is what `--report` reports on, so it has to see how this ends, whichever way that is.
+    let outcome = (|| -> Result<i32> {
+        if args.verbose && action.compile {
+            if let Some(ref reason) = action.compile_reason {
+                note!("compiling because: {}", reason);
+            }
+        }
+
+        // This goes last, so that it isn't buried under the script's output, and has to happen however the run ends.
+        let _defer_report = {
+            let keep = args.keep_build_dir;
+            let action = &action;
+            let input = &input;
+            Defer::<_, MainError>::defer(move || {
+                if keep {
+                    report_build_dir(input, action);
+                }
+                Ok(())
+            })
+        };
+
+        let diagnostics = match args.color_diagnostics_to_file {
+            Some(ref path) => Some(DiagnosticsFile::create(path)?),
+            None => None,
+        };
+
+        let compiled = gen_pkg_and_compile(
+            &input,
+            &action,
+            args.keep_build_dir,
+            diagnostics.as_ref(),
+            !args.no_hints,
+            args.build_limits,
+            &timings,
+        );
+        if compiled.is_err() && !args.expr_fragments.is_empty() {
+            note!(
+                "the expression was put together from {} `--expr` fragments, a line each; \
+                 all but the last are statements, and the last is the value displayed",
+                args.expr_fragments.len() + 1
+            );
+        }
+        compiled?;
+
+        if args.verbose && !args.gen_pkg_only {
+            for (profile, size) in exe_sizes(&action) {
+                note!("{} executable is {}", profile, describe_size(size));
+            }
+        }
+
+        // Once we're done, clean out old packages from the cache.  There's no point if we've already done a full clear, though, and `--no-cache` leaves the cache alone altogether.
+        let _defer_clear = {
+            // To get around partially moved args problems.
+            let cc = args.clear_cache;
+            let keep = args.keep_build_dir || args.no_cache;
+            let timings = &timings;
+            Defer::<_, MainError>::defer(move || {
+                let _t = timings.scope("gc");
+                if !(cc || keep) {
+                    // An old entry we can't get rid of is no reason to fail the run.
+                    if let Err(err) = clean_cache(consts::MAX_CACHE_AGE_MS) {
+                        info!("could not clean the cache: {}", err);
+                    }
+                }
+                Ok(())
+            })
+        };
+
+        // Run it!
+        if action.execute {
+            let _t = timings.scope("run");
+            if let Some(ref before) = args.before {
+                match run_hook("--before", before, &run_env)? {
+                    0 => (),
+                    n => {
+                        eprintln!(
+                            "error: the `--before` command failed with exit status {}; not running the script",
+                            n
+                        );
+                        return Ok(n);
+                    }
+                }
+            }
+
+            let (runner, doc_deps, output, append) = (
+                args.runner.as_deref(),
+                args.doc_deps,
+                args.output.as_deref(),
+                args.append,
+            );
+            let (clean_panics, no_hints) = (args.clean_panics && !args.verbose, args.no_hints);
+            // `--after` is run whatever happens to the script, so everything up to it is collected into `res`.
+            let res = (|| -> Result<i32> {
+                // Until the script is done, a Ctrl+C (or a `kill`) is for it to deal with; we wait to see how it ends.
+                let _signals = platform::SignalGuard::install();
+
+                fn hint<F: FnOnce(&mut Command) -> &mut Command>(f: F) -> F {
+                    f
+                }
+                let add_env = hint(|cmd| {
+                    for (k, v) in &run_env {
+                        cmd.env(k, v);
+                    }
+                    cmd
+                });
+
+                let mut cmd = exec_command(
+                    &action,
+                    runner,
+                    sandbox.as_ref(),
+                    doc_deps,
+                    &exec_args,
+                    &run_env,
+                    false,
+                )?;
+                if action.build_kind.can_exec_directly() {
+                    info!("executing {:?}", cmd);
+                    add_env(&mut cmd);
+                    /*
+                    The script gets `cargo-script`'s own stdin, stdout and stderr, never pipes, so anything which checks for a terminal (for colours, or a progress bar) sees the same thing as if it were run directly.  Only Cargo's output is ever taken in, for hints and `--color-diagnostics-to-file`.  The exceptions are ones the user asked for: `--output`, and `--clean-panics`, which has to read stderr.
+                    */
+                    if let Some(path) = output {
+                        cmd.stdout(open_output(path, append)?);
+                    }
+                    logging::trace_command(&cmd);
+                    let code = if clean_panics {
+                        panics::run_cleanly(&mut cmd, |file, line| {
+                            relocate_to_script(&input, &action, file, line)
+                        })?
+                    } else {
+                        let mut child = cmd.spawn()?;
+                        platform::forward_signals_to(&child);
+                        platform::exit_code(child.wait()?)
+                    };
+                    if input.path().is_none() && action.emit_metadata {
+                        mark_pkg_ran(&action.pkg_path)?;
+                    }
+                    match code {
+                        0 => (),
+                        n => return Ok(n),
+                    }
+                } else {
+                    info!("running `cargo {}`", action.build_kind.exec_command());
+                    add_env(&mut cmd);
+                    if let Some(ref diagnostics) = diagnostics {
+                        diagnostics.redirect(&mut cmd)?;
+                    }
+                    logging::trace_command(&cmd);
+                    if let BuildKind::Tree = action.build_kind {
+                        return show_tree(&mut cmd, &input, &action.pkg_path);
+                    }
+                    let status = match diagnostics {
+                        None if !no_hints => hints::run_with_hints(&mut cmd, None)?,
+                        _ => {
+                            let mut child = cmd.spawn()?;
+                            platform::forward_signals_to(&child);
+                            child.wait()?
+                        }
+                    };
+                    if let Some(ref diagnostics) = diagnostics {
+                        if !(no_hints || status.success()) {
+                            diagnostics.show_hints();
+                        }
+                    }
+                    match platform::exit_code(status) {
+                        0 => (),
+                        n => return Ok(n),
+                    }
+
+                    if let BuildKind::Check = action.build_kind {
+                        write_check_hash(&action.pkg_path, &action.metadata)?;
+                    }
+                }
+                Ok(0)
+            })();
+
+            // The script makes its data directory if it wants one; all that's left is to say whose it is.
+            if let (Some(dir), Some(path), false) = (data_dir.as_ref(), input.path(), args.no_cache)
+            {
+                if let Err(err) = note_data_source(dir, path) {
+                    warning!("{}", err);
+                }
+            }
+
+            if let Some(ref after) = args.after {
+                match run_hook("--after", after, &run_env)? {
+                    0 => (),
+                    n => {
+                        eprintln!("error: the `--after` command failed with exit status {}", n);
+                        if let Ok(0) = res {
+                            return Ok(n);
+                        }
+                    }
+                }
+            }
+            match res? {
+                0 => (),
+                n => return Ok(n),
+            }
+        } else if let BuildKind::Check = action.build_kind {
+            status!("Script has not changed since it last passed `cargo check`.");
+        }
+
+        // If nothing else failed, I suppose we succeeded.
+        Ok(0)
+    })();
+
+    if let Some(ref path) = args.report {
+        let sizes = match outcome.is_ok() && !args.gen_pkg_only {
+            true => exe_sizes(&action),
+            false => vec![],
+        };
+        let size_of = |profile| {
+            sizes
+                .iter()
+                .find(|&&(p, _)| p == profile)
+                .map(|&(_, size)| size)
+        };
+        let report = report::Report {
+            script: input.path().map(|p| p.display().to_string()),
+            cache_hit: !action.compile,
+            build_ms: report::millis(timings.get("cargo")),
+            run_ms: report::millis(timings.get("run")),
+            exit_code: match outcome {
+                Ok(code) => code,
+                Err(ref err) => err.exit_code(),
+            },
+            error: outcome.as_ref().err().map(ToString::to_string),
+            toolchain: tool_version("rustc").ok(),
+            edition: package_edition(&action.manifest).unwrap_or_else(|| "2015".into()),
+            debug_exe_bytes: size_of("debug"),
+            release_exe_bytes: size_of("release"),
+        };
+        report::write(path, args.append_report, &report)?;
+    }
+    outcome
+}
+
+/**
+Runs the command given to `--before` or `--after` with the shell, in the same environment as the script, and returns its exit code.
+*/
+fn run_hook(flag: &str, command: &str, run_env: &[(OsString, OsString)]) -> Result<i32> {
+    let mut cmd = platform::shell_command(command);
+    for (k, v) in run_env {
+        cmd.env(k, v);
+    }
+    // A hook reading stdin would eat input meant for the script.
+    cmd.stdin(process::Stdio::null());
+    info!("running {} command {:?}", flag, cmd);
+    logging::trace_command(&cmd);
+    let status = cmd.status().map_err(|err| {
+        MainError::OtherOwned(
+            Blame::Human,
+            format!("could not run the `{}` command: {}", flag, err),
+        )
+    })?;
+    Ok(status.code().unwrap_or(1))
+}
+
+/**
+Opens the file given to `--output`, creating any directories it needs.
+*/
+fn open_output(path: &Path, append: bool) -> Result<fs::File> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .map_err(|err| {
+            MainError::OtherOwned(
+                Blame::Human,
+                format!("could not open {} for output: {}", path.display(), err),
+            )
+        })
+}
+
+/**
+Builds the command which runs the input once it has been built: either the executable itself, or Cargo, depending on the build kind.
+
+The script's environment is left for the caller to add.  For a dry run, nothing has necessarily been built yet, so the executable is assumed to be wherever it is expected to end up.
+*/
+fn exec_command(
+    action: &InputAction,
+    runner: Option<&str>,
+    sandbox: Option<&Sandbox>,
+    doc_deps: bool,
+    script_args: &[OsString],
+    run_env: &[(OsString, OsString)],
+    dry_run: bool,
+) -> Result<Command> {
+    if action.build_kind.can_exec_directly() {
+        let exe_path = match get_exe_path(action.build_kind, &action.pkg_path) {
+            Ok(exe_path) => exe_path,
+            Err(_) if dry_run => expected_exe_path(
+                &action.bin_name,
+                action.use_bincache,
+                &action.pkg_path,
+                &action.metadata,
+            )?,
+            Err(err) => return Err(err),
+        };
+        let mut cmd = match (runner, sandbox) {
+            (Some(runner), _) => runner_command(runner, &exe_path, run_env)?,
+            (None, Some(sandbox)) => sandbox.command(&exe_path)?,
+            (None, None) => Command::new(exe_path),
+        };
+        cmd.args(script_args);
+        return Ok(cmd);
+    }
+
+    let mut cmd = action.cargo(action.build_kind.exec_command())?;
+    // This time, Cargo is what runs the script, so it gets the script's stdin.
+    cmd.stdin(process::Stdio::inherit());
+    match action.build_kind {
+        BuildKind::Test | BuildKind::Tree => {
+            cmd.args(script_args);
+        }
+        BuildKind::Bench => {
+            if action.criterion_bench {
+                cmd.arg("--bench").arg(&action.bin_name);
+            }
+            // Criterion would otherwise keep its results in the target directory, which might be shared.
+            cmd.env(
+                "CRITERION_HOME",
+                std::env::current_dir()?
+                    .join(&action.pkg_path)
+                    .join("criterion"),
+            );
+            cmd.args(script_args);
+        }
+        BuildKind::Doc => {
+            // Cargo falls back to just saying where the docs are if it can't find a browser.
+            cmd.arg("--open");
+            if !doc_deps {
+                cmd.arg("--no-deps");
+            }
+            cmd.args(script_args);
+        }
+        _ => (),
+    }
+    Ok(cmd)
+}
+
+/**
+Adds an argument for the test harness to the arguments for `cargo test`: that is, after the `--` which separates Cargo's arguments from the harness's, adding one if there isn't one already.
+*/
+fn with_harness_arg(args: &[OsString], harness_arg: &str) -> Vec<OsString> {
+    let mut args = args.to_vec();
+    if !args.iter().any(|arg| arg == "--") {
+        args.push("--".into());
+    }
+    args.push(harness_arg.into());
+    args
+}
+
+#[test]
+fn test_with_harness_arg() {
+    let with = |args: &[&str]| {
+        let args: Vec<OsString> = args.iter().map(Into::into).collect();
+        with_harness_arg(&args, "--list")
+    };
+    let v = |args: &[&str]| -> Vec<OsString> { args.iter().map(Into::into).collect() };
+
+    assert_eq!(with(&[]), v(&["--", "--list"]));
+    assert_eq!(with(&["name"]), v(&["name", "--", "--list"]));
+    assert_eq!(
+        with(&["name", "--", "--ignored"]),
+        v(&["name", "--", "--ignored", "--list"])
+    );
+}
+
+/**
+Shows what a run would do, for `--dry-run`.
+
+`action` must have come from `decide_action_for` with `dry_run` set, so that nothing in the cache has been changed.
+*/
+fn show_dry_run(
+    input: &Input<'_>,
+    action: &InputAction,
+    run_cmd: Option<&Command>,
+    format: MessageFormat,
+) -> Result<i32> {
+    #[derive(RustcEncodable)]
+    struct DryRun {
+        input: &'static str,
+        script: Option<String>,
+        id: Option<String>,
+        package: String,
+        cache_hit: Option<bool>,
+        reason: Option<String>,
+        build_command: Option<String>,
+        run_command: Option<String>,
+    }
+
+    let build_cmd = match action.compile {
+        true => Some(action.cargo("build")?),
+        false => None,
+    };
+
+    // Cargo decides for itself what to rebuild for anything other than a regular run or a check.
+    let cache_hit = match action.build_kind {
+        BuildKind::Normal => Some(!action.compile),
+        BuildKind::Check => Some(!action.execute),
+        _ => None,
+    };
+
+    let dry_run = DryRun {
+        input: match *input {
+            Input::File(..) => "file",
+            Input::Expr(..) => "expression",
+            Input::Loop(..) => "loop",
+        },
+        script: input.path().map(|p| p.display().to_string()),
+        id: match action.using_cache {
+            true => action
+                .pkg_path
+                .file_name()
+                .map(|id| id.to_string_lossy().into_owned()),
+            false => None,
+        },
+        package: action.pkg_path.display().to_string(),
+        cache_hit,
+        reason: action.compile_reason.clone(),
+        build_command: build_cmd.as_ref().map(command_line),
+        run_command: run_cmd.map(command_line),
+    };
+
+    match format {
+        MessageFormat::Json => {
+            let json = rustc_serialize::json::encode(&dry_run).map_err(|err| err.to_string())?;
+            println!("{}", json);
+        }
+        MessageFormat::Human => {
+            println!("input: {}", dry_run.input);
+            if let Some(ref script) = dry_run.script {
+                println!("script: {}", script);
+            }
+            println!("id: {}", dry_run.id.as_ref().map_or("(none)", |id| &**id));
+            println!("package: {}", dry_run.package);
+            match (dry_run.cache_hit, &dry_run.reason) {
+                (Some(true), _) => println!("cache: hit"),
+                (Some(false), Some(reason)) => println!("cache: miss ({})", reason),
+                (Some(false), None) => println!("cache: miss"),
+                (None, _) => println!("cache: not used"),
+            }
+            println!(
+                "build: {}",
+                dry_run.build_command.as_ref().map_or("(none)", |c| &**c)
+            );
+            println!(
+                "run: {}",
+                dry_run.run_command.as_ref().map_or("(none)", |c| &**c)
+            );
+        }
+    }
+    Ok(0)
+}
+
+/**
+Renders a command as something that could be pasted into a shell, including any environment variables set for it.
+*/
+fn command_line(cmd: &Command) -> String {
+    fn quote(s: &std::ffi::OsStr) -> String {
+        let s = s.to_string_lossy();
+        let plain = !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_alphanumeric() || "-_=+./:,@%^".contains(c));
+        match plain {
+            true => s.into_owned(),
+            false => format!("'{}'", s.replace('\'', r"'\''")),
+        }
+    }
+
+    let envs = cmd
+        .get_envs()
+        .filter_map(|(k, v)| v.map(|v| format!("{}={}", k.to_string_lossy(), quote(v))));
+    let args = Some(cmd.get_program())
+        .into_iter()
+        .chain(cmd.get_args())
+        .map(quote);
+    envs.chain(args).collect::<Vec<_>>().join(" ")
+}
+
+/**
+Maps a location in the generated package's source back to the script it came from, if it can.
+
+This only works if the script's code made it into the package unchanged, which is true of files, bar their hashbang.
+*/
+fn relocate_to_script(
+    input: &Input<'_>,
+    action: &InputAction,
+    file: &str,
+    line: usize,
+) -> Option<(String, usize)> {
+    let (path, content) = match *input {
+        Input::File(_, path, content, _) => (path, content),
+        _ => return None,
+    };
+    let gen_name = format!("{}.rs", input.safe_name());
+    if Path::new(file) != Path::new(&gen_name) && Path::new(file) != action.pkg_path.join(&gen_name)
+    {
+        return None;
+    }
+    if !content.ends_with(&*action.script) {
+        return None;
+    }
+    let offset = content[..content.len() - action.script.len()]
+        .matches('\n')
+        .count();
+    Some((path.display().to_string(), line + offset))
+}
+
+/**
+Warns about each of `wildcards`, the dependencies which can be any version.  Where the package at `pkg_path` has already locked one to a version, that's the one suggested.
+*/
+fn wildcard_dep_warnings(wildcards: &[String], pkg_path: &Path) -> Vec<String> {
+    let lock_path = pkg_path.join("Cargo.lock");
+    let locked = match wildcards.is_empty() || !lock_path.is_file() {
+        true => vec![],
+        false => update::locked_versions(&lock_path).unwrap_or_default(),
+    };
+    wildcards
+        .iter()
+        .map(|name| {
+            let suggestion = match update::locked_for(&locked, name).into_iter().max() {
+                Some(version) => format!("`{}=\"{}\"`, the version it's locked to now", name, version),
+                None => format!("`{}=\"VERSION\"`", name),
+            };
+            format!(
+                "dependency `{}` can be any version, so a new release could break the script; give it one with {}",
+                name, suggestion
+            )
+        })
+        .collect()
+}
+
+/**
+The sizes of the script's executable for each profile it has been built with, starting with the one this run is using.
+
+Both profiles' executables can be in the cache at once, and it's the difference between them that's worth knowing when deciding whether to ship a debug build.
+*/
+fn exe_sizes(action: &InputAction) -> Vec<(&'static str, u64)> {
+    let exe_path = match get_exe_path(action.build_kind, &action.pkg_path) {
+        Ok(path) => path,
+        Err(_) => return vec![],
+    };
+    let size = |path: &Path| {
+        fs::metadata(path)
+            .ok()
+            .filter(|m| m.is_file())
+            .map(|m| m.len())
+    };
+
+    let profile = profile_name(&action.metadata);
+    let mut sizes: Vec<_> = size(&exe_path).map(|s| (profile, s)).into_iter().collect();
+
+    // The other profile's executable is in a sibling of this one's directory.
+    let other = match profile {
+        "debug" => "release",
+        _ => "debug",
+    };
+    let dir = exe_path.parent();
+    if let (Some(dir), Some(name)) = (dir, exe_path.file_name()) {
+        if dir.file_name() == Some(profile.as_ref()) {
+            if let Some(s) = dir.parent().and_then(|d| size(&d.join(other).join(name))) {
+                sizes.push((other, s));
+            }
+        }
+    }
+    sizes
+}
+
+/**
+Formats a size in bytes for people, in the largest binary unit it has at least one of.
+*/
+fn describe_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[test]
+fn test_describe_size() {
+    assert_eq!(describe_size(0), "0 B");
+    assert_eq!(describe_size(1023), "1023 B");
+    assert_eq!(describe_size(1024), "1.0 KiB");
+    assert_eq!(describe_size(1536), "1.5 KiB");
+    assert_eq!(describe_size(4_404_019), "4.2 MiB");
+    assert_eq!(describe_size(3 << 40), "3072.0 GiB");
+}
+
+/**
+Tells the user where the generated package for `--keep-build-dir` is, so they can poke at it with Cargo directly.
+*/
+fn report_build_dir(input: &Input<'_>, action: &InputAction) {
+    let pkg_path = &action.pkg_path;
+    let pkg_path = platform::simplify_path(&pkg_path.canonicalize().unwrap_or(pkg_path.clone()));
+    note!("kept the generated package in `{}`", pkg_path.display());
+    note!("  manifest: `{}`", pkg_path.join("Cargo.toml").display());
+    note!(
+        "  source: `{}`",
+        pkg_path.join(format!("{}.rs", input.safe_name())).display()
+    );
+}
+
+/**
+Prints every file `script` could be taken to mean, for `which --all`.  The first is the one which would actually be run.
+*/
+fn show_script_candidates(script: &OsString) -> Result<i32> {
+    let found: Vec<_> = expand_bundles(script_candidates(Path::new(script)))
+        .into_iter()
+        .filter(|path| fs::File::open(path).is_ok())
+        .collect();
+    if found.is_empty() {
+        return Err("could not find script".into());
+    }
+    for path in found {
+        println!("{}", canonical_path(&path)?.display());
+    }
+    Ok(0)
+}
+
+/**
+Shows where the package and executable for an input are, or would be, for `which`.
+
+`action` must have come from `decide_action_for` with `gen_pkg_only` set, so that nothing in the cache has been looked at too closely, let alone changed.
+*/
+fn show_which(input: &Input<'_>, action: &InputAction, format: MessageFormat) -> Result<i32> {
+    #[derive(RustcEncodable)]
+    struct Which {
+        script: Option<String>,
+        script_exists: bool,
+        id: Option<String>,
+        package: String,
+        package_exists: bool,
+        binary: Option<String>,
+        binary_exists: bool,
+    }
+
+    let script = match *input {
+        Input::File(_, path, _, _) => Some(path),
+        Input::Expr(..) | Input::Loop(..) => None,
+    };
+
+    // Once the package has been built, we know exactly where the executable went.
+    let binary = match action.build_kind {
+        BuildKind::Normal => Some(get_exe_path(action.build_kind, &action.pkg_path).or_else(
+            |_| {
+                expected_exe_path(
+                    &action.bin_name,
+                    action.use_bincache,
+                    &action.pkg_path,
+                    &action.metadata,
+                )
+            },
+        )?),
+        _ => None,
+    };
+
+    let which = Which {
+        script: script.map(|p| p.display().to_string()),
+        script_exists: script.map_or(false, |p| p.is_file_polyfill()),
+        id: match action.using_cache {
+            true => action
+                .pkg_path
+                .file_name()
+                .map(|id| id.to_string_lossy().into_owned()),
+            false => None,
+        },
+        package: action.pkg_path.display().to_string(),
+        package_exists: action.manifest_path().is_file_polyfill(),
+        binary: binary.as_ref().map(|p| p.display().to_string()),
+        binary_exists: binary.as_ref().map_or(false, |p| p.is_file_polyfill()),
+    };
+
+    match format {
+        MessageFormat::Json => {
+            let json = rustc_serialize::json::encode(&which).map_err(|err| err.to_string())?;
+            println!("{}", json);
+        }
+        MessageFormat::Human => {
+            let exists = |b| if b { "exists" } else { "missing" };
+            let script = match *input {
+                Input::File(..) => format!(
+                    "{} ({})",
+                    which.script.unwrap(),
+                    exists(which.script_exists)
+                ),
+                Input::Expr(..) => "(expression)".into(),
+                Input::Loop(..) => "(loop)".into(),
+            };
+            println!("script: {}", script);
+            println!("id: {}", which.id.as_ref().map_or("(none)", |id| &**id));
+            println!(
+                "package: {} ({})",
+                which.package,
+                exists(which.package_exists)
+            );
+            match which.binary {
+                Some(ref binary) => {
+                    println!("binary: {} ({})", binary, exists(which.binary_exists))
+                }
+                None => println!("binary: (none)"),
+            }
+        }
+    }
+    Ok(0)
+}
+
+/**
+Runs `cargo tree` for a generated package, presenting it in terms of the script it came from, rather than its location in the cache.
+*/
+fn show_tree(cmd: &mut Command, input: &Input<'_>, pkg_path: &Path) -> Result<i32> {
+    logging::trace_command(cmd);
+    let out = cmd.stderr(process::Stdio::inherit()).output()?;
+    if !out.status.success() {
+        return Ok(out.status.code().unwrap_or(1));
+    }
+
+    let pkg_path = platform::simplify_path(&pkg_path.canonicalize()?);
+    let source = match *input {
+        Input::File(_, path, _, _) => path.display().to_string(),
+        Input::Expr(..) => "expression".into(),
+        Input::Loop(..) => "loop".into(),
+    };
+    let tree = String::from_utf8_lossy(&out.stdout).replace(
+        &format!("({})", pkg_path.display()),
+        &format!("({})", source),
+    );
+    print!("{}", tree);
+    Ok(0)
+}
+
+/**
+Builds the command which runs `exe_path` using the given `--runner`.
+
+WebAssembly runtimes don't give modules the host's environment, so for `wasmtime`, the variables in `env` are also passed on explicitly.  The script's own arguments are left for the caller to add.
+*/
+fn runner_command(runner: &str, exe_path: &Path, env: &[(OsString, OsString)]) -> Result<Command> {
+    let mut parts = runner.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or((Blame::Human, "`--runner` needs a command to run"))?;
+
+    let mut cmd = Command::new(program);
+    cmd.args(parts);
+    if Path::new(program).file_stem() == Some("wasmtime".as_ref()) {
+        for (k, v) in env {
+            let mut kv = k.clone();
+            kv.push("=");
+            kv.push(v);
+            cmd.arg("--env").arg(kv);
+        }
+    }
+    cmd.arg(exe_path);
+    Ok(cmd)
+}
+
+#[test]
+fn test_runner_command() {
+    let args = |cmd: &Command| -> Vec<String> {
+        ::std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect()
+    };
+    let env = [("A".into(), "1".into()), ("B".into(), "x y".into())];
+    let exe = Path::new("out/s.wasm");
+
+    assert_eq!(
+        args(&runner_command("valgrind", exe, &env).unwrap()),
+        vec!["valgrind", "out/s.wasm"]
+    );
+    assert_eq!(
+        args(&runner_command("  env  X=1 ", exe, &env).unwrap()),
+        vec!["env", "X=1", "out/s.wasm"]
+    );
+    assert_eq!(
+        args(&runner_command("wasmtime run", exe, &env).unwrap()),
+        vec![
+            "wasmtime",
+            "run",
+            "--env",
+            "A=1",
+            "--env",
+            "B=x y",
+            "out/s.wasm"
+        ]
+    );
+    assert_eq!(
+        args(&runner_command("/opt/bin/wasmtime", exe, &[]).unwrap()),
+        vec!["/opt/bin/wasmtime", "out/s.wasm"]
+    );
+    assert!(runner_command(" ", exe, &env).is_err());
+}
+
+/**
+Does this target triple produce WebAssembly modules, rather than native executables?
+*/
+fn is_wasm_target(target: &str) -> bool {
+    target.starts_with("wasm32-") || target.starts_with("wasm64-")
+}
+
+/**
+Clean up the cache folder.
+
+Looks for all folders whose metadata says they were created at least `max_age` in the past and kills them dead.  Packages pinned with `cargo script pin` are spared, unless `max_age` is 0, which clears out everything.
+*/
+fn clean_cache(max_age: u64) -> Result<()> {
+    info!("cleaning cache with max_age: {:?}", max_age);
+
+    if max_age == 0 {
+        info!("max_age is 0, clearing binary cache...");
+        let cache_dir = get_binary_cache_path()?;
+        if ALLOW_AUTO_REMOVE {
+            if let Err(err) = fs::remove_dir_all(&cache_dir) {
+                error!("failed to remove binary cache {:?}: {}", cache_dir, err);
+            }
+        }
+    }
+
+    let cutoff = platform::current_time() - max_age;
     info!("cutoff:     {:>20?} ms", cutoff);
 
     let cache_dir = get_script_cache_path()?;
     for child in fs::read_dir(cache_dir)? {
-        let child = child?;
-        let path = child.path();
+        let path = match child {
+            Ok(child) => child.path(),
+            Err(err) => {
+                info!("skipping unreadable cache entry: {}", err);
+                continue;
+            }
+        };
         if path.is_file_polyfill() {
             continue;
         }
@@ -711,8 +2980,13 @@ fn clean_cache(max_age: u64) -> Result<()> {
             So, instead, we're just going to go by the timestamp on the metadata file *itself*.
             */
             let meta_mtime = {
+                // Packages which have only ever been checked have a check hash instead of metadata.  Ones only used for things like `--tree` or `--doc` have neither, so they go by their manifest.
                 let meta_path = get_pkg_metadata_path(&path);
-                let meta_file = match fs::File::open(&meta_path) {
+                let check_hash_path = get_check_hash_path(&path);
+                let meta_file = match fs::File::open(&meta_path)
+                    .or_else(|_| fs::File::open(&check_hash_path))
+                    .or_else(|_| fs::File::open(path.join("Cargo.toml")))
+                {
                     Ok(file) => file,
                     Err(..) => {
                         info!("couldn't open metadata for {:?}", path);
@@ -723,22 +2997,184 @@ fn clean_cache(max_age: u64) -> Result<()> {
             };
             info!("meta_mtime: {:>20?} ms", meta_mtime);
 
-            (meta_mtime <= cutoff)
-        };
+            (meta_mtime <= cutoff)
+        };
+
+        if max_age > 0 && is_pinned(&path) {
+            info!("keeping pinned {:?}", path);
+            continue;
+        }
+
+        if remove_dir() {
+            info!("removing {:?}", path);
+            if ALLOW_AUTO_REMOVE {
+                if let Err(err) = fs::remove_dir_all(&path) {
+                    error!("failed to remove {:?} from cache: {}", path, err);
+                }
+            } else {
+                info!("(suppressed remove)");
+            }
+        }
+    }
+    info!("done cleaning cache.");
+    Ok(())
+}
+
+/**
+Has the package been pinned with `cargo script pin`?
+*/
+fn is_pinned(pkg_path: &Path) -> bool {
+    get_pkg_metadata(pkg_path)
+        .map(|meta| meta.pinned == Some(true))
+        .unwrap_or(false)
+}
+
+/**
+Lists the packages in the script cache, oldest first, marking the ones which are pinned.
+
+If given, `since` and `until` (in milliseconds since the UNIX epoch) limit this to packages last built within that window.  As in `clean_cache`, we go by the timestamp on the metadata file, which is only written after a successful build.
+*/
+fn list_cache(since: Option<u64>, until: Option<u64>) -> Result<()> {
+    info!("listing cache since {:?} until {:?}", since, until);
+
+    let cache_dir = get_script_cache_path()?;
+    let children = match fs::read_dir(&cache_dir) {
+        Ok(children) => children,
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut entries = vec![];
+    for child in children {
+        let path = child?.path();
+        if path.is_file_polyfill() {
+            continue;
+        }
+
+        let built = match fs::File::open(get_pkg_metadata_path(&path)) {
+            Ok(file) => platform::file_last_modified(&file),
+            Err(..) => {
+                info!("couldn't open metadata for {:?}", path);
+                continue;
+            }
+        };
+        if since.map_or(false, |t| built < t) || until.map_or(false, |t| built > t) {
+            continue;
+        }
+
+        let meta = get_pkg_metadata(&path);
+        let pinned = meta.as_ref().map_or(false, |m| m.pinned == Some(true));
+        let source = match meta {
+            Ok(PackageMetadata { path: Some(p), .. }) => p,
+            Ok(_)
+                if path
+                    .file_name()
+                    .map_or(false, |n| n.to_string_lossy().starts_with("content-")) =>
+            {
+                "<content-addressed>".into()
+            }
+            Ok(_) => "<expression>".into(),
+            Err(_) => "<unreadable metadata>".into(),
+        };
+        entries.push((built, source, path, pinned));
+    }
+    entries.sort();
+
+    for (built, source, path, pinned) in entries {
+        let built = time::at_utc(time::Timespec::new((built / 1000) as i64, 0));
+        let pinned = if pinned { "  (pinned)" } else { "" };
+        println!(
+            "{}  {}  {}{}",
+            built.rfc3339(),
+            source,
+            path.display(),
+            pinned
+        );
+    }
+    Ok(())
+}
+
+/**
+Works out the ID of an input's package in the cache, for anything other than a content-addressed package.  This is the input's own ID, plus whatever else goes into a build which differently-flagged runs would otherwise keep rebuilding over the top of each other for.
+*/
+fn cache_id(
+    input: &Input<'_>,
+    deps: &[(String, String)],
+    rustflags: Option<&str>,
+    profile: &[(&str, &str)],
+    deps_from: Option<&manifest::DepsFrom>,
+) -> Result<OsString> {
+    let mut id = input.compute_id(deps.iter().map(|(n, v)| (&**n, &**v)))?;
+    if let Some(flags) = rustflags {
+        id.push(format!("-rustflags-{}", rustflags_id(flags)));
+    }
+    // Likewise `--panic` and `--strip`; a stripped executable won't do for a run that didn't ask for one.
+    for (key, value) in profile {
+        id.push(format!("-{}-{}", key, value));
+    }
+    // The versions a dependency ends up with depend on the project, not just the script.
+    if let Some(deps_from) = deps_from {
+        id.push(format!("-deps-{}", &deps_from.digest[..8]));
+    }
+    Ok(id)
+}
+
+/**
+Works out where the package a plain run of a script would use is in the cache, for the subcommands which look after it.  These only ever have the script to go on, but `CARGO_SCRIPT_DEPS_FROM` (as `deps_from`) applies to them all the same.
+*/
+fn script_pkg_path(input: &Input<'_>, deps_from: Option<&manifest::DepsFrom>) -> Result<PathBuf> {
+    Ok(get_script_cache_path()?.join(cache_id(input, &[], None, &[], deps_from)?))
+}
 
-        if remove_dir() {
-            info!("removing {:?}", path);
-            if ALLOW_AUTO_REMOVE {
-                if let Err(err) = fs::remove_dir_all(&path) {
-                    error!("failed to remove {:?} from cache: {}", path, err);
-                }
-            } else {
-                info!("(suppressed remove)");
+/**
+Loads the project named by `CARGO_SCRIPT_DEPS_FROM`, if there is one, for the subcommands which don't take `--deps-from`.
+*/
+fn env_deps_from() -> Result<Option<manifest::DepsFrom>> {
+    match std::env::var_os(consts::DEPS_FROM_ENV).filter(|p| !p.is_empty()) {
+        Some(path) => Ok(Some(manifest::DepsFrom::load(Path::new(&path))?)),
+        None => Ok(None),
+    }
+}
+
+/**
+Finds every cache entry which belongs to the script at `script_path`, going by the path recorded in its metadata rather than its ID.  A script can have more than one: one for each set of flags which changes its ID, and any left over from before it was last changed.
+*/
+fn script_cache_entries(script_path: &Path) -> Result<Vec<(PathBuf, PackageMetadata)>> {
+    let children = match fs::read_dir(get_script_cache_path()?) {
+        Ok(children) => children,
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err.into()),
+    };
+    let script_path = script_path.to_string_lossy();
+    let mut entries = vec![];
+    for child in children {
+        let path = child?.path();
+        if let Ok(meta) = get_pkg_metadata(&path) {
+            if meta.path.as_deref() == Some(&*script_path) {
+                entries.push((path, meta));
             }
         }
     }
-    info!("done cleaning cache.");
-    Ok(())
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(entries)
+}
+
+/**
+Takes the pin from any of a script's cache entries which were built from what the script used to be, for its new entry (`pkg_path`), built from what it is now (`content_hash`).  The old entries are left to expire like any other, rather than being kept forever for a script which no longer exists.  Returns whether there was a pin to take.
+*/
+fn take_over_pin(pkg_path: &Path, script_path: &Path, content_hash: &str) -> Result<bool> {
+    let mut pinned = false;
+    for (path, mut meta) in script_cache_entries(script_path)? {
+        let replaced = meta.content_hash.as_deref() != Some(content_hash);
+        if path == pkg_path || !replaced || meta.pinned != Some(true) {
+            continue;
+        }
+        info!("moving pin from {:?} to {:?}", path, pkg_path);
+        meta.pinned = None;
+        write_pkg_metadata(&path, &meta)?;
+        pinned = true;
+    }
+    Ok(pinned)
 }
 
 /**
@@ -746,7 +3182,15 @@ Generate and compile a package from the input.
 
 Why take `PackageMetadata`?  To ensure that any information we need to depend on for compilation *first* passes through `decide_action_for` *and* is less likely to not be serialised with the rest of the metadata.
 */
-fn gen_pkg_and_compile(input: &Input<'_>, action: &InputAction) -> Result<()> {
+fn gen_pkg_and_compile(
+    input: &Input<'_>,
+    action: &InputAction,
+    keep: bool,
+    diagnostics: Option<&DiagnosticsFile>,
+    hints: bool,
+    limits: watchdog::Limits,
+    timings: &Timings,
+) -> Result<()> {
     let pkg_path = &action.pkg_path;
     let meta = &action.metadata;
     let old_meta = action.old_metadata.as_ref();
@@ -754,11 +3198,46 @@ fn gen_pkg_and_compile(input: &Input<'_>, action: &InputAction) -> Result<()> {
     let mani_str = &action.manifest;
     let script_str = &action.script;
 
+    let mani_path = action.manifest_path();
+    let mani_hash = old_meta.map(|m| &*m.manifest_hash);
+    let script_path = pkg_path.join(format!("{}.rs", input.safe_name()));
+    /*
+    There are times (particularly involving shared target dirs) where we can't rely on Cargo to correctly detect invalidated builds.  As such, if we've been told to *force* a recompile, we'll deliberately force the script to be overwritten, which will invalidate the timestamp, which will lead to a recompile.
+    */
+    let script_hash = if action.force_compile {
+        debug!("told to force compile, ignoring script hash");
+        None
+    } else {
+        old_meta.map(|m| &*m.script_hash)
+    };
+
+    /*
+    Someone poking at the generated package shouldn't have their changes quietly thrown away by the next run.  This has to happen before anything is written *or* cleaned up.  Metadata that isn't good enough to reuse the package by still says what was written last time.
+    */
+    if !action.discard_local_changes {
+        let stale_meta = match old_meta {
+            Some(_) => None,
+            None => get_pkg_metadata(pkg_path).ok(),
+        };
+        let written = old_meta.or(stale_meta.as_ref());
+        let mani_written = written.map(|m| &*m.manifest_hash);
+        let script_written = written.map(|m| &*m.script_hash);
+        check_unmodified(&mani_path, mani_str, mani_hash, mani_written)?;
+        check_unmodified(&script_path, script_str, script_hash, script_written)?;
+    }
+
     info!("creating pkg dir...");
     fs::create_dir_all(pkg_path)?;
+    if action.using_cache {
+        mark_cache_dir(&get_script_cache_path()?);
+    }
+    if action.use_bincache {
+        fs::create_dir_all(bincache_target_dir(meta)?)?;
+        mark_cache_dir(&get_binary_cache_path()?);
+    }
     let cleanup_dir: Defer<'_, _, MainError> = Defer::defer(|| {
         // DO NOT try deleting ANYTHING if we're not cleaning up inside our own cache.  We *DO NOT* want to risk killing user files.
-        if action.using_cache {
+        if action.using_cache && !keep {
             info!("cleaning up cache directory {:?}", pkg_path);
             if ALLOW_AUTO_REMOVE {
                 fs::remove_dir_all(pkg_path)?;
@@ -769,41 +3248,81 @@ fn gen_pkg_and_compile(input: &Input<'_>, action: &InputAction) -> Result<()> {
         Ok(())
     });
 
-    let mut meta = meta.clone();
+    // Cargo would only trip over an edition it doesn't know once it's asked to build, and not very clearly.
+    if action.compile || (action.execute && !action.build_kind.can_exec_directly()) {
+        check_edition_supported(mani_str)?;
+    }
 
-    info!("generating Cargo package...");
-    let mani_path = {
-        let mani_path = action.manifest_path();
-        let mani_hash = old_meta.map(|m| &*m.manifest_hash);
-        match overwrite_file(&mani_path, mani_str, mani_hash)? {
-            FileOverwrite::Same => (),
-            FileOverwrite::Changed { new_hash } => {
-                meta.manifest_hash = new_hash;
+    let mut meta = meta.clone();
+    meta.pinned = match old_meta
+        .cloned()
+        .or_else(|| get_pkg_metadata(pkg_path).ok())
+    {
+        Some(old_meta) => old_meta.pinned,
+        // A script which has been changed gets a new entry, which should still be pinned if the old one was.
+        None => match (
+            action.using_cache,
+            input.path(),
+            meta.content_hash.as_deref(),
+        ) {
+            (true, Some(script_path), Some(content_hash)) => {
+                match take_over_pin(pkg_path, script_path, content_hash) {
+                    Ok(true) => Some(true),
+                    Ok(false) => None,
+                    Err(err) => {
+                        info!("could not carry the script's pin over: {}", err);
+                        None
+                    }
+                }
             }
-        }
-        mani_path
+            _ => None,
+        },
     };
 
-    {
-        let script_path = pkg_path.join(format!("{}.rs", input.safe_name()));
-        /*
-        There are times (particularly involving shared target dirs) where we can't rely on Cargo to correctly detect invalidated builds.  As such, if we've been told to *force* a recompile, we'll deliberately force the script to be overwritten, which will invalidate the timestamp, which will lead to a recompile.
-        */
-        let script_hash = if action.force_compile {
-            debug!("told to force compile, ignoring script hash");
-            None
-        } else {
-            old_meta.map(|m| &*m.script_hash)
-        };
-        match overwrite_file(&script_path, script_str, script_hash)? {
-            FileOverwrite::Same => (),
-            FileOverwrite::Changed { new_hash } => {
-                meta.script_hash = new_hash;
-            }
+    /*
+    The metadata is what tells later runs that the package is up to date, so if we're about to rebuild, get rid of it first.  It only comes back once everything below has succeeded; that way, being interrupted part-way through can't leave behind a package that *looks* fresh.
+    */
+    if action.compile {
+        let meta_path = get_pkg_metadata_path(pkg_path);
+        match fs::remove_file(&meta_path) {
+            Ok(()) => info!("removed stale metadata {:?}", meta_path),
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err.into()),
+        }
+
+        // Expressions get a say in the matter before anything is written; see `PackageState`.
+        if input.path().is_none() {
+            let meta = PackageMetadata {
+                state: Some(PackageState::Generating),
+                ..meta.clone()
+            };
+            write_pkg_metadata(pkg_path, &meta)?;
+        }
+    }
+
+    info!("generating Cargo package...");
+    let manifest_timer = timings.scope("manifest");
+    match overwrite_file(&mani_path, mani_str, mani_hash)? {
+        FileOverwrite::Same => (),
+        FileOverwrite::Changed { new_hash } => {
+            meta.manifest_hash = new_hash;
+        }
+    }
+    match overwrite_file(&script_path, script_str, script_hash)? {
+        FileOverwrite::Same => (),
+        FileOverwrite::Changed { new_hash } => {
+            meta.script_hash = new_hash;
         }
     }
 
+    let old_files = old_meta.and_then(|m| m.files.as_deref()).unwrap_or(&[]);
+    write_pkg_files(pkg_path, &action.files, old_files)?;
+
+    if input.path().is_none() {
+        meta.state = Some(PackageState::Built);
+    }
     let meta = meta;
+    drop(manifest_timer);
 
     /*
     *bursts through wall* It's Cargo Time! (Possibly)
@@ -815,13 +3334,17 @@ fn gen_pkg_and_compile(input: &Input<'_>, action: &InputAction) -> Result<()> {
     As such, we want to ignore any compilation problems until *after* we've written the metadata and disarmed the cleanup callback.
     */
     if action.compile {
+        let _t = timings.scope("cargo");
+        if let Input::File(_, path, _, _) = *input {
+            if let Some(ws_mani) = manifest::find_enclosing_workspace(path) {
+                note!(
+                    "script is inside the workspace at `{}`, but will be built as a standalone package",
+                    ws_mani.display()
+                );
+            }
+        }
+
         info!("compiling...");
-        let mut cmd = cargo(
-            "build",
-            &*mani_path.to_string_lossy(),
-            action.use_bincache,
-            &meta,
-        )?;
 
         #[cfg(feature = "suppress-cargo-output")]
         macro_rules! get_status {
@@ -844,20 +3367,77 @@ fn gen_pkg_and_compile(input: &Input<'_>, action: &InputAction) -> Result<()> {
             };
         }
 
-        match get_status!(cmd)
-            .map_err(|e| Into::<MainError>::into(e))?
-            .code()
-        {
-            Some(0) => (),
-            Some(st) => return Err(format!("cargo failed with status {}", st).into()),
-            None => return Err("cargo failed".into()),
+        #[cfg(feature = "suppress-cargo-output")]
+        let quiet_for = Some(::std::time::Duration::from_millis(CARGO_OUTPUT_TIMEOUT));
+        #[cfg(not(feature = "suppress-cargo-output"))]
+        let quiet_for = None;
+
+        /*
+        With `--build-timeout` or `--build-retries`, Cargo's output is always taken in, since whether to try again depends on what it said.  Hints are held back until there's no trying again, so they're only given once.
+        */
+        let mut retries = 0;
+        let (status, output) = loop {
+            let mut cmd = cargo("build", &mani_path, action.use_bincache, &meta)?;
+            action.add_jobs(&mut cmd);
+            action.add_offline(&mut cmd);
+            logging::trace_command(&cmd);
+
+            let (status, output) = match diagnostics {
+                Some(diagnostics) => {
+                    let start = diagnostics.len();
+                    let status =
+                        watchdog::spawn(diagnostics.redirect(&mut cmd)?, limits.timeout)?.wait()?;
+                    (status, diagnostics.output_since(start))
+                }
+                None if hints || limits.any() => {
+                    hints::run_teed(&mut cmd, quiet_for, limits.timeout)?
+                }
+                None => (
+                    get_status!(cmd).map_err(|e| Into::<MainError>::into(e))?,
+                    String::new(),
+                ),
+            };
+            if status.success()
+                || retries == limits.retries
+                || !watchdog::looks_like_network_failure(&output)
+            {
+                break (status, output);
+            }
+            retries += 1;
+            let delay = watchdog::retry_delay(retries);
+            warning!(
+                "cargo couldn't reach the network; trying again in {}s ({} of {})",
+                delay.as_secs(),
+                retries,
+                limits.retries
+            );
+            std::thread::sleep(delay);
         };
+        let failed = match status.code() {
+            Some(0) => None,
+            Some(st) => Some(format!("cargo failed with status {}", st)),
+            None => Some("cargo failed".to_owned()),
+        };
+        if let Some(failed) = failed {
+            if hints {
+                hints::show_hints(&output);
+            }
+            return Err(match diagnostics {
+                Some(diagnostics) => format!(
+                    "{}; see `{}` for what it said",
+                    failed,
+                    diagnostics.path.display()
+                ),
+                None => failed,
+            }
+            .into());
+        }
 
         // Find out and cache what the executable was called.
-        let _ = cargo_target(
-            input,
+        let exe_path = cargo_target(
+            &action.bin_name,
             pkg_path,
-            &*mani_path.to_string_lossy(),
+            &mani_path,
             action.use_bincache,
             &meta,
         )?;
@@ -866,9 +3446,8 @@ fn gen_pkg_and_compile(input: &Input<'_>, action: &InputAction) -> Result<()> {
             // Write out the metadata hash to tie this executable to a particular chunk of metadata.  This is to avoid issues with multiple scripts with the same name being compiled to a common target directory.
             let meta_hash = action.metadata.sha1_hash();
             info!("writing meta hash: {:?}...", meta_hash);
-            let exe_meta_hash_path = get_meta_hash_path(action.use_bincache, pkg_path)?;
-            let mut f = fs::File::create(&exe_meta_hash_path)?;
-            write!(&mut f, "{}", meta_hash)?;
+            let exe_meta_hash_path = get_meta_hash_path(action.use_bincache, &exe_path)?;
+            write_file_atomic(&exe_meta_hash_path, meta_hash.as_bytes())?;
         }
     }
 
@@ -892,6 +3471,9 @@ struct InputAction {
     /// Compile the input into a fresh executable?
     compile: bool,
 
+    /// Why the input has to be compiled (or, for a check, checked), if it does.
+    compile_reason: Option<String>,
+
     /**
     Force Cargo to do a recompile, even if it thinks it doesn't have to.
 
@@ -899,6 +3481,9 @@ struct InputAction {
     */
     force_compile: bool,
 
+    /// Overwrite the generated manifest and source even if they've been changed by hand since they were written?  Only `--force` does this.
+    discard_local_changes: bool,
+
     /// Emit a metadata file?
     emit_metadata: bool,
 
@@ -930,8 +3515,32 @@ struct InputAction {
     /// The script source.
     script: String,
 
+    /// Other files to copy into the package.
+    files: Vec<manifest::PackageFile>,
+
+    /// The Cargo configuration files next to the script, outermost first.
+    script_config: Vec<script_config::ConfigFile>,
+
     /// Did the user ask to run tests or benchmarks?
     build_kind: BuildKind,
+
+    /// How many jobs Cargo should build with, if that's not left to Cargo.  This has no bearing on what gets built, so it isn't part of the metadata.
+    jobs: Option<i32>,
+
+    /// Keep Cargo off the network?  Like `jobs`, this isn't part of the metadata: a package built offline is no different from one that wasn't.
+    no_net: bool,
+
+    /// Is the script being built as a Criterion benchmark, rather than as a binary?
+    criterion_bench: bool,
+
+    /// The name of the generated package.
+    pkg_name: String,
+
+    /// The name of the binary target to run.
+    bin_name: String,
+
+    /// Things the user should be warned about.
+    warnings: Vec<String>,
 }
 
 impl InputAction {
@@ -940,12 +3549,30 @@ impl InputAction {
     }
 
     fn cargo(&self, cmd: &str) -> Result<Command> {
-        cargo(
+        let mut cargo_cmd = cargo(
             cmd,
-            &*self.manifest_path().to_string_lossy(),
+            &self.manifest_path(),
             self.use_bincache,
             &self.metadata,
-        )
+        )?;
+        // `tree` doesn't build anything, and won't take `--jobs`.
+        if cmd != "tree" {
+            self.add_jobs(&mut cargo_cmd);
+        }
+        self.add_offline(&mut cargo_cmd);
+        Ok(cargo_cmd)
+    }
+
+    fn add_jobs(&self, cmd: &mut Command) {
+        if let Some(jobs) = self.jobs {
+            cmd.arg("--jobs").arg(jobs.to_string());
+        }
+    }
+
+    fn add_offline(&self, cmd: &mut Command) {
+        if self.no_net {
+            cmd.arg("--offline");
+        }
     }
 }
 
@@ -960,36 +3587,390 @@ struct PackageMetadata {
     /// Path to the script file.
     path: Option<String>,
 
-    /// Last-modified timestamp for script file.
-    modified: Option<u64>,
+    /// Last-modified timestamp for script file.  This is recorded, but *not* used to decide whether the package is up to date; see `is_fresh_for`.
+    modified: Option<u64>,
+
+    /// Hash of the script file's contents.  `cargo script update` clears this, so that the script is built again against the dependencies it has just locked.
+    content_hash: Option<String>,
+
+    /// Identifier of the built-in templates at the time the package was generated.
+    templates: Option<String>,
+
+    /// Template used.
+    template: Option<String>,
+
+    /// Was the script compiled in debug mode?
+    debug: bool,
+
+    /// Sorted list of dependencies.
+    deps: Vec<(String, String)>,
+
+    /// Sorted list of injected prelude items.
+    prelude: Vec<String>,
+
+    /// Cargo features
+    features: Option<String>,
+
+    /// Values passed on to Cargo's `--config`.
+    cargo_config: Vec<String>,
+
+    /// Paths and hashes of the Cargo configuration files next to the script, which are passed on to Cargo's `--config` ahead of `cargo_config`.
+    script_config: Option<Vec<(String, String)>>,
+
+    /// Target triple, if not the host.
+    target: Option<String>,
+
+    /// Value given to `--rustflags`.
+    rustflags: Option<String>,
+
+    /// `RUSTFLAGS` from the environment, if `--rustflags` wasn't given.  Cargo rebuilds everything when these change, so it's worth saying that's why.
+    env_rustflags: Option<String>,
+
+    /// Profile settings from `--panic` and `--strip`, as the key and its value.
+    profile: Option<Vec<(String, String)>>,
+
+    /// Hash of the generated `Cargo.toml` file.
+    manifest_hash: String,
+
+    /// Hash of the generated source file.
+    script_hash: String,
+
+    /// Paths and hashes of any other files copied into the package, such as a build script.
+    files: Option<Vec<(String, String)>>,
+
+    /// Paths and hashes of the files pulled into the script with `//# include`.
+    includes: Option<Vec<(String, String)>>,
+
+    /// How far the package has got.  Only tracked for expressions and loops; see `PackageState`.
+    state: Option<PackageState>,
+
+    /// Set by `cargo script pin`, and kept across rebuilds, to keep the package from being cleaned out of the cache for its age.
+    pinned: Option<bool>,
+}
+
+impl PackageMetadata {
+    pub fn sha1_hash(&self) -> String {
+        // Yes, I *do* feel dirty for doing it like this.  :D
+        hash_str(&format!("{:?}", self.without_mtime_or_state()))
+    }
+
+    /**
+    Can a package built from this metadata be reused for `other`?
+
+    This compares everything *except* the modification time, the package's state, and whether it's pinned.  Timestamps can't be trusted: scripts on network filesystems or restored from backups can be dated years into the past or future, or not at all.  We've already read the script by the time we get here, so the content hashes decide it instead.
+    */
+    pub fn is_fresh_for(&self, other: &PackageMetadata) -> bool {
+        self.without_mtime_or_state() == other.without_mtime_or_state()
+    }
+
+    /**
+    Describes what is different about `other`, for explaining why a package has to be rebuilt.
+
+    The hashes of the generated files change whenever anything else does, so they're only mentioned if there's nothing more specific to say.
+    */
+    pub fn differences(&self, other: &PackageMetadata) -> Vec<&'static str> {
+        let mut diffs = vec![];
+        match (&self.content_hash, &other.content_hash) {
+            (None, Some(_)) => diffs.push("dependencies were updated"),
+            (old, new) if old != new => diffs.push("script changed"),
+            _ => (),
+        }
+        if self.templates != other.templates || self.template != other.template {
+            diffs.push("template changed");
+        }
+        if self.debug != other.debug {
+            diffs.push("debug setting differs");
+        }
+        if self.deps != other.deps {
+            diffs.push("dependencies differ");
+        }
+        if self.prelude != other.prelude {
+            diffs.push("prelude differs");
+        }
+        if self.features != other.features {
+            diffs.push("features differ");
+        }
+        if self.cargo_config != other.cargo_config {
+            diffs.push("Cargo config differs");
+        }
+        if self.script_config != other.script_config {
+            diffs.push("Cargo configuration next to the script changed");
+        }
+        if self.target != other.target {
+            diffs.push("target differs");
+        }
+        if self.rustflags != other.rustflags {
+            diffs.push("`--rustflags` differ");
+        }
+        if self.env_rustflags != other.env_rustflags {
+            diffs.push("RUSTFLAGS in the environment differ");
+        }
+        if self.profile != other.profile {
+            diffs.push("`--panic` or `--strip` differ");
+        }
+        if self.files != other.files {
+            diffs.push("build script or included files changed");
+        }
+        if self.includes != other.includes {
+            diffs.push("files pulled in with `//# include` changed");
+        }
+        if diffs.is_empty() {
+            if self.manifest_hash != other.manifest_hash {
+                diffs.push("manifest changed");
+            }
+            if self.script_hash != other.script_hash {
+                diffs.push("generated source changed");
+            }
+        }
+        if diffs.is_empty() {
+            diffs.push("metadata did not match");
+        }
+        diffs
+    }
+
+    fn without_mtime_or_state(&self) -> PackageMetadata {
+        PackageMetadata {
+            modified: None,
+            state: None,
+            pinned: None,
+            ..self.clone()
+        }
+    }
+}
+
+/**
+How far an expression's (or loop's) package has got.
+
+Expression packages are throwaways with nobody's name on them, so it matters more that a half-finished one doesn't hang about confusing later runs.  Cleaning up as we go can't be relied on for that: being killed by Ctrl+C doesn't give us the chance.  Instead, the state is written to the metadata *before* anything is generated, and a package which is found still `Generating` on a later run is thrown away and started again.
+*/
+#[derive(Clone, Copy, Debug, Eq, PartialEq, RustcDecodable, RustcEncodable)]
+enum PackageState {
+    /// The package is being generated and compiled, or was interrupted while it was.
+    Generating,
+    /// The package was compiled successfully.
+    Built,
+    /// The compiled executable has been run.
+    Ran,
+}
+
+/**
+Adds the lint levels from `--allow`, `--warn` and `--deny` to the flags from `--rustflags`.  Without `--rustflags`, they're added to `RUSTFLAGS` from the environment instead, so they don't throw those away.
+
+Lint names aren't checked beyond making sure they're a single word that rustc will see as such; rustc can complain about any it doesn't know.
+*/
+fn with_lint_levels(
+    rustflags: Option<String>,
+    lint_levels: &[(&'static str, String)],
+) -> Result<Option<String>> {
+    if lint_levels.is_empty() {
+        return Ok(rustflags);
+    }
+    let mut flags = rustflags
+        .or_else(|| std::env::var("RUSTFLAGS").ok())
+        .unwrap_or_default();
+    for &(flag, ref lint) in lint_levels {
+        if lint.is_empty()
+            || !lint
+                .chars()
+                .all(|c| c.is_alphanumeric() || "_-:".contains(c))
+        {
+            return Err(MainError::OtherOwned(
+                Blame::Human,
+                format!("`{}` is not a lint name", lint),
+            ));
+        }
+        if !flags.trim().is_empty() {
+            flags.push(' ');
+        }
+        flags.push_str(&format!("{} {}", flag, lint));
+    }
+    Ok(Some(flags.trim_start().into()))
+}
+
+#[test]
+fn test_with_lint_levels() {
+    let levels = [
+        ("-A", "dead_code".to_string()),
+        ("-D", "clippy::all".to_string()),
+    ];
+
+    assert_eq!(
+        with_lint_levels(Some("-g".into()), &[]).unwrap(),
+        Some("-g".into())
+    );
+    assert_eq!(
+        with_lint_levels(Some("--cfg a".into()), &levels).unwrap(),
+        Some("--cfg a -A dead_code -D clippy::all".into())
+    );
+    assert_eq!(
+        with_lint_levels(Some(String::new()), &levels[1..]).unwrap(),
+        Some("-D clippy::all".into())
+    );
+    assert!(with_lint_levels(Some(String::new()), &[("-D", "unsafe code".into())]).is_err());
+    assert!(with_lint_levels(Some(String::new()), &[("-D", "".into())]).is_err());
+}
+
+/**
+Adds manifest keys for the profile settings from `--panic` and `--strip` to those from `--manifest-key`.  They go first, so an explicit `--manifest-key` still wins.
+
+Tests and benchmarks are always built to unwind, whatever the profile says, so asking them to abort is an error rather than something to quietly ignore.
+*/
+fn with_profile_settings(
+    manifest_keys: &[String],
+    settings: &[(&str, &str)],
+    build_kind: BuildKind,
+) -> Result<Vec<String>> {
+    let mut keys = vec![];
+    for &(key, value) in settings {
+        match (key, value, build_kind) {
+            ("panic", "abort", BuildKind::Test) | ("panic", "abort", BuildKind::Bench) => {
+                return Err((
+                    Blame::Human,
+                    "`--panic abort` can't be used with `--test` or `--bench`; \
+                     the test harness needs panics to unwind",
+                )
+                    .into());
+            }
+            _ => (),
+        }
+        for profile in &["dev", "release"] {
+            keys.push(format!("profile.{}.{}={}", profile, key, value));
+        }
+    }
+    keys.extend_from_slice(manifest_keys);
+    Ok(keys)
+}
+
+#[test]
+fn test_with_profile_settings() {
+    let keys = vec!["profile.release.lto=true".to_string()];
+
+    assert_eq!(
+        with_profile_settings(&keys, &[], BuildKind::Test).unwrap(),
+        keys
+    );
+    assert_eq!(
+        with_profile_settings(&keys, &[("panic", "abort")], BuildKind::Normal).unwrap(),
+        vec![
+            "profile.dev.panic=abort",
+            "profile.release.panic=abort",
+            "profile.release.lto=true",
+        ]
+    );
+    assert_eq!(
+        with_profile_settings(
+            &[],
+            &[("panic", "unwind"), ("strip", "symbols")],
+            BuildKind::Test
+        )
+        .unwrap(),
+        vec![
+            "profile.dev.panic=unwind",
+            "profile.release.panic=unwind",
+            "profile.dev.strip=symbols",
+            "profile.release.strip=symbols",
+        ]
+    );
+    assert!(with_profile_settings(&[], &[("panic", "abort")], BuildKind::Test).is_err());
+    assert!(with_profile_settings(&[], &[("panic", "abort")], BuildKind::Bench).is_err());
+}
+
+/**
+Works out the absolute path to a package directory given with `--pkg-path`, resolving symlinks the same way as for the script's own path.  The directory needn't exist yet.
+*/
+fn absolute_pkg_path(pkg_path: &Path) -> Result<PathBuf> {
+    let abs_path = std::env::current_dir()?.join(pkg_path);
+    let resolved = abs_path.canonicalize().ok().or_else(|| {
+        let parent = abs_path.parent()?.canonicalize().ok()?;
+        Some(parent.join(abs_path.file_name()?))
+    });
+    Ok(platform::simplify_path(&resolved.unwrap_or(abs_path)))
+}
+
+/**
+Everything about a run, besides the input itself, that goes into deciding what to do with it.
+*/
+struct ActionOptions<'a> {
+    /// Dependencies from the command line, as `(name, version)`.
+    deps: Vec<(String, String)>,
+
+    /// Manifest keys from `--manifest-key`.
+    manifest_keys: &'a [String],
+
+    /// A manifest to merge over the script's own, from `--manifest-from-stdin`.
+    manifest_override: Option<&'a str>,
+
+    /// The project to take dependency versions from, for `--deps-from`.
+    deps_from: Option<&'a manifest::DepsFrom>,
+
+    /// The directory of the script bundle the input is the main file of, if it is one.
+    bundle: Option<&'a Path>,
+
+    /// Items to inject into the script's prelude.
+    prelude: Vec<String>,
+
+    /// Build in debug mode?  `None` leaves it to the script's manifest.
+    debug: Option<bool>,
+
+    /// Where to put the package, instead of the cache.
+    pkg_path: Option<PathBuf>,
+
+    /// Only generate the package, without building or running it?
+    gen_pkg_only: bool,
+
+    /// Only look at the package, without changing anything, as for `which` and `--hash-only`?
+    look_only: bool,
+
+    /// Build the package, but don't run it?
+    build_only: bool,
+
+    /// Rebuild even if the package looks up to date?
+    force: bool,
+
+    /// Cargo features to build with.
+    features: Option<String>,
+
+    /// Settings to pass on to Cargo with `--config`.
+    cargo_config: Vec<String>,
+
+    /// How many jobs Cargo builds with.
+    jobs: Option<i32>,
+
+    /// Keep Cargo off the network?
+    no_net: bool,
 
-    /// Template used.
-    template: Option<String>,
+    /// The target triple to build for.
+    target: Option<String>,
 
-    /// Was the script compiled in debug mode?
-    debug: bool,
+    /// Flags for rustc, including any lint levels.
+    rustflags: Option<String>,
 
-    /// Sorted list of dependencies.
-    deps: Vec<(String, String)>,
+    /// Profile settings, from `--panic` and `--strip`.
+    profile: &'a [(&'a str, &'a str)],
 
-    /// Sorted list of injected prelude items.
-    prelude: Vec<String>,
+    /// Build as a GUI program, without a console window on Windows?
+    gui: bool,
 
-    /// Cargo features
-    features: Option<String>,
+    /// Use the shared binary cache?  `None` leaves it to the default.
+    use_bincache: Option<bool>,
 
-    /// Hash of the generated `Cargo.toml` file.
-    manifest_hash: String,
+    /// What kind of build this is.
+    build_kind: BuildKind,
 
-    /// Hash of the generated source file.
-    script_hash: String,
-}
+    /// Run the script as a test module?
+    test_module: bool,
 
-impl PackageMetadata {
-    pub fn sha1_hash(&self) -> String {
-        // Yes, I *do* feel dirty for doing it like this.  :D
-        hash_str(&format!("{:?}", self))
-    }
+    /// The package's name, from `--name`.
+    pkg_name: Option<&'a str>,
+
+    /// The binary target to run, from `--bin`.
+    bin: Option<&'a str>,
+
+    /// Key the package by what's in it, rather than by the script?
+    content_addressed: bool,
+
+    /// Only say what would be done?
+    dry_run: bool,
 }
 
 /**
@@ -997,72 +3978,257 @@ For the given input, this constructs the package metadata and checks the cache t
 */
 fn decide_action_for(
     input: &Input<'_>,
-    deps: Vec<(String, String)>,
-    prelude: Vec<String>,
-    debug: bool,
-    pkg_path: Option<String>,
-    gen_pkg_only: bool,
-    build_only: bool,
-    force: bool,
-    features: Option<String>,
-    use_bincache: Option<bool>,
-    build_kind: BuildKind,
+    options: ActionOptions<'_>,
+    timings: &Timings,
 ) -> Result<InputAction> {
-    let (pkg_path, using_cache) = pkg_path.map(|p| (p.into(), false)).unwrap_or_else(|| {
-        // This can't fail.  Seriously, we're *fucked* if we can't work this out.
-        let cache_path = get_script_cache_path().unwrap();
-        info!("cache_path: {:?}", cache_path);
+    let ActionOptions {
+        deps,
+        manifest_keys,
+        manifest_override,
+        deps_from,
+        bundle,
+        prelude,
+        debug,
+        pkg_path,
+        gen_pkg_only,
+        look_only,
+        build_only,
+        force,
+        features,
+        cargo_config,
+        jobs,
+        no_net,
+        target,
+        rustflags,
+        profile,
+        gui,
+        use_bincache,
+        build_kind,
+        test_module,
+        pkg_name,
+        bin,
+        content_addressed,
+        dry_run,
+    } = options;
 
-        let id = {
-            let deps_iter = deps.iter().map(|&(ref n, ref v)| (n as &str, v as &str));
+    info!("splitting input...");
+    let manifest_timer = timings.scope("manifest");
+    let split = manifest::split_input(
+        input,
+        &manifest::SplitOptions {
+            deps: &deps,
+            manifest_keys,
+            prelude_items: &prelude,
+            test_module,
+            pkg_name,
+            bin,
+            manifest_override,
+            deps_from,
+        },
+    )?;
+    let (mut mani_str, mut script_str, mut files) = (split.manifest, split.script, split.files);
+
+    // Criterion benchmarks have to be built as benchmarks; otherwise, it's down to libtest's `#[bench]`.
+    let criterion_bench = match build_kind {
+        BuildKind::Bench if manifest::is_criterion_bench(&mani_str, &script_str) => {
+            let (bench_mani, lib) = manifest::bench_manifest(&mani_str, &split.bin_name)?;
+            mani_str = bench_mani;
+            files.extend(lib);
+            true
+        }
+        BuildKind::Bench => {
+            let rustc = tool_version("rustc").err_tag("could not work out the toolchain")?;
+            if !rustc.contains("-nightly") && !rustc.contains("-dev") {
+                return Err((
+                    Blame::Human,
+                    "`#[bench]` functions need a nightly toolchain (try `cargo +nightly script --bench`), \
+                     unless the script uses Criterion",
+                )
+                    .into());
+            }
+            false
+        }
+        _ => false,
+    };
 
-            // Again, also fucked if we can't work this out.
-            input.compute_id(deps_iter).unwrap()
-        };
-        info!("id: {:?}", id);
+    // Everything else in a script bundle goes along with its main file, so changing any of it means a rebuild.
+    add_bundle_files(input, bundle, &mut mani_str, &mut files)?;
+
+    let files_meta = match files.is_empty() {
+        true => None,
+        false => {
+            let mut meta: Vec<_> = files
+                .iter()
+                .map(|f| (f.path.clone(), hash_bytes(&f.content)))
+                .collect();
+            meta.sort();
+            Some(meta)
+        }
+    };
 
-        (cache_path.join(&id), true)
-    });
-    info!("pkg_path: {:?}", pkg_path);
-    info!("using_cache: {:?}", using_cache);
+    let includes_meta = match split.includes.is_empty() {
+        true => None,
+        false => Some(
+            split
+                .includes
+                .iter()
+                .map(|(path, content)| (path.to_string_lossy().into_owned(), hash_str(content)))
+                .collect::<Vec<_>>(),
+        ),
+    };
 
-    info!("splitting input...");
-    let (mani_str, script_str) = manifest::split_input(input, &deps, &prelude)?;
+    let script_config = match *input {
+        Input::File(_, path, ..) => script_config::find(path)?,
+        _ => vec![],
+    };
+    let script_config_meta = match script_config.is_empty() {
+        true => None,
+        false => Some(
+            script_config
+                .iter()
+                .map(|f| (f.path.to_string_lossy().into_owned(), hash_str(&f.content)))
+                .collect::<Vec<_>>(),
+        ),
+    };
+
+    // The script's own defaults only apply where the command line didn't say.
+    let debug_flag = debug;
+    let debug = debug.or(split.defaults.debug).unwrap_or(false);
+    let features = features.or(split.defaults.features);
+
+    // Only Windows has a console subsystem to get out of.
+    let builds_for_windows = match target {
+        Some(ref t) => t.contains("-windows-"),
+        None => cfg!(windows),
+    };
+    if (gui || split.defaults.gui == Some(true)) && builds_for_windows {
+        script_str = manifest::with_windows_subsystem(&script_str);
+    }
+
+    let mut warnings = split.warnings;
+    for file in script_config.iter().filter(|f| f.sets_target_dir()) {
+        warnings.push(format!(
+            "ignoring `build.target-dir` in `{}`; cargo script needs to know where the executable is built",
+            file.path.display()
+        ));
+    }
+    if let (Input::File(_, _, content, _), BuildKind::Normal) = (input, build_kind) {
+        if manifest::looks_like_test_module(content) {
+            warnings.push(
+                "script has no `main` function, but does have tests; \
+                 use `--run-as-test-module` to run them"
+                    .into(),
+            );
+        }
+    }
 
     // Forcibly override some flags based on build kind.
     let (debug, force, build_only) = match build_kind {
         BuildKind::Normal => (debug, force, build_only),
-        BuildKind::Test => (true, false, false),
+        BuildKind::Test => (debug_flag.unwrap_or(true), false, false),
         BuildKind::Bench => (false, false, false),
+        BuildKind::Check => (debug, force, false),
+        BuildKind::Tree | BuildKind::Doc => (debug, false, false),
+    };
+
+    let (pkg_path, using_cache) = match pkg_path {
+        Some(p) => (p, false),
+        None => {
+            // This can't fail.  Seriously, we're *fucked* if we can't work this out.
+            let cache_path = get_script_cache_path().unwrap();
+            info!("cache_path: {:?}", cache_path);
+
+            let id = if content_addressed {
+                let toolchain = tool_version("rustc")
+                    .err_tag("could not work out the toolchain for a content-addressed package")?;
+                let flags = format!(
+                    "debug={:?};features={:?};config={:?};script_config={:?};target={:?};rustflags={:?};kind={:?};files={:?}",
+                    debug, features, cargo_config, script_config_meta, target, rustflags, build_kind, files_meta
+                );
+                input.compute_content_id(&mani_str, &script_str, &toolchain, &flags)
+            } else {
+                // Again, also fucked if we can't work this out.
+                let _t = timings.scope("read");
+                cache_id(input, &deps, rustflags.as_deref(), profile, deps_from).unwrap()
+            };
+            info!("id: {:?}", id);
+
+            (cache_path.join(&id), true)
+        }
+    };
+    info!("pkg_path: {:?}", pkg_path);
+    info!("using_cache: {:?}", using_cache);
+
+    // Packages outside the cache might be checked in or compared, so they shouldn't depend on where they were generated.
+    let mani_str = match using_cache {
+        true => mani_str,
+        false => manifest::relative_manifest_paths(&mani_str, &absolute_pkg_path(&pkg_path)?)?,
     };
 
     // Construct input metadata.
     let input_meta = {
-        let (path, mtime, template) = match *input {
-            Input::File(_, path, _, mtime) => {
-                (Some(path.to_string_lossy().into_owned()), Some(mtime), None)
-            }
-            Input::Expr(_, template) => (None, None, template),
-            Input::Loop(..) => (None, None, None),
+        let (path, mtime, content_hash, template) = match *input {
+            // A content-addressed package doesn't belong to any one script.
+            Input::File(..) if content_addressed => (None, None, None, None),
+            Input::File(_, path, content, mtime) => (
+                Some(path.to_string_lossy().into_owned()),
+                Some(mtime),
+                Some(hash_str(content)),
+                None,
+            ),
+            Input::Expr(_, template, _) => (None, None, None, template),
+            Input::Loop(..) => (None, None, None, None),
         };
         PackageMetadata {
             path: path,
             modified: mtime,
+            content_hash,
+            templates: Some(templates::builtin_id()),
             template: template.map(Into::into),
             debug: debug,
             deps: deps,
             prelude: prelude,
             features: features,
+            cargo_config,
+            script_config: script_config_meta,
+            target,
+            env_rustflags: match rustflags {
+                Some(_) => None,
+                None => std::env::var("RUSTFLAGS")
+                    .ok()
+                    .filter(|flags| !flags.is_empty()),
+            },
+            rustflags,
+            profile: match profile.is_empty() {
+                true => None,
+                false => Some(
+                    profile
+                        .iter()
+                        .map(|&(key, value)| (key.into(), value.into()))
+                        .collect(),
+                ),
+            },
             manifest_hash: hash_str(&mani_str),
             script_hash: hash_str(&script_str),
+            files: files_meta,
+            includes: includes_meta,
+            state: None,
+            pinned: None,
         }
     };
     info!("input_meta: {:?}", input_meta);
+    drop(manifest_timer);
+    let _t = timings.scope("cache");
 
     // Lazy powers, ACTIVATE!
     let mut action = InputAction {
         compile: force,
+        compile_reason: match force {
+            true => Some("forced with `--force`".into()),
+            false => None,
+        },
         force_compile: force,
+        discard_local_changes: force,
         emit_metadata: true,
         execute: !build_only,
         pkg_path: pkg_path,
@@ -1072,7 +4238,15 @@ fn decide_action_for(
         old_metadata: None,
         manifest: mani_str,
         script: script_str,
+        files,
+        script_config,
         build_kind: build_kind,
+        jobs,
+        no_net,
+        pkg_name: split.pkg_name,
+        bin_name: split.bin_name,
+        criterion_bench,
+        warnings,
     };
 
     macro_rules! bail {
@@ -1084,9 +4258,22 @@ fn decide_action_for(
         }
     }
 
-    // If we were told to only generate the package, we need to stop *now*
+    if force {
+        info!("recompiling because: forced with `--force`");
+    }
+
+    // Being forced to recompile trumps any other reason there might be.
+    fn because(action: &InputAction, reason: String) -> Option<String> {
+        action.compile_reason.clone().or(Some(reason))
+    }
+
+    // If we were told to only generate the package, we need to stop *now*.  What was generated last time is still worth knowing, so that anything unchanged is left alone.  That doesn't apply to `which` or `--hash-only`, which only want to know where things go, and leave the cache well alone.
     if gen_pkg_only {
-        bail!(compile: false, execute: false)
+        let old_metadata = match look_only {
+            true => None,
+            false => load_cache_entry(input, &action.pkg_path, false).ok(),
+        };
+        bail!(compile: false, execute: false, old_metadata: old_metadata)
     }
 
     // If we're not doing a regular build, stop.
@@ -1096,22 +4283,53 @@ fn decide_action_for(
             info!("not recompiling because: user asked for test/bench");
             bail!(compile: false, force_compile: false)
         }
+        BuildKind::Check => {
+            /*
+            A check does not produce an executable, so we *must not* write out metadata: if we did, a later run could conclude that a stale executable was up to date.  Successful checks are instead recorded separately.
+            */
+            info!("not recompiling because: user asked for check");
+            let meta_hash = action.metadata.sha1_hash();
+            let checked = !force && get_check_hash(&action.pkg_path).as_ref() == Some(&meta_hash);
+            if checked {
+                info!("not checking because: check hash matches");
+            }
+            let compile_reason = match (checked, action.compile_reason) {
+                (true, _) => None,
+                (false, Some(reason)) => Some(reason),
+                (false, None) => Some("no check has passed since the script last changed".into()),
+            };
+            bail!(compile: false, compile_reason: compile_reason, force_compile: false, emit_metadata: false, execute: !checked)
+        }
+        BuildKind::Tree | BuildKind::Doc => {
+            // As with a check, there's no executable for metadata to vouch for.
+            info!("not recompiling because: user asked for dependency tree or docs");
+            bail!(compile: false, force_compile: false, emit_metadata: false)
+        }
     }
 
-    let cache_meta = match get_pkg_metadata(&action.pkg_path) {
+    // A dry run mustn't change anything, so it leaves broken packages where they are.
+    let may_remove = action.using_cache && !dry_run;
+    let cache_meta = match load_cache_entry(input, &action.pkg_path, may_remove) {
         Ok(meta) => meta,
-        Err(err) => {
-            info!("recompiling because: failed to load metadata");
-            debug!("get_pkg_metadata error: {}", err.description());
-            bail!(compile: true)
+        Err(reason) => {
+            info!("recompiling because: {}", reason);
+            bail!(compile: true, compile_reason: because(&action, reason))
         }
     };
 
-    if cache_meta != action.metadata {
-        info!("recompiling because: metadata did not match");
+    if cache_meta.path != action.metadata.path {
+        info!("recompiling because: package was generated for a different script");
+        debug!("input path: {:?}", action.metadata.path);
+        debug!("cache path: {:?}", cache_meta.path);
+        bail!(compile: true, compile_reason: because(&action, "package was generated for a different script".into()))
+    }
+
+    if !cache_meta.is_fresh_for(&action.metadata) {
+        let reason = cache_meta.differences(&action.metadata).join(", ");
+        info!("recompiling because: {}", reason);
         debug!("input metadata: {:?}", action.metadata);
         debug!("cache metadata: {:?}", cache_meta);
-        bail!(old_metadata: Some(cache_meta), compile: true)
+        bail!(old_metadata: Some(cache_meta), compile: true, compile_reason: because(&action, reason))
     }
 
     action.old_metadata = Some(cache_meta);
@@ -1119,14 +4337,13 @@ fn decide_action_for(
     /*
     Next test: does the executable exist at all?
     */
-    let exe_exists = match get_exe_path(action.build_kind, &action.pkg_path) {
-        Ok(exe_path) => exe_path.is_file_polyfill(),
-        Err(_) => false,
+    let exe_path = match get_exe_path(action.build_kind, &action.pkg_path) {
+        Ok(ref exe_path) if exe_path.is_file_polyfill() => exe_path.clone(),
+        _ => {
+            info!("recompiling because: executable doesn't exist or isn't a file");
+            bail!(compile: true, compile_reason: because(&action, "executable is missing".into()))
+        }
     };
-    if !exe_exists {
-        info!("recompiling because: executable doesn't exist or isn't a file");
-        bail!(compile: true)
-    }
 
     /*
     Finally: check to see if `{exe_path}.meta-hash` exists and contains a hash that matches the metadata.  Yes, this is somewhat round-about, but we need to do this to account for cases where Cargo's target directory has been set to a fixed, shared location.
@@ -1134,25 +4351,24 @@ fn decide_action_for(
     Note that we *do not* do this if we aren't using the cache.
     */
     if action.use_bincache {
-        let exe_meta_hash_path = get_meta_hash_path(action.use_bincache, &action.pkg_path).unwrap();
+        let exe_meta_hash_path = get_meta_hash_path(action.use_bincache, &exe_path).unwrap();
         if !exe_meta_hash_path.is_file_polyfill() {
             info!("recompiling because: meta hash doesn't exist or isn't a file");
-            bail!(compile: true, force_compile: true)
+            bail!(compile: true, compile_reason: because(&action, "executable's metadata hash is missing".into()), force_compile: true)
         }
-        let exe_meta_hash = {
-            let mut f = fs::File::open(&exe_meta_hash_path)?;
-            let mut s = String::new();
-            f.read_to_string(&mut s)?;
-            s
-        };
+        trace!("reading {:?}", exe_meta_hash_path);
+        let exe_meta_hash = fs::read_to_string(&exe_meta_hash_path).ok();
         let meta_hash = action.metadata.sha1_hash();
-        if meta_hash != exe_meta_hash {
+        if Some(meta_hash) != exe_meta_hash {
             info!("recompiling because: meta hash doesn't match");
-            bail!(compile: true, force_compile: true)
+            bail!(compile: true, compile_reason: because(&action, "executable was built for different metadata".into()), force_compile: true)
         }
     }
 
     // That's enough; let's just go with it.
+    if !action.compile {
+        info!("cache hit: package and executable are up to date");
+    }
     Ok(action)
 }
 
@@ -1170,8 +4386,12 @@ where
     // We don't directly run tests and benchmarks.
     match build_kind {
         BuildKind::Normal => (),
-        BuildKind::Test | BuildKind::Bench => {
-            return Err("tried to get executable path for test/bench build".into());
+        BuildKind::Test
+        | BuildKind::Bench
+        | BuildKind::Check
+        | BuildKind::Tree
+        | BuildKind::Doc => {
+            return Err("tried to get executable path for test/bench/check/tree/doc build".into());
         }
     }
 
@@ -1186,15 +4406,60 @@ where
 
 /**
 Figures out where the `meta-hash` file should be.
+
+This lives next to the executable rather than in the package, since the binary cache is shared: two packages which build executables with the same name will overwrite each other's, and the hash is how we tell whose executable is currently there.
 */
-fn get_meta_hash_path<P>(use_bincache: bool, pkg_path: P) -> Result<PathBuf>
+fn get_meta_hash_path<P>(use_bincache: bool, exe_path: P) -> Result<PathBuf>
 where
     P: AsRef<Path>,
 {
     if !use_bincache {
         panic!("tried to get meta-hash path when not using binary cache");
     }
-    Ok(pkg_path.as_ref().join("target.meta-hash"))
+    let mut path = exe_path.as_ref().as_os_str().to_owned();
+    path.push(".meta-hash");
+    Ok(path.into())
+}
+
+/**
+Figures out where the `check-hash` file should be.
+
+This records the metadata hash of the last package which successfully passed `cargo check`.
+*/
+fn get_check_hash_path<P>(pkg_path: P) -> PathBuf
+where
+    P: AsRef<Path>,
+{
+    pkg_path.as_ref().join("target.check-hash")
+}
+
+/**
+Load the hash of the last successfully checked metadata, if there is one.
+*/
+fn get_check_hash<P>(pkg_path: P) -> Option<String>
+where
+    P: AsRef<Path>,
+{
+    let check_hash_path = get_check_hash_path(pkg_path);
+    trace!("reading {:?}", check_hash_path);
+    let mut s = String::new();
+    fs::File::open(check_hash_path)
+        .and_then(|mut f| f.read_to_string(&mut s))
+        .ok()
+        .map(|_| s)
+}
+
+/**
+Record that the given metadata successfully passed `cargo check`.
+*/
+fn write_check_hash<P>(pkg_path: P, meta: &PackageMetadata) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let meta_hash = meta.sha1_hash();
+    info!("writing check hash: {:?}...", meta_hash);
+    write_file_atomic(get_check_hash_path(pkg_path), meta_hash.as_bytes())?;
+    Ok(())
 }
 
 /**
@@ -1206,11 +4471,12 @@ where
 {
     let meta_path = get_pkg_metadata_path(pkg_path);
     debug!("meta_path: {:?}", meta_path);
+    trace!("reading {:?}", meta_path);
     let mut meta_file = fs::File::open(&meta_path)?;
 
     let meta_str = {
         let mut s = String::new();
-        meta_file.read_to_string(&mut s).unwrap();
+        meta_file.read_to_string(&mut s)?;
         s
     };
     let meta: PackageMetadata =
@@ -1219,6 +4485,70 @@ where
     Ok(meta)
 }
 
+/**
+Load the metadata for an existing package, provided the package is actually usable.
+
+If there's no metadata, or the metadata can't be loaded, or the package was interrupted while being built, or the package is missing files we skip regenerating when the metadata matches, this returns why: a broken package is treated the same as no package at all.  If `may_remove` is set, a broken package is also removed so that it gets rebuilt from scratch; this should only be done inside the cache.
+*/
+fn load_cache_entry(
+    input: &Input<'_>,
+    pkg_path: &Path,
+    may_remove: bool,
+) -> std::result::Result<PackageMetadata, String> {
+    let meta_path = get_pkg_metadata_path(pkg_path);
+    if !meta_path.exists() {
+        info!("no metadata at {:?}", meta_path);
+        return Err("metadata missing".into());
+    }
+
+    let problem = match get_pkg_metadata(pkg_path) {
+        Err(err) => format!("could not load metadata: {}", err),
+        Ok(PackageMetadata {
+            state: Some(PackageState::Generating),
+            ..
+        }) => "it was interrupted while being built".into(),
+        Ok(meta) => {
+            let script_name = format!("{}.rs", input.safe_name());
+            match ["Cargo.toml", &*script_name]
+                .iter()
+                .find(|name| !pkg_path.join(name).is_file_polyfill())
+            {
+                Some(name) => format!("`{}` is missing", name),
+                None => return Ok(meta),
+            }
+        }
+    };
+    let reason = format!("package is broken ({})", problem);
+
+    if !may_remove {
+        info!("ignoring broken package {:?}: {}", pkg_path, problem);
+        return Err(reason);
+    }
+
+    note!(
+        "cache entry {} is broken ({}); rebuilding it",
+        pkg_path.display(),
+        problem
+    );
+    if ALLOW_AUTO_REMOVE {
+        if let Err(err) = fs::remove_dir_all(pkg_path) {
+            error!("failed to remove {:?} from cache: {}", pkg_path, err);
+        }
+    } else {
+        info!("(suppressed remove)");
+    }
+    Err(reason)
+}
+
+/**
+Records that an expression's package has been run.
+*/
+fn mark_pkg_ran(pkg_path: &Path) -> Result<()> {
+    let mut meta = get_pkg_metadata(pkg_path)?;
+    meta.state = Some(PackageState::Ran);
+    write_pkg_metadata(pkg_path, &meta)
+}
+
 /**
 Work out the path to a package's metadata file.
 */
@@ -1238,10 +4568,19 @@ where
 {
     let meta_path = get_pkg_metadata_path(pkg_path);
     debug!("meta_path: {:?}", meta_path);
-    let mut meta_file = fs::File::create(&meta_path)?;
     let meta_str = rustc_serialize::json::encode(meta).map_err(|err| err.to_string())?;
-    write!(&mut meta_file, "{}", meta_str)?;
-    meta_file.flush()?;
+    write_file_atomic(&meta_path, meta_str.as_bytes())?;
+    Ok(())
+}
+
+/**
+Makes sure the script and binary caches can be written to, creating them if need be.  If one can't, this says which, and why.
+*/
+fn check_cache_writable() -> std::result::Result<(), (PathBuf, std::io::Error)> {
+    let dirs = [get_script_cache_path(), get_binary_cache_path()];
+    for dir in dirs.iter().flatten() {
+        util::check_writable(dir).map_err(|err| (dir.clone(), err))?;
+    }
     Ok(())
 }
 
@@ -1262,34 +4601,377 @@ fn get_binary_cache_path() -> Result<PathBuf> {
 }
 
 /**
-Attempts to locate the script specified by the given path.  If the path as-given doesn't yield anything, it will try adding file extensions.
+Returns the path to the directory holding the scripts' data directories.  This sits alongside the script cache, rather than in it, so that cleaning the cache doesn't touch it.
+*/
+fn get_script_data_path() -> Result<PathBuf> {
+    let cache_path = platform::get_cache_dir()?;
+    Ok(cache_path.join("script-data"))
+}
+
+/**
+Works out where the note of which script a data directory belongs to is kept.  It goes next to the directory, rather than in it, since what's in there is the script's business.
+*/
+fn get_data_source_path(data_dir: &Path) -> PathBuf {
+    let mut name = data_dir.file_name().unwrap_or_default().to_owned();
+    name.push(".source");
+    data_dir.with_file_name(name)
+}
+
+/**
+Notes down which script a data directory is for, once the script has created it, for `--list-data`.
+*/
+fn note_data_source(data_dir: &Path, script_path: &Path) -> Result<()> {
+    let source_path = get_data_source_path(data_dir);
+    if !data_dir.is_dir() || source_path.exists() {
+        return Ok(());
+    }
+    info!("noting source of data directory {:?}", data_dir);
+    let mut source = vec![];
+    platform::write_path(&mut source, script_path)?;
+    write_file_atomic(&source_path, &source)
+        .map_err(MainError::from)
+        .err_tag(format!(
+            "could not note down which script the data directory `{}` is for",
+            data_dir.display()
+        ))
+        .shift_blame(Blame::Human)
+}
+
+/**
+Removes every script's data directory.
+*/
+fn clear_data() -> Result<()> {
+    let data_dir = get_script_data_path()?;
+    info!("clearing data directories in {:?}", data_dir);
+    if ALLOW_AUTO_REMOVE {
+        match fs::remove_dir_all(&data_dir) {
+            Ok(()) => (),
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+/**
+Lists the data directories, along with the scripts they belong to, sorted by script.
+*/
+fn list_data() -> Result<()> {
+    let data_dir = get_script_data_path()?;
+    let children = match fs::read_dir(&data_dir) {
+        Ok(children) => children,
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut entries = vec![];
+    for child in children {
+        let path = child?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let source = fs::File::open(get_data_source_path(&path))
+            .and_then(|mut file| platform::read_path(&mut file))
+            .map(|source| source.display().to_string())
+            .unwrap_or_else(|_| "<unknown script>".into());
+        entries.push((source, path));
+    }
+    entries.sort();
+
+    for (source, path) in entries {
+        println!("{}  {}", source, path.display());
+    }
+    Ok(())
+}
+
+/**
+Writes a `.gitignore` and `CACHEDIR.TAG` into the given cache directory, unless they're already there.
+
+Failing to do so isn't worth stopping over, so problems are only logged.
+*/
+fn mark_cache_dir(dir: &Path) {
+    for &(name, content) in &[
+        (".gitignore", consts::CACHE_GITIGNORE),
+        ("CACHEDIR.TAG", consts::CACHEDIR_TAG),
+    ] {
+        let path = dir.join(name);
+        if path.exists() {
+            continue;
+        }
+        info!("writing {:?}", path);
+        if let Err(err) = fs::write(&path, content) {
+            error!("failed to write {:?}: {}", path, err);
+        }
+    }
+}
+
+/**
+Attempts to locate the script specified by the given path.  If the path as-given doesn't yield anything, it will try adding file extensions.  A directory is taken as a script bundle, and stands for its main file.
 */
 fn find_script<P>(path: P) -> Option<(PathBuf, fs::File)>
 where
     P: AsRef<Path>,
 {
-    let path = path.as_ref();
+    expand_bundles(script_candidates(path.as_ref()))
+        .into_iter()
+        .find_map(|path| fs::File::open(&path).ok().map(|file| (path, file)))
+}
+
+/**
+Replaces any directories in `candidates` with the files which could be their main files as script bundles.
+*/
+fn expand_bundles(candidates: Vec<PathBuf>) -> Vec<PathBuf> {
+    candidates
+        .into_iter()
+        .flat_map(|path| match path.is_dir() {
+            true => bundle_main_candidates(&path),
+            false => vec![path],
+        })
+        .collect()
+}
+
+/**
+The name of a script bundle: that of its directory, even if it was given as something like `.`.
+*/
+fn bundle_name(dir: &Path) -> Option<OsString> {
+    match dir.file_name() {
+        Some(name) => Some(name.to_owned()),
+        None => dir.canonicalize().ok()?.file_name().map(ToOwned::to_owned),
+    }
+}
+
+/**
+Lists the files which could be the main file of a script bundle in `dir`, in the order they're tried: `main.crs`, then a file named after the bundle, with `.rs` doing as well as `.crs` for either.
+*/
+fn bundle_main_candidates(dir: &Path) -> Vec<PathBuf> {
+    let mut candidates = vec![dir.join("main.crs"), dir.join("main.rs")];
+    if let Some(name) = bundle_name(dir) {
+        for ext in &[".crs", ".rs"] {
+            let mut file = name.clone();
+            file.push(ext);
+            candidates.push(dir.join(file));
+        }
+    }
+    candidates
+}
+
+/**
+A script file, read in, along with everything about where it came from that decides its cache entry.
+*/
+struct LoadedScript {
+    /// The file `find_script` found, as it was found.
+    found: PathBuf,
+
+    /// The script's name: that of the file, or of the bundle it's the main file of.
+    name: String,
+
+    /// The absolute, canonical path of the file.
+    path: PathBuf,
+
+    /// The file's contents.
+    content: String,
+
+    /// When the file was last modified.
+    mtime: u64,
+
+    /// The absolute, canonical path of the bundle directory, if the file is a bundle's main file.
+    bundle: Option<PathBuf>,
+}
+
+impl LoadedScript {
+    fn input(&self) -> Input<'_> {
+        Input::File(&self.name, &self.path, &self.content, self.mtime)
+    }
+}
+
+/**
+Finds and reads the script `script` stands for, decoding it with `encoding` if it isn't UTF-8.
+
+Everything that runs a script, or looks up its cache entry, loads it with this, so that they all agree on which entry that is.
+*/
+fn load_script(script: &OsStr, encoding: Option<InputEncoding>) -> Result<LoadedScript> {
+    let (found, mut file) = find_script(script).ok_or("could not find script")?;
+    let bundle_dir = bundle_dir(Path::new(script), &found);
+
+    let name = match bundle_dir {
+        Some(ref dir) => bundle_name(dir),
+        None => found.file_stem().map(ToOwned::to_owned),
+    }
+    .map(|os| os.to_string_lossy().into_owned())
+    .unwrap_or("unknown".into());
+
+    let mut bytes = vec![];
+    file.read_to_end(&mut bytes)?;
+    let content = match encoding {
+        None => String::from_utf8(bytes).map_err(|_| {
+            MainError::OtherOwned(
+                Blame::Human,
+                format!(
+                    "`{}` is not valid UTF-8; if it's in some other encoding, say which with `--input-encoding`",
+                    found.display()
+                ),
+            )
+        })?,
+        Some(encoding) => encoding.decode(&bytes).ok_or_else(|| {
+            MainError::OtherOwned(
+                Blame::Human,
+                format!("`{}` is not valid {}", found.display(), encoding.name()),
+            )
+        })?,
+    };
+    let mtime = platform::file_last_modified(&file);
+
+    Ok(LoadedScript {
+        path: canonical_path(&found)?,
+        bundle: match bundle_dir {
+            Some(dir) => Some(canonical_path(&dir)?),
+            None => None,
+        },
+        found,
+        name,
+        content,
+        mtime,
+    })
+}
+
+/**
+Adds everything else in the script bundle `bundle`, if there is one, to the package for its main file, `input`, next to the `files` it already has.
+*/
+fn add_bundle_files(
+    input: &Input<'_>,
+    bundle: Option<&Path>,
+    mani: &mut String,
+    files: &mut Vec<manifest::PackageFile>,
+) -> Result<()> {
+    if let (Some(dir), Input::File(_, main, _, _)) = (bundle, input) {
+        let script_file = format!("{}.rs", input.safe_name());
+        let bundled = manifest::bundle_files(dir, main, files, &script_file)?;
+        files.extend(bundled);
+        *mani = manifest::without_target_discovery(mani)?;
+    }
+    Ok(())
+}
+
+/**
+Makes `path` absolute and canonical.  A script's package ID is derived from its path, so it has to be the same however the script was reached: via a relative path, through a symlink, *etc.*
+*/
+fn canonical_path(path: &Path) -> Result<PathBuf> {
+    let abs_path = std::env::current_dir()?.join(path);
+    Ok(platform::simplify_path(
+        &abs_path.canonicalize().unwrap_or(abs_path),
+    ))
+}
+
+/**
+If `found`, which `find_script` found for `script`, is the main file of a script bundle, returns the bundle's directory.
+*/
+fn bundle_dir(script: &Path, found: &Path) -> Option<PathBuf> {
+    let dir = found.parent()?;
+    let is_bundle = dir.is_dir()
+        && script_candidates(script).iter().any(|path| path == dir)
+        && bundle_main_candidates(dir).iter().any(|path| path == found);
+    match is_bundle {
+        true => Some(dir.to_owned()),
+        false => None,
+    }
+}
+
+#[test]
+fn test_bundle_main_candidates() {
+    let p = |s: &str| PathBuf::from(s);
+    assert_eq!(
+        bundle_main_candidates(Path::new("tools/my.tool")),
+        [
+            p("tools/my.tool/main.crs"),
+            p("tools/my.tool/main.rs"),
+            p("tools/my.tool/my.tool.crs"),
+            p("tools/my.tool/my.tool.rs"),
+        ]
+    );
+}
+
+/**
+Lists the files `path` could refer to as a script, in the order they're tried.  A bare name, such as `mytool`, is also looked for in each of the directories in `CARGO_SCRIPT_PATH_DIRS`, after the current directory.
+*/
+fn script_candidates(path: &Path) -> Vec<PathBuf> {
+    let search_dirs: Vec<PathBuf> = match std::env::var_os(consts::SCRIPT_PATH_DIRS_ENV) {
+        Some(dirs) => std::env::split_paths(&dirs)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .collect(),
+        None => vec![],
+    };
+    script_candidates_in(path, &search_dirs)
+}
 
-    // Try the path directly.
-    if let Ok(file) = fs::File::open(path) {
-        return Some((path.into(), file));
-    }
+/**
+Lists the files `path` could refer to as a script, searching `search_dirs` for bare names.
+*/
+fn script_candidates_in(path: &Path, search_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let with_exts = |path: PathBuf| {
+        // Try the path directly.
+        let mut candidates = vec![path.clone()];
+
+        // If it had an extension, don't bother trying any others.
+        if path.extension().is_none() {
+            candidates.extend(
+                consts::SEARCH_EXTS
+                    .iter()
+                    .map(|ext| path.with_extension(ext)),
+            );
+        }
+        candidates
+    };
 
-    // If it had an extension, don't bother trying any others.
-    if path.extension().is_some() {
-        return None;
-    }
+    let mut candidates = with_exts(path.to_owned());
 
-    // Ok, now try other extensions.
-    for &ext in consts::SEARCH_EXTS {
-        let path = path.with_extension(ext);
-        if let Ok(file) = fs::File::open(&path) {
-            return Some((path, file));
+    // As with the shell's `PATH`, anything with a directory in it is only ever taken as it is.
+    let mut components = path.components();
+    let is_bare_name = matches!(
+        (components.next(), components.next()),
+        (Some(std::path::Component::Normal(_)), None)
+    );
+    if is_bare_name {
+        for dir in search_dirs {
+            candidates.extend(with_exts(dir.join(path)));
         }
     }
+    candidates
+}
+
+#[test]
+fn test_script_candidates_in() {
+    let p = |s: &str| PathBuf::from(s);
+    let dirs = [p("/scripts"), p("/more")];
+
+    assert_eq!(
+        script_candidates_in(Path::new("tool"), &dirs),
+        [
+            p("tool"),
+            p("tool.crs"),
+            p("tool.rs"),
+            p("/scripts/tool"),
+            p("/scripts/tool.crs"),
+            p("/scripts/tool.rs"),
+            p("/more/tool"),
+            p("/more/tool.crs"),
+            p("/more/tool.rs"),
+        ]
+    );
+    assert_eq!(
+        script_candidates_in(Path::new("tool.rs"), &dirs),
+        [p("tool.rs"), p("/scripts/tool.rs"), p("/more/tool.rs")]
+    );
 
-    // Welp. ¯\_(ツ)_/¯
-    None
+    // Paths aren't searched for.
+    assert_eq!(
+        script_candidates_in(Path::new("./tool.rs"), &dirs),
+        [p("./tool.rs")]
+    );
+    assert_eq!(
+        script_candidates_in(Path::new("sub/tool.rs"), &dirs),
+        [p("sub/tool.rs")]
+    );
+    assert_eq!(script_candidates_in(Path::new("tool"), &[]).len(), 3);
 }
 
 /**
@@ -1307,16 +4989,16 @@ pub enum Input<'a> {
     /**
     The input is an expression.
 
-    The tuple member is: the script contents, and the template (if any).
+    The tuple members are: the script contents, the template (if any), and the type to give its value (if any).
     */
-    Expr(&'a str, Option<&'a str>),
+    Expr(&'a str, Option<&'a str>, Option<&'a str>),
 
     /**
     The input is a loop expression.
 
-    The tuple member is: the script contents, whether the `--count` flag was given.
+    The tuple members are: the script contents, whether the `--count` flag was given, for `--fold`, the initial value of the accumulator and the closure to finish with, and whether the `--filter` flag was given.
     */
-    Loop(&'a str, bool),
+    Loop(&'a str, bool, Option<(&'a str, &'a str)>, bool),
 }
 
 impl<'a> Input<'a> {
@@ -1388,6 +5070,36 @@ impl<'a> Input<'a> {
         }
     }
 
+    /**
+    Compute the ID of the input's data directory, if it gets one.  Unlike the package ID, this only goes by where the script is, so editing it doesn't lose its data.
+    */
+    pub fn data_id(&self) -> Result<Option<OsString>> {
+        use sha1::Sha1;
+
+        match *self {
+            Input::File(name, path, _, _) => {
+                // This has to be hashed the same way as the path in `compute_id`.
+                let mut path_bytes = vec![];
+                platform::write_path(&mut path_bytes, path)?;
+                let mut hasher = Sha1::new();
+                hasher.update(b"path:");
+                hasher.update(&path_bytes);
+                hasher.update(b";");
+
+                let mut digest = hasher.digest().to_string();
+                digest.truncate(consts::ID_DIGEST_LEN_MAX);
+
+                let mut id = OsString::new();
+                id.push("file-");
+                id.push(name);
+                id.push("-");
+                id.push(if STUB_HASHES { "stub" } else { &*digest });
+                Ok(Some(id))
+            }
+            Input::Expr(..) | Input::Loop(..) => Ok(None),
+        }
+    }
+
     /**
     Compute the package ID for the input.  This is used as the name of the cache folder into which the Cargo package will be generated.
     */
@@ -1411,11 +5123,21 @@ impl<'a> Input<'a> {
         };
 
         match *self {
-            File(name, path, _, _) => {
+            File(name, path, content, _) => {
                 let mut hasher = Sha1::new();
 
-                // Hash the path to the script.
-                hasher.update(&path.to_string_lossy().as_bytes());
+                // Hash the exact path to the script; a lossy conversion could map distinct paths to the same ID.
+                let mut path_bytes = vec![];
+                platform::write_path(&mut path_bytes, path)?;
+                hasher.update(b"path:");
+                hasher.update(&path_bytes);
+                hasher.update(b";");
+
+                // Hash the contents as well, so that a script which now lives where another used to can't pick up the other's package.
+                hasher.update(b"content:");
+                hasher.update(hash_str(content).as_bytes());
+                hasher.update(b";");
+
                 let mut digest = hasher.digest().to_string();
                 digest.truncate(consts::ID_DIGEST_LEN_MAX);
 
@@ -1426,13 +5148,20 @@ impl<'a> Input<'a> {
                 id.push(if STUB_HASHES { "stub" } else { &*digest });
                 Ok(id)
             }
-            Expr(content, template) => {
+            Expr(content, template, expr_as) => {
                 let mut hasher = hash_deps();
 
                 hasher.update(b"template:");
                 hasher.update(template.unwrap_or("").as_bytes());
                 hasher.update(b";");
 
+                // As with `--fold`, this is left out when not given, so that existing IDs don't change.
+                if let Some(expr_as) = expr_as {
+                    hasher.update(b"type:");
+                    hasher.update(expr_as.as_bytes());
+                    hasher.update(b";");
+                }
+
                 hasher.update(&content.as_bytes());
                 let mut digest = hasher.digest().to_string();
                 digest.truncate(consts::ID_DIGEST_LEN_MAX);
@@ -1442,13 +5171,27 @@ impl<'a> Input<'a> {
                 id.push(if STUB_HASHES { "stub" } else { &*digest });
                 Ok(id)
             }
-            Loop(content, count) => {
+            Loop(content, count, fold, filter) => {
                 let mut hasher = hash_deps();
 
                 // Make sure to include the [non-]presence of the `--count` flag in the flag, since it changes the actual generated script output.
                 hasher.update(b"count:");
                 hasher.update(if count { b"true;" } else { b"false;" });
 
+                // Likewise `--fold`, although it's left out entirely when not given, so that existing IDs don't change.
+                if let Some((init, finish)) = fold {
+                    for part in &[init, finish] {
+                        hasher.update(b"fold:");
+                        hasher.update(part.len().to_string().as_bytes());
+                        hasher.update(b":");
+                        hasher.update(part.as_bytes());
+                        hasher.update(b";");
+                    }
+                }
+                if filter {
+                    hasher.update(b"filter;");
+                }
+
                 hasher.update(&content.as_bytes());
                 let mut digest = hasher.digest().to_string();
                 digest.truncate(consts::ID_DIGEST_LEN_MAX);
@@ -1460,14 +5203,189 @@ impl<'a> Input<'a> {
             }
         }
     }
+
+    /**
+    Compute a content-addressed package ID for the input.
+
+    Unlike `compute_id`, this doesn't care where the input came from: it depends only on the generated manifest and source, the toolchain, and whatever `flags` affect how the package is built.  Inputs which would produce identical packages get the same ID.
+    */
+    pub fn compute_content_id(
+        &self,
+        manifest: &str,
+        script: &str,
+        toolchain: &str,
+        flags: &str,
+    ) -> OsString {
+        use sha1::Sha1;
+
+        let mut hasher = Sha1::new();
+        for &(key, value) in &[
+            ("manifest", manifest),
+            ("script", script),
+            ("toolchain", toolchain),
+            ("flags", flags),
+        ] {
+            // Hash the lengths too, so that content can't "move" between fields.
+            hasher.update(format!("{}:{}:", key, value.len()).as_bytes());
+            hasher.update(value.as_bytes());
+            hasher.update(b";");
+        }
+        let mut digest = hasher.digest().to_string();
+        digest.truncate(consts::ID_DIGEST_LEN_MAX);
+
+        let mut id = OsString::new();
+        id.push("content-");
+        id.push(self.safe_name());
+        id.push("-");
+        id.push(if STUB_HASHES { "stub" } else { &*digest });
+        id
+    }
+}
+
+#[test]
+fn test_compute_id() {
+    let id = |path: &str, content: &str| {
+        Input::File("test", Path::new(path), content, 0)
+            .compute_id(vec![])
+            .unwrap()
+    };
+
+    let a = id("/scripts/a/test.rs", "fn main() {}");
+    assert!(a.to_str().unwrap().starts_with("file-test-"));
+    assert_eq!(a, id("/scripts/a/test.rs", "fn main() {}"));
+
+    // Same name, near-identical paths.
+    assert_ne!(a, id("/scripts/a-/test.rs", "fn main() {}"));
+    assert_ne!(a, id("/scripts/b/test.rs", "fn main() {}"));
+
+    // Same path, different contents.
+    assert_ne!(a, id("/scripts/a/test.rs", "fn main() { }"));
+}
+
+#[test]
+fn test_metadata_differences() {
+    let meta = PackageMetadata {
+        path: Some("/scripts/n.rs".into()),
+        modified: Some(1),
+        content_hash: Some("c".into()),
+        templates: None,
+        template: None,
+        debug: false,
+        deps: vec![],
+        prelude: vec![],
+        features: None,
+        cargo_config: vec![],
+        script_config: None,
+        target: None,
+        rustflags: None,
+        env_rustflags: None,
+        profile: None,
+        manifest_hash: "m".into(),
+        script_hash: "s".into(),
+        files: None,
+        includes: None,
+        state: None,
+        pinned: None,
+    };
+    let diff = |other: PackageMetadata| meta.differences(&other);
+
+    assert_eq!(
+        diff(PackageMetadata {
+            content_hash: Some("c2".into()),
+            script_hash: "s2".into(),
+            ..meta.clone()
+        }),
+        vec!["script changed"]
+    );
+    assert_eq!(
+        PackageMetadata {
+            content_hash: None,
+            ..meta.clone()
+        }
+        .differences(&meta),
+        vec!["dependencies were updated"]
+    );
+    assert_eq!(
+        diff(PackageMetadata {
+            features: Some("f".into()),
+            debug: true,
+            manifest_hash: "m2".into(),
+            ..meta.clone()
+        }),
+        vec!["debug setting differs", "features differ"]
+    );
+    assert_eq!(
+        diff(PackageMetadata {
+            manifest_hash: "m2".into(),
+            ..meta.clone()
+        }),
+        vec!["manifest changed"]
+    );
+    assert_eq!(
+        diff(PackageMetadata {
+            files: Some(vec![("build.rs".into(), "b".into())]),
+            manifest_hash: "m2".into(),
+            ..meta.clone()
+        }),
+        vec!["build script or included files changed"]
+    );
+    assert_eq!(
+        diff(PackageMetadata {
+            env_rustflags: Some("-C target-cpu=native".into()),
+            ..meta.clone()
+        }),
+        vec!["RUSTFLAGS in the environment differ"]
+    );
+    assert_eq!(
+        diff(PackageMetadata {
+            modified: Some(2),
+            ..meta.clone()
+        }),
+        vec!["metadata did not match"]
+    );
+
+    // Pinning a package doesn't make it any less up to date.
+    assert!(meta.is_fresh_for(&PackageMetadata {
+        pinned: Some(true),
+        ..meta.clone()
+    }));
+}
+
+#[test]
+fn test_compute_content_id() {
+    let id = |path: &str, name: &str, manifest: &str, flags: &str| {
+        Input::File(name, Path::new(path), "", 0).compute_content_id(
+            manifest,
+            "fn main() {}",
+            "rustc 1.0.0",
+            flags,
+        )
+    };
+
+    let a = id("/a/test.rs", "test", "[package]", "debug=false");
+    assert!(a.to_str().unwrap().starts_with("content-test-"));
+
+    // Where the script lives doesn't matter...
+    assert_eq!(a, id("/b/test.rs", "test", "[package]", "debug=false"));
+
+    // ... but what it builds does.
+    assert_ne!(a, id("/a/test.rs", "test", "[package]\n", "debug=false"));
+    assert_ne!(a, id("/a/test.rs", "test", "[package]", "debug=true"));
+    assert_ne!(a, id("/a/test.rs", "test", "[package]d", "ebug=false"));
 }
 
 /**
 Shorthand for hashing a string.
 */
 fn hash_str(s: &str) -> String {
+    hash_bytes(s.as_bytes())
+}
+
+fn hash_bytes(b: &[u8]) -> String {
     use sha1::Sha1;
-    Sha1::from(s).digest().to_string()
+    let digest = Sha1::from(b).digest().to_string();
+    trace!("digest of {} bytes: {}", b.len(), digest);
+    digest
 }
 
 enum FileOverwrite {
@@ -1490,34 +5408,197 @@ where
     }
 
     debug!(".. hashes differ; new_hash: {:?}", new_hash);
-    let mut file = fs::File::create(path)?;
-    write!(&mut file, "{}", content)?;
-    file.flush()?;
+    write_file_atomic(path, content.as_bytes())?;
     Ok(FileOverwrite::Changed { new_hash: new_hash })
 }
 
+/**
+Makes sure that the generated file at `path` hasn't been changed by hand before `overwrite_file` replaces it with `content`.  `hash` is the one that will be given to `overwrite_file`, and `written` is the hash of what was written last time, if that's known.
+
+Nothing is read unless the file is actually going to be overwritten.
+*/
+fn check_unmodified(
+    path: &Path,
+    content: &str,
+    hash: Option<&str>,
+    written: Option<&str>,
+) -> Result<()> {
+    let new_hash = hash_str(content);
+    if Some(&*new_hash) == hash {
+        return Ok(());
+    }
+    let written = match written {
+        Some(written) => written,
+        None => return Ok(()),
+    };
+    trace!("reading {:?} to check for local modifications", path);
+    let on_disk = match fs::read(path) {
+        Ok(bytes) => hash_bytes(&bytes),
+        Err(_) => return Ok(()),
+    };
+    match modified_locally(&on_disk, written, &new_hash) {
+        false => Ok(()),
+        true => Err(MainError::OtherOwned(
+            Blame::Human,
+            format!(
+                "local modifications were detected in `{}`, which cargo-script generated; pass `--force` to overwrite them",
+                path.display()
+            ),
+        )),
+    }
+}
+
+/**
+Has a generated file been changed by hand?  It has if what's `on_disk` is neither what was `written` last time, nor what's about to be written in its place.  All three are hashes.
+*/
+fn modified_locally(on_disk: &str, written: &str, new: &str) -> bool {
+    on_disk != written && on_disk != new
+}
+
+#[test]
+fn test_modified_locally() {
+    // Untouched since it was written, whether or not it's about to change.
+    assert!(!modified_locally("a", "a", "a"));
+    assert!(!modified_locally("a", "a", "b"));
+    // Changed, but to exactly what's about to be written anyway.
+    assert!(!modified_locally("b", "a", "b"));
+    // Changed, to something else.
+    assert!(modified_locally("c", "a", "b"));
+    assert!(modified_locally("b", "a", "a"));
+}
+
+/**
+Writes the files a package needs besides its manifest and script, and removes any of `old_files` it no longer needs.  Left behind, an old `build.rs` would still be picked up by Cargo.
+
+Files which are already up to date are left alone, so that Cargo doesn't re-run the build script for no reason.
+*/
+fn write_pkg_files(
+    pkg_path: &Path,
+    files: &[manifest::PackageFile],
+    old_files: &[(String, String)],
+) -> Result<()> {
+    for (old_path, _) in old_files {
+        if !files.iter().any(|f| f.path == *old_path) {
+            info!("removing {:?} from package", old_path);
+            match fs::remove_file(pkg_path.join(old_path)) {
+                Ok(()) => (),
+                Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => (),
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    let stale: Vec<bool> = files
+        .iter()
+        .map(|f| fs::read(pkg_path.join(&f.path)).ok().as_ref() != Some(&f.content))
+        .collect();
+    let any_stale = stale.contains(&true);
+
+    for (file, stale) in files.iter().zip(stale) {
+        /*
+        A build script which prints any `rerun-if-*` lines (as `cc` does) stops Cargo from re-running it just because some other file in the package changed.  Touching the build script itself always works, so it goes along with anything else that's changed.
+        */
+        let touch = any_stale && file.path == consts::BUILD_SCRIPT_NAME;
+        if !(stale || touch) {
+            continue;
+        }
+        info!("writing {:?} into package", file.path);
+        let path = pkg_path.join(&file.path);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_file_atomic(&path, &file.content)?;
+    }
+    Ok(())
+}
+
+/**
+The file Cargo's diagnostics are sent to, for `--color-diagnostics-to-file`.
+*/
+struct DiagnosticsFile {
+    path: PathBuf,
+    file: fs::File,
+}
+
+impl DiagnosticsFile {
+    /**
+    Creates (or empties) the file.  This happens whether or not there's anything to build, so that whoever reads it never sees the diagnostics from some earlier run.
+    */
+    fn create(path: &Path) -> Result<Self> {
+        let file = fs::File::create(path)
+            .map_err(MainError::from)
+            .err_tag(format!("could not create `{}`", path.display()))
+            .shift_blame(Blame::Human)?;
+        Ok(DiagnosticsFile {
+            path: path.into(),
+            file,
+        })
+    }
+
+    /**
+    Sends a Cargo command's stderr to the file.  Cargo only colours its output for a terminal by default, so it's told to keep doing so.
+    */
+    fn redirect<'c>(&self, cmd: &'c mut Command) -> Result<&'c mut Command> {
+        Ok(cmd
+            .env("CARGO_TERM_COLOR", "always")
+            .stderr(self.file.try_clone()?))
+    }
+
+    /**
+    Shows hints for whatever a failed command wrote to the file.
+    */
+    fn show_hints(&self) {
+        hints::show_hints(&fs::read_to_string(&self.path).unwrap_or_default());
+    }
+
+    /**
+    How much has been written to the file so far.
+    */
+    fn len(&self) -> u64 {
+        self.file.metadata().map(|md| md.len()).unwrap_or(0)
+    }
+
+    /**
+    Everything written to the file after the first `start` bytes, such as by one attempt at a build.
+    */
+    fn output_since(&self, start: u64) -> String {
+        let output = fs::read(&self.path).unwrap_or_default();
+        let start = std::cmp::min(start as usize, output.len());
+        String::from_utf8_lossy(&output[start..]).into_owned()
+    }
+}
+
 /**
 Constructs a Cargo command that runs on the script package.
 */
 fn cargo(
     cmd_name: &str,
-    manifest: &str,
+    manifest: &Path,
     use_bincache: bool,
     meta: &PackageMetadata,
 ) -> Result<Command> {
     let mut cmd = Command::new("cargo");
     cmd.arg(cmd_name).arg("--manifest-path").arg(manifest);
 
+    // Whatever is piped in belongs to the script; Cargo has no business reading any of it.
+    cmd.stdin(process::Stdio::null());
+
     if platform::force_cargo_color() {
         cmd.arg("--color").arg("always");
     }
 
     if use_bincache {
-        cmd.env("CARGO_TARGET_DIR", get_binary_cache_path()?);
+        cmd.env("CARGO_TARGET_DIR", bincache_target_dir(meta)?);
     }
 
-    // Block `--release` on `bench`.
-    if !meta.debug && cmd_name != "bench" {
+    if let Some(ref flags) = meta.rustflags {
+        // This takes precedence over `RUSTFLAGS`, so it has to go.
+        cmd.env_remove("CARGO_ENCODED_RUSTFLAGS")
+            .env("RUSTFLAGS", flags);
+    }
+
+    // Block `--release` on `bench`, and `tree`, which doesn't understand it.
+    if !meta.debug && cmd_name != "bench" && cmd_name != "tree" {
         cmd.arg("--release");
     }
 
@@ -1525,6 +5606,47 @@ fn cargo(
         cmd.arg("--features").arg(features);
     }
 
+    // Configuration from next to the script goes first, so `--config` can still override it.  It isn't allowed to move the target directory, though, or the executable couldn't be found.
+    if let Some(ref files) = meta.script_config {
+        for (path, _) in files {
+            cmd.arg("--config").arg(path);
+        }
+        let pkg_path = manifest.parent().unwrap_or_else(|| Path::new("."));
+        let target_dir = cargo_target_dir(use_bincache, pkg_path, meta)?;
+        cmd.arg("--config").arg(format!(
+            "build.target-dir={}",
+            toml::Value::String(target_dir.to_string_lossy().into_owned())
+        ));
+    }
+
+    for config in &meta.cargo_config {
+        cmd.arg("--config").arg(config);
+    }
+
+    if let Some(ref target) = meta.target {
+        cmd.arg("--target").arg(target);
+    }
+
+    lazy_static! {
+        /**
+        When this run started building.  Every Cargo command in a run gets the same time, since Cargo rebuilds a script which uses `env!` on it whenever it changes.
+        */
+        static ref BUILD_TIME: String = time::now_utc().rfc3339().to_string();
+    }
+
+    /*
+    Let the script find out where it came from with `env!`.  These only reach `rustc` through Cargo's environment, so they make no difference to scripts which don't look at them.  Scripts have their content hash; anything else has the hash of the generated source instead.
+    */
+    cmd.env(
+        "CARGO_SCRIPT_SOURCE_PATH",
+        meta.path.as_deref().unwrap_or(""),
+    )
+    .env(
+        "CARGO_SCRIPT_SOURCE_DIGEST",
+        meta.content_hash.as_ref().unwrap_or(&meta.script_hash),
+    )
+    .env("CARGO_SCRIPT_BUILD_TIME", &*BUILD_TIME);
+
     Ok(cmd)
 }
 
@@ -1534,9 +5656,9 @@ Tries to find the path to a package's target file.
 This will also cache this information such that `exe_path` can find it later.
 */
 fn cargo_target<P>(
-    input: &Input<'_>,
+    bin_name: &str,
     pkg_path: P,
-    manifest: &str,
+    manifest: &Path,
     use_bincache: bool,
     meta: &PackageMetadata,
 ) -> Result<PathBuf>
@@ -1548,7 +5670,8 @@ where
     }
 
     trace!(
-        "cargo_target(_, {:?}, {:?}, {:?}, _)",
+        "cargo_target({:?}, {:?}, {:?}, {:?}, _)",
+        bin_name,
         pkg_path.as_ref(),
         manifest,
         use_bincache
@@ -1565,24 +5688,44 @@ where
         false
     };
 
-    let exe_path = if use_guess {
-        cargo_target_by_guess(input, use_bincache, pkg_path.as_ref(), meta)?
-    } else {
-        cargo_target_by_message(input, manifest, use_bincache, meta)?
+    /*
+    If asking Cargo doesn't work out, fall back to guessing.  This can happen if the output isn't what we expect, or if the path Cargo gives us doesn't actually exist.
+    */
+    let by_message = match use_guess {
+        true => None,
+        false => match cargo_target_by_message(bin_name, manifest, use_bincache, meta) {
+            Ok(ref exe_path) if !exe_path.is_file_polyfill() => {
+                info!(
+                    "cargo reported executable {:?}, but it doesn't exist; guessing instead",
+                    exe_path
+                );
+                None
+            }
+            Ok(exe_path) => Some(exe_path),
+            Err(err) => {
+                info!(
+                    "couldn't get executable from cargo: {}; guessing instead",
+                    err
+                );
+                None
+            }
+        },
+    };
+    let exe_path = match by_message {
+        Some(exe_path) => exe_path,
+        None => cargo_target_by_guess(bin_name, use_bincache, pkg_path.as_ref(), meta)?,
     };
 
     trace!(".. exe_path: {:?}", exe_path);
 
     // Before we return, cache the result.
     {
-        use std::fs::File;
-
-        let manifest_path = Path::new(manifest);
-        let package_path = manifest_path.parent().unwrap();
+        let package_path = manifest.parent().unwrap();
         let cache_path = package_path.join("target.exe_path");
 
-        let mut f = File::create(&cache_path)?;
-        platform::write_path(&mut f, &exe_path)?;
+        let mut buf = vec![];
+        platform::write_path(&mut buf, &exe_path)?;
+        write_file_atomic(&cache_path, &buf)?;
     }
 
     Ok(exe_path)
@@ -1592,34 +5735,140 @@ where
 Figures out where the output executable for the input should be by guessing.
 
 Depending on the configuration, this might not work.  On the other hand, this actually works (usually) prior to Cargo 0.18 (Rust 1.17).
+
+This looks in the profile directory of the target directory, as well as the profile directories for any explicit targets (*i.e.* `target/<triple>/release`).  If the executable isn't in any of them, the error lists where we looked.
 */
 fn cargo_target_by_guess(
-    input: &Input<'_>,
+    bin_name: &str,
     use_bincache: bool,
     pkg_path: &Path,
     meta: &PackageMetadata,
 ) -> Result<PathBuf> {
     trace!(
-        "cargo_target_by_guess(_, {:?}, {:?}, _)",
+        "cargo_target_by_guess({:?}, {:?}, {:?}, _)",
+        bin_name,
         use_bincache,
         pkg_path
     );
 
-    let profile = match meta.debug {
+    let profile = profile_name(meta);
+    let target_path = cargo_target_dir(use_bincache, pkg_path, meta)?;
+    let exe_name = exe_file_name(bin_name, meta);
+
+    let mut search_dirs = vec![];
+    if let Some(ref target) = meta.target {
+        search_dirs.push(target_path.join(target).join(profile));
+    }
+    search_dirs.push(target_path.join(profile));
+    if let Ok(entries) = fs::read_dir(&target_path) {
+        let mut triple_dirs: Vec<_> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path().join(profile))
+            .filter(|p| p.is_dir())
+            .collect();
+        triple_dirs.sort();
+        search_dirs.extend(triple_dirs);
+    }
+
+    for dir in &search_dirs {
+        let exe_path = dir.join(&exe_name);
+        trace!(".. trying {:?}", exe_path);
+        if exe_path.is_file_polyfill() {
+            return Ok(exe_path);
+        }
+    }
+
+    let mut msg = format!(
+        "could not find the compiled executable `{}` after a successful build; searched:",
+        exe_name
+    );
+    for dir in &search_dirs {
+        msg.push_str(&format!("\n\t{}", dir.display()));
+    }
+    Err(msg.into())
+}
+
+/**
+Works out where Cargo should put the executable for a package which hasn't been built yet.  This is where `cargo_target_by_guess` looks first.
+*/
+fn expected_exe_path(
+    bin_name: &str,
+    use_bincache: bool,
+    pkg_path: &Path,
+    meta: &PackageMetadata,
+) -> Result<PathBuf> {
+    let mut path = cargo_target_dir(use_bincache, pkg_path, meta)?;
+    if let Some(ref target) = meta.target {
+        path.push(target);
+    }
+    path.push(profile_name(meta));
+    path.push(exe_file_name(bin_name, meta));
+    Ok(path)
+}
+
+/**
+The target directory Cargo will use for a package.
+*/
+fn cargo_target_dir(
+    use_bincache: bool,
+    pkg_path: &Path,
+    meta: &PackageMetadata,
+) -> Result<PathBuf> {
+    if use_bincache {
+        bincache_target_dir(meta)
+    } else if let Some(target_dir) = std::env::var_os("CARGO_TARGET_DIR") {
+        // Cargo resolves this relative to the working directory, same as us.
+        Ok(target_dir.into())
+    } else {
+        Ok(pkg_path.join("target"))
+    }
+}
+
+/**
+The target directory for a package using the binary cache.  Builds with `--rustflags` get a directory per set of flags: Cargo would otherwise rebuild every dependency each time the flags changed, for everyone sharing the cache.
+
+The same goes for `--panic` and `--strip`, only more so: the executable itself is the same file whatever they are, so builds with different settings would overwrite each other's.
+*/
+fn bincache_target_dir(meta: &PackageMetadata) -> Result<PathBuf> {
+    let bincache_path = get_binary_cache_path()?;
+    let mut parts = vec![];
+    if let Some(ref flags) = meta.rustflags {
+        parts.push(format!("rustflags-{}", rustflags_id(flags)));
+    }
+    for (key, value) in meta.profile.iter().flatten() {
+        parts.push(format!("{}-{}", key, value));
+    }
+    Ok(match parts.is_empty() {
+        true => bincache_path,
+        false => bincache_path.join(parts.join("-")),
+    })
+}
+
+/**
+A short name for a set of `--rustflags`, for telling apart cache entries built with different ones.
+*/
+fn rustflags_id(flags: &str) -> String {
+    hash_str(flags)[..8].into()
+}
+
+fn profile_name(meta: &PackageMetadata) -> &'static str {
+    match meta.debug {
         true => "debug",
         false => "release",
+    }
+}
+
+/**
+The file name of a binary target's executable, which depends on what it's being built for.
+*/
+fn exe_file_name(bin_name: &str, meta: &PackageMetadata) -> String {
+    let exe_suffix = match meta.target {
+        None => std::env::consts::EXE_SUFFIX,
+        Some(ref t) if is_wasm_target(t) => ".wasm",
+        Some(ref t) if t.contains("-windows-") => ".exe",
+        Some(_) => "",
     };
-    let target_path = if use_bincache {
-        get_binary_cache_path()?
-    } else {
-        pkg_path.join("target")
-    };
-    let mut exe_path = target_path
-        .join(profile)
-        .join(&input.package_name())
-        .into_os_string();
-    exe_path.push(std::env::consts::EXE_SUFFIX);
-    Ok(exe_path.into())
+    format!("{}{}", bin_name, exe_suffix)
 }
 
 /**
@@ -1628,8 +5877,8 @@ Gets the path to the package's target file by parsing the output of `cargo build
 This only works on Cargo 0.18 (Rust 1.17) and higher.
 */
 fn cargo_target_by_message(
-    input: &Input<'_>,
-    manifest: &str,
+    bin_name: &str,
+    manifest: &Path,
     use_bincache: bool,
     meta: &PackageMetadata,
 ) -> Result<PathBuf> {
@@ -1637,7 +5886,8 @@ fn cargo_target_by_message(
     use std::io::{BufRead, BufReader};
 
     trace!(
-        "cargo_target_by_message(_, {:?}, {:?}, _)",
+        "cargo_target_by_message({:?}, {:?}, {:?}, _)",
+        bin_name,
         manifest,
         use_bincache
     );
@@ -1647,7 +5897,7 @@ fn cargo_target_by_message(
     cmd.stdout(process::Stdio::piped());
     cmd.stderr(process::Stdio::null());
 
-    trace!(".. cmd: {:?}", cmd);
+    logging::trace_command(&cmd);
 
     let mut child = cmd.spawn()?;
     match child.wait()?.code() {
@@ -1669,7 +5919,6 @@ fn cargo_target_by_message(
     let mut line = String::with_capacity(1024);
     let mut stdout = BufReader::new(child.stdout.take().unwrap());
     let null = json::Json::Null;
-    let package_name = input.package_name();
 
     let mut line_num = 0;
     loop {
@@ -1695,14 +5944,24 @@ fn cargo_target_by_message(
             .find_path(&["target", "name"])
             .unwrap_or(&null)
             .as_string()
-            != Some(&package_name)
+            != Some(bin_name)
         {
             trace!(
                 "   couldn't find `target.name`, or it wasn't {:?}",
-                package_name
+                bin_name
             );
             continue;
         }
+        // A dependency's library can have the same target name as our binary.
+        let is_bin = msg
+            .find_path(&["target", "kind"])
+            .and_then(|k| k.as_array())
+            .map(|k| k.iter().any(|k| k.as_string() == Some("bin")))
+            .unwrap_or(false);
+        if !is_bin {
+            trace!("   `target.kind` wasn't `bin`");
+            continue;
+        }
 
         // Looks like it; grab the path.
         let exe_path = msg
@@ -1717,6 +5976,103 @@ fn cargo_target_by_message(
     }
 }
 
+/**
+Prints the versions of the tools and templates used to build scripts, for `--version`.
+*/
+fn print_version_details() {
+    for tool in &["cargo", "rustc"] {
+        match tool_version(tool) {
+            Ok(version) => println!("{}", version),
+            Err(err) => println!("{} unknown ({})", tool, err),
+        }
+    }
+    println!("templates {}", templates::builtin_id());
+}
+
+/**
+Gets the first line of `<tool> --version`.
+*/
+fn tool_version(tool: &str) -> Result<String> {
+    let mut cmd = Command::new(tool);
+    cmd.arg("--version").stdin(process::Stdio::null());
+    logging::trace_command(&cmd);
+    let out = cmd.output()?;
+    if !out.status.success() {
+        return Err(format!("`{} --version` failed", tool).into());
+    }
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    Ok(stdout.lines().next().unwrap_or("").trim().into())
+}
+
+/**
+Gets `package.edition` from a manifest.  Without one, Cargo builds the package as Rust 2015.
+*/
+fn package_edition(manifest: &str) -> Option<String> {
+    let mani: Option<toml::Value> = toml::from_str(manifest).ok();
+    mani.as_ref()
+        .and_then(|m| m.get("package"))
+        .and_then(|p| p.get("edition"))
+        .and_then(toml::Value::as_str)
+        .map(Into::into)
+}
+
+/**
+Makes sure the active Cargo is recent enough for the edition the manifest asks for, so that scripts using a newer edition get a clear error, rather than one about Cargo features.
+
+If Cargo's version can't be worked out, this leaves it to Cargo.
+*/
+fn check_edition_supported(manifest: &str) -> Result<()> {
+    let edition = match package_edition(manifest) {
+        Some(edition) => edition,
+        None => return Ok(()),
+    };
+    let edition = &*edition;
+    let cargo_ver = match cargo_version() {
+        Ok(ver) => ver,
+        Err(err) => {
+            info!("not checking edition {:?}: {}", edition, err);
+            return Ok(());
+        }
+    };
+    match edition_needs(edition, &cargo_ver) {
+        None => Ok(()),
+        Some((major, minor)) => Err(MainError::OtherOwned(
+            Blame::Human,
+            format!(
+                "the script uses edition {}, which needs Cargo {}.{} or later, but this is Cargo {}; \
+                 update the toolchain (try `rustup update`), or use a newer one (such as `cargo +stable script`)",
+                edition, major, minor, cargo_ver
+            ),
+        )),
+    }
+}
+
+/**
+Works out whether `cargo_ver` is too old for `edition`, and if so, which version it would need.
+
+Pre-releases count as the release they lead up to, since a nightly may well have the edition already; editions which aren't known here are left to Cargo.
+*/
+fn edition_needs(edition: &str, cargo_ver: &Version) -> Option<(u64, u64)> {
+    consts::EDITION_CARGO_VERSIONS
+        .iter()
+        .find(|&&(e, _)| e == edition)
+        .map(|&(_, needs)| needs)
+        .filter(|&needs| (cargo_ver.major, cargo_ver.minor) < needs)
+}
+
+#[test]
+fn test_edition_needs() {
+    let v = |s: &str| Version::parse(s).unwrap();
+    assert_eq!(edition_needs("2015", &v("1.0.0")), None);
+    assert_eq!(edition_needs("2018", &v("1.30.1")), Some((1, 31)));
+    assert_eq!(edition_needs("2018", &v("1.31.0")), None);
+    assert_eq!(edition_needs("2021", &v("1.55.0")), Some((1, 56)));
+    assert_eq!(edition_needs("2024", &v("1.80.0")), Some((1, 85)));
+    assert_eq!(edition_needs("2024", &v("1.85.0-nightly")), None);
+    assert_eq!(edition_needs("2024", &v("1.86.0")), None);
+    assert_eq!(edition_needs("2077", &v("1.80.0")), None);
+}
+
 /**
 Get the version of the currently active cargo.
 */
@@ -1728,7 +6084,8 @@ fn cargo_version() -> Result<Version> {
     }
 
     let mut cmd = Command::new("cargo");
-    cmd.arg("-V");
+    cmd.arg("-V").stdin(process::Stdio::null());
+    logging::trace_command(&cmd);
 
     let child = cmd.output()?;
     match child.status.code() {
@@ -11,16 +11,24 @@ or distributed except according to those terms.
 This module is concerned with how `cargo-script` extracts the manfiest from a script file.
 */
 use crate::consts;
-use crate::error::{Blame, MainError, Result};
+use crate::error::{Blame, MainError, Result, ResultExt};
+use crate::platform;
 use crate::templates;
+use crate::util::{self, PathExt};
 use crate::Input;
 use lazy_static::lazy_static;
 use log::{error, info};
 use regex::Regex;
 use std::collections::HashMap;
-use std::path::Path;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 lazy_static! {
+    static ref RE_FN_MAIN: Regex =
+        Regex::new(r"(?m)^\s*(pub(\([^)]*\))?\s+)?fn\s+main\s*\(").unwrap();
+    static ref RE_TEST_ATTR: Regex = Regex::new(r"#\s*\[\s*test\s*\]").unwrap();
+    static ref RE_CRITERION_MAIN: Regex = Regex::new(r"(?m)^\s*criterion_main\s*!").unwrap();
     static ref RE_SHORT_MANIFEST: Regex =
         Regex::new(r"^(?i)\s*//\s*cargo-deps\s*:(.*?)(\r\n|\n)").unwrap();
     static ref RE_MARGIN: Regex = Regex::new(r"^\s*\*( |$)").unwrap();
@@ -28,6 +36,22 @@ lazy_static! {
     static ref RE_NESTING: Regex = Regex::new(r"/\*|\*/").unwrap();
     static ref RE_COMMENT: Regex = Regex::new(r"^\s*//!").unwrap();
     static ref RE_HASHBANG: Regex = Regex::new(r"^#![^\[].*?(\r\n|\n)").unwrap();
+    static ref RE_INCLUDE: Regex =
+        Regex::new(r"(?m)^[ \t]*//#[ \t]*include[ \t]+(.+?)[ \t]*\r?$").unwrap();
+    static ref RE_MOD_DECL: Regex = Regex::new(r"\bmod\s+(r#)?\w+\s*;").unwrap();
+    static ref RE_DOC_LINE_PREFIX: Regex = Regex::new(r"^\s*(//!|\*)").unwrap();
+    static ref RE_TOML_TABLE: Regex = Regex::new(r"^\s*\[\s*([^\[\]]+?)\s*\]").unwrap();
+    static ref RE_DEPS_TABLE: Regex = Regex::new(
+        r#"^(?:target\.(?:'[^']*'|"[^"]*"|[^.'"]+)\.)?(?:dev-|build-)?dependencies(?:\.(.+))?$"#
+    )
+    .unwrap();
+    static ref RE_TOML_KEY: Regex =
+        Regex::new(r#"^\s*(?:"([^"]+)"|'([^']+)'|([A-Za-z0-9_-]+))\s*=\s*"#).unwrap();
+    static ref RE_TOML_STRING: Regex = Regex::new(r#"^(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    static ref RE_INLINE_VERSION: Regex =
+        Regex::new(r#"[{,]\s*version\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    static ref RE_INLINE_PACKAGE: Regex =
+        Regex::new(r#"[{,]\s*package\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
     static ref RE_CRATE_COMMENT: Regex = {
         Regex::new(
             r"(?x)
@@ -39,43 +63,185 @@ lazy_static! {
     };
 }
 
+/**
+The result of splitting an input.
+*/
+#[derive(Debug)]
+pub struct SplitInput {
+    /// The complete Cargo manifest.
+    pub manifest: String,
+
+    /// The Rust source.
+    pub script: String,
+
+    /// The name of the generated package.
+    pub pkg_name: String,
+
+    /// The name of the binary target to run.
+    pub bin_name: String,
+
+    /// Defaults for command-line options, given by the script itself.
+    pub defaults: ScriptDefaults,
+
+    /// Other files which have to be copied into the package, such as a build script.
+    pub files: Vec<PackageFile>,
+
+    /// Files pulled into the script with `//# include`, and what was in them.
+    pub includes: Vec<(PathBuf, String)>,
+
+    /// Where each of the script's modules came from, and where its copy in the package is.  The copies are among `files`.
+    pub modules: Vec<(PathBuf, String)>,
+
+    /// Things the user should be warned about.
+    pub warnings: Vec<String>,
+}
+
+/**
+A file which is copied into the generated package, besides the manifest and the script.
+*/
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PackageFile {
+    /// Where the file goes, relative to the package.
+    pub path: String,
+
+    /// What goes in it.
+    pub content: Vec<u8>,
+}
+
+/**
+Defaults for command-line options, taken from the `[package.metadata.cargo-script]` table of the script's manifest.
+
+Anything given explicitly on the command line takes precedence over these.
+*/
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct ScriptDefaults {
+    /// Should the script be built in debug mode?  Set by either `debug` or `release`.
+    pub debug: Option<bool>,
+
+    /// Cargo features to enable, as for `--features`.
+    pub features: Option<String>,
+
+    /// Should the script be built as a Windows GUI program, as for `--gui`?
+    pub gui: Option<bool>,
+}
+
+/**
+What goes into splitting an input, besides the input itself; see `split_input` for what each of these does.  The default is a plain run, with nothing from the command line.
+*/
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SplitOptions<'a> {
+    /// Dependencies from the command line, as `(name, version)`.
+    pub deps: &'a [(String, String)],
+
+    /// Overrides from `--manifest-key`.
+    pub manifest_keys: &'a [String],
+
+    /// Items to inject into the prelude of an expression or loop.
+    pub prelude_items: &'a [String],
+
+    /// Is the script to be built as a set of tests?
+    pub test_module: bool,
+
+    /// The name to give the package, from `--name`.
+    pub pkg_name: Option<&'a str>,
+
+    /// The binary target to run, from `--bin`.
+    pub bin: Option<&'a str>,
+
+    /// A manifest from `--manifest-from-stdin`.
+    pub manifest_override: Option<&'a str>,
+
+    /// The project to take dependency versions from, for `--deps-from`.
+    pub deps_from: Option<&'a DepsFrom>,
+}
+
 /**
 Splits input into a complete Cargo manifest and unadultered Rust source.
 
 Unless we have prelude items to inject, in which case it will be *slightly* adulterated.
+
+If `test_module` is set and a file script has no `main` function, an empty one is added so that the script can be built as a set of tests.
+
+If `pkg_name` is given, it is used as the name of the generated package.  Otherwise, the package is named after the input, unless that would collide with one of its dependencies.
+
+`manifest_keys` are overrides from `--manifest-key`, which are applied over everything else; see `parse_manifest_key`.
+
+If `bin` is given, it names the binary target to run, rather than the one built from the script.
+
+`manifest_override` is a manifest from `--manifest-from-stdin`.  It is merged over the script's embedded manifest and `--dep`, but `manifest_keys` still go over the top of it.
 */
-pub fn split_input(
-    input: &Input<'_>,
-    deps: &[(String, String)],
-    prelude_items: &[String],
-) -> Result<(String, String)> {
+pub fn split_input(input: &Input<'_>, options: &SplitOptions<'_>) -> Result<SplitInput> {
+    let SplitOptions {
+        deps,
+        manifest_keys,
+        prelude_items,
+        test_module,
+        pkg_name,
+        bin,
+        manifest_override,
+        deps_from,
+    } = *options;
     let template_buf;
+    let test_module_buf;
+    let include_buf;
+    let mut items = "";
+    let mut build_block = None;
+    let mut includes = vec![];
+    let mut module_source = None;
     let (part_mani, source, template, sub_prelude) = match *input {
-        Input::File(_, _, content, _) => {
+        Input::File(_, path, content, _) => {
             assert_eq!(prelude_items.len(), 0);
             let content = strip_hashbang(content);
             let (manifest, source) =
                 find_embedded_manifest(content).unwrap_or((Manifest::Toml(""), content));
+            build_block = find_embedded_build_script(content);
+            let source = match RE_INCLUDE.is_match(source) {
+                true => {
+                    let (expanded, included) = expand_includes(source, path)?;
+                    include_buf = expanded;
+                    includes = included;
+                    &include_buf[..]
+                }
+                false => source,
+            };
+            module_source = Some(source);
+            let source = if test_module && !has_fn_main(source) {
+                test_module_buf = format!("{}{}", source, consts::TEST_MODULE_MAIN);
+                &test_module_buf[..]
+            } else {
+                source
+            };
 
             (manifest, source, templates::get_template("file")?, false)
         }
-        Input::Expr("meaning-of-life", None) | Input::Expr("meaning_of_life", None) => (
-            Manifest::Toml(""),
-            r#"
+        Input::Expr("meaning-of-life", None, None) | Input::Expr("meaning_of_life", None, None) => {
+            (
+                Manifest::Toml(""),
+                r#"
                 println!("42");
                 std::process::exit(42);
             "#,
-            templates::get_template("expr")?,
-            true,
-        ),
-        Input::Expr(content, template) => {
+                templates::get_template("expr")?,
+                true,
+            )
+        }
+        Input::Expr(content, template, _) => {
+            check_braces("expression", content)?;
+            let (expr_items, content) = split_expr_items(content)?;
+            items = expr_items;
             template_buf = templates::get_template(template.unwrap_or("expr"))?;
             let (manifest, template_src) = find_embedded_manifest(&template_buf)
                 .unwrap_or((Manifest::Toml(""), &template_buf));
             (manifest, content, template_src.into(), true)
         }
-        Input::Loop(content, count) => {
-            let templ = if count { "loop-count" } else { "loop" };
+        Input::Loop(content, count, fold, filter) => {
+            check_braces("loop closure", content)?;
+            let templ = match (count, fold, filter) {
+                (_, Some(_), _) => "loop-fold",
+                (_, None, true) => "loop-filter",
+                (true, None, false) => "loop-count",
+                (false, None, false) => "loop",
+            };
             (
                 Manifest::Toml(""),
                 content,
@@ -90,15 +256,36 @@ pub fn split_input(
     subs.insert(consts::SCRIPT_BODY_SUB, &source[..]);
 
     if sub_prelude {
-        prelude_str =
-            String::with_capacity(prelude_items.iter().map(|i| i.len() + 1).sum::<usize>());
-        for i in prelude_items {
+        prelude_str = String::with_capacity(
+            prelude_items.iter().map(|i| i.len() + 1).sum::<usize>() + items.len() + 1,
+        );
+        for i in prelude_items.iter().map(|i| &**i).chain(Some(items)) {
             prelude_str.push_str(i);
             prelude_str.push_str("\n");
         }
         subs.insert(consts::SCRIPT_PRELUDE_SUB, &prelude_str[..]);
     }
 
+    if let Input::Expr(_, template_name, expr_as) = *input {
+        // A template of the user's own might not know about `--expr-as`; better to say so than to quietly ignore it.
+        if expr_as.is_some() && !template.contains(&format!("#{{{}}}", consts::EXPR_TYPE_SUB)) {
+            return Err(MainError::OtherOwned(
+                Blame::Human,
+                format!(
+                    "the `{}` template has no `#{{{}}}` substitution, so it can't be used with `--expr-as`",
+                    template_name.unwrap_or("expr"),
+                    consts::EXPR_TYPE_SUB
+                ),
+            ));
+        }
+        subs.insert(consts::EXPR_TYPE_SUB, expr_as.unwrap_or("_"));
+    }
+
+    if let Input::Loop(_, _, Some((init, finish)), _) = *input {
+        subs.insert(consts::LOOP_INIT_SUB, init);
+        subs.insert(consts::LOOP_FINISH_SUB, finish);
+    }
+
     let source = templates::expand(&template, &subs)?;
 
     info!("part_mani: {:?}", part_mani);
@@ -107,28 +294,127 @@ pub fn split_input(
     let part_mani = part_mani.into_toml()?;
     info!("part_mani: {:?}", part_mani);
 
+    let manifest_keys = manifest_keys
+        .iter()
+        .map(|spec| parse_manifest_key(spec))
+        .collect::<Result<Vec<_>>>()?;
+    let override_mani = match manifest_override {
+        Some(s) => Some(toml::from_str::<toml::value::Table>(s).map_err(|err| {
+            MainError::OtherOwned(
+                Blame::Human,
+                format!("the manifest from stdin is not valid TOML: {}", err),
+            )
+        })?),
+        None => None,
+    };
+
+    // If the user named the package themselves, we leave it alone.
+    let user_named = pkg_name.is_some()
+        || part_mani
+            .get("package")
+            .and_then(|p| p.get("name"))
+            .is_some()
+        || override_mani
+            .as_ref()
+            .and_then(|m| m.get("package"))
+            .and_then(|p| p.get("name"))
+            .is_some()
+        || manifest_keys
+            .iter()
+            .any(|(key, _)| key[..] == ["package", "name"]);
+
     // It's-a mergin' time!
-    let def_mani = default_manifest(input)?;
+    let def_mani = default_manifest(input, pkg_name)?;
     let dep_mani = deps_manifest(deps)?;
 
+    let def_bin = def_mani.get("bin").and_then(|b| b.get(0)).cloned();
+    let def_bin_path = def_bin.as_ref().and_then(|b| b.get("path")).cloned();
+
     let mani = merge_manifest(def_mani, part_mani)?;
-    let mani = merge_manifest(mani, dep_mani)?;
+    let dep_mani = keep_dep_registries(&mani, dep_mani);
+    let mut mani = merge_manifest(mani, dep_mani)?;
+    if let Some(override_mani) = override_mani {
+        mani = merge_manifest(mani, override_mani)?;
+    }
+    for (key, value) in manifest_keys {
+        set_manifest_key(&mut mani, &key, value)?;
+    }
+
+    if let Some(deps_from) = deps_from {
+        fill_dep_versions(&mut mani, deps_from);
+    }
+
+    // If the script supplied its own binary targets, they probably still want to be built from the script.
+    if let (Some(toml::Value::Array(bins)), Some(def_bin_path)) =
+        (mani.get_mut("bin"), &def_bin_path)
+    {
+        for bin in bins.iter_mut() {
+            if let toml::Value::Table(bin) = bin {
+                bin.entry("path").or_insert_with(|| def_bin_path.clone());
+            }
+        }
+
+        // Any others are extras, which don't replace the script's own.
+        if !bins.iter().any(|b| b.get("path") == Some(def_bin_path)) {
+            bins.insert(0, def_bin.unwrap());
+        }
+    }
+
+    let mut warnings = vec![];
+    let defaults = script_defaults(&mani, &mut warnings)?;
+    if !user_named {
+        if let Some(note) = disambiguate_pkg_name(&mut mani) {
+            warnings.push(note);
+        }
+    }
+
+    let pkg_name = mani
+        .get("package")
+        .and_then(|p| p.get("name"))
+        .and_then(|n| n.as_str())
+        .ok_or((Blame::Human, "manifest does not specify a package name"))?
+        .to_owned();
+
+    let script_file = def_bin_path.as_ref().and_then(|p| p.as_str());
+    let bin_name = select_bin(&mani, script_file, bin)?;
 
     // Fix up relative paths.
-    let mani = fix_manifest_paths(mani, &input.base_path())?;
+    let mut mani = fix_manifest_paths(mani, &input.base_path())?;
+    let mut files = package_files(&mut mani, build_block, &input.base_path(), script_file)?;
+    let mut modules = vec![];
+    if let Some(source) = module_source {
+        for (from, file) in module_files(source, &input.base_path())? {
+            if !files.iter().any(|f| f.path == file.path) {
+                modules.push((from, file.path.clone()));
+                files.push(file);
+            }
+        }
+    }
     info!("mani: {:?}", mani);
 
     let mani_str = format!("{}", toml::Value::Table(mani));
     info!("mani_str: {}", mani_str);
 
-    Ok((mani_str, source))
+    Ok(SplitInput {
+        manifest: mani_str,
+        script: source,
+        pkg_name,
+        bin_name,
+        defaults,
+        files,
+        includes,
+        modules,
+        warnings,
+    })
 }
 
 #[test]
 fn test_split_input() {
     macro_rules! si {
         ($i:expr) => {
-            split_input(&$i, &[], &[]).ok()
+            split_input(&$i, &SplitOptions::default())
+                .ok()
+                .map(|s| (s.manifest, s.script))
         };
     }
 
@@ -155,6 +441,8 @@ path = "n.rs"
 authors = ["Anonymous"]
 name = "n"
 version = "0.1.0"
+
+[workspace]
 "#,
             r#"fn main() {}"#
         )
@@ -177,6 +465,8 @@ path = "n.rs"
 authors = ["Anonymous"]
 name = "n"
 version = "0.1.0"
+
+[workspace]
 "#,
             r#"
 ---
@@ -202,6 +492,8 @@ path = "n.rs"
 authors = ["Anonymous"]
 name = "n"
 version = "0.1.0"
+
+[workspace]
 "#,
             r#"[dependencies]
 time="0.1.25"
@@ -228,6 +520,8 @@ time = "0.1.25"
 authors = ["Anonymous"]
 name = "n"
 version = "0.1.0"
+
+[workspace]
 "#,
             r#"
 // Cargo-Deps: time="0.1.25"
@@ -254,6 +548,8 @@ time = "0.1.25"
 authors = ["Anonymous"]
 name = "n"
 version = "0.1.0"
+
+[workspace]
 "#,
             r#"
 // Cargo-Deps: time="0.1.25", libc="0.2.5"
@@ -286,6 +582,8 @@ time = "0.1.25"
 authors = ["Anonymous"]
 name = "n"
 version = "0.1.0"
+
+[workspace]
 "#,
             r#"
 /*!
@@ -302,6 +600,344 @@ fn main() {}
     );
 }
 
+/**
+Reads the script's defaults for command-line options from its manifest.
+
+Cargo ignores anything under `package.metadata`, so the table is left in place.  Keys we don't understand are warned about, rather than rejected, so that scripts written for a newer version of `cargo-script` still run.
+*/
+fn script_defaults(
+    mani: &toml::value::Table,
+    warnings: &mut Vec<String>,
+) -> Result<ScriptDefaults> {
+    let mut defaults = ScriptDefaults::default();
+
+    let table = match mani
+        .get("package")
+        .and_then(|p| p.get("metadata"))
+        .and_then(|m| m.get("cargo-script"))
+    {
+        Some(toml::Value::Table(table)) => table,
+        Some(_) => {
+            return Err((
+                Blame::Human,
+                "`package.metadata.cargo-script` in the manifest must be a table",
+            )
+                .into())
+        }
+        None => return Ok(defaults),
+    };
+
+    let bool_key = |key: &str| -> Result<Option<bool>> {
+        match table.get(key) {
+            None => Ok(None),
+            Some(toml::Value::Boolean(b)) => Ok(Some(*b)),
+            Some(_) => Err(MainError::OtherOwned(
+                Blame::Human,
+                format!(
+                    "`{}` in `[package.metadata.cargo-script]` must be `true` or `false`",
+                    key
+                ),
+            )),
+        }
+    };
+
+    defaults.debug =
+        match (bool_key("debug")?, bool_key("release")?) {
+            (Some(debug), Some(release)) if debug == release => return Err((
+                Blame::Human,
+                "`debug` and `release` in `[package.metadata.cargo-script]` contradict each other",
+            )
+                .into()),
+            (Some(debug), _) => Some(debug),
+            (None, release) => release.map(|r| !r),
+        };
+    defaults.gui = bool_key("gui")?;
+
+    defaults.features = match table.get("features") {
+        None => None,
+        Some(toml::Value::String(s)) => Some(s.clone()),
+        Some(toml::Value::Array(fs)) if fs.iter().all(|f| f.is_str()) => Some(
+            fs.iter()
+                .filter_map(|f| f.as_str())
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+        Some(_) => {
+            return Err((
+                Blame::Human,
+                "`features` in `[package.metadata.cargo-script]` must be a string or an array of strings",
+            )
+                .into())
+        }
+    };
+
+    for key in table.keys() {
+        match &**key {
+            "debug" | "release" | "features" | "gui" | "include" => (),
+            _ => warnings.push(format!(
+                "unsupported key `{}` in `[package.metadata.cargo-script]`; ignoring it",
+                key
+            )),
+        }
+    }
+
+    Ok(defaults)
+}
+
+#[test]
+fn test_split_input_loop_fold() {
+    let script = |i| split_input(&i, &SplitOptions::default()).unwrap().script;
+
+    let folded = script(Input::Loop(
+        "|n, _| *n += 1",
+        false,
+        Some(("0", "|n| n * 2")),
+        false,
+    ));
+    assert!(folded.contains("let mut acc = {0};"));
+    assert!(folded.contains("{|n, _| *n += 1}"));
+    assert!(folded.contains("{|n| n * 2}"));
+
+    let plain = script(Input::Loop("|l| l", false, None, false));
+    assert!(!plain.contains("acc"));
+
+    let filter = script(Input::Loop("|l| l.contains('x')", false, None, true));
+    assert!(filter.contains("FnMut(&str) -> bool"));
+    assert!(filter.contains("print!(\"{}\", line_buffer);"));
+}
+
+#[test]
+fn test_split_input_crlf() {
+    let dummy_path: ::std::path::PathBuf = "p".into();
+    macro_rules! si {
+        ($c:expr) => {
+            split_input(
+                &Input::File("n", &dummy_path, $c, 0),
+                &SplitOptions::default(),
+            )
+            .map(|s| (s.manifest, s.script))
+            .unwrap()
+        };
+    }
+
+    for lf in &[
+        "#!/usr/bin/env run-cargo-script\n// cargo-deps: time=\"0.1.25\", libc\nfn main() {}\n",
+        "#!/usr/bin/env run-cargo-script\n//! Doc.\n//!\n//! ```cargo\n//! [dependencies]\n//! time = \"0.1.25\"\n//! ```\nfn main() {}\n",
+        "/*!\n * Doc.\n *\n * ```cargo\n * [dependencies]\n * time = \"0.1.25\"\n * ```\n */\nfn main() {}\n",
+        "/*!\n```cargo\n[dependencies]\ntime = \"0.1.25\"\n```\n*/\nfn main() {}\n",
+    ] {
+        let crlf = lf.replace("\n", "\r\n");
+        let (lf_mani, lf_script) = si!(lf);
+        let (crlf_mani, crlf_script) = si!(&crlf);
+        assert!(lf_mani.contains("time = \"0.1.25\""), "with {:?}", lf);
+        assert_eq!(crlf_mani, lf_mani, "with {:?}", crlf);
+        assert_eq!(crlf_script, lf_script.replace("\n", "\r\n"), "with {:?}", crlf);
+        assert!(!crlf_script.starts_with("#!"));
+    }
+}
+
+#[test]
+fn test_script_defaults() {
+    macro_rules! sd {
+        ($s:expr) => {{
+            let mani: toml::value::Table = toml::from_str($s).unwrap();
+            let mut warnings = vec![];
+            script_defaults(&mani, &mut warnings)
+                .map(|d| (d, warnings))
+                .map_err(|e| e.to_string())
+        }};
+    }
+
+    macro_rules! d {
+        ($debug:expr, $features:expr) => {
+            ScriptDefaults {
+                debug: $debug,
+                features: $features.map(|s: &str| s.to_string()),
+                gui: None,
+            }
+        };
+    }
+
+    assert_eq!(sd!(""), Ok((d!(None, None), vec![])));
+    assert_eq!(sd!("[package]\nname = \"n\""), Ok((d!(None, None), vec![])));
+    assert_eq!(
+        sd!("[package.metadata.cargo-script]\nrelease = true"),
+        Ok((d!(Some(false), None), vec![]))
+    );
+    assert_eq!(
+        sd!("[package.metadata.cargo-script]\nrelease = false"),
+        Ok((d!(Some(true), None), vec![]))
+    );
+    assert_eq!(
+        sd!("[package.metadata.cargo-script]\ndebug = true\nrelease = false"),
+        Ok((d!(Some(true), None), vec![]))
+    );
+    assert_eq!(
+        sd!("[package.metadata.cargo-script]\ngui = true"),
+        Ok((
+            ScriptDefaults {
+                gui: Some(true),
+                ..d!(None, None)
+            },
+            vec![]
+        ))
+    );
+    assert_eq!(
+        sd!("[package.metadata.cargo-script]\ngui = \"yes\"").map(|_| ()),
+        Err("`gui` in `[package.metadata.cargo-script]` must be `true` or `false`".into())
+    );
+    assert_eq!(
+        sd!("[package.metadata.cargo-script]\nfeatures = \"a b\""),
+        Ok((d!(None, Some("a b")), vec![]))
+    );
+    assert_eq!(
+        sd!("[package.metadata.cargo-script]\nfeatures = [\"a\", \"b\"]"),
+        Ok((d!(None, Some("a b")), vec![]))
+    );
+    assert_eq!(
+        sd!("[package.metadata.cargo-script]\ntoolchain = \"nightly\""),
+        Ok((
+            d!(None, None),
+            vec![
+                "unsupported key `toolchain` in `[package.metadata.cargo-script]`; ignoring it"
+                    .to_string()
+            ]
+        ))
+    );
+
+    assert!(sd!("[package.metadata]\ncargo-script = 1").is_err());
+    assert!(sd!("[package.metadata.cargo-script]\nrelease = \"yes\"").is_err());
+    assert!(sd!("[package.metadata.cargo-script]\ndebug = true\nrelease = true").is_err());
+    assert!(sd!("[package.metadata.cargo-script]\nfeatures = [1]").is_err());
+}
+
+/**
+Pulls the files named by `//# include <path>` lines into a script's source, so that scripts can share code without needing a package to put it in.
+
+The included files go ahead of the script's own source, in the order they're named, each preceded by anything *it* includes.  Paths are relative to the file the line is in, so the script's own includes are relative to the script.  A file is only included once, however many times it's named, but a file which ends up including itself is an error.
+
+Returns the source with everything included, along with the path and contents of each file that was.
+*/
+pub fn expand_includes(
+    source: &str,
+    script_path: &Path,
+) -> Result<(String, Vec<(PathBuf, String)>)> {
+    let mut stack = vec![script_path
+        .canonicalize()
+        .unwrap_or_else(|_| script_path.into())];
+    let mut included = vec![];
+    collect_includes(source, script_path, &mut stack, &mut included)?;
+
+    let mut expanded = String::new();
+    for (_, content) in &included {
+        expanded.push_str(content);
+        if !content.ends_with('\n') {
+            expanded.push('\n');
+        }
+    }
+    expanded.push_str(source);
+    Ok((expanded, included))
+}
+
+fn collect_includes(
+    source: &str,
+    from: &Path,
+    stack: &mut Vec<PathBuf>,
+    included: &mut Vec<(PathBuf, String)>,
+) -> Result<()> {
+    for cap in RE_INCLUDE.captures_iter(source) {
+        let name = cap[1].trim_matches('"');
+        let path = from.parent().unwrap_or_else(|| Path::new("")).join(name);
+        let path = path.canonicalize().map_err(|err| {
+            MainError::OtherOwned(
+                Blame::Human,
+                format!(
+                    "could not find `{}`, included from `{}`: {}",
+                    name,
+                    from.display(),
+                    err
+                ),
+            )
+        })?;
+
+        if let Some(i) = stack.iter().position(|p| *p == path) {
+            let cycle: Vec<_> = stack[i..]
+                .iter()
+                .chain(Some(&path))
+                .map(|p| format!("`{}`", p.display()))
+                .collect();
+            return Err(MainError::OtherOwned(
+                Blame::Human,
+                format!("`//# include` cycle: {}", cycle.join(" includes ")),
+            ));
+        }
+        if included.iter().any(|(p, _)| *p == path) {
+            continue;
+        }
+
+        let content = fs::read_to_string(&path).map_err(|err| {
+            MainError::OtherOwned(
+                Blame::Human,
+                format!(
+                    "could not read `{}`, included from `{}`: {}",
+                    path.display(),
+                    from.display(),
+                    err
+                ),
+            )
+        })?;
+        stack.push(path.clone());
+        collect_includes(&content, &path, stack, included)?;
+        stack.pop();
+        included.push((path, content));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_expand_includes() {
+    let dir = tempdir::TempDir::new("cargo-script-test-include").unwrap();
+    let dir = dir.path().canonicalize().unwrap();
+    let write = |name: &str, content: &str| fs::write(dir.join(name), content).unwrap();
+    fs::create_dir(dir.join("lib")).unwrap();
+    write("lib/a.rs", "//# include b.rs\nfn a() {}");
+    write("lib/b.rs", "fn b() {}\n");
+    write("lib/c.rs", "//# include \"b.rs\"\nfn c() {}\n");
+    write("lib/loop.rs", "//# include ../script.rs\n");
+
+    let script = dir.join("script.rs");
+    let (source, included) = expand_includes(
+        "//# include lib/a.rs\n  //# include lib/c.rs\nfn main() {}\n",
+        &script,
+    )
+    .unwrap();
+    assert_eq!(
+        source,
+        "fn b() {}\n//# include b.rs\nfn a() {}\n//# include \"b.rs\"\nfn c() {}\n\
+         //# include lib/a.rs\n  //# include lib/c.rs\nfn main() {}\n"
+    );
+    let paths: Vec<_> = included.iter().map(|(p, _)| p.clone()).collect();
+    assert_eq!(
+        paths,
+        vec![
+            dir.join("lib/b.rs"),
+            dir.join("lib/a.rs"),
+            dir.join("lib/c.rs")
+        ]
+    );
+
+    write("script.rs", "//# include lib/loop.rs\nfn main() {}\n");
+    let err = expand_includes("//# include lib/loop.rs\n", &script).unwrap_err();
+    assert!(err.to_string().contains("`//# include` cycle"), "{}", err);
+
+    let err = expand_includes("//# include missing.rs\n", &script).unwrap_err();
+    assert!(
+        err.to_string().contains("could not find `missing.rs`"),
+        "{}",
+        err
+    );
+}
+
 /**
 Returns a slice of the input string with the leading hashbang, if there is one, omitted.
 */
@@ -366,9 +1002,14 @@ impl<'s> Manifest<'s> {
             DepList(s) => Manifest::dep_list_to_toml(s),
         }
         .map_err(|e| {
+            // Windows paths are the usual way to end up with one of these.
+            let msg = match e.to_string().contains("invalid escape") {
+                true => "could not parse embedded manifest (to write a path with `\\` in it, use single quotes, as in `path = '..\\lib'`, or `/` instead)",
+                false => "could not parse embedded manifest",
+            };
             MainError::Tag(
                 Blame::Human,
-                "could not parse embedded manifest".into(),
+                msg.into(),
                 Box::new(MainError::Other(Blame::Internal, Box::new(e))),
             )
         })
@@ -377,7 +1018,7 @@ impl<'s> Manifest<'s> {
     fn dep_list_to_toml(s: &str) -> ::std::result::Result<toml::value::Table, toml::de::Error> {
         let mut r = String::new();
         r.push_str("[dependencies]\n");
-        for dep in s.trim().split(',') {
+        for dep in split_dep_list(s.trim()) {
             // If there's no version specified, add one.
             match dep.contains('=') {
                 true => {
@@ -395,52 +1036,565 @@ impl<'s> Manifest<'s> {
     }
 }
 
-/**
-Locates a manifest embedded in Rust source.
+#[test]
+fn test_manifest_escape_hint() {
+    let err = |m| Manifest::Toml(m).into_toml().unwrap_err().to_string();
+    assert!(err("[dependencies]\nlib = { path = \"..\\lib\" }\n").contains("use single quotes"));
+    assert!(!err("[dependencies\n").contains("use single quotes"));
+    assert!(
+        Manifest::Toml("[dependencies]\nlib = { path = '..\\lib' }\n")
+            .into_toml()
+            .is_ok()
+    );
+}
 
-Returns `Some((manifest, source))` if it finds a manifest, `None` otherwise.
+/**
+Splits a `cargo-deps` list on the commas which separate dependencies, leaving alone any inside an inline table or a string, such as in `foo={ version = "1", registry = "company" }`.
 */
-fn find_embedded_manifest(s: &str) -> Option<(Manifest<'_>, &str)> {
-    find_short_comment_manifest(s).or_else(|| find_code_block_manifest(s))
+fn split_dep_list(s: &str) -> Vec<&str> {
+    let mut deps = vec![];
+    let mut depth = 0usize;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut start = 0;
+
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some('"'), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '{') | (None, '[') => depth += 1,
+            (None, '}') | (None, ']') => depth = depth.saturating_sub(1),
+            (None, ',') if depth == 0 => {
+                deps.push(&s[start..i]);
+                start = i + 1;
+            }
+            (None, _) => (),
+        }
+    }
+    deps.push(&s[start..]);
+    deps
 }
 
-#[test]
-fn test_find_embedded_manifest() {
-    use self::Manifest::*;
+/**
+Makes sure the braces in an expression or loop closure pair up.  The code is put inside a block in the template, so a stray `}` would close that block early, and rustc would complain about the template rather than the code.
 
-    let fem = find_embedded_manifest;
+Braces in comments and in string and character literals don't count.
+*/
+fn check_braces(what: &str, code: &str) -> Result<()> {
+    let chars: Vec<char> = code.chars().collect();
+    let is_ident = |i: usize| chars[i].is_alphanumeric() || chars[i] == '_';
+    let position = |i: usize| {
+        let before = &chars[..i];
+        let line = before.iter().filter(|&&c| c == '\n').count() + 1;
+        let line_start = before.iter().rposition(|&c| c == '\n').map_or(0, |p| p + 1);
+        (line, i - line_start + 1)
+    };
 
-    assert_eq!(fem("fn main() {}"), None);
+    let mut open = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '/' if chars.get(i + 1) == Some(&'/') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                let mut depth = 0;
+                while i < chars.len() {
+                    match (chars[i], chars.get(i + 1)) {
+                        ('/', Some(&'*')) => depth += 1,
+                        ('*', Some(&'/')) => depth -= 1,
+                        _ => {
+                            i += 1;
+                            continue;
+                        }
+                    }
+                    i += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                continue;
+            }
+            '"' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    i += if chars[i] == '\\' { 2 } else { 1 };
+                }
+            }
+            // A raw string, so long as the `r` doesn't end some other identifier.
+            'r' if (i == 0
+                || !is_ident(i - 1)
+                || (chars[i - 1] == 'b' && (i == 1 || !is_ident(i - 2))))
+                && chars[i + 1..].iter().find(|&&c| c != '#') == Some(&'"') =>
+            {
+                let hashes = chars[i + 1..].iter().take_while(|&&c| c == '#').count();
+                i += hashes + 2;
+                while i < chars.len()
+                    && !(chars[i] == '"'
+                        && chars[i + 1..]
+                            .iter()
+                            .take(hashes)
+                            .filter(|&&c| c == '#')
+                            .count()
+                            == hashes)
+                {
+                    i += 1;
+                }
+                i += hashes;
+            }
+            // Either a character literal, or a lifetime or label, which has no closing quote.
+            '\'' if chars.get(i + 1) == Some(&'\\') => {
+                i += 3;
+                while i < chars.len() && chars[i] != '\'' {
+                    i += 1;
+                }
+            }
+            '\'' if chars.get(i + 2) == Some(&'\'') => i += 2,
+            '{' => open.push(i),
+            '}' => {
+                if open.pop().is_none() {
+                    let (line, col) = position(i);
+                    return Err(MainError::OtherOwned(
+                        Blame::Human,
+                        format!(
+                            "the {} has an unmatched `}}` at line {}, column {}",
+                            what, line, col
+                        ),
+                    ));
+                }
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+
+    match open.pop() {
+        Some(i) => {
+            let (line, col) = position(i);
+            Err(MainError::OtherOwned(
+                Blame::Human,
+                format!(
+                    "the {} has an unclosed `{{` at line {}, column {}",
+                    what, line, col
+                ),
+            ))
+        }
+        None => Ok(()),
+    }
+}
 
+#[test]
+fn test_check_braces() {
+    let cb = |code| check_braces("expression", code).map_err(|e| e.to_string());
+
+    assert_eq!(cb("1"), Ok(()));
+    assert_eq!(cb("let x = { 5 }; if x > 2 { x } else { 0 }"), Ok(()));
+    assert_eq!(cb("'{'"), Ok(()));
+    assert_eq!(cb("('\\'', '\\u{7d}', b'}')"), Ok(()));
+    assert_eq!(cb("\"}\\\"}\".len()"), Ok(()));
+    assert_eq!(cb("r#\"}\"#.len() + br\"\\\".len()"), Ok(()));
     assert_eq!(
-        fem("
-fn main() {}
-"),
-        None
+        cb("fn f<'a>(s: &'a str) -> &'a str { s } 'l: loop { break 'l f(\"\") }"),
+        Ok(())
     );
+    assert_eq!(cb("1 // }\n/* { /* } */ */"), Ok(()));
+    assert_eq!(cb("for { }"), Ok(()));
 
-    // Ensure removed prefix manifests don't work.
     assert_eq!(
-        fem(r#"
----
-fn main() {}
-"#),
-        None
+        cb("1 }; fn oops() { 2"),
+        Err("the expression has an unmatched `}` at line 1, column 3".into())
     );
-
     assert_eq!(
-        fem("[dependencies]
-time = \"0.1.25\"
----
-fn main() {}
-"),
-        None
+        cb("let v = vec![1];\nv.iter().map(|x| { x + 1 ).count()"),
+        Err("the expression has an unclosed `{` at line 2, column 18".into())
     );
-
     assert_eq!(
-        fem("[dependencies]
-time = \"0.1.25\"
-
+        check_braces("loop closure", "|l| {").map_err(|e| e.to_string()),
+        Err("the loop closure has an unclosed `{` at line 1, column 5".into())
+    );
+}
+
+/**
+Splits any item definitions, such as helper functions or types, off the front of an expression.  These go in the prelude, rather than inside `try_main`, so the expression's value can be of a type it defines, and its functions can be generic or return `impl Trait`.
+
+Items after the final expression are an error, since the expression would otherwise quietly become a statement, and its value would go unshown.  If the expression can't be parsed, it's left alone, so that rustc can say what's wrong with it.
+*/
+fn split_expr_items(expr: &str) -> Result<(&str, &str)> {
+    use syn::parse::{ParseStream, Parser};
+    use syn::spanned::Spanned;
+    use syn::{Block, Item, Stmt};
+
+    let item_after = |start: usize| {
+        let before = &expr[..start];
+        let line = before.matches('\n').count() + 1;
+        let col = before.rsplit('\n').next().unwrap().chars().count() + 1;
+        Err(MainError::OtherOwned(
+            Blame::Human,
+            format!(
+                "the expression defines an item at line {}, column {}, after its final expression; \
+                 items have to come first",
+                line, col
+            ),
+        ))
+    };
+
+    let stmts = match Block::parse_within.parse_str(expr) {
+        Ok(stmts) => stmts,
+        Err(err) => {
+            // An item straight after the final expression looks like a missing semicolon.
+            let start = err.span().byte_range().start;
+            let only_items = |input: ParseStream<'_>| {
+                while !input.is_empty() {
+                    input.parse::<Item>()?;
+                }
+                Ok(())
+            };
+            if start > 0
+                && !expr[start..].trim().is_empty()
+                && only_items.parse_str(&expr[start..]).is_ok()
+            {
+                return item_after(start);
+            }
+            info!("could not parse expression: {}", err);
+            return Ok(("", expr));
+        }
+    };
+
+    let is_item = |stmt: &&Stmt| matches!(stmt, Stmt::Item(_));
+    let leading = stmts.iter().take_while(is_item).count();
+    let trailing = stmts[leading..].iter().rev().take_while(is_item).count();
+    if leading < stmts.len() && trailing > 0 {
+        return item_after(stmts[stmts.len() - trailing].span().byte_range().start);
+    }
+
+    let split = match leading {
+        0 => 0,
+        n => stmts[n - 1].span().byte_range().end,
+    };
+    Ok(expr.split_at(split))
+}
+
+#[test]
+fn test_split_expr_items() {
+    let sei = |expr| split_expr_items(expr).map_err(|e| e.to_string());
+
+    assert_eq!(sei("1 + 1"), Ok(("", "1 + 1")));
+    assert_eq!(sei("println!(\"hi\"); 2"), Ok(("", "println!(\"hi\"); 2")));
+    assert_eq!(
+        sei("fn fib(n: u64) -> u64 { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } } fib(10)"),
+        Ok((
+            "fn fib(n: u64) -> u64 { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } }",
+            " fib(10)"
+        ))
+    );
+    assert_eq!(
+        sei("#[derive(Debug)]\nstruct P(i32);\nuse std::mem;\nmem::size_of::<P>()"),
+        Ok((
+            "#[derive(Debug)]\nstruct P(i32);\nuse std::mem;",
+            "\nmem::size_of::<P>()"
+        ))
+    );
+
+    // Items in the middle stay where they are.
+    assert_eq!(
+        sei("let x = 2; fn f(x: i32) -> i32 { x } f(x)"),
+        Ok(("", "let x = 2; fn f(x: i32) -> i32 { x } f(x)"))
+    );
+
+    // Only items is fine; the value is just `()`.
+    assert_eq!(sei("fn f() {}"), Ok(("fn f() {}", "")));
+
+    // Anything that won't parse is left alone, for rustc to complain about.
+    assert_eq!(sei("1 +"), Ok(("", "1 +")));
+
+    assert_eq!(
+        sei("f(2)\nfn f(x: i32) -> i32 { x }"),
+        Err(
+            "the expression defines an item at line 2, column 1, after its final expression; \
+             items have to come first"
+                .into()
+        )
+    );
+    assert_eq!(
+        sei("let x = 1; x; struct S;"),
+        Err(
+            "the expression defines an item at line 1, column 15, after its final expression; \
+             items have to come first"
+                .into()
+        )
+    );
+}
+
+/**
+Makes a script build as a Windows GUI program, which doesn't get a console window, for `--gui`.
+
+The attribute has to come before anything else in the crate.  It goes on the first line, rather than a line of its own, so that line numbers in the generated source still match the script's.
+*/
+pub fn with_windows_subsystem(script: &str) -> String {
+    format!("#![windows_subsystem = \"windows\"] {}", script)
+}
+
+#[test]
+fn test_with_windows_subsystem() {
+    let script = "//! Doc.\n#![allow(unused)]\nfn main() {}\n";
+    let gui = with_windows_subsystem(script);
+    assert!(gui.starts_with("#![windows_subsystem = \"windows\"] //! Doc.\n"));
+    assert_eq!(gui.lines().count(), script.lines().count());
+    assert!(gui.ends_with(script));
+}
+
+/**
+Returns `true` if the source appears to define a `main` function.
+
+This is a heuristic: it looks for a line starting with `fn main(`, and doesn't try to understand comments, strings, or nested modules.
+*/
+fn has_fn_main(s: &str) -> bool {
+    RE_FN_MAIN.is_match(s)
+}
+
+/**
+Returns `true` if the source looks like it's just a set of tests: it has a `#[test]` function, but no `main` function.
+*/
+pub fn looks_like_test_module(s: &str) -> bool {
+    RE_TEST_ATTR.is_match(s) && !has_fn_main(s)
+}
+
+/**
+Returns `true` if the script is a set of Criterion benchmarks: it uses `criterion_main!`, and depends on `criterion` (usually as a dev-dependency).
+*/
+pub fn is_criterion_bench(mani: &str, script: &str) -> bool {
+    use toml::Value;
+
+    if !RE_CRITERION_MAIN.is_match(script) {
+        return false;
+    }
+    let mani: Value = match toml::from_str(mani) {
+        Ok(mani) => mani,
+        Err(_) => return false,
+    };
+    ["dependencies", "dev-dependencies"].iter().any(|key| {
+        mani.get(key)
+            .and_then(|deps| deps.get("criterion"))
+            .is_some()
+    })
+}
+
+/**
+Turns the manifest of a generated package into one which builds the script as a Criterion benchmark, rather than as the binary target `bin_name`.
+
+Criterion supplies its own `main`, so the benchmark has to go without libtest's harness.  Cargo won't have a package with nothing but benchmarks in it, so if there are no other binaries left, the package gets an empty library as well; that's the returned file.
+*/
+pub fn bench_manifest(mani: &str, bin_name: &str) -> Result<(String, Option<PackageFile>)> {
+    use toml::Value;
+
+    let mut mani: toml::value::Table =
+        toml::from_str(mani).map_err(|e| MainError::Other(Blame::Internal, Box::new(e)))?;
+
+    let mut bench = None;
+    let mut bins_left = false;
+    if let Some(Value::Array(bins)) = mani.get_mut("bin") {
+        let is_script = |bin: &Value| bin.get("name").and_then(Value::as_str) == Some(bin_name);
+        bench = bins.iter().position(is_script).map(|i| bins.remove(i));
+        bins_left = !bins.is_empty();
+    }
+    if !bins_left {
+        mani.remove("bin");
+    }
+    let mut bench = match bench {
+        Some(Value::Table(bench)) => bench,
+        _ => {
+            return Err(MainError::OtherOwned(
+                Blame::Internal,
+                format!("generated manifest has no binary target `{}`", bin_name),
+            ))
+        }
+    };
+    bench.insert("harness".into(), Value::Boolean(false));
+    mani.insert("bench".into(), Value::Array(vec![Value::Table(bench)]));
+
+    let lib = match bins_left {
+        true => None,
+        false => {
+            let mut lib = toml::value::Table::new();
+            lib.insert("path".into(), Value::String(consts::BENCH_LIB_NAME.into()));
+            mani.insert("lib".into(), Value::Table(lib));
+            Some(PackageFile {
+                path: consts::BENCH_LIB_NAME.into(),
+                content: vec![],
+            })
+        }
+    };
+
+    Ok((format!("{}", Value::Table(mani)), lib))
+}
+
+#[test]
+fn test_is_criterion_bench() {
+    let mani = "[dev-dependencies]\ncriterion = \"0.5\"\n";
+    let script = "criterion_group!(benches, a);\ncriterion_main!(benches);\n";
+
+    assert!(is_criterion_bench(mani, script));
+    assert!(is_criterion_bench(
+        "[dependencies.criterion]\nversion = \"0.5\"\n",
+        script
+    ));
+    assert!(!is_criterion_bench("[dependencies]\n", script));
+    assert!(!is_criterion_bench(mani, "#[bench]\nfn b() {}\n"));
+}
+
+#[test]
+fn test_bench_manifest() {
+    let (mani, lib) = bench_manifest(
+        r#"[[bin]]
+name = "n"
+path = "n.rs"
+
+[package]
+name = "n"
+version = "0.1.0"
+"#,
+        "n",
+    )
+    .unwrap();
+    assert_eq!(
+        mani,
+        r#"[[bench]]
+harness = false
+name = "n"
+path = "n.rs"
+
+[lib]
+path = "cargo-script-bench-lib.rs"
+
+[package]
+name = "n"
+version = "0.1.0"
+"#
+    );
+    assert_eq!(lib.map(|f| f.path), Some(consts::BENCH_LIB_NAME.into()));
+
+    let (mani, lib) = bench_manifest(
+        r#"[[bin]]
+name = "helper"
+path = "helper.rs"
+
+[[bin]]
+name = "n"
+path = "n.rs"
+"#,
+        "n",
+    )
+    .unwrap();
+    assert_eq!(
+        mani,
+        r#"[[bench]]
+harness = false
+name = "n"
+path = "n.rs"
+
+[[bin]]
+name = "helper"
+path = "helper.rs"
+"#
+    );
+    assert_eq!(lib, None);
+}
+
+#[test]
+fn test_split_dep_list() {
+    assert_eq!(split_dep_list("time"), vec!["time"]);
+    assert_eq!(
+        split_dep_list(r#"time="0.1.25", libc="0.2.5""#),
+        vec![r#"time="0.1.25""#, r#" libc="0.2.5""#]
+    );
+    assert_eq!(
+        split_dep_list(r#"a={ version = "1", registry = "company" },b"#),
+        vec![r#"a={ version = "1", registry = "company" }"#, "b"]
+    );
+    assert_eq!(
+        split_dep_list(r#"a={ version = "1", features = ["x", "y"] },b=">=1, <2""#),
+        vec![
+            r#"a={ version = "1", features = ["x", "y"] }"#,
+            r#"b=">=1, <2""#
+        ]
+    );
+    assert_eq!(
+        split_dep_list(r#"a={ registry = "odd\",name" },b"#),
+        vec![r#"a={ registry = "odd\",name" }"#, "b"]
+    );
+}
+
+#[test]
+fn test_looks_like_test_module() {
+    assert!(!looks_like_test_module(""));
+    assert!(!looks_like_test_module("fn main() {}"));
+    assert!(!looks_like_test_module(
+        "#[test]\nfn a() {}\n\nfn main() {}"
+    ));
+    assert!(!looks_like_test_module(
+        "#[test]\nfn a() {}\n\npub fn main () {}"
+    ));
+    assert!(looks_like_test_module("#[test]\nfn a() {}"));
+    assert!(looks_like_test_module(
+        "#[ test ]\nfn a() {}\nfn main_helper() {}"
+    ));
+    assert!(looks_like_test_module(
+        "#[cfg(test)]\nmod tests {\n    #[test]\n    fn a() {}\n}"
+    ));
+}
+
+/**
+Locates a manifest embedded in Rust source.
+
+Returns `Some((manifest, source))` if it finds a manifest, `None` otherwise.
+*/
+fn find_embedded_manifest(s: &str) -> Option<(Manifest<'_>, &str)> {
+    find_short_comment_manifest(s).or_else(|| find_code_block_manifest(s))
+}
+
+#[test]
+fn test_find_embedded_manifest() {
+    use self::Manifest::*;
+
+    let fem = find_embedded_manifest;
+
+    assert_eq!(fem("fn main() {}"), None);
+
+    assert_eq!(
+        fem("
+fn main() {}
+"),
+        None
+    );
+
+    // Ensure removed prefix manifests don't work.
+    assert_eq!(
+        fem(r#"
+---
+fn main() {}
+"#),
+        None
+    );
+
+    assert_eq!(
+        fem("[dependencies]
+time = \"0.1.25\"
+---
+fn main() {}
+"),
+        None
+    );
+
+    assert_eq!(
+        fem("[dependencies]
+time = \"0.1.25\"
+
 fn main() {}
 "),
         None
@@ -660,76 +1814,591 @@ fn find_code_block_manifest(s: &str) -> Option<(Manifest<'_>, &str)> {
 }
 
 /**
-Extracts the first `Cargo` fenced code block from a chunk of Markdown.
+Locates a build script embedded in Rust source, as a `build-script` code block in the same doc comment a code block manifest would be in.
 */
-fn scrape_markdown_manifest(content: &str) -> Result<Option<String>> {
-    use hoedown::{Buffer, Markdown, Render};
+fn find_embedded_build_script(s: &str) -> Option<String> {
+    let start = RE_CRATE_COMMENT.captures(s)?.get(1)?.start();
+    let comment = match extract_comment(&s[start..]) {
+        Ok(s) => s,
+        Err(err) => {
+            error!("error slicing comment: {}", err);
+            return None;
+        }
+    };
+    scrape_markdown_block(&comment, consts::BUILD_SCRIPT_LANG).unwrap_or(None)
+}
 
-    // To match librustdoc/html/markdown.rs, HOEDOWN_EXTENSIONS.
-    let exts = hoedown::NO_INTRA_EMPHASIS
-        | hoedown::TABLES
-        | hoedown::FENCED_CODE
-        | hoedown::AUTOLINK
-        | hoedown::STRIKETHROUGH
-        | hoedown::SUPERSCRIPT
-        | hoedown::FOOTNOTES;
+/**
+The leading doc comment of a script, split into lines, along with where its `cargo` code block is.
+*/
+struct ManifestBlock<'s> {
+    /// Offset of the comment in the (hashbang-less) source.
+    start: usize,
+    /// Every line from the start of the comment to the end of the source.
+    lines: Vec<&'s str>,
+    /// How many of `lines` belong to the comment.
+    comment_len: usize,
+    /// Index of the line opening the code block.
+    open: usize,
+    /// Index of the line closing the code block.
+    close: usize,
+    /// Is this a `/*!` comment, rather than `//!` lines?
+    block: bool,
+}
 
-    let md = Markdown::new(&content).extensions(exts);
+/**
+Finds the leading doc comment of `content`, which must already have had its hashbang stripped, and the `cargo` code block inside it.
+*/
+fn find_manifest_block(content: &str) -> Option<ManifestBlock<'_>> {
+    let start = match (
+        find_code_block_manifest(content),
+        RE_CRATE_COMMENT.captures(content),
+    ) {
+        (Some(_), Some(cap)) => cap.get(1).unwrap().start(),
+        _ => return None,
+    };
 
-    struct ManifestScraper {
-        seen_manifest: bool,
-    }
+    let is_fence = |line: &str| {
+        doc_line_text(line).starts_with("```") || doc_line_text(line).starts_with("~~~")
+    };
 
-    impl Render for ManifestScraper {
-        fn code_block(
-            &mut self,
-            output: &mut Buffer,
-            text: Option<&Buffer>,
-            lang: Option<&Buffer>,
-        ) {
-            let lang = lang.map(|b| b.to_str().unwrap()).unwrap_or("");
+    let comment = &content[start..];
+    let lines: Vec<&str> = comment.split_inclusive('\n').collect();
+    let block = comment.starts_with("/*!");
+    let comment_len = match block {
+        true => lines
+            .iter()
+            .position(|l| l.contains("*/"))
+            .map_or(lines.len(), |i| i + 1),
+        false => lines
+            .iter()
+            .position(|l| !l.trim_start().starts_with("//!"))
+            .unwrap_or(lines.len()),
+    };
 
-            if !self.seen_manifest && lang.eq_ignore_ascii_case("cargo") {
-                // Pass it through.
-                info!("found code block manifest");
-                if let Some(text) = text {
-                    output.pipe(text);
-                }
-                self.seen_manifest = true;
-            }
-        }
-    }
+    let open = lines[..comment_len]
+        .iter()
+        .position(|l| is_fence(l) && doc_line_text(l)[3..].trim().eq_ignore_ascii_case("cargo"))?;
+    let close = lines[open + 1..comment_len]
+        .iter()
+        .position(|l| is_fence(l))
+        .map(|i| open + 1 + i)?;
+
+    Some(ManifestBlock {
+        start,
+        lines,
+        comment_len,
+        open,
+        close,
+        block,
+    })
+}
 
-    let mut ms = ManifestScraper {
-        seen_manifest: false,
+/**
+What's left of a line of a doc comment without its markers.
+*/
+fn doc_line_text(line: &str) -> &str {
+    let line = line.trim();
+    let line = line.strip_prefix("//!").unwrap_or(line);
+    let line = line.strip_prefix("/*!").unwrap_or(line);
+    let line = line.strip_suffix("*/").unwrap_or(line);
+    line.trim_start_matches('*').trim()
+}
+
+/**
+Returns the script's source without its hashbang or embedded manifest, for use as a regular Cargo package.
+
+Only the `cargo` code block is taken out of a doc comment, so the rest of the script's documentation survives.  If that leaves the comment empty, it goes too.
+*/
+pub fn strip_embedded_manifest(content: &str) -> String {
+    let content = strip_hashbang(content);
+    if let Some(m) = RE_SHORT_MANIFEST.find(content) {
+        return content[m.end()..].into();
+    }
+    let ManifestBlock {
+        start,
+        mut lines,
+        comment_len,
+        open,
+        close,
+        block,
+    } = match find_manifest_block(content) {
+        Some(mb) => mb,
+        None => return content.into(),
     };
-    let mani_buf = ms.render(&md);
+    let (before, comment) = content.split_at(start);
+
+    // Don't take the end of a block comment with us.
+    let keep_last = block && close + 1 == comment_len;
+    lines.drain(open..close + 1);
+    let mut comment_len = comment_len - (close + 1 - open);
+    if keep_last {
+        let end = match comment.contains("\r\n") {
+            true => "*/\r\n",
+            false => "*/\n",
+        };
+        lines.insert(open, end);
+        comment_len += 1;
+    }
 
-    if !ms.seen_manifest {
-        return Ok(None);
+    if lines[..comment_len]
+        .iter()
+        .all(|l| doc_line_text(l).is_empty())
+    {
+        lines.drain(..comment_len);
     }
-    mani_buf
-        .to_str()
-        .map(|s| Some(s.into()))
-        .map_err(|_| "error decoding manifest as UTF-8".into())
+
+    let mut r = String::from(before);
+    r.extend(lines);
+    r
 }
 
 #[test]
-fn test_scrape_markdown_manifest() {
-    macro_rules! smm {
-        ($c:expr) => {
-            scrape_markdown_manifest($c).map_err(|e| e.to_string())
-        };
-    }
+fn test_strip_embedded_manifest() {
+    let sem = strip_embedded_manifest;
 
+    assert_eq!(sem("fn main() {}\n"), "fn main() {}\n");
     assert_eq!(
-        smm!(
-            r#"There is no manifest in this comment.
-"#
-        ),
-        Ok(None)
+        sem("#!/usr/bin/env run-cargo-script\nfn main() {}\n"),
+        "fn main() {}\n"
     );
-
+    assert_eq!(
+        sem("// cargo-deps: time=\"0.1.25\"\nfn main() {}\n"),
+        "fn main() {}\n"
+    );
+    assert_eq!(
+        sem(r#"#!/usr/bin/env run-cargo-script
+//! ```cargo
+//! [dependencies]
+//! time = "0.1.25"
+//! ```
+fn main() {}
+"#),
+        "fn main() {}\n"
+    );
+    assert_eq!(
+        sem(r#"//! Prints the time.
+//!
+//! ```cargo
+//! [dependencies]
+//! time = "0.1.25"
+//! ```
+//!
+//! Really.
+fn main() {}
+"#),
+        r#"//! Prints the time.
+//!
+//!
+//! Really.
+fn main() {}
+"#
+    );
+    assert_eq!(
+        sem(r#"/*!
+```cargo
+[dependencies]
+time = "0.1.25"
+```
+*/
+fn main() {}
+"#),
+        "fn main() {}\n"
+    );
+    assert_eq!(
+        sem(r#"/*!
+ * Prints the time.
+ *
+ * ```cargo
+ * [dependencies]
+ * time = "0.1.25"
+ * ```
+ */
+fn main() {}
+"#),
+        r#"/*!
+ * Prints the time.
+ *
+ */
+fn main() {}
+"#
+    );
+
+    // Other code blocks aren't touched.
+    let other = r#"//! ```
+//! let x = 1;
+//! ```
+fn main() {}
+"#;
+    assert_eq!(sem(other), other);
+
+    // CRLF line endings are kept, and don't get in the way.
+    for lf in &[
+        "#!/usr/bin/env run-cargo-script\n// cargo-deps: time\nfn main() {}\n",
+        "#!/usr/bin/env run-cargo-script\n//! Doc.\n//! ```cargo\n//! [dependencies]\n//! ```\nfn main() {}\n",
+        "/*!\n * Doc.\n * ```cargo\n * [dependencies]\n * ``` */\nfn main() {}\n",
+        "/*!\n```cargo\n[dependencies]\n```\n*/\nfn main() {}\n",
+    ] {
+        let crlf = lf.replace("\n", "\r\n");
+        assert_eq!(sem(&crlf), sem(lf).replace("\n", "\r\n"), "with {:?}", crlf);
+    }
+}
+
+/**
+Splits a script into its leading lines which are special to cargo-script (the hashbang, and the doc comment or `cargo-deps` line holding the embedded manifest, along with any blank lines after them), and the Rust source which follows.
+
+If there's no embedded manifest, a leading doc comment is left with the source.
+*/
+pub fn split_script_header(content: &str) -> (&str, &str) {
+    let rest = strip_hashbang(content);
+    let mut end = content.len() - rest.len();
+
+    if let Some(m) = RE_SHORT_MANIFEST.find(rest) {
+        end += m.end();
+    } else if let (Some(_), Some(cap)) = (
+        find_code_block_manifest(rest),
+        RE_CRATE_COMMENT.captures(rest),
+    ) {
+        let start = cap.get(1).unwrap().start();
+        let comment = &rest[start..];
+        let len = match comment.starts_with("/*!") {
+            true => {
+                // Block comments nest, so find the `*/` that actually closes this one.
+                let mut depth = 0;
+                let close = RE_NESTING.find_iter(comment).find(|m| {
+                    depth += if m.as_str() == "/*" { 1 } else { -1 };
+                    depth == 0
+                });
+                let close = close.map_or(comment.len(), |m| m.end());
+                close
+                    + comment[close..]
+                        .find('\n')
+                        .map_or(comment.len() - close, |i| i + 1)
+            }
+            false => comment
+                .split_inclusive('\n')
+                .take_while(|l| l.trim_start().starts_with("//!"))
+                .map(str::len)
+                .sum(),
+        };
+        end += start + len;
+    }
+
+    let blank: usize = content[end..]
+        .split_inclusive('\n')
+        .take_while(|l| l.ends_with('\n') && l.trim().is_empty())
+        .map(str::len)
+        .sum();
+    content.split_at(end + blank)
+}
+
+#[test]
+fn test_split_script_header() {
+    let ssh = split_script_header;
+
+    assert_eq!(ssh("fn main() {}\n"), ("", "fn main() {}\n"));
+    assert_eq!(
+        ssh("#!/usr/bin/env run-cargo-script\n\nfn main() {}\n"),
+        ("#!/usr/bin/env run-cargo-script\n\n", "fn main() {}\n")
+    );
+    assert_eq!(
+        ssh("// cargo-deps: time=\"0.1.25\"\r\nfn main() {}\r\n"),
+        ("// cargo-deps: time=\"0.1.25\"\r\n", "fn main() {}\r\n")
+    );
+    assert_eq!(
+        ssh(r#"#!/usr/bin/env run-cargo-script
+//! Prints the time.
+//!
+//! ```cargo
+//! [dependencies]
+//! time = "0.1.25"
+//! ```
+
+use std::io;
+fn main() {}
+"#),
+        (
+            r#"#!/usr/bin/env run-cargo-script
+//! Prints the time.
+//!
+//! ```cargo
+//! [dependencies]
+//! time = "0.1.25"
+//! ```
+
+"#,
+            "use std::io;\nfn main() {}\n"
+        )
+    );
+    assert_eq!(
+        ssh(r#"/*!
+```cargo
+[dependencies]
+time = "0.1.25"
+```
+*/
+fn main() {}
+"#),
+        (
+            "/*!\n```cargo\n[dependencies]\ntime = \"0.1.25\"\n```\n*/\n",
+            "fn main() {}\n"
+        )
+    );
+
+    // Doc comments without a manifest are just source.
+    assert_eq!(
+        ssh("//! Doc.\nfn main() {}\n"),
+        ("", "//! Doc.\nfn main() {}\n")
+    );
+}
+
+/**
+Rewrites the version requirements of the dependencies in a script's embedded manifest, leaving every other byte of the script as it was.
+
+`new_req` is given the package name and current requirement (`*` if there isn't one) of each dependency, and returns what to replace the requirement with, if anything.  Dependencies given by `path` or `git` are passed along too, so it's up to `new_req` to leave those alone.
+*/
+pub fn rewrite_dep_reqs<F>(content: &str, mut new_req: F) -> String
+where
+    F: FnMut(&str, &str) -> Option<String>,
+{
+    let mut edits = vec![];
+    let rest = strip_hashbang(content);
+    let base = content.len() - rest.len();
+
+    if let Some(cap) = RE_SHORT_MANIFEST.captures(rest) {
+        let list = cap.get(1).unwrap();
+        for dep in split_dep_list(list.as_str()) {
+            let offset =
+                base + list.start() + (dep.as_ptr() as usize - list.as_str().as_ptr() as usize);
+            match dep.find('=') {
+                Some(eq) => edits.extend(rewrite_dep_value(
+                    dep[..eq].trim(),
+                    &dep[eq + 1..],
+                    offset + eq + 1,
+                    &mut new_req,
+                )),
+                None if dep.trim().is_empty() => (),
+                None => {
+                    // No version means any version; give it one.
+                    if let Some(req) = new_req(dep.trim(), "*") {
+                        let end = offset + dep.trim_end().len();
+                        edits.push((end..end, format!("=\"{}\"", req)));
+                    }
+                }
+            }
+        }
+    } else if let Some(mb) = find_manifest_block(rest) {
+        let mut offset =
+            base + mb.start + mb.lines[..=mb.open].iter().map(|l| l.len()).sum::<usize>();
+
+        // `None` outside of a dependency table, `Some(None)` in one like `[dependencies]`, and `Some(Some(name))` in one like `[dependencies.name]`.
+        let mut table: Option<Option<String>> = None;
+        for line in &mb.lines[mb.open + 1..mb.close] {
+            let prefix = RE_DOC_LINE_PREFIX.find(line).map_or(0, |m| m.end());
+            let toml_line = &line[prefix..];
+            if toml_line.trim_start().starts_with('[') {
+                table = RE_TOML_TABLE
+                    .captures(toml_line)
+                    .and_then(|cap| RE_DEPS_TABLE.captures(cap.get(1).unwrap().as_str()))
+                    .map(|cap| {
+                        cap.get(1).map(|m| {
+                            m.as_str()
+                                .trim_matches(|c| c == '"' || c == '\'')
+                                .to_owned()
+                        })
+                    });
+            } else if let (Some(table), Some(cap)) = (&table, RE_TOML_KEY.captures(toml_line)) {
+                let key = cap.get(1).or(cap.get(2)).or(cap.get(3)).unwrap().as_str();
+                let at = cap.get(0).unwrap().end();
+                let name = match table {
+                    None => Some(key),
+                    Some(name) if key == "version" => Some(&**name),
+                    Some(_) => None,
+                };
+                if let Some(name) = name {
+                    let value = &toml_line[at..];
+                    edits.extend(rewrite_dep_value(
+                        name,
+                        value,
+                        offset + prefix + at,
+                        &mut new_req,
+                    ));
+                }
+            }
+            offset += line.len();
+        }
+    }
+
+    let mut r = String::from(content);
+    for (range, req) in edits.into_iter().rev() {
+        r.replace_range(range, &req);
+    }
+    r
+}
+
+/**
+Works out the edit to make to the value of the dependency `name`, which starts at `offset` in the script.  This is either a version requirement, or an inline table which might contain one.
+*/
+fn rewrite_dep_value<F>(
+    name: &str,
+    value: &str,
+    offset: usize,
+    new_req: &mut F,
+) -> Option<(Range<usize>, String)>
+where
+    F: FnMut(&str, &str) -> Option<String>,
+{
+    let offset = offset + value.len() - value.trim_start().len();
+    let value = value.trim_start();
+
+    let (name, req) = match value.starts_with('{') {
+        true => {
+            let package = RE_INLINE_PACKAGE
+                .captures(value)
+                .map(|cap| cap.get(1).or(cap.get(2)).unwrap().as_str());
+            let cap = RE_INLINE_VERSION.captures(value)?;
+            (package.unwrap_or(name), cap.get(1).or(cap.get(2)).unwrap())
+        }
+        false => {
+            let cap = RE_TOML_STRING.captures(value)?;
+            (name, cap.get(1).or(cap.get(2)).unwrap())
+        }
+    };
+
+    let new = new_req(name, req.as_str())?;
+    Some((offset + req.start()..offset + req.end(), new))
+}
+
+#[test]
+fn test_rewrite_dep_reqs() {
+    let versions: HashMap<&str, &str> = vec![
+        ("boolinator", "2.4.0"),
+        ("time", "0.1.40"),
+        ("libc", "0.2.150"),
+        ("serde", "1.0.190"),
+        ("regex", "1.10.2"),
+    ]
+    .into_iter()
+    .collect();
+    let rdr = |s| {
+        rewrite_dep_reqs(s, |name, req| {
+            let version = versions.get(name)?;
+            Some(match req.starts_with('=') {
+                true => format!("={}", version),
+                false => version.to_string(),
+            })
+        })
+    };
+
+    macro_rules! golden {
+        ($name:expr) => {
+            assert_eq!(
+                rdr(include_str!(concat!(
+                    "../tests/data/rewrite-deps/",
+                    $name,
+                    ".rs"
+                ))),
+                include_str!(concat!("../tests/data/rewrite-deps/", $name, ".saved.rs")),
+                "rewriting {}",
+                $name
+            )
+        };
+    }
+
+    golden!("short");
+    golden!("code-fence");
+    golden!("doc-block");
+
+    // Nothing to rewrite.
+    for s in &[
+        "fn main() {}\n",
+        "// cargo-deps: unknown\nfn main() {}\n",
+        "//! ```cargo\n//! [package]\n//! version = \"0.1.0\"\n//! ```\nfn main() {}\n",
+    ] {
+        assert_eq!(rdr(s), *s);
+    }
+}
+
+/**
+Extracts the first `Cargo` fenced code block from a chunk of Markdown.
+*/
+fn scrape_markdown_manifest(content: &str) -> Result<Option<String>> {
+    scrape_markdown_block(content, "cargo")
+}
+
+/**
+Extracts the first fenced code block with the language token `lang` from a chunk of Markdown.
+*/
+fn scrape_markdown_block(content: &str, lang: &str) -> Result<Option<String>> {
+    use hoedown::{Buffer, Markdown, Render};
+
+    // To match librustdoc/html/markdown.rs, HOEDOWN_EXTENSIONS.
+    let exts = hoedown::NO_INTRA_EMPHASIS
+        | hoedown::TABLES
+        | hoedown::FENCED_CODE
+        | hoedown::AUTOLINK
+        | hoedown::STRIKETHROUGH
+        | hoedown::SUPERSCRIPT
+        | hoedown::FOOTNOTES;
+
+    let md = Markdown::new(&content).extensions(exts);
+
+    struct ManifestScraper<'a> {
+        lang: &'a str,
+        seen_manifest: bool,
+    }
+
+    impl<'a> Render for ManifestScraper<'a> {
+        fn code_block(
+            &mut self,
+            output: &mut Buffer,
+            text: Option<&Buffer>,
+            lang: Option<&Buffer>,
+        ) {
+            let lang = lang.map(|b| b.to_str().unwrap()).unwrap_or("");
+
+            if !self.seen_manifest && lang.eq_ignore_ascii_case(self.lang) {
+                // Pass it through.
+                info!("found `{}` code block", self.lang);
+                if let Some(text) = text {
+                    output.pipe(text);
+                }
+                self.seen_manifest = true;
+            }
+        }
+    }
+
+    let mut ms = ManifestScraper {
+        lang,
+        seen_manifest: false,
+    };
+    let mani_buf = ms.render(&md);
+
+    if !ms.seen_manifest {
+        return Ok(None);
+    }
+    mani_buf
+        .to_str()
+        .map(|s| Some(s.into()))
+        .map_err(|_| format!("error decoding `{}` code block as UTF-8", lang).into())
+}
+
+#[test]
+fn test_scrape_markdown_manifest() {
+    macro_rules! smm {
+        ($c:expr) => {
+            scrape_markdown_manifest($c).map_err(|e| e.to_string())
+        };
+    }
+
+    assert_eq!(
+        smm!(
+            r#"There is no manifest in this comment.
+"#
+        ),
+        Ok(None)
+    );
+
     assert_eq!(
         smm!(
             r#"There is no manifest in this comment.
@@ -1040,11 +2709,12 @@ time = "*"
 /**
 Generates a default Cargo manifest for the given input.
 */
-fn default_manifest(input: &Input<'_>) -> Result<toml::value::Table> {
+fn default_manifest(input: &Input<'_>, pkg_name: Option<&str>) -> Result<toml::value::Table> {
     let mani_str = {
-        let pkg_name = input.package_name();
-        let mut subs = HashMap::with_capacity(2);
-        subs.insert(consts::MANI_NAME_SUB, &*pkg_name);
+        let bin_name = input.package_name();
+        let mut subs = HashMap::with_capacity(3);
+        subs.insert(consts::MANI_NAME_SUB, pkg_name.unwrap_or(&*bin_name));
+        subs.insert(consts::MANI_BIN_NAME_SUB, &*bin_name);
         subs.insert(consts::MANI_FILE_SUB, &input.safe_name()[..]);
         templates::expand(consts::DEFAULT_MANIFEST, &subs)?
     };
@@ -1058,58 +2728,1277 @@ fn default_manifest(input: &Input<'_>) -> Result<toml::value::Table> {
 }
 
 /**
-Generates a partial Cargo manifest containing the specified dependencies.
-*/
-fn deps_manifest(deps: &[(String, String)]) -> Result<toml::value::Table> {
-    let mut mani_str = String::new();
-    mani_str.push_str("[dependencies]\n");
-
-    for &(ref name, ref ver) in deps {
-        mani_str.push_str(name);
-        mani_str.push_str("=");
+Looks for a Cargo workspace enclosing the given script.
 
-        // We only want to quote the version if it *isn't* a table.
-        let quotes = match ver.starts_with("{") {
-            true => "",
-            false => "\"",
+Returns the path to the workspace's manifest, if there is one.  Generated packages always declare themselves as their own workspace, so this is only used to tell the user what's going on.
+*/
+pub fn find_enclosing_workspace(script_path: &Path) -> Option<PathBuf> {
+    let script_path = script_path.canonicalize().ok()?;
+    for dir in script_path.ancestors().skip(1) {
+        let mani_path = dir.join("Cargo.toml");
+        let mani: toml::Value = match fs::read_to_string(&mani_path)
+            .ok()
+            .and_then(|s| toml::from_str(&s).ok())
+        {
+            Some(mani) => mani,
+            None => continue,
         };
-        mani_str.push_str(quotes);
-        mani_str.push_str(ver);
-        mani_str.push_str(quotes);
-        mani_str.push_str("\n");
+        if mani.get("workspace").is_some() {
+            return Some(platform::simplify_path(&mani_path));
+        }
     }
-
-    toml::from_str(&mani_str).map_err(|e| {
-        MainError::Tag(
-            Blame::Human,
-            "could not parse dependency manifest".into(),
-            Box::new(MainError::Other(Blame::Internal, Box::new(e))),
-        )
-    })
+    None
 }
 
 /**
-Given two Cargo manifests, merges the second *into* the first.
+Parses and validates a dependency spec given on the command line.
 
-Note that the "merge" in this case is relatively simple: only *top-level* tables are actually merged; everything else is just outright replaced.
+A spec can be just a package name (which will assume the latest version), `name=version`, or `name={...}` where the braces contain an inline TOML table.  Returns the package name and the version requirement (or table).
 */
-fn merge_manifest(
-    mut into_t: toml::value::Table,
-    from_t: toml::value::Table,
-) -> Result<toml::value::Table> {
-    for (k, v) in from_t {
-        match v {
-            toml::Value::Table(from_t) => {
-                use toml::map::Entry;
+pub fn parse_dep_spec(spec: &str) -> Result<(String, String)> {
+    let (name, version) = match spec.find('=') {
+        Some(i) => (&spec[..i], &spec[i + 1..]),
+        None => (spec, "*"),
+    };
 
-                // Merge.
-                match into_t.entry(k) {
-                    Entry::Vacant(e) => {
-                        e.insert(toml::Value::Table(from_t));
-                    }
-                    Entry::Occupied(e) => {
-                        let into_t = as_table_mut(e.into_mut()).ok_or((
-                            Blame::Human,
+    check_dep_spec(name, version).map_err(|problem| {
+        MainError::OtherOwned(
+            Blame::Human,
+            format!(
+                "invalid dependency {:?}: {}\n{}",
+                spec,
+                problem,
+                consts::DEP_SPEC_EXAMPLES
+            ),
+        )
+    })?;
+
+    Ok((name.into(), version.into()))
+}
+
+fn check_dep_spec(name: &str, version: &str) -> ::std::result::Result<(), String> {
+    use semver::VersionReq;
+
+    if name.is_empty() {
+        return Err("package name is empty".into());
+    }
+    if name.trim() != name || version.trim() != version {
+        return Err("unexpected whitespace; remove any spaces around the `=`".into());
+    }
+    if name.len() > consts::DEP_NAME_LEN_MAX {
+        return Err(format!(
+            "package name is longer than {} characters",
+            consts::DEP_NAME_LEN_MAX
+        ));
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|&c| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+    {
+        let hint = match c {
+            ':' | '@' => format!("; did you mean `{}`?", name.replacen(c, "=", 1)),
+            _ => "".into(),
+        };
+        return Err(format!(
+            "package name contains invalid character {:?}{}",
+            c, hint
+        ));
+    }
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        return Err("package name must start with a letter or `_`".into());
+    }
+
+    if version.is_empty() {
+        return Err("version is empty".into());
+    }
+    if version.starts_with('{') {
+        return match toml::from_str::<toml::Value>(&format!("dep = {}", version)) {
+            Ok(ref v) if v.get("dep").map(|d| d.is_table()) == Some(true) => Ok(()),
+            Ok(_) => Err("dependency table is not a table".into()),
+            Err(e) => Err(format!("dependency table is not valid TOML: {}", e)),
+        };
+    }
+    if let Err(e) = VersionReq::parse(version) {
+        return Err(format!(
+            "{:?} is not a valid version requirement: {}",
+            version, e
+        ));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_parse_dep_spec() {
+    let good: &[(&str, (&str, &str))] = &[
+        ("serde", ("serde", "*")),
+        ("serde=1", ("serde", "1")),
+        ("serde=1.0", ("serde", "1.0")),
+        ("serde=1.0.104", ("serde", "1.0.104")),
+        ("serde==1.0.104", ("serde", "=1.0.104")),
+        ("serde=^1.0", ("serde", "^1.0")),
+        ("serde=~1.0", ("serde", "~1.0")),
+        ("serde=>=1.0, <2.0", ("serde", ">=1.0, <2.0")),
+        ("serde=*", ("serde", "*")),
+        ("serde=1.0.0-alpha.1", ("serde", "1.0.0-alpha.1")),
+        ("serde_json", ("serde_json", "*")),
+        ("serde-json=1", ("serde-json", "1")),
+        (
+            "serde={ version = \"1\", features = [\"derive\"] }",
+            ("serde", "{ version = \"1\", features = [\"derive\"] }"),
+        ),
+        (
+            "internal={ version = \"1\", registry = \"company\" }",
+            ("internal", "{ version = \"1\", registry = \"company\" }"),
+        ),
+        ("_private=0.1", ("_private", "0.1")),
+        ("x2=0.1", ("x2", "0.1")),
+    ];
+
+    for &(spec, (name, version)) in good {
+        assert_eq!(
+            parse_dep_spec(spec).map_err(|e| e.to_string()),
+            Ok((name.into(), version.into())),
+            "with spec {:?}",
+            spec
+        );
+    }
+
+    let bad: &[(&str, &str)] = &[
+        ("", "package name is empty"),
+        ("=1.0", "package name is empty"),
+        ("serde=", "version is empty"),
+        ("serde:1.0", "did you mean `serde=1.0`?"),
+        ("serde@1.0", "did you mean `serde=1.0`?"),
+        ("serde = 1", "unexpected whitespace"),
+        ("serde =1", "unexpected whitespace"),
+        ("serde= 1", "unexpected whitespace"),
+        ("ser.de", "invalid character '.'"),
+        ("sérde", "invalid character 'é'"),
+        ("serde/json", "invalid character '/'"),
+        ("1serde", "must start with a letter"),
+        ("-serde", "must start with a letter"),
+        ("serde=one", "not a valid version requirement"),
+        ("serde=1.0.0.0", "not a valid version requirement"),
+        ("serde=>>1", "not a valid version requirement"),
+        ("serde={ version = 1", "not valid TOML"),
+        ("serde={ version: \"1\" }", "not valid TOML"),
+    ];
+
+    for &(spec, problem) in bad {
+        match parse_dep_spec(spec) {
+            Ok(v) => panic!("spec {:?} should be invalid, got {:?}", spec, v),
+            Err(e) => {
+                assert!(e.is_human(), "with spec {:?}", spec);
+                let msg = e.to_string();
+                assert!(
+                    msg.starts_with(&format!("invalid dependency {:?}: ", spec)),
+                    "with spec {:?}: {}",
+                    spec,
+                    msg
+                );
+                assert!(msg.contains(problem), "with spec {:?}: {}", spec, msg);
+                assert!(msg.contains("--dep regex=1.3"), "with spec {:?}", spec);
+            }
+        }
+    }
+
+    let long_name = "a".repeat(consts::DEP_NAME_LEN_MAX);
+    assert!(parse_dep_spec(&long_name).is_ok());
+    let long_name = "a".repeat(consts::DEP_NAME_LEN_MAX + 1);
+    assert!(parse_dep_spec(&long_name).is_err());
+}
+
+/**
+Generates a partial Cargo manifest containing the specified dependencies.
+*/
+fn deps_manifest(deps: &[(String, String)]) -> Result<toml::value::Table> {
+    let mut mani_str = String::new();
+    mani_str.push_str("[dependencies]\n");
+
+    for &(ref name, ref ver) in deps {
+        mani_str.push_str(name);
+        mani_str.push_str("=");
+
+        // We only want to quote the version if it *isn't* a table.
+        let quotes = match ver.starts_with("{") {
+            true => "",
+            false => "\"",
+        };
+        mani_str.push_str(quotes);
+        mani_str.push_str(ver);
+        mani_str.push_str(quotes);
+        mani_str.push_str("\n");
+    }
+
+    toml::from_str(&mani_str).map_err(|e| {
+        MainError::Tag(
+            Blame::Human,
+            "could not parse dependency manifest".into(),
+            Box::new(MainError::Other(Blame::Internal, Box::new(e))),
+        )
+    })
+}
+
+/**
+Carries the registry of any dependency in `mani` over to the same dependency in `dep_mani`.
+
+Dependencies from `--dep` replace the script's own entry for them outright.  Without this, bumping the version of a dependency from a private registry would quietly go looking for it on crates.io instead.  Dependencies which already say where they come from are left alone.
+
+A dependency the script only has for some targets (*i.e.* under `[target.'cfg(..)'.dependencies]`) is looked for there, too.  Those sections themselves are left as they are.
+*/
+fn keep_dep_registries(
+    mani: &toml::value::Table,
+    mut dep_mani: toml::value::Table,
+) -> toml::value::Table {
+    use toml::Value;
+
+    let new_deps = match dep_mani.get_mut("dependencies") {
+        Some(Value::Table(new)) => new,
+        _ => return dep_mani,
+    };
+    let target_deps = mani
+        .get("target")
+        .and_then(Value::as_table)
+        .into_iter()
+        .flat_map(|targets| targets.values())
+        .filter_map(|target| target.get("dependencies"));
+    let old_deps: Vec<_> = mani
+        .get("dependencies")
+        .into_iter()
+        .chain(target_deps)
+        .filter_map(Value::as_table)
+        .collect();
+
+    for (name, dep) in new_deps.iter_mut() {
+        let old = old_deps
+            .iter()
+            .filter_map(|deps| deps.get(name))
+            .find(|old| {
+                consts::DEP_REGISTRY_KEYS
+                    .iter()
+                    .any(|&k| old.get(k).is_some())
+            });
+        let registry: Vec<_> = match old {
+            Some(Value::Table(old)) => consts::DEP_REGISTRY_KEYS
+                .iter()
+                .filter_map(|&k| old.get(k).map(|v| (k, v.clone())))
+                .collect(),
+            _ => continue,
+        };
+        if registry.is_empty() {
+            continue;
+        }
+
+        let mut table = match dep {
+            Value::String(version) => {
+                let mut table = toml::value::Table::new();
+                table.insert("version".into(), Value::String(version.clone()));
+                table
+            }
+            Value::Table(table) => table.clone(),
+            _ => continue,
+        };
+        let has_source = ["git", "path"]
+            .iter()
+            .chain(consts::DEP_REGISTRY_KEYS)
+            .any(|k| table.contains_key(*k));
+        if has_source {
+            continue;
+        }
+
+        for (k, v) in registry {
+            table.insert(k.into(), v);
+        }
+        *dep = Value::Table(table);
+    }
+
+    dep_mani
+}
+
+#[test]
+fn test_keep_dep_registries() {
+    let dummy_path: ::std::path::PathBuf = "p".into();
+    let f = |c| Input::File("n", &dummy_path, c, 0);
+
+    let dep = |content, deps: &[(&str, &str)], name: &str| {
+        let deps: Vec<_> = deps
+            .iter()
+            .map(|&(n, v)| (n.to_owned(), v.to_owned()))
+            .collect();
+        let split = split_input(
+            &f(content),
+            &SplitOptions {
+                deps: &deps,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let mani: toml::Value = toml::from_str(&split.manifest).unwrap();
+        mani["dependencies"][name].clone()
+    };
+
+    let v = |s: &str| toml::from_str::<toml::Value>(&format!("d = {}", s)).unwrap()["d"].clone();
+
+    let script = r#"//! ```cargo
+//! [dependencies]
+//! internal = { version = "1", registry = "company" }
+//! elsewhere = { version = "1", registry-index = "sparse+https://example.invalid/" }
+//! regex = "1"
+//! ```
+fn main() {}
+"#;
+
+    assert_eq!(
+        dep(script, &[], "internal"),
+        v(r#"{ registry = "company", version = "1" }"#)
+    );
+    assert_eq!(
+        dep(script, &[("internal", "1.2")], "internal"),
+        v(r#"{ registry = "company", version = "1.2" }"#)
+    );
+    assert_eq!(
+        dep(
+            script,
+            &[("internal", "{ version = \"1.2\", features = [\"x\"] }")],
+            "internal"
+        ),
+        v(r#"{ features = ["x"], registry = "company", version = "1.2" }"#)
+    );
+    assert_eq!(
+        dep(script, &[("elsewhere", "1.2")], "elsewhere"),
+        v(r#"{ registry-index = "sparse+https://example.invalid/", version = "1.2" }"#)
+    );
+
+    // Only a dependency which doesn't say where it comes from inherits the registry.
+    assert_eq!(
+        dep(
+            script,
+            &[("internal", "{ version = \"1.2\", registry = \"other\" }")],
+            "internal"
+        ),
+        v(r#"{ registry = "other", version = "1.2" }"#)
+    );
+    assert_eq!(
+        dep(
+            script,
+            &[("internal", "{ path = \"../internal\" }")],
+            "internal"
+        ),
+        v(r#"{ path = "../internal" }"#)
+    );
+    assert_eq!(dep(script, &[("regex", "1.3")], "regex"), v(r#""1.3""#));
+
+    // Inline tables in a `cargo-deps` list can contain commas.
+    assert_eq!(
+        dep(
+            "// cargo-deps: internal={ version = \"1\", registry = \"company\" }, regex\nfn main() {}",
+            &[],
+            "internal"
+        ),
+        v(r#"{ registry = "company", version = "1" }"#)
+    );
+
+    // A dependency the script only has for some targets still lends its registry.
+    let script = r#"//! ```cargo
+//! [target.'cfg(unix)'.dependencies]
+//! internal = { version = "1", registry = "company" }
+//! ```
+fn main() {}
+"#;
+    assert_eq!(
+        dep(script, &[("internal", "1.2")], "internal"),
+        v(r#"{ registry = "company", version = "1.2" }"#)
+    );
+}
+
+/**
+The dependencies of an existing Cargo project, as read for `--deps-from`, which a script's dependencies without a version can take their version from.
+*/
+#[derive(Debug)]
+pub struct DepsFrom {
+    /// The manifest they came from.
+    pub path: PathBuf,
+
+    /// Identifies what was read, including from the workspace's root, so that using an updated manifest makes for a different package.
+    pub digest: String,
+
+    /// The project's own `[dependencies]`.
+    deps: toml::value::Table,
+
+    /// `[workspace.dependencies]`, from the project's manifest if it's the root of its workspace, or else from the root.
+    workspace_deps: toml::value::Table,
+}
+
+impl DepsFrom {
+    /**
+    Reads the manifest at `path`, along with the root of the workspace it's in, if there is one.
+    */
+    pub fn load(path: &Path) -> Result<DepsFrom> {
+        let read = |path: &Path| {
+            fs::read_to_string(path).map_err(|err| {
+                MainError::OtherOwned(
+                    Blame::Human,
+                    format!("could not read `{}` (--deps-from): {}", path.display(), err),
+                )
+            })
+        };
+        let path = platform::simplify_path(&std::env::current_dir()?.join(path));
+        let content = read(&path)?;
+        let mani = parse_manifest_file(&path, &content)?;
+
+        // The project only has a `workspace` table if it's the root; otherwise, the root is further up.
+        let mut root = None;
+        if !mani.contains_key("workspace") {
+            for dir in path.parent().into_iter().flat_map(Path::ancestors).skip(1) {
+                let root_path = dir.join("Cargo.toml");
+                if !root_path.is_file() {
+                    continue;
+                }
+                let root_content = read(&root_path)?;
+                if parse_manifest_file(&root_path, &root_content)?.contains_key("workspace") {
+                    root = Some((root_path, root_content));
+                    break;
+                }
+            }
+        }
+
+        DepsFrom::parse(&path, &content, root.as_ref().map(|(p, c)| (&**p, &**c)))
+    }
+
+    /**
+    Pulls the dependencies out of a project's manifest, and its workspace root's, if it has one, making any paths in them absolute.
+    */
+    fn parse(path: &Path, content: &str, root: Option<(&Path, &str)>) -> Result<DepsFrom> {
+        // Paths under `[workspace.dependencies]` aren't in `MANIFEST_PATHS`, so these are fixed up by passing them off as `[dependencies]`.
+        let deps_table = |mani: &toml::value::Table, key: &[&str], base: &Path| {
+            let deps = key
+                .iter()
+                .try_fold(mani, |t, k| t.get(*k).and_then(|t| t.as_table()))
+                .cloned()
+                .unwrap_or_default();
+            let mut wrapper = toml::value::Table::new();
+            wrapper.insert("dependencies".into(), toml::Value::Table(deps));
+            let mut wrapper = fix_manifest_paths(wrapper, base)?;
+            match wrapper.remove("dependencies") {
+                Some(toml::Value::Table(deps)) => Ok::<_, MainError>(deps),
+                _ => unreachable!(),
+            }
+        };
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        let mani = parse_manifest_file(path, content)?;
+        let deps = deps_table(&mani, &["dependencies"], base)?;
+        let (workspace_deps, root_content) = match root {
+            Some((root_path, root_content)) => {
+                let root = parse_manifest_file(root_path, root_content)?;
+                let root_base = root_path.parent().unwrap_or(Path::new(""));
+                (
+                    deps_table(&root, &["workspace", "dependencies"], root_base)?,
+                    root_content,
+                )
+            }
+            None => (deps_table(&mani, &["workspace", "dependencies"], base)?, ""),
+        };
+
+        let digest = {
+            use sha1::Sha1;
+            let mut hasher = Sha1::new();
+            hasher.update(content.as_bytes());
+            hasher.update(root_content.as_bytes());
+            hasher.digest().to_string()
+        };
+
+        Ok(DepsFrom {
+            path: path.to_owned(),
+            digest,
+            deps,
+            workspace_deps,
+        })
+    }
+
+    /**
+    Looks up the project's dependency `name`: in its own `[dependencies]` first, and then in `[workspace.dependencies]`.
+
+    A dependency the project inherits from its workspace (with `workspace = true`) gets the workspace's entry, plus any features the project adds to it.  Whether the project's dependency is optional has nothing to do with a script, so that's left out.
+    */
+    fn lookup(&self, name: &str) -> Option<toml::Value> {
+        use toml::Value;
+
+        let spec = match self.deps.get(name) {
+            Some(Value::Table(t)) if t.get("workspace").and_then(Value::as_bool) == Some(true) => {
+                let mut spec = dep_table(self.workspace_deps.get(name)?.clone());
+                if let Some(Value::Array(extra)) = t.get("features") {
+                    let features = spec
+                        .entry("features")
+                        .or_insert_with(|| Value::Array(vec![]));
+                    if let Value::Array(features) = features {
+                        for feature in extra {
+                            if !features.contains(feature) {
+                                features.push(feature.clone());
+                            }
+                        }
+                    }
+                }
+                Value::Table(spec)
+            }
+            Some(spec) => spec.clone(),
+            None => self.workspace_deps.get(name)?.clone(),
+        };
+
+        Some(match spec {
+            Value::Table(mut t) => {
+                t.remove("optional");
+                Value::Table(t)
+            }
+            spec => spec,
+        })
+    }
+}
+
+/**
+Parses the manifest of a project given to `--deps-from`.
+*/
+fn parse_manifest_file(path: &Path, content: &str) -> Result<toml::value::Table> {
+    toml::from_str(content).map_err(|err| {
+        MainError::OtherOwned(
+            Blame::Human,
+            format!("`{}` is not a valid manifest: {}", path.display(), err),
+        )
+    })
+}
+
+/**
+Turns a dependency given as just a version requirement into the table it's short for.
+*/
+fn dep_table(spec: toml::Value) -> toml::value::Table {
+    match spec {
+        toml::Value::Table(t) => t,
+        req => {
+            let mut t = toml::value::Table::new();
+            t.insert("version".into(), req);
+            t
+        }
+    }
+}
+
+/**
+Gives every dependency in `mani` which doesn't have a version (or has `*`), and doesn't come from a path or git, the version `deps_from` has for it.
+
+Anything else the script says about the dependency, such as which features it wants, is kept, with features added to those the project uses.  A dependency the project doesn't have is left as any version.
+*/
+fn fill_dep_versions(mani: &mut toml::value::Table, deps_from: &DepsFrom) {
+    use toml::Value;
+
+    for deps in dep_tables_mut(mani) {
+        for (name, spec) in deps.iter_mut() {
+            if !is_wildcard_dep(spec) {
+                continue;
+            }
+            let found = match deps_from.lookup(name) {
+                Some(found) => found,
+                None => continue,
+            };
+            *spec = match spec {
+                Value::Table(t) => {
+                    let mut merged = dep_table(found);
+                    for (key, value) in t.iter() {
+                        match (&key[..], value, merged.get_mut(key)) {
+                            ("version", ..) => (),
+                            ("features", Value::Array(extra), Some(Value::Array(features))) => {
+                                for feature in extra {
+                                    if !features.contains(feature) {
+                                        features.push(feature.clone());
+                                    }
+                                }
+                            }
+                            _ => {
+                                merged.insert(key.clone(), value.clone());
+                            }
+                        }
+                    }
+                    Value::Table(merged)
+                }
+                _ => found,
+            };
+        }
+    }
+}
+
+/**
+Lists the dependencies in a generated manifest which can be any version at all, whether they were given as `*` or with no version.  Each is only listed once, however many tables it's in.
+*/
+pub fn wildcard_deps(manifest: &str) -> Result<Vec<String>> {
+    let mut mani: toml::value::Table =
+        toml::from_str(manifest).map_err(|e| MainError::Other(Blame::Internal, Box::new(e)))?;
+    let mut names = vec![];
+    for deps in dep_tables_mut(&mut mani) {
+        for (name, spec) in deps.iter() {
+            if is_wildcard_dep(spec) && !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/**
+Finds every table of dependencies in a manifest, including those only for some targets.
+*/
+fn dep_tables_mut(mani: &mut toml::value::Table) -> Vec<&mut toml::value::Table> {
+    use toml::Value;
+
+    let kinds = ["dependencies", "dev-dependencies", "build-dependencies"];
+    let mut tables = vec![];
+    for (key, value) in mani.iter_mut() {
+        match (&key[..], value) {
+            ("target", Value::Table(targets)) => {
+                for target in targets.iter_mut().filter_map(|(_, t)| t.as_table_mut()) {
+                    for (kind, deps) in target.iter_mut() {
+                        if let (true, Value::Table(deps)) = (kinds.contains(&&kind[..]), deps) {
+                            tables.push(deps);
+                        }
+                    }
+                }
+            }
+            (kind, Value::Table(deps)) if kinds.contains(&kind) => tables.push(deps),
+            _ => (),
+        }
+    }
+    tables
+}
+
+/**
+Can this dependency be any version from its registry?  Dependencies from a path or git don't come from a registry at all.
+*/
+fn is_wildcard_dep(spec: &toml::Value) -> bool {
+    match spec {
+        toml::Value::String(req) => req.trim() == "*",
+        toml::Value::Table(t) => {
+            t.get("version")
+                .map_or(true, |v| v.as_str().map(str::trim) == Some("*"))
+                && !["path", "git", "workspace"]
+                    .iter()
+                    .any(|k| t.contains_key(*k))
+        }
+        _ => false,
+    }
+}
+
+#[test]
+fn test_wildcard_deps() {
+    let wd = |m: &str| wildcard_deps(m).unwrap();
+    assert_eq!(
+        wd(r#"
+[dependencies]
+rand = "*"
+regex = "1"
+serde = { features = ["derive"] }
+local = { path = "../local" }
+git-dep = { git = "https://example.invalid/dep" }
+[dev-dependencies]
+rand = { version = "*" }
+[target.'cfg(unix)'.build-dependencies]
+cc = " * "
+"#),
+        vec!["cc", "rand", "serde"]
+    );
+    assert!(wd("[dependencies]\nregex = \"1\"\n").is_empty());
+    assert!(wd("[package]\nname = \"n\"\n").is_empty());
+}
+
+#[test]
+fn test_deps_from() {
+    let v = |s: &str| toml::from_str::<toml::Value>(&format!("d = {}", s)).unwrap()["d"].clone();
+    let base = if cfg!(windows) { "C:\\proj" } else { "/proj" };
+    let path = Path::new(base).join("member").join("Cargo.toml");
+    let root = Path::new(base).join("Cargo.toml");
+
+    let project = r#"
+[package]
+name = "member"
+version = "0.1.0"
+
+[dependencies]
+regex = "1.5"
+serde = { workspace = true, features = ["rc"] }
+local = { path = "../local", optional = true }
+"#;
+    let workspace = r#"
+[workspace]
+members = ["member"]
+
+[workspace.dependencies]
+serde = { version = "1.0.100", features = ["derive"] }
+regex = "0.2"
+log = "0.4"
+"#;
+    let deps_from = DepsFrom::parse(&path, project, Some((&root, workspace))).unwrap();
+
+    // The project's own dependencies come before the workspace's.
+    assert_eq!(deps_from.lookup("regex"), Some(v(r#""1.5""#)));
+    assert_eq!(deps_from.lookup("log"), Some(v(r#""0.4""#)));
+    assert_eq!(
+        deps_from.lookup("serde"),
+        Some(v(r#"{ version = "1.0.100", features = ["derive", "rc"] }"#))
+    );
+    assert_eq!(deps_from.lookup("missing"), None);
+
+    // Paths are relative to the project, not the script, and whether it's optional doesn't matter.
+    let local = path.parent().unwrap().join("../local");
+    assert_eq!(
+        deps_from.lookup("local").unwrap()["path"].as_str(),
+        Some(&*local.to_string_lossy())
+    );
+    assert_eq!(deps_from.lookup("local").unwrap().get("optional"), None);
+
+    // The root of a workspace has its own `[workspace.dependencies]`.
+    let only_root = DepsFrom::parse(&root, workspace, None).unwrap();
+    assert_eq!(only_root.lookup("log"), Some(v(r#""0.4""#)));
+    assert_ne!(only_root.digest, deps_from.digest);
+
+    let dummy_path: ::std::path::PathBuf = "p".into();
+    let f = |c| Input::File("n", &dummy_path, c, 0);
+    let dep = |content, deps: &[(&str, &str)]| {
+        let deps: Vec<_> = deps
+            .iter()
+            .map(|&(n, v)| (n.to_owned(), v.to_owned()))
+            .collect();
+        let split = split_input(
+            &f(content),
+            &SplitOptions {
+                deps: &deps,
+                deps_from: Some(&deps_from),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let mani: toml::Value = toml::from_str(&split.manifest).unwrap();
+        (
+            mani["dependencies"].clone(),
+            wildcard_deps(&split.manifest).unwrap(),
+        )
+    };
+
+    // Whatever the project doesn't have is left as any version.
+    let (deps, wildcards) = dep(
+        "// cargo-deps: regex, log=\"0.3\", serde={ features = [\"std\"] }, missing\nfn main() {}",
+        &[("log_ext", "*")],
+    );
+    assert_eq!(deps["regex"], v(r#""1.5""#));
+    assert_eq!(deps["log"], v(r#""0.3""#));
+    assert_eq!(
+        deps["serde"],
+        v(r#"{ version = "1.0.100", features = ["derive", "rc", "std"] }"#)
+    );
+    assert_eq!(deps["missing"], v(r#""*""#));
+    assert_eq!(deps["log_ext"], v(r#""*""#));
+    assert_eq!(wildcards, vec!["log_ext", "missing"]);
+}
+
+#[test]
+fn test_split_input_build_script() {
+    let data_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("data")
+        .join("build-script");
+    let script_path = data_path.join("p.rs");
+    let split = |c| {
+        split_input(
+            &Input::File("n", &script_path, c, 0),
+            &SplitOptions::default(),
+        )
+    };
+    let build = |split: &SplitInput| {
+        let mani: toml::Value = toml::from_str(&split.manifest).unwrap();
+        mani["package"].get("build").cloned()
+    };
+
+    let plain = split("fn main() {}").unwrap();
+    assert_eq!(plain.files, vec![]);
+    assert_eq!(build(&plain), None);
+
+    let block = split(
+        r#"//! ```build-script
+//! fn main() {}
+//! ```
+fn main() {}
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        block.files,
+        vec![PackageFile {
+            path: "build.rs".into(),
+            content: b"fn main() {}\n".to_vec(),
+        }]
+    );
+    assert_eq!(build(&block), Some(toml::Value::String("build.rs".into())));
+
+    let file = split(
+        r#"//! ```cargo
+//! [package]
+//! build = "build.rs"
+//! [package.metadata.cargo-script]
+//! include = ["shim.c"]
+//! ```
+fn main() {}
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        file.files,
+        vec![
+            PackageFile {
+                path: "build.rs".into(),
+                content: fs::read(data_path.join("build.rs")).unwrap(),
+            },
+            PackageFile {
+                path: "shim.c".into(),
+                content: fs::read(data_path.join("shim.c")).unwrap(),
+            },
+        ]
+    );
+    assert_eq!(build(&file), Some(toml::Value::String("build.rs".into())));
+    assert_eq!(file.warnings, Vec::<String>::new());
+
+    assert!(split(
+        r#"//! ```cargo
+//! [package]
+//! build = "build.rs"
+//! ```
+//!
+//! ```build-script
+//! fn main() {}
+//! ```
+fn main() {}
+"#
+    )
+    .is_err());
+    assert!(split(
+        r#"//! ```cargo
+//! [package.metadata.cargo-script]
+//! include = ["../build-script/shim.c"]
+//! ```
+fn main() {}
+"#
+    )
+    .is_err());
+}
+
+#[test]
+fn test_split_input_manifest_keys() {
+    let dummy_path: ::std::path::PathBuf = "p".into();
+    let f = |c| Input::File("n", &dummy_path, c, 0);
+    let mani = |content, keys: &[&str]| {
+        let keys: Vec<String> = keys.iter().map(|&k| k.into()).collect();
+        split_input(
+            &f(content),
+            &SplitOptions {
+                deps: &[("regex".into(), "1".into())],
+                manifest_keys: &keys,
+                ..Default::default()
+            },
+        )
+        .map(|split| toml::from_str::<toml::Value>(&split.manifest).unwrap())
+        .map_err(|e| e.to_string())
+    };
+    let script = r#"//! ```cargo
+//! [package]
+//! edition = "2015"
+//! ```
+fn main() {}
+"#;
+
+    let m = mani(
+        script,
+        &[
+            "package.edition=2021",
+            "dependencies.regex=1.10",
+            "profile.release.lto=true",
+        ],
+    )
+    .unwrap();
+    assert_eq!(m["package"]["edition"].as_str(), Some("2021"));
+    assert_eq!(m["package"]["name"].as_str(), Some("n"));
+    assert_eq!(m["dependencies"]["regex"].as_str(), Some("1.10"));
+    assert_eq!(m["profile"]["release"]["lto"].as_bool(), Some(true));
+
+    // Later keys win.
+    let m = mani(script, &["package.edition=2018", "package.edition=2021"]).unwrap();
+    assert_eq!(m["package"]["edition"].as_str(), Some("2021"));
+
+    // A package named this way is left alone, even if it collides with a dependency.
+    let m = mani(script, &["package.name=regex"]).unwrap();
+    assert_eq!(m["package"]["name"].as_str(), Some("regex"));
+
+    assert!(mani(script, &["package.edition.year=2021"]).is_err());
+    assert!(mani(script, &["bin.name=x"]).is_err());
+    assert!(mani(script, &["edition=2021"]).is_err());
+}
+
+#[test]
+fn test_split_input_manifest_override() {
+    let dummy_path: ::std::path::PathBuf = "p".into();
+    let f = |c| Input::File("n", &dummy_path, c, 0);
+    let mani = |over: &str, keys: &[&str]| {
+        let keys: Vec<String> = keys.iter().map(|&k| k.into()).collect();
+        split_input(
+            &f(r#"//! ```cargo
+//! [package]
+//! edition = "2015"
+//! [dependencies]
+//! time = "0.1"
+//! ```
+fn main() {}
+"#),
+            &SplitOptions {
+                deps: &[("regex".into(), "1".into())],
+                manifest_keys: &keys,
+                manifest_override: Some(over),
+                ..Default::default()
+            },
+        )
+        .map(|split| toml::from_str::<toml::Value>(&split.manifest).unwrap())
+        .map_err(|e| e.to_string())
+    };
+
+    // It's merged over the script's own manifest and `--dep`, table by table.
+    let m = mani(
+        "[package]\nedition = \"2021\"\n[dependencies]\nregex = \"1.10\"\nlog = \"0.4\"\n",
+        &[],
+    )
+    .unwrap();
+    assert_eq!(m["package"]["edition"].as_str(), Some("2021"));
+    assert_eq!(m["package"]["name"].as_str(), Some("n"));
+    assert_eq!(m["dependencies"]["time"].as_str(), Some("0.1"));
+    assert_eq!(m["dependencies"]["regex"].as_str(), Some("1.10"));
+    assert_eq!(m["dependencies"]["log"].as_str(), Some("0.4"));
+
+    // `--manifest-key` still has the last word.
+    let m = mani("[package]\nedition = \"2021\"\n", &["package.edition=2018"]).unwrap();
+    assert_eq!(m["package"]["edition"].as_str(), Some("2018"));
+
+    // Naming the package counts as the user naming it.
+    let m = mani("[package]\nname = \"regex\"\n", &[]).unwrap();
+    assert_eq!(m["package"]["name"].as_str(), Some("regex"));
+
+    assert!(mani("[package", &[])
+        .unwrap_err()
+        .contains("the manifest from stdin is not valid TOML"));
+}
+
+#[test]
+fn test_split_input_bins() {
+    let data_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("data")
+        .join("multi-bin");
+    let script_path = data_path.join("n.rs");
+    let split = |c, bin| {
+        split_input(
+            &Input::File("n", &script_path, c, 0),
+            &SplitOptions {
+                bin,
+                ..Default::default()
+            },
+        )
+        .map_err(|e| e.to_string())
+    };
+    let bins = |split: &SplitInput| {
+        let mani: toml::Value = toml::from_str(&split.manifest).unwrap();
+        mani["bin"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| {
+                let s = |k| b[k].as_str().unwrap().to_owned();
+                (s("name"), s("path"))
+            })
+            .collect::<Vec<_>>()
+    };
+    let pair = |n: &str, p: &str| (n.to_owned(), p.to_owned());
+
+    let script = r#"//! ```cargo
+//! [[bin]]
+//! name = "helper"
+//! path = "helper.rs"
+//! ```
+fn main() {}
+"#;
+
+    // The script's own binary target is kept, and is still the one run.
+    let s = split(script, None).unwrap();
+    assert_eq!(
+        bins(&s),
+        vec![pair("n", "n.rs"), pair("helper", "helper.rs")]
+    );
+    assert_eq!(s.bin_name, "n");
+    assert_eq!(
+        s.files,
+        vec![PackageFile {
+            path: "helper.rs".into(),
+            content: fs::read(data_path.join("helper.rs")).unwrap(),
+        }]
+    );
+    assert_eq!(split(script, Some("helper")).unwrap().bin_name, "helper");
+
+    // Renaming the script's target doesn't add another one.
+    let s = split(
+        r#"//! ```cargo
+//! [[bin]]
+//! name = "helper"
+//! path = "helper.rs"
+//!
+//! [[bin]]
+//! name = "renamed"
+//! ```
+fn main() {}
+"#,
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        bins(&s),
+        vec![pair("helper", "helper.rs"), pair("renamed", "n.rs")]
+    );
+    assert_eq!(s.bin_name, "renamed");
+
+    assert_eq!(
+        split(script, Some("nope")).map(|s| s.bin_name),
+        Err("there is no binary target named `nope`; the script has `n`, `helper`".into())
+    );
+    assert_eq!(
+        split(
+            r#"//! ```cargo
+//! [[bin]]
+//! name = "n"
+//! path = "helper.rs"
+//! ```
+fn main() {}
+"#,
+            None
+        )
+        .map(|s| s.bin_name),
+        Err("manifest has more than one binary target named `n`".into())
+    );
+    assert!(split(
+        r#"//! ```cargo
+//! [[bin]]
+//! name = "missing"
+//! path = "missing.rs"
+//! ```
+fn main() {}
+"#,
+        None
+    )
+    .unwrap_err()
+    .starts_with("could not read `missing.rs` for binary target `missing`"));
+}
+
+#[test]
+fn test_split_input_target_deps() {
+    let dummy_path: ::std::path::PathBuf = "/scripts/p".into();
+    let f = |c| Input::File("n", &dummy_path, c, 0);
+    let mani = |content| {
+        let split = split_input(&f(content), &SplitOptions::default()).unwrap();
+        toml::from_str::<toml::Value>(&split.manifest).unwrap()
+    };
+
+    let m = mani(
+        r#"//! ```cargo
+//! [target.'cfg(unix)'.dependencies]
+//! boolinator = "=0.1.0"
+//!
+//! [target.'cfg(windows)'.build-dependencies]
+//! helper = { path = "helper" }
+//! ```
+fn main() {}
+"#,
+    );
+    assert_eq!(
+        m["target"]["cfg(unix)"]["dependencies"]["boolinator"].as_str(),
+        Some("=0.1.0")
+    );
+    assert_eq!(
+        m["target"]["cfg(windows)"]["build-dependencies"]["helper"]["path"].as_str(),
+        Path::new("/scripts").join("helper").to_str()
+    );
+    assert!(m
+        .get("dependencies")
+        .and_then(toml::Value::as_table)
+        .map_or(true, |t| t.is_empty()));
+}
+
+/**
+Parses a `--manifest-key` override, of the form `dotted.key=value`, into the path to the key and its new value.
+
+The key is written as it would be in a manifest, so any part of it which isn't a bare key has to be quoted: *e.g.* `target.'cfg(unix)'.dependencies.libc=0.2`.  The value is TOML as well, except that anything which *isn't* valid TOML is taken as a string, so `package.description=Does things` works without any extra quoting.
+
+Numbers are taken as strings, too, except in the `profile` sections.  Nowhere else in a manifest takes a number, so `package.edition=2021` and `dependencies.regex=1.10` were almost certainly meant as the strings they look like.
+*/
+fn parse_manifest_key(spec: &str) -> Result<(Vec<String>, toml::Value)> {
+    use toml::Value;
+
+    let bad = |why: &str| {
+        MainError::OtherOwned(
+            Blame::Human,
+            format!("invalid manifest key `{}`: {}", spec, why),
+        )
+    };
+
+    let eq = spec.find('=').ok_or_else(|| bad("expected `KEY=VALUE`"))?;
+    let (key, raw) = (spec[..eq].trim(), &spec[eq + 1..]);
+
+    // TOML already knows how to split up a dotted key, quotes and all.
+    let mut table: toml::value::Table = toml::from_str(&format!("{} = 0", key))
+        .ok()
+        .filter(|_| !key.is_empty())
+        .ok_or_else(|| bad("the key is not a valid TOML key"))?;
+    let mut path = vec![];
+    while let Some((part, inner)) = table.into_iter().next() {
+        path.push(part);
+        table = match inner {
+            Value::Table(inner) => inner,
+            _ => break,
+        };
+    }
+
+    let value = match toml::from_str::<toml::value::Table>(&format!("v = {}", raw)) {
+        Ok(mut t) => match t.remove("v").unwrap() {
+            Value::Integer(_) | Value::Float(_) if path[0] != "profile" => {
+                Value::String(raw.trim().into())
+            }
+            value => value,
+        },
+        Err(_) => Value::String(raw.into()),
+    };
+    Ok((path, value))
+}
+
+#[test]
+fn test_parse_manifest_key() {
+    use toml::Value;
+
+    let pmk = |s| parse_manifest_key(s).map_err(|e| e.to_string());
+    let key = |k: &[&str]| k.iter().map(|&k| k.to_owned()).collect::<Vec<_>>();
+    let s = |v: &str| Value::String(v.into());
+
+    assert_eq!(
+        pmk("package.edition=2021"),
+        Ok((key(&["package", "edition"]), s("2021")))
+    );
+    assert_eq!(
+        pmk("dependencies.regex = 1.10"),
+        Ok((key(&["dependencies", "regex"]), s("1.10")))
+    );
+    assert_eq!(
+        pmk("profile.release.opt-level=3"),
+        Ok((key(&["profile", "release", "opt-level"]), Value::Integer(3)))
+    );
+    assert_eq!(
+        pmk("package.publish=false"),
+        Ok((key(&["package", "publish"]), Value::Boolean(false)))
+    );
+    assert_eq!(
+        pmk("package.description=Does things"),
+        Ok((key(&["package", "description"]), s("Does things")))
+    );
+    assert_eq!(
+        pmk(r#"package.description="x = y""#),
+        Ok((key(&["package", "description"]), s("x = y")))
+    );
+    assert_eq!(
+        pmk("target.'cfg(unix)'.dependencies.libc=0.2"),
+        Ok((
+            key(&["target", "cfg(unix)", "dependencies", "libc"]),
+            s("0.2")
+        ))
+    );
+    assert_eq!(
+        pmk(r#"dependencies.regex={ version = "1", default-features = false }"#)
+            .map(|(_, v)| v.get("default-features").cloned()),
+        Ok(Some(Value::Boolean(false)))
+    );
+
+    assert!(pmk("package.edition").is_err());
+    assert!(pmk("=2021").is_err());
+    assert!(pmk("package..edition=2021").is_err());
+    assert!(pmk("package edition=2021").is_err());
+}
+
+/**
+Sets a key in the manifest, creating any tables it should be in, as for `--manifest-key`.
+
+Cargo does the real checking of what's in the manifest; this only makes sure that the key *can* go where it's been asked to.
+*/
+fn set_manifest_key(
+    mani: &mut toml::value::Table,
+    key: &[String],
+    value: toml::Value,
+) -> Result<()> {
+    use toml::Value;
+
+    let name = || key.join(".");
+    let (last, parents) = key.split_last().expect("empty manifest key");
+    if parents.is_empty() && !(value.is_table() || value.is_array()) {
+        return Err(MainError::OtherOwned(
+            Blame::Human,
+            format!(
+                "cannot set manifest key `{}`: only sections can go at the top level",
+                name()
+            ),
+        ));
+    }
+
+    let mut table = mani;
+    for (i, part) in parents.iter().enumerate() {
+        let entry = table
+            .entry(part.clone())
+            .or_insert_with(|| Value::Table(Default::default()));
+        table = match entry {
+            Value::Table(t) => t,
+            _ => {
+                return Err(MainError::OtherOwned(
+                    Blame::Human,
+                    format!(
+                        "cannot set manifest key `{}`: `{}` is not a table",
+                        name(),
+                        parents[..=i].join(".")
+                    ),
+                ))
+            }
+        };
+    }
+
+    info!("setting manifest key {:?} to {:?}", key, value);
+    table.insert(last.clone(), value);
+    Ok(())
+}
+
+/**
+Given two Cargo manifests, merges the second *into* the first.
+
+Note that the "merge" in this case is relatively simple: only *top-level* tables are actually merged; everything else is just outright replaced.
+*/
+fn merge_manifest(
+    mut into_t: toml::value::Table,
+    from_t: toml::value::Table,
+) -> Result<toml::value::Table> {
+    for (k, v) in from_t {
+        match v {
+            toml::Value::Table(from_t) => {
+                use toml::map::Entry;
+
+                // Merge.
+                match into_t.entry(k) {
+                    Entry::Vacant(e) => {
+                        e.insert(toml::Value::Table(from_t));
+                    }
+                    Entry::Occupied(e) => {
+                        let into_t = as_table_mut(e.into_mut()).ok_or((
+                            Blame::Human,
                             "cannot merge manifests: cannot merge \
                              table and non-table values",
                         ))?;
@@ -1135,31 +4024,468 @@ fn merge_manifest(
 }
 
 /**
-Given a Cargo manifest, attempts to rewrite relative file paths to absolute ones, allowing the manifest to be relocated.
+Picks the binary target to run: the one called `bin` if that's given, or else the one built from the script itself (`script_file`).
 */
-fn fix_manifest_paths(mani: toml::value::Table, base: &Path) -> Result<toml::value::Table> {
-    // Values that need to be rewritten:
-    let paths: &[&[&str]] = &[
-        &["build-dependencies", "*", "path"],
-        &["dependencies", "*", "path"],
-        &["dev-dependencies", "*", "path"],
-        &["package", "build"],
-        &["target", "*", "dependencies", "*", "path"],
+fn select_bin(
+    mani: &toml::value::Table,
+    script_file: Option<&str>,
+    bin: Option<&str>,
+) -> Result<String> {
+    use toml::Value;
+
+    let bins: Vec<_> = mani
+        .get("bin")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .collect();
+    let mut names = vec![];
+    for target in &bins {
+        let name = target
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or((Blame::Human, "manifest has a binary target with no name"))?;
+        if names.contains(&name) {
+            return Err(MainError::OtherOwned(
+                Blame::Human,
+                format!("manifest has more than one binary target named `{}`", name),
+            ));
+        }
+        names.push(name);
+    }
+
+    let selected = match bin {
+        Some(bin) if names.contains(&bin) => Some(bin),
+        Some(bin) => {
+            return Err(MainError::OtherOwned(
+                Blame::Human,
+                format!(
+                    "there is no binary target named `{}`; the script has {}",
+                    bin,
+                    names
+                        .iter()
+                        .map(|n| format!("`{}`", n))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            ))
+        }
+        None => bins
+            .iter()
+            .zip(&names)
+            .find(|(target, _)| target.get("path").and_then(Value::as_str) == script_file)
+            .or_else(|| bins.iter().zip(&names).next())
+            .map(|(_, &name)| name),
+    };
+    selected.map(Into::into).ok_or_else(|| {
+        (
+            Blame::Human,
+            "manifest does not specify a binary target name",
+        )
+            .into()
+    })
+}
+
+/**
+Works out which files have to be copied into the generated package, and points the manifest at the copies.
+
+Cargo runs a build script from the directory of its package, which is off in the cache somewhere.  So that any relative paths the build script uses still work, the build script (from either a `build-script` code block or the file named by `package.build`) is copied into the package as `build.rs`, and the files listed in `package.metadata.cargo-script.include` are copied to the same places relative to the package as they are to the script.  The same goes for the sources of any `[[bin]]` or `[[example]]` targets other than the script itself (`script_file`).
+
+This has to happen *after* `fix_manifest_paths`, so that `package.build` has already been made absolute.
+*/
+fn package_files(
+    mani: &mut toml::value::Table,
+    build_block: Option<String>,
+    base: &Path,
+    script_file: Option<&str>,
+) -> Result<Vec<PackageFile>> {
+    use toml::Value;
+
+    let read = |path: &Path| {
+        fs::read(path)
+            .map_err(MainError::from)
+            .err_tag(format!("could not read `{}`", path.display()))
+            .shift_blame(Blame::Human)
+    };
+
+    // Copies have to stay inside the package.
+    let copy = |path: &str, what: &str| -> Result<PackageFile> {
+        use std::path::Component;
+
+        let inside = Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if !inside {
+            return Err(MainError::OtherOwned(
+                Blame::Human,
+                format!(
+                    "cannot copy `{}` into the package (for {}): paths must be relative, and inside the script's directory",
+                    path, what
+                ),
+            ));
+        }
+        let content = fs::read(base.join(path))
+            .map_err(MainError::from)
+            .err_tag(format!("could not read `{}` for {}", path, what))
+            .shift_blame(Blame::Human)?;
+        Ok(PackageFile {
+            path: path.into(),
+            content,
+        })
+    };
+
+    let mut files = vec![];
+    if let Some(Value::Table(pkg)) = mani.get_mut("package") {
+        let build = match (build_block, pkg.get("build")) {
+            (Some(_), Some(_)) => {
+                return Err((
+                    Blame::Human,
+                    "script has both a `build-script` code block and a `package.build` key; use one or the other",
+                )
+                    .into())
+            }
+            (Some(block), None) => Some(block.into_bytes()),
+            (None, Some(Value::String(path))) => Some(read(Path::new(path))?),
+            (None, _) => None,
+        };
+        if let Some(content) = build {
+            pkg.insert(
+                "build".into(),
+                Value::String(consts::BUILD_SCRIPT_NAME.into()),
+            );
+            files.push(PackageFile {
+                path: consts::BUILD_SCRIPT_NAME.into(),
+                content,
+            });
+        }
+
+        let include = pkg
+            .get("metadata")
+            .and_then(|m| m.get("cargo-script"))
+            .and_then(|cs| cs.get("include"));
+        let include = match include {
+            None => &[][..],
+            Some(Value::Array(paths)) if paths.iter().all(Value::is_str) => paths,
+            Some(_) => {
+                return Err((
+                    Blame::Human,
+                    "`include` in `[package.metadata.cargo-script]` must be an array of paths",
+                )
+                    .into())
+            }
+        };
+        for path in include.iter().filter_map(Value::as_str) {
+            files.push(copy(path, "`include`")?);
+        }
+    }
+
+    for &(key, kind) in &[("bin", "binary"), ("example", "example")] {
+        let targets = mani.get(key).and_then(Value::as_array);
+        for target in targets.into_iter().flatten() {
+            let path = match target.get("path").and_then(Value::as_str) {
+                Some(path) if Some(path) != script_file && Path::new(path).is_relative() => path,
+                _ => continue,
+            };
+            let name = target.get("name").and_then(Value::as_str).unwrap_or("?");
+            files.push(copy(path, &format!("{} target `{}`", kind, name))?);
+        }
+    }
+
+    Ok(files)
+}
+
+/**
+Finds the files for the modules a script declares with `mod name;`, so they can be copied into the package along with it.
+
+Modules are looked for where rustc would look for them, relative to the script's directory, except that `.crs` files will do as well as `.rs` ones: `name.rs`, `name.crs`, `name/mod.rs`, then `name/mod.crs`.  Whichever it is, the copy in the package is a `.rs` file, so rustc can find it there.  The modules' own modules are found the same way.
+
+Declarations with a `#[path]` attribute are left alone, as are modules which can't be found, and any source which doesn't parse; rustc can say what's wrong with those itself.  Returns where each module came from, along with its copy.
+*/
+fn module_files(source: &str, base: &Path) -> Result<Vec<(PathBuf, PackageFile)>> {
+    let mut files = vec![];
+    if RE_MOD_DECL.is_match(source) {
+        if let Ok(file) = syn::parse_file(source) {
+            collect_modules(&file.items, base, "", &mut files)?;
+        }
+    }
+    Ok(files)
+}
+
+fn collect_modules(
+    items: &[syn::Item],
+    dir: &Path,
+    pkg_dir: &str,
+    files: &mut Vec<(PathBuf, PackageFile)>,
+) -> Result<()> {
+    for item in items {
+        let module = match *item {
+            syn::Item::Mod(ref module) => module,
+            _ => continue,
+        };
+        if module.attrs.iter().any(|attr| attr.path().is_ident("path")) {
+            continue;
+        }
+        let name = module.ident.to_string();
+        let name = name.trim_start_matches("r#");
+        let sub_dir = format!("{}{}/", pkg_dir, name);
+
+        if let Some((_, ref items)) = module.content {
+            collect_modules(items, &dir.join(name), &sub_dir, files)?;
+            continue;
+        }
+
+        let found = [
+            (format!("{}.rs", name), format!("{}{}.rs", pkg_dir, name)),
+            (format!("{}.crs", name), format!("{}{}.rs", pkg_dir, name)),
+            (format!("{}/mod.rs", name), format!("{}mod.rs", sub_dir)),
+            (format!("{}/mod.crs", name), format!("{}mod.rs", sub_dir)),
+        ]
+        .iter()
+        .map(|(path, pkg_path)| (dir.join(path), pkg_path.clone()))
+        .find(|(path, _)| path.is_file_polyfill());
+        let (path, pkg_path) = match found {
+            Some(found) => found,
+            None => continue,
+        };
+
+        let content = fs::read_to_string(&path)
+            .map_err(MainError::from)
+            .err_tag(format!("could not read module `{}`", path.display()))
+            .shift_blame(Blame::Human)?;
+        if let Ok(file) = syn::parse_file(&content) {
+            collect_modules(&file.items, &dir.join(name), &sub_dir, files)?;
+        }
+        files.push((
+            path,
+            PackageFile {
+                path: pkg_path,
+                content: content.into_bytes(),
+            },
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_module_files() {
+    let dir = tempdir::TempDir::new("cargo-script-test-modules").unwrap();
+    let dir = dir.path();
+    let write = |name: &str, content: &str| fs::write(dir.join(name), content).unwrap();
+    fs::create_dir_all(dir.join("tree/leaf")).unwrap();
+    fs::create_dir_all(dir.join("inline")).unwrap();
+    write("plain.rs", "pub fn f() {}\n");
+    write("tree/mod.crs", "pub mod leaf;\nmod gone;\n");
+    write("tree/leaf/mod.rs", "mod r#fn;\n");
+    write("tree/leaf/fn.crs", "");
+    write("inline/deep.crs", "");
+    write("elsewhere.rs", "");
+
+    let source = r#"
+mod plain;
+pub(crate) mod tree;
+mod inline { mod deep; }
+#[path = "elsewhere.rs"]
+mod moved;
+fn main() {}
+"#;
+    let found: Vec<_> = module_files(source, dir)
+        .unwrap()
+        .into_iter()
+        .map(|(from, file)| (from.strip_prefix(dir).unwrap().to_owned(), file.path))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            (PathBuf::from("plain.rs"), "plain.rs".to_owned()),
+            (
+                PathBuf::from("tree/leaf/fn.crs"),
+                "tree/leaf/fn.rs".to_owned()
+            ),
+            (
+                PathBuf::from("tree/leaf/mod.rs"),
+                "tree/leaf/mod.rs".to_owned()
+            ),
+            (PathBuf::from("tree/mod.crs"), "tree/mod.rs".to_owned()),
+            (
+                PathBuf::from("inline/deep.crs"),
+                "inline/deep.rs".to_owned()
+            ),
+        ]
+    );
+
+    assert!(module_files("fn main() {}", dir).unwrap().is_empty());
+    assert!(module_files("mod plain; fn main( {}", dir)
+        .unwrap()
+        .is_empty());
+}
+
+/**
+Finds the files in a script bundle (a directory run as a script) to copy into its package, so that the bundle's modules, and anything it reads with `include_str!` and the like, are where its main file (`main`) expects them to be.
+
+Everything under `dir` is copied to the same place in the package, except for the main file itself, anything hidden (such as `.git`), and the bundle's own `target` directory.  Files which are already being copied (`existing`) are left alone, as are any which would trample on something the package needs for itself: its manifest, lockfile, build script and the like, or the script's own copy, `script_file`.  Returns the files in order of their paths in the package.
+*/
+pub fn bundle_files(
+    dir: &Path,
+    main: &Path,
+    existing: &[PackageFile],
+    script_file: &str,
+) -> Result<Vec<PackageFile>> {
+    let reserved = [
+        "Cargo.toml",
+        "Cargo.lock",
+        "target.exe_path",
+        consts::BUILD_SCRIPT_NAME,
+        consts::METADATA_FILE,
+        script_file,
     ];
 
+    let mut files = vec![];
+    let mut dirs = vec![(dir.to_owned(), String::new())];
+    while let Some((dir, pkg_dir)) = dirs.pop() {
+        let entries = fs::read_dir(&dir)
+            .map_err(MainError::from)
+            .err_tag(format!(
+                "could not read bundle directory `{}`",
+                dir.display()
+            ))?;
+        for entry in entries {
+            let entry = entry?;
+            let name = match entry.file_name().into_string() {
+                Ok(name) => name,
+                Err(name) => {
+                    return Err(MainError::OtherOwned(
+                        Blame::Human,
+                        format!(
+                            "cannot copy `{}` into the package: its name is not valid UTF-8",
+                            dir.join(name).display()
+                        ),
+                    ))
+                }
+            };
+            let path = entry.path();
+            let pkg_path = format!("{}{}", pkg_dir, name);
+            if name.starts_with('.') || path == main {
+                continue;
+            }
+            if path.is_dir() {
+                if pkg_path != "target" {
+                    dirs.push((path, format!("{}/", pkg_path)));
+                }
+                continue;
+            }
+            if reserved.contains(&&*pkg_path) || existing.iter().any(|f| f.path == pkg_path) {
+                continue;
+            }
+            let content = fs::read(&path)
+                .map_err(MainError::from)
+                .err_tag(format!("could not read `{}`", path.display()))
+                .shift_blame(Blame::Human)?;
+            files.push(PackageFile {
+                path: pkg_path,
+                content,
+            });
+        }
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+/**
+Turns off Cargo's discovery of targets from where their sources are, such as the `src/bin` and `tests` directories, for a package which has a script bundle copied into it.  Otherwise, any of the bundle's files which happened to be in those places would become targets of their own.  Anything the manifest already says about it is left alone.
+*/
+pub fn without_target_discovery(manifest: &str) -> Result<String> {
+    let mut mani: toml::value::Table = toml::from_str(manifest).map_err(|e| {
+        MainError::Tag(
+            Blame::Internal,
+            "could not parse package manifest".into(),
+            Box::new(MainError::Other(Blame::Internal, Box::new(e))),
+        )
+    })?;
+    if let Some(toml::Value::Table(pkg)) = mani.get_mut("package") {
+        for &key in &["autobins", "autoexamples", "autotests", "autobenches"] {
+            pkg.entry(key.to_owned())
+                .or_insert(toml::Value::Boolean(false));
+        }
+    }
+    Ok(format!("{}", toml::Value::Table(mani)))
+}
+
+#[test]
+fn test_bundle_files() {
+    let dir = tempdir::TempDir::new("cargo-script-test-bundle").unwrap();
+    let dir = dir.path();
+    let write = |name: &str, content: &str| fs::write(dir.join(name), content).unwrap();
+    for sub in &["data", "target/debug", ".git", "util"] {
+        fs::create_dir_all(dir.join(sub)).unwrap();
+    }
+    write("main.crs", "mod helper;\nfn main() {}\n");
+    write("helper.rs", "pub fn f() {}\n");
+    write("data/words.txt", "hello\n");
+    write("util/mod.rs", "");
+    write("target/debug/main", "");
+    write(".git/HEAD", "");
+    write(".hidden", "");
+    write("Cargo.toml", "");
+    write("build.rs", "");
+    write("bundle.rs", "");
+
+    let existing = [PackageFile {
+        path: "helper.rs".into(),
+        content: vec![],
+    }];
+    let files = bundle_files(dir, &dir.join("main.crs"), &existing, "bundle.rs").unwrap();
+    let paths: Vec<_> = files.iter().map(|f| &*f.path).collect();
+    assert_eq!(paths, ["data/words.txt", "util/mod.rs"]);
+    assert_eq!(files[0].content, b"hello\n");
+}
+
+#[test]
+fn test_without_target_discovery() {
+    let mani = without_target_discovery(
+        r#"
+[package]
+name = "bundle"
+autotests = true
+"#,
+    )
+    .unwrap();
+    let mani: toml::value::Table = toml::from_str(&mani).unwrap();
+    let pkg = mani["package"].as_table().unwrap();
+    assert_eq!(pkg["autobins"].as_bool(), Some(false));
+    assert_eq!(pkg["autoexamples"].as_bool(), Some(false));
+    assert_eq!(pkg["autotests"].as_bool(), Some(true));
+    assert_eq!(pkg["autobenches"].as_bool(), Some(false));
+}
+
+/// The values in a manifest which are file paths, and need to be rewritten if the manifest moves.
+const MANIFEST_PATHS: &[&[&str]] = &[
+    &["build-dependencies", "*", "path"],
+    &["dependencies", "*", "path"],
+    &["dev-dependencies", "*", "path"],
+    &["package", "build"],
+    &["target", "*", "build-dependencies", "*", "path"],
+    &["target", "*", "dependencies", "*", "path"],
+    &["target", "*", "dev-dependencies", "*", "path"],
+];
+
+/**
+Given a Cargo manifest, attempts to rewrite relative file paths to absolute ones, allowing the manifest to be relocated.
+*/
+fn fix_manifest_paths(mani: toml::value::Table, base: &Path) -> Result<toml::value::Table> {
     let mut mani = toml::Value::Table(mani);
 
-    for path in paths {
+    for path in MANIFEST_PATHS {
         iterate_toml_mut_path(&mut mani, path, &mut |v| {
             match *v {
                 toml::Value::String(ref mut s) => {
                     if Path::new(s).is_relative() {
-                        let p = base.join(&*s);
-                        match p.to_str() {
-                            Some(p) => *s = p.into(),
-                            None => {}
-                        }
+                        let p = platform::simplify_path(&base.join(&*s));
+                        *s = util::path_to_utf8(&p)
+                            .err_tag("cannot make a relative path in the manifest absolute")?
+                            .into();
                     }
+                    // Whichever separators the script used, Cargo gets the same ones.
+                    *s = platform::manifest_path(s);
                 }
                 _ => {}
             }
@@ -1173,6 +4499,105 @@ fn fix_manifest_paths(mani: toml::value::Table, base: &Path) -> Result<toml::val
     }
 }
 
+/**
+Rewrites the absolute paths in a generated manifest to be relative to `pkg_path`, where the package is being generated.
+
+This is for packages outside the cache, which might be checked in or compared between machines: absolute paths would tie them to wherever they happened to be generated.  Paths with no relative form are left as they are.
+*/
+pub fn relative_manifest_paths(mani: &str, pkg_path: &Path) -> Result<String> {
+    let mut mani: toml::Value =
+        toml::from_str(mani).map_err(|e| MainError::Other(Blame::Internal, Box::new(e)))?;
+
+    for path in MANIFEST_PATHS {
+        iterate_toml_mut_path(&mut mani, path, &mut |v| {
+            if let toml::Value::String(ref mut s) = *v {
+                if let Some(rel) = util::relative_path(pkg_path, Path::new(&*s)) {
+                    *s = rel;
+                }
+            }
+            Ok(())
+        })?
+    }
+
+    Ok(format!("{}", mani))
+}
+
+#[cfg(unix)]
+#[test]
+fn test_relative_manifest_paths() {
+    let mani = relative_manifest_paths(
+        r#"[dependencies.near]
+path = "/proj/near"
+
+[dependencies.registry]
+version = "1"
+
+[package]
+build = "build.rs"
+name = "n"
+
+[target.'cfg(unix)'.dependencies.far]
+path = "/elsewhere/far"
+"#,
+        Path::new("/proj/gen"),
+    )
+    .unwrap();
+    assert_eq!(
+        mani,
+        r#"[dependencies.near]
+path = "../near"
+
+[dependencies.registry]
+version = "1"
+
+[package]
+build = "build.rs"
+name = "n"
+[target."cfg(unix)".dependencies.far]
+path = "../../elsewhere/far"
+"#
+    );
+}
+
+#[cfg(windows)]
+#[test]
+fn test_fix_manifest_paths_separators() {
+    let mani: toml::value::Table = toml::from_str(
+        r#"
+[dependencies]
+back = { path = '..\lib\back' }
+forward = { path = "../lib/forward" }
+mixed = { path = 'sub/dir\mixed' }
+absolute = { path = 'D:\elsewhere\absolute' }
+verbatim = { path = '\\?\D:\elsewhere\verbatim' }
+"#,
+    )
+    .unwrap();
+    let mani = fix_manifest_paths(mani, Path::new(r"C:\proj\scripts")).unwrap();
+    let path = |name: &str| {
+        mani["dependencies"][name]["path"]
+            .as_str()
+            .unwrap()
+            .to_owned()
+    };
+
+    assert_eq!(path("back"), "C:/proj/scripts/../lib/back");
+    assert_eq!(path("forward"), "C:/proj/scripts/../lib/forward");
+    assert_eq!(path("mixed"), "C:/proj/scripts/sub/dir/mixed");
+    assert_eq!(path("absolute"), "D:/elsewhere/absolute");
+    assert_eq!(path("verbatim"), r"\\?\D:\elsewhere\verbatim");
+
+    // What has to keep its backslashes is escaped, and reads back the same.
+    let mani_str = format!("{}", toml::Value::Table(mani.clone()));
+    assert!(
+        mani_str.contains(r#""\\\\?\\D:\\elsewhere\\verbatim""#),
+        "{}",
+        mani_str
+    );
+    let reread: toml::value::Table = toml::from_str(&mani_str).unwrap();
+    assert_eq!(reread, mani);
+}
+
 /**
 Iterates over the specified TOML values via a path specification.
 */
@@ -1210,3 +4635,169 @@ where
 
     Ok(())
 }
+
+/**
+Renames the manifest's package if its name would collide with one of its dependencies, or with a reserved crate name.
+
+The binary target is left alone, so the compiled executable keeps its usual name.  Returns a note for the user if the package was renamed.
+*/
+fn disambiguate_pkg_name(mani: &mut toml::value::Table) -> Option<String> {
+    let name = match mani
+        .get("package")
+        .and_then(|p| p.get("name"))
+        .and_then(|n| n.as_str())
+    {
+        Some(name) => name.to_owned(),
+        None => return None,
+    };
+
+    let crate_name = |s: &str| s.replace("-", "_");
+    let ident = crate_name(&name);
+
+    let collides_with = consts::RESERVED_PKG_NAMES
+        .iter()
+        .map(|&n| n.to_owned())
+        .chain(dependency_names(mani))
+        .find(|dep| crate_name(dep) == ident)?;
+
+    let new_name = format!("{}{}", name, consts::PKG_NAME_COLLISION_SUFFIX);
+    info!("renaming package {:?} -> {:?}", name, new_name);
+    if let Some(toml::Value::Table(pkg)) = mani.get_mut("package") {
+        pkg.insert("name".into(), toml::Value::String(new_name.clone()));
+    }
+
+    Some(format!(
+        "package name `{}` collides with crate `{}`; using `{}` instead (use `--name` to choose a different name)",
+        name, collides_with, new_name
+    ))
+}
+
+/**
+Returns the names of all dependencies in a manifest, including the real names of any which have been renamed.
+*/
+fn dependency_names(mani: &toml::value::Table) -> Vec<String> {
+    let mut names = vec![];
+
+    let mut add_table = |deps: Option<&toml::Value>| {
+        if let Some(toml::Value::Table(deps)) = deps {
+            for (name, dep) in deps {
+                names.push(name.clone());
+                if let Some(package) = dep.get("package").and_then(|p| p.as_str()) {
+                    names.push(package.into());
+                }
+            }
+        }
+    };
+
+    for key in &["dependencies", "dev-dependencies", "build-dependencies"] {
+        add_table(mani.get(*key));
+    }
+
+    if let Some(toml::Value::Table(targets)) = mani.get("target") {
+        for target in targets.values() {
+            for key in &["dependencies", "dev-dependencies", "build-dependencies"] {
+                add_table(target.get(*key));
+            }
+        }
+    }
+
+    names
+}
+
+#[test]
+fn test_disambiguate_pkg_name() {
+    let dummy_path: ::std::path::PathBuf = "p".into();
+    let dummy_path = &dummy_path;
+    let f = |n, c| Input::File(n, dummy_path, c, 0);
+
+    macro_rules! si {
+        ($i:expr) => {
+            split_input(&$i, &SplitOptions::default())
+                .map(|s| (s.pkg_name, s.warnings.len()))
+                .map_err(|e| e.to_string())
+        };
+        ($i:expr, $name:expr) => {
+            split_input(
+                &$i,
+                &SplitOptions {
+                    pkg_name: Some($name),
+                    ..Default::default()
+                },
+            )
+            .map(|s| (s.pkg_name, s.warnings.len()))
+            .map_err(|e| e.to_string())
+        };
+    }
+
+    assert_eq!(si!(f("n", "fn main() {}")), Ok(("n".into(), 0)));
+    assert_eq!(
+        si!(f("rand", "// cargo-deps: rand\nfn main() {}")),
+        Ok(("rand-script".into(), 1))
+    );
+    assert_eq!(
+        si!(f("serde-json", "// cargo-deps: serde_json\nfn main() {}")),
+        Ok(("serde-json-script".into(), 1))
+    );
+    assert_eq!(
+        si!(f(
+            "my_rand",
+            r#"// cargo-deps: my_rand={package="rand"}
+fn main() {}"#
+        )),
+        Ok(("my_rand-script".into(), 1))
+    );
+    assert_eq!(
+        si!(f(
+            "rand",
+            r#"// cargo-deps: rand_core
+fn main() {}"#
+        )),
+        Ok(("rand".into(), 0))
+    );
+    assert_eq!(
+        si!(f("test", "fn main() {}")),
+        Ok(("test-script".into(), 1))
+    );
+    assert_eq!(
+        si!(f("rand", "// cargo-deps: rand\nfn main() {}"), "dice"),
+        Ok(("dice".into(), 0))
+    );
+    assert_eq!(
+        si!(f(
+            "rand",
+            r#"//! ```cargo
+//! [package]
+//! name = "rand"
+//! [dependencies]
+//! rand = "*"
+//! ```
+fn main() {}"#
+        )),
+        Ok(("rand".into(), 0))
+    );
+
+    assert_eq!(
+        split_input(
+            &f(
+                "n",
+                r#"//! ```cargo
+//! [[bin]]
+//! name = "renamed"
+//! ```
+fn main() {}"#
+            ),
+            &SplitOptions::default()
+        )
+        .map(|s| (s.bin_name, s.manifest.contains("path = \"n.rs\"")))
+        .map_err(|e| e.to_string()),
+        Ok(("renamed".into(), true))
+    );
+
+    // The binary target should keep its name.
+    let split = split_input(
+        &f("rand", "// cargo-deps: rand\nfn main() {}"),
+        &SplitOptions::default(),
+    )
+    .unwrap();
+    assert!(split.manifest.contains("[[bin]]\nname = \"rand\"\n"));
+}
@@ -0,0 +1,198 @@
+/*
+Copyright ⓒ 2017 cargo-script contributors.
+
+Licensed under the MIT license (see LICENSE or <http://opensource.org
+/licenses/MIT>) or the Apache License, Version 2.0 (see LICENSE of
+<http://www.apache.org/licenses/LICENSE-2.0>), at your option. All
+files in the project carrying such notice may not be copied, modified,
+or distributed except according to those terms.
+*/
+/*!
+This module contains code related to creating new scripts.
+*/
+use crate::consts;
+use crate::error::{Blame, MainError, Result, ResultExt};
+use crate::templates;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub struct Args {
+    pub name: String,
+    pub kind: Kind,
+    pub crs: bool,
+    pub force: bool,
+}
+
+/**
+What sort of starting point the new script should have.
+*/
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Kind {
+    File,
+    Expr,
+    Loop,
+}
+
+impl Kind {
+    /**
+    The name of the template used for this kind of script.
+    */
+    fn template_name(self) -> &'static str {
+        match self {
+            Kind::File => "new",
+            Kind::Expr => "new-expr",
+            Kind::Loop => "new-loop",
+        }
+    }
+}
+
+impl Args {
+    pub fn subcommand() -> clap::App<'static, 'static> {
+        use clap::{Arg, ArgGroup, SubCommand};
+
+        SubCommand::with_name("new")
+            .about("Create a new script from a template.")
+            .arg(
+                Arg::with_name("name")
+                    .help("Name of the script to create.  If it has no extension, `.crs` is added.")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("expr")
+                    .help("Start from a script which evaluates and displays an expression.")
+                    .long("expr")
+                    .short("e"),
+            )
+            .arg(
+                Arg::with_name("loop")
+                    .help("Start from a script which runs once for each line from stdin.")
+                    .long("loop")
+                    .short("l"),
+            )
+            .group(ArgGroup::with_name("expr_or_loop").args(&["expr", "loop"]))
+            .arg(
+                Arg::with_name("crs")
+                    .help("Give the script the `.crs` extension, even if its name already has one: `tool.rs` becomes `tool.crs`, and `tool.v2` becomes `tool.v2.crs`.")
+                    .long("crs"),
+            )
+            .arg(
+                Arg::with_name("force")
+                    .help("Overwrite the script if it already exists.")
+                    .long("force"),
+            )
+    }
+
+    pub fn parse(m: &clap::ArgMatches<'_>) -> Self {
+        let kind = match (m.is_present("expr"), m.is_present("loop")) {
+            (false, false) => Kind::File,
+            (true, false) => Kind::Expr,
+            (false, true) => Kind::Loop,
+            (true, true) => unreachable!(),
+        };
+        Args {
+            name: m.value_of("name").unwrap().into(),
+            kind,
+            crs: m.is_present("crs"),
+            force: m.is_present("force"),
+        }
+    }
+}
+
+pub fn try_main(args: Args) -> Result<i32> {
+    let path = script_path(&args.name, args.crs);
+
+    if path.exists() && !args.force {
+        return Err((
+            Blame::Human,
+            format!(
+                "`{}` already exists; use `--force` to overwrite it",
+                path.display()
+            ),
+        )
+            .into());
+    }
+
+    let content = render(args.kind, &path)?;
+    fs::write(&path, content)
+        .map_err(MainError::from)
+        .err_tag(format!("could not write `{}`", path.display()))
+        .shift_blame(Blame::Human)?;
+    make_executable(&path)?;
+
+    println!("Created `{}`.", path.display());
+    Ok(0)
+}
+
+/**
+Works out where the new script should go, adding the default extension if there isn't one.  With `crs`, the extension is always `.crs`: a `.rs` extension is swapped for it, and any other is kept in front of it.
+*/
+fn script_path(name: &str, crs: bool) -> PathBuf {
+    let path = PathBuf::from(name);
+    match path.extension().and_then(|ext| ext.to_str()) {
+        None | Some("rs") if crs => path.with_extension("crs"),
+        Some(ext) if crs && ext != "crs" => path.with_extension(format!("{}.crs", ext)),
+        Some(_) => path,
+        None => path.with_extension("crs"),
+    }
+}
+
+/**
+Renders the contents of the new script at `path`.
+*/
+fn render(kind: Kind, path: &Path) -> Result<String> {
+    let template = templates::get_template(kind.template_name())?;
+    let name = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut subs = HashMap::with_capacity(1);
+    subs.insert(consts::NEW_NAME_SUB, &*name);
+    templates::expand(&template, &subs)
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut perms = fs::metadata(path)?.permissions();
+    perms.set_mode(perms.mode() | ((perms.mode() & 0o444) >> 2));
+    fs::set_permissions(path, perms)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn make_executable(_: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[test]
+fn test_script_path() {
+    assert_eq!(script_path("foo", false), PathBuf::from("foo.crs"));
+    assert_eq!(script_path("foo.rs", false), PathBuf::from("foo.rs"));
+    assert_eq!(script_path("dir/foo", false), PathBuf::from("dir/foo.crs"));
+    assert_eq!(script_path("foo", true), PathBuf::from("foo.crs"));
+    assert_eq!(script_path("foo.rs", true), PathBuf::from("foo.crs"));
+    assert_eq!(script_path("foo.crs", true), PathBuf::from("foo.crs"));
+    assert_eq!(script_path("tool.v2", true), PathBuf::from("tool.v2.crs"));
+}
+
+#[test]
+fn test_builtin_templates() {
+    let mut subs = HashMap::new();
+    subs.insert(consts::NEW_NAME_SUB, "foo");
+
+    for template in &[
+        consts::NEW_SCRIPT_TEMPLATE,
+        consts::NEW_EXPR_SCRIPT_TEMPLATE,
+        consts::NEW_LOOP_SCRIPT_TEMPLATE,
+    ] {
+        let text = templates::expand(template, &subs).unwrap();
+        assert!(text.starts_with("#!/usr/bin/env run-cargo-script\n//! foo\n"));
+        assert!(text.contains("//! ```cargo\n"));
+        assert!(text.contains("fn main() {\n"));
+    }
+}
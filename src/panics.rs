@@ -0,0 +1,288 @@
+/*
+Copyright ⓒ 2017 cargo-script contributors.
+
+Licensed under the MIT license (see LICENSE or <http://opensource.org
+/licenses/MIT>) or the Apache License, Version 2.0 (see LICENSE of
+<http://www.apache.org/licenses/LICENSE-2.0>), at your option. All
+files in the project carrying such notice may not be copied, modified,
+or distributed except according to those terms.
+*/
+/*!
+This module contains code for `--clean-panics`, which boils a script's panic down to a one-line summary.
+*/
+use crate::error::Result;
+use lazy_static::lazy_static;
+use log::info;
+use regex::Regex;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+
+lazy_static! {
+    /// How panics have been reported since Rust 1.73: the location, then the message on the lines which follow.  Newer toolchains put the thread's ID after its name.
+    static ref RE_PANIC: Regex =
+        Regex::new(r"^thread '(.*)'(?: \(\d+\))? panicked at (.*:\d+:\d+):$").unwrap();
+    /// How panics were reported before that: the message and the location, all on one line.
+    static ref RE_OLD_PANIC: Regex =
+        Regex::new(r"^thread '(.*)'(?: \(\d+\))? panicked at '(.*)', (.*:\d+:\d+)$").unwrap();
+    static ref RE_LOCATION: Regex = Regex::new(r"^(.*):(\d+):(\d+)$").unwrap();
+}
+
+/// The exit code of a Rust program which died because of a panic.
+const PANIC_EXIT_CODE: i32 = 101;
+
+/**
+A panic, as picked out of a script's stderr.
+*/
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Panic {
+    thread: String,
+    location: String,
+    message: Vec<String>,
+}
+
+impl Panic {
+    fn summary(&self) -> String {
+        let thread = match &*self.thread {
+            "main" => String::new(),
+            thread => format!(" in thread '{}'", thread),
+        };
+        let message = self
+            .message
+            .iter()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("; ");
+        format!(
+            "script panicked{} at {}: {}",
+            thread, self.location, message
+        )
+    }
+}
+
+#[derive(Debug)]
+enum State {
+    Normal,
+    Message,
+    Backtrace,
+}
+
+/**
+Picks panics out of a script's stderr, a line at a time.
+
+Everything that makes up a panic report (the message, the backtrace, and the hint about `RUST_BACKTRACE`) is swallowed; everything else should be passed through untouched.
+*/
+#[derive(Debug)]
+struct PanicFilter {
+    state: State,
+    panics: Vec<Panic>,
+    swallowed: Vec<u8>,
+}
+
+impl PanicFilter {
+    fn new() -> Self {
+        PanicFilter {
+            state: State::Normal,
+            panics: vec![],
+            swallowed: vec![],
+        }
+    }
+
+    /**
+    Feeds the next line of stderr to the filter, returning `true` if it was part of a panic report.
+    */
+    fn line(&mut self, raw: &[u8]) -> bool {
+        let text = String::from_utf8_lossy(raw);
+        let line = text.trim_end_matches(|c| c == '\r' || c == '\n');
+
+        let swallow = match self.state {
+            State::Normal => self.start_panic(line),
+            State::Message | State::Backtrace if line.starts_with("note: ") => {
+                // This is the hint pointing at `RUST_BACKTRACE`, which ends the report.
+                self.state = State::Normal;
+                true
+            }
+            State::Message if line == "stack backtrace:" => {
+                self.state = State::Backtrace;
+                true
+            }
+            State::Message => {
+                if !self.start_panic(line) {
+                    self.panics.last_mut().unwrap().message.push(line.into());
+                }
+                true
+            }
+            State::Backtrace if line.starts_with(' ') => true,
+            State::Backtrace => {
+                // Backtraces don't always end with a note, such as when they're printed in full.
+                self.state = State::Normal;
+                self.start_panic(line)
+            }
+        };
+        if swallow {
+            self.swallowed.extend_from_slice(raw);
+        }
+        swallow
+    }
+
+    fn start_panic(&mut self, line: &str) -> bool {
+        if let Some(caps) = RE_PANIC.captures(line) {
+            self.panics.push(Panic {
+                thread: caps[1].into(),
+                location: caps[2].into(),
+                message: vec![],
+            });
+            self.state = State::Message;
+            true
+        } else if let Some(caps) = RE_OLD_PANIC.captures(line) {
+            self.panics.push(Panic {
+                thread: caps[1].into(),
+                location: caps[3].into(),
+                message: vec![caps[2].into()],
+            });
+            self.state = State::Message;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/**
+Runs a script, replacing any panic it reports with a one-line summary.
+
+`relocate` is given the file and line of the panic's location, and can point it somewhere more useful, such as the script itself rather than the generated package.
+
+The summary is only shown if the script actually died from a panic.  If it survived (say, because the panic was in a thread it didn't join), whatever was swallowed is written out as-is once the script is done, so nothing is lost.
+*/
+pub fn run_cleanly<F>(cmd: &mut Command, relocate: F) -> Result<i32>
+where
+    F: Fn(&str, usize) -> Option<(String, usize)>,
+{
+    info!("running with clean panics {:?}", cmd);
+    crate::logging::trace_command(cmd);
+    let mut child = cmd.stderr(Stdio::piped()).spawn()?;
+    crate::platform::forward_signals_to(&child);
+
+    let mut filter = PanicFilter::new();
+    {
+        let mut stderr_in = BufReader::new(child.stderr.take().unwrap());
+        let stderr = io::stderr();
+        let mut line = vec![];
+        loop {
+            line.clear();
+            if stderr_in.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            if !filter.line(&line) {
+                let mut stderr = stderr.lock();
+                stderr.write_all(&line)?;
+                stderr.flush()?;
+            }
+        }
+    }
+    let code = crate::platform::exit_code(child.wait()?);
+
+    if code != PANIC_EXIT_CODE || filter.panics.is_empty() {
+        io::stderr().write_all(&filter.swallowed)?;
+        return Ok(code);
+    }
+
+    for panic in &filter.panics {
+        let mut panic = panic.clone();
+        let relocated = RE_LOCATION.captures(&panic.location).and_then(|caps| {
+            let line = caps[2].parse().ok()?;
+            relocate(&caps[1], line).map(|(file, line)| format!("{}:{}:{}", file, line, &caps[3]))
+        });
+        if let Some(location) = relocated {
+            panic.location = location;
+        }
+        eprintln!("error: {}", panic.summary());
+    }
+    note!("run with `--verbose` to see the full panic, and with `RUST_BACKTRACE=1` to see a backtrace");
+    Ok(code)
+}
+
+#[test]
+fn test_panic_filter() {
+    let filter = |lines: &[&str]| {
+        let mut filter = PanicFilter::new();
+        let passed: Vec<String> = lines
+            .iter()
+            .filter(|line| !filter.line(format!("{}\n", line).as_bytes()))
+            .map(|&line| line.into())
+            .collect();
+        let summaries: Vec<String> = filter.panics.iter().map(Panic::summary).collect();
+        (passed, summaries)
+    };
+
+    assert_eq!(
+        filter(&[
+            "before",
+            "thread 'main' panicked at p.rs:4:6:",
+            "index out of bounds: the len is 0 but the index is 3",
+            "note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace",
+        ]),
+        (
+            vec!["before".into()],
+            vec![
+                "script panicked at p.rs:4:6: index out of bounds: the len is 0 but the index is 3"
+                    .into()
+            ]
+        )
+    );
+
+    assert_eq!(
+        filter(&[
+            "thread 'worker' panicked at p.rs:10:5:",
+            "assertion `left == right` failed",
+            "  left: 1",
+            " right: 2",
+            "stack backtrace:",
+            "   0: rust_begin_unwind",
+            "   1: p::main",
+            "note: Some details are omitted, run with `RUST_BACKTRACE=full` for a verbose backtrace.",
+            "after",
+        ]),
+        (
+            vec!["after".into()],
+            vec!["script panicked in thread 'worker' at p.rs:10:5: assertion `left == right` failed; left: 1; right: 2".into()]
+        )
+    );
+
+    assert_eq!(
+        filter(&[
+            "thread 'main' (24094) panicked at p.rs:3:5:",
+            "oh no",
+            "note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace",
+        ]),
+        (vec![], vec!["script panicked at p.rs:3:5: oh no".into()])
+    );
+
+    assert_eq!(
+        filter(&["thread 'worker' (7) panicked at p.rs:1:1:", "boom"]),
+        (
+            vec![],
+            vec!["script panicked in thread 'worker' at p.rs:1:1: boom".into()]
+        )
+    );
+
+    assert_eq!(
+        filter(&[
+            "thread 'main' panicked at 'oh no', p.rs:2:5",
+            "note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace",
+        ]),
+        (vec![], vec!["script panicked at p.rs:2:5: oh no".into()])
+    );
+
+    assert_eq!(
+        filter(&["thread 'main' panicked at the disco", "note: not a panic"]),
+        (
+            vec![
+                "thread 'main' panicked at the disco".into(),
+                "note: not a panic".into()
+            ],
+            vec![]
+        )
+    );
+}
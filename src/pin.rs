@@ -0,0 +1,82 @@
+/*
+Copyright ⓒ 2017 cargo-script contributors.
+
+Licensed under the MIT license (see LICENSE or <http://opensource.org
+/licenses/MIT>) or the Apache License, Version 2.0 (see LICENSE of
+<http://www.apache.org/licenses/LICENSE-2.0>), at your option. All
+files in the project carrying such notice may not be copied, modified,
+or distributed except according to those terms.
+*/
+/*!
+This module contains code for the `pin` and `unpin` subcommands, which keep a script's cache entry from being cleaned out when it gets old.
+*/
+use crate::error::{Blame, MainError, Result};
+use log::info;
+use std::ffi::OsString;
+
+#[derive(Debug)]
+pub struct Args {
+    pub script: OsString,
+    pub pinned: bool,
+}
+
+impl Args {
+    pub fn subcommand() -> clap::App<'static, 'static> {
+        script_subcommand("pin")
+            .about("Keep a script's cache entry, however long it goes without being run.  Clearing the cache with `--clear-cache` still removes it.")
+    }
+
+    pub fn unpin_subcommand() -> clap::App<'static, 'static> {
+        script_subcommand("unpin").about(
+            "Let a pinned script's cache entry be cleaned out once it gets old, like any other.",
+        )
+    }
+
+    pub fn parse(m: &clap::ArgMatches<'_>, pinned: bool) -> Self {
+        Args {
+            script: m.value_of_os("script").unwrap().to_owned(),
+            pinned,
+        }
+    }
+}
+
+fn script_subcommand(name: &'static str) -> clap::App<'static, 'static> {
+    use clap::{AppSettings, Arg, SubCommand};
+
+    SubCommand::with_name(name)
+        .setting(AppSettings::AllowInvalidUtf8)
+        .arg(
+            Arg::with_name("script")
+                .help("Script file whose cache entry to change.")
+                .index(1)
+                .required(true),
+        )
+}
+
+pub fn try_main(args: Args) -> Result<i32> {
+    let script = crate::load_script(&args.script, None)?;
+
+    // Every entry the script has is pinned, whichever flags it was built with; `take_over_pin` looks after any it gets once it's changed.
+    let entries = crate::script_cache_entries(&script.path)?;
+    if entries.is_empty() {
+        return Err(MainError::OtherOwned(
+            Blame::Human,
+            format!(
+                "`{}` has no cache entry to {}; run it first",
+                script.found.display(),
+                if args.pinned { "pin" } else { "unpin" }
+            ),
+        ));
+    }
+    for (pkg_path, mut meta) in entries {
+        info!("setting pinned to {} for {:?}", args.pinned, pkg_path);
+        meta.pinned = if args.pinned { Some(true) } else { None };
+        crate::write_pkg_metadata(&pkg_path, &meta)?;
+    }
+
+    match args.pinned {
+        true => println!("Pinned `{}`.", script.found.display()),
+        false => println!("Unpinned `{}`.", script.found.display()),
+    }
+    Ok(0)
+}
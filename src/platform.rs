@@ -12,8 +12,9 @@ This module is for platform-specific stuff.
 */
 
 pub use self::inner::{
-    current_time, file_last_modified, force_cargo_color, get_cache_dir, get_config_dir,
-    migrate_old_data, read_path, write_path,
+    current_time, exit_code, file_last_modified, force_cargo_color, forward_signals_to,
+    get_cache_dir, get_config_dir, manifest_path, migrate_old_data, read_path, shell_command,
+    simplify_path, write_path, ProcessTree, SignalGuard,
 };
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -28,6 +29,132 @@ impl MigrationKind {
     }
 }
 
+/**
+The longest path most Windows programs will cope with, unless it has a `\\?\` prefix.
+*/
+#[cfg(any(windows, test))]
+const WIN_MAX_PATH: usize = 260;
+
+/**
+Turns a Windows "verbatim" path (*i.e.* one starting with `\\?\`) into its ordinary equivalent, if there is one.
+
+Returns `None` if the path isn't verbatim, or if it *needs* to be: because it's too long, or because some component would mean something different without the prefix (like `nul`, `a.`, or `..`).
+*/
+#[cfg(any(windows, test))]
+fn simplify_verbatim(s: &str) -> Option<String> {
+    let simple = if let Some(rest) = s.strip_prefix(r"\\?\UNC\") {
+        format!(r"\\{}", rest)
+    } else if let Some(rest) = s.strip_prefix(r"\\?\") {
+        let drive = rest.as_bytes();
+        if drive.len() < 3 || !drive[0].is_ascii_alphabetic() || &drive[1..3] != b":\\" {
+            return None;
+        }
+        rest.into()
+    } else {
+        return None;
+    };
+
+    if simple.len() >= WIN_MAX_PATH {
+        return None;
+    }
+
+    const RESERVED: &[&str] = &["aux", "con", "conin$", "conout$", "nul", "prn"];
+    let is_reserved = |c: &str| {
+        let stem = c
+            .split('.')
+            .next()
+            .unwrap_or("")
+            .trim_end()
+            .to_ascii_lowercase();
+        RESERVED.contains(&&*stem)
+            || ((stem.starts_with("com") || stem.starts_with("lpt"))
+                && stem.len() == 4
+                && stem.as_bytes()[3].is_ascii_digit())
+    };
+
+    let skip = if simple.starts_with(r"\\") { 2 } else { 1 };
+    for component in simple.split('\\').skip(skip) {
+        if component == "."
+            || component == ".."
+            || component.ends_with('.')
+            || component.ends_with(' ')
+            || component.contains(|c| "<>:\"/|?*".contains(c) || (c as u32) < 32)
+            || is_reserved(component)
+        {
+            return None;
+        }
+    }
+
+    Some(simple)
+}
+
+#[test]
+fn test_simplify_verbatim() {
+    macro_rules! s {
+        ($s:expr) => {
+            simplify_verbatim($s).as_ref().map(|s| &**s)
+        };
+    }
+
+    assert_eq!(s!(r"C:\Users\a\x.rs"), None);
+    assert_eq!(s!(r"\\server\share\x.rs"), None);
+
+    assert_eq!(s!(r"\\?\C:\Users\a\x.rs"), Some(r"C:\Users\a\x.rs"));
+    assert_eq!(s!(r"\\?\c:\"), Some(r"c:\"));
+    assert_eq!(
+        s!(r"\\?\UNC\server\share\tools\x.rs"),
+        Some(r"\\server\share\tools\x.rs")
+    );
+
+    assert_eq!(s!(r"\\?\Volume{0000}\x.rs"), None);
+    assert_eq!(s!(r"\\?\C:\a\nul"), None);
+    assert_eq!(s!(r"\\?\C:\a\COM1.txt"), None);
+    assert_eq!(s!(r"\\?\C:\a\trailing."), None);
+    assert_eq!(s!(r"\\?\C:\a\..\b"), None);
+    assert_eq!(s!(r"\\?\C:\a\b:c"), None);
+    assert_eq!(s!(r"\\?\C:\a\console"), Some(r"C:\a\console"));
+
+    let long = format!(r"\\?\C:\{}", "a".repeat(WIN_MAX_PATH));
+    assert_eq!(simplify_verbatim(&long), None);
+}
+
+/**
+Rewrites a Windows path so that only `sep` separates its components, since `\` and `/` can be mixed freely.
+
+Returns `None` if there's nothing to change, or if the path is verbatim (*i.e.* starts with `\\?\`), since Windows takes those literally: a `/` in one isn't a separator, and can't be used as one.
+*/
+#[cfg(any(windows, test))]
+fn with_separators(s: &str, sep: char) -> Option<String> {
+    let other = match sep {
+        '/' => '\\',
+        _ => '/',
+    };
+    match s.starts_with(r"\\?\") || !s.contains(other) {
+        true => None,
+        false => Some(s.replace(other, &sep.to_string())),
+    }
+}
+
+#[test]
+fn test_with_separators() {
+    assert_eq!(
+        with_separators(r"scripts\tools/x.rs", '\\').as_deref(),
+        Some(r"scripts\tools\x.rs")
+    );
+    assert_eq!(with_separators(r"scripts\tools\x.rs", '\\'), None);
+    assert_eq!(
+        with_separators(r"C:\proj\lib", '/').as_deref(),
+        Some("C:/proj/lib")
+    );
+    assert_eq!(
+        with_separators(r"\\server\share/lib", '/').as_deref(),
+        Some("//server/share/lib")
+    );
+    assert_eq!(with_separators("../lib", '/'), None);
+    assert_eq!(with_separators(r"\\?\C:\a\b", '/'), None);
+    assert_eq!(with_separators(r"\\?\C:\a/b", '\\'), None);
+}
+
 #[cfg(any(unix, windows))]
 mod inner_unix_or_windows {
     /**
@@ -62,7 +189,8 @@ mod inner {
     use std::os::unix::ffi::OsStrExt;
     use std::os::unix::fs::MetadataExt;
     use std::path::{Path, PathBuf};
-    use std::{cmp, env, fs, io};
+    use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+    use std::{cmp, env, fs, io, mem, ptr};
 
     /**
     Gets the last-modified time of a file, in milliseconds since the UNIX epoch.
@@ -77,7 +205,7 @@ mod inner {
     /**
     Get a directory suitable for storing user- and machine-specific data which may or may not be persisted across sessions.
 
-    This is chosen to match the location where Cargo places its cache data.
+    If `$CARGO_HOME` is defined, this is chosen to match the location where Cargo places its cache data.  Otherwise, this follows the XDG base directory spec, using `$XDG_CACHE_HOME/cargo-script` (or `~/.cache/cargo-script`).  On macOS, or if an older version already put a cache in `~/.cargo`, it uses `~/.cargo` instead.
     */
     pub fn get_cache_dir() -> Result<PathBuf, MainError> {
         // try $CARGO_HOME then fall back to $HOME
@@ -97,21 +225,56 @@ mod inner {
             return Ok(home.into());
         }
 
-        if let Some(home) = env::var_os("HOME") {
-            return Ok(Path::new(&home).join(".cargo"));
+        let home = env::var_os("HOME").map(PathBuf::from);
+
+        if let Some(home) = home.as_ref() {
+            let old_home = home.join(".cargo");
+            if cfg!(target_os = "macos")
+                || old_home.join("script-cache").exists()
+                || old_home.join("binary-cache").exists()
+            {
+                return Ok(old_home);
+            }
         }
 
-        Err((Blame::Human, "neither $CARGO_HOME nor $HOME is defined").into())
+        xdg_dir("XDG_CACHE_HOME", home.as_deref(), ".cache")
+            .ok_or((Blame::Human, "neither $CARGO_HOME nor $HOME is defined").into())
     }
 
     /**
     Get a directory suitable for storing user-specific configuration data.
 
-    This is chosen to match the location where Cargo places its configuration data.
+    If `$CARGO_HOME` is defined, this is the same as the cache directory.  Otherwise, this follows the XDG base directory spec, using `$XDG_CONFIG_HOME/cargo-script` (or `~/.config/cargo-script`).  On macOS, or if an older version already put templates in `~/.cargo`, it uses `~/.cargo` instead.
     */
     pub fn get_config_dir() -> Result<PathBuf, MainError> {
-        // Currently, this appears to be the same as the cache directory.
-        get_cache_dir()
+        if env::var_os("CARGO_HOME").is_some() {
+            return get_cache_dir();
+        }
+
+        let home = env::var_os("HOME").map(PathBuf::from);
+
+        if let Some(home) = home.as_ref() {
+            let old_home = home.join(".cargo");
+            if cfg!(target_os = "macos") || old_home.join("script-templates").exists() {
+                return Ok(old_home);
+            }
+        }
+
+        xdg_dir("XDG_CONFIG_HOME", home.as_deref(), ".config")
+            .ok_or((Blame::Human, "neither $CARGO_HOME nor $HOME is defined").into())
+    }
+
+    /**
+    Works out `cargo-script`'s directory under an XDG base directory.
+
+    The spec says relative paths in `var` are to be ignored, in which case this falls back to `default` under the user's home directory.
+    */
+    fn xdg_dir(var: &str, home: Option<&Path>, default: &str) -> Option<PathBuf> {
+        env::var_os(var)
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .or_else(|| home.map(|h| h.join(default)))
+            .map(|base| base.join("cargo-script"))
     }
 
     pub fn migrate_old_data(kind: MigrationKind) -> (Vec<String>, Result<(), MainError>) {
@@ -222,6 +385,24 @@ mod inner {
         w.write_all(path.as_os_str().as_bytes())
     }
 
+    /**
+    Returns the path in the form it should be given to other programs.
+
+    On Windows, this removes unnecessary `\\?\` prefixes.  Nothing needs doing here.
+    */
+    pub fn simplify_path(path: &Path) -> PathBuf {
+        path.into()
+    }
+
+    /**
+    Returns a path in the form it should be written into a generated manifest.
+
+    On Windows, this writes it with `/` separators.  Here, `/` is the only separator there is.
+    */
+    pub fn manifest_path(path: &str) -> String {
+        path.into()
+    }
+
     pub fn read_path<R>(r: &mut R) -> io::Result<PathBuf>
     where
         R: io::Read,
@@ -240,6 +421,228 @@ mod inner {
     pub fn force_cargo_color() -> bool {
         atty::is(atty::Stream::Stderr)
     }
+
+    /**
+    Builds a command which runs `command` with the shell, as `sh -c`.
+    */
+    pub fn shell_command(command: &str) -> std::process::Command {
+        let mut cmd = std::process::Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    }
+
+    /**
+    A child process, and everything it starts, so that they can all be killed at once.
+
+    On Unix, this is the child's process group: it's put in one of its own, so the group goes by the child's PID.  Everything it starts stays in the group, unless it goes out of its way to leave.
+    */
+    pub struct ProcessTree {
+        pgid: Option<libc::pid_t>,
+    }
+
+    impl ProcessTree {
+        /**
+        Sets up `cmd` so that what it spawns can be treated as one tree.  Note that this takes it out of the terminal's foreground process group, so Ctrl+C no longer reaches it by itself; see `forward_signals`.
+        */
+        pub fn prepare(cmd: &mut std::process::Command) -> io::Result<ProcessTree> {
+            use std::os::unix::process::CommandExt;
+            cmd.process_group(0);
+            Ok(ProcessTree { pgid: None })
+        }
+
+        /**
+        Takes in the child spawned from the command given to `prepare`.
+        */
+        pub fn adopt(&mut self, child: &std::process::Child) -> io::Result<()> {
+            self.pgid = Some(child.id() as libc::pid_t);
+            Ok(())
+        }
+
+        /**
+        Has the `SignalGuard`, which should come from `SignalGuard::install_for_tree`, pass signals on to everything in the tree.  Without a guard, this does nothing.
+        */
+        pub fn forward_signals(&self) {
+            let pgid = match self.pgid {
+                Some(pgid) if pgid > 0 && GUARDED.load(Ordering::SeqCst) => pgid,
+                _ => return,
+            };
+            FORWARD_TO.store(-pgid, Ordering::SeqCst);
+            // The signal stays pending, since it's raised again once the guard is gone.
+            match PENDING.load(Ordering::SeqCst) {
+                0 => (),
+                sig => unsafe {
+                    libc::kill(-pgid, sig);
+                },
+            }
+        }
+
+        /**
+        Kills everything in the tree.
+        */
+        pub fn kill(&self) -> io::Result<()> {
+            match self.pgid {
+                // Anything not above zero would take out other processes, including our own.
+                Some(pgid) if pgid > 0 => {
+                    if unsafe { libc::kill(-pgid, libc::SIGKILL) } == 0 {
+                        Ok(())
+                    } else {
+                        Err(io::Error::last_os_error())
+                    }
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+
+    /// The signals which are passed on to the script.
+    const FORWARDED_SIGNALS: [libc::c_int; 4] =
+        [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGQUIT];
+
+    /// Is there a `SignalGuard` alive?
+    static GUARDED: AtomicBool = AtomicBool::new(false);
+
+    /// Is the `SignalGuard` for a `ProcessTree`, rather than a script?
+    static WHOLE_TREE: AtomicBool = AtomicBool::new(false);
+
+    /// The process `SignalGuard` passes signals on to, or zero for none.  A process group goes by its negated ID, as with `kill`.
+    static FORWARD_TO: AtomicI32 = AtomicI32::new(0);
+
+    /// A signal which came in before there was anywhere to pass it on to, or zero for none.
+    static PENDING: AtomicI32 = AtomicI32::new(0);
+
+    extern "C" fn forward_signal(
+        sig: libc::c_int,
+        info: *mut libc::siginfo_t,
+        _: *mut libc::c_void,
+    ) {
+        /*
+        Ctrl+C, and the terminal hanging up, already reach the script by themselves, since it's in the terminal's process group; passing those on would give it each one twice.  Only signals some other process sent to us in particular need passing on.
+        */
+        let sent_by_process = !info.is_null() && unsafe { (*info).si_pid() } != 0;
+        // A process tree is in a group of its own, which the terminal doesn't reach.  Once it's been stopped, the signal is for us too.
+        let whole_tree = WHOLE_TREE.load(Ordering::SeqCst);
+        if !sent_by_process && !whole_tree {
+            return;
+        }
+        // Until `forward_signals_to` is called, which can be just after the script has started and said so, the signal waits for it.
+        match FORWARD_TO.load(Ordering::SeqCst) {
+            0 => PENDING.store(sig, Ordering::SeqCst),
+            pid => {
+                unsafe {
+                    libc::kill(pid, sig);
+                }
+                if whole_tree {
+                    PENDING.store(sig, Ordering::SeqCst);
+                }
+            }
+        }
+    }
+
+    /**
+    While this is alive, `SIGINT`, `SIGTERM`, `SIGHUP` and `SIGQUIT` don't kill `cargo-script`, but are passed on to the process given to `forward_signals_to`, or the tree given to `ProcessTree::forward_signals`.  That way, the script gets the chance to clean up, and we get to see how it ended.
+
+    Signals which were being ignored when this was installed stay ignored.
+    */
+    pub struct SignalGuard {
+        old: Vec<(libc::c_int, libc::sigaction)>,
+    }
+
+    impl SignalGuard {
+        pub fn install() -> SignalGuard {
+            SignalGuard::install_with(false)
+        }
+
+        /**
+        Installs a guard for a `ProcessTree`, which `ProcessTree::forward_signals` then passes signals on to.  Ctrl+C is passed on as well, since the tree doesn't get it from the terminal, and once the tree has had it, it's raised again for us when the guard is dropped.
+        */
+        pub fn install_for_tree() -> SignalGuard {
+            SignalGuard::install_with(true)
+        }
+
+        fn install_with(whole_tree: bool) -> SignalGuard {
+            // Anything left over from before this guard has nothing to do with it.
+            FORWARD_TO.store(0, Ordering::SeqCst);
+            PENDING.store(0, Ordering::SeqCst);
+            WHOLE_TREE.store(whole_tree, Ordering::SeqCst);
+            GUARDED.store(true, Ordering::SeqCst);
+            let mut old = vec![];
+            for &sig in &FORWARDED_SIGNALS {
+                unsafe {
+                    let mut prev: libc::sigaction = mem::zeroed();
+                    if libc::sigaction(sig, ptr::null(), &mut prev) != 0
+                        || prev.sa_sigaction == libc::SIG_IGN
+                    {
+                        continue;
+                    }
+                    let mut action: libc::sigaction = mem::zeroed();
+                    action.sa_sigaction = forward_signal
+                        as extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void)
+                        as libc::sighandler_t;
+                    action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+                    libc::sigemptyset(&mut action.sa_mask);
+                    if libc::sigaction(sig, &action, &mut prev) == 0 {
+                        old.push((sig, prev));
+                    } else {
+                        info!(
+                            "could not forward signal {}: {}",
+                            sig,
+                            io::Error::last_os_error()
+                        );
+                    }
+                }
+            }
+            SignalGuard { old }
+        }
+    }
+
+    impl Drop for SignalGuard {
+        fn drop(&mut self) {
+            GUARDED.store(false, Ordering::SeqCst);
+            FORWARD_TO.store(0, Ordering::SeqCst);
+            WHOLE_TREE.store(false, Ordering::SeqCst);
+            for (sig, prev) in self.old.drain(..) {
+                unsafe {
+                    libc::sigaction(sig, &prev, ptr::null_mut());
+                }
+            }
+            // A signal that never had anywhere to go was meant for us after all, as was one a process tree was stopped with.
+            match PENDING.swap(0, Ordering::SeqCst) {
+                0 => (),
+                sig => unsafe {
+                    libc::raise(sig);
+                },
+            }
+        }
+    }
+
+    /**
+    Has the `SignalGuard` pass signals on to `child`.  Without one, this does nothing, since the signals aren't being caught.
+    */
+    pub fn forward_signals_to(child: &std::process::Child) {
+        if !GUARDED.load(Ordering::SeqCst) {
+            return;
+        }
+        let pid = child.id() as libc::pid_t;
+        FORWARD_TO.store(pid, Ordering::SeqCst);
+        match PENDING.swap(0, Ordering::SeqCst) {
+            0 => (),
+            sig => unsafe {
+                libc::kill(pid, sig);
+            },
+        }
+    }
+
+    /**
+    The exit code to pass on for a child which exited with `status`.  One killed by a signal gets 128 plus the signal's number, as it would from a shell.
+    */
+    pub fn exit_code(status: std::process::ExitStatus) -> i32 {
+        use std::os::unix::process::ExitStatusExt;
+        match (status.code(), status.signal()) {
+            (Some(code), _) => code,
+            (None, Some(sig)) => 128 + sig,
+            (None, None) => 1,
+        }
+    }
 }
 
 #[cfg(windows)]
@@ -250,6 +653,7 @@ pub mod inner {
 
     use super::MigrationKind;
     use crate::error::MainError;
+    use log::info;
     use std::ffi::OsString;
     use std::fmt;
     use std::fs;
@@ -257,6 +661,7 @@ pub mod inner {
     use std::mem;
     use std::os::windows::ffi::{OsStrExt, OsStringExt};
     use std::path::{Path, PathBuf};
+    use winapi::shared::minwindef::{BOOL, DWORD, FALSE, TRUE};
 
     /**
     Gets the last-modified time of a file, in milliseconds since the UNIX epoch.
@@ -366,6 +771,33 @@ pub mod inner {
         Ok(())
     }
 
+    /**
+    Returns the path in the form it should be given to other programs.
+
+    `canonicalize` and friends produce `\\?\` paths, which lots of programs (including the shell and, in places, Cargo) don't understand.  This strips the prefix whenever doing so doesn't change what the path refers to.
+
+    Separators are made consistent, too.  `scripts/tools\x.rs` and `scripts\tools\x.rs` are the same script, and have to be recorded the same way to be recognised as such.
+    */
+    pub fn simplify_path(path: &Path) -> PathBuf {
+        let path: PathBuf = match path.to_str().and_then(super::simplify_verbatim) {
+            Some(simple) => simple.into(),
+            None => path.into(),
+        };
+        match path.to_str().and_then(|s| super::with_separators(s, '\\')) {
+            Some(simple) => simple.into(),
+            None => path,
+        }
+    }
+
+    /**
+    Returns a path in the form it should be written into a generated manifest.
+
+    Cargo understands `/` on Windows, and unlike `\`, it doesn't have to be escaped in TOML, so that's what's used.  Verbatim paths keep their backslashes, which the TOML writer escapes.
+    */
+    pub fn manifest_path(path: &str) -> String {
+        super::with_separators(path, '/').unwrap_or_else(|| path.into())
+    }
+
     pub fn read_path<R>(r: &mut R) -> io::Result<PathBuf>
     where
         R: io::Read,
@@ -391,4 +823,133 @@ pub mod inner {
     pub fn force_cargo_color() -> bool {
         false
     }
+
+    /**
+    Builds a command which runs `command` with the shell, as `cmd /C`.
+
+    The command is passed on as it is, rather than quoted, since `cmd` has quoting rules of its own.
+    */
+    pub fn shell_command(command: &str) -> std::process::Command {
+        use std::os::windows::process::CommandExt;
+        let mut cmd = std::process::Command::new("cmd");
+        cmd.arg("/C").raw_arg(command);
+        cmd
+    }
+
+    /**
+    A child process, and everything it starts, so that they can all be killed at once.
+
+    On Windows, this is a Job Object, which the child is assigned to once it has been spawned.  Processes it starts from then on belong to the job as well.
+    */
+    pub struct ProcessTree {
+        job: winapi::shared::ntdef::HANDLE,
+    }
+
+    // A job handle can be used from any thread.
+    unsafe impl Send for ProcessTree {}
+
+    impl ProcessTree {
+        /**
+        Creates the job for what `cmd` spawns.  Nothing about the command itself needs changing.
+        */
+        pub fn prepare(cmd: &mut std::process::Command) -> io::Result<ProcessTree> {
+            let _ = cmd;
+            let job = unsafe {
+                winapi::um::jobapi2::CreateJobObjectW(::std::ptr::null_mut(), ::std::ptr::null())
+            };
+            if job.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(ProcessTree { job })
+        }
+
+        /**
+        Assigns the child spawned from the command given to `prepare` to the job.
+        */
+        pub fn adopt(&mut self, child: &std::process::Child) -> io::Result<()> {
+            use std::os::windows::io::AsRawHandle;
+            let process = child.as_raw_handle() as winapi::shared::ntdef::HANDLE;
+            match unsafe { winapi::um::jobapi2::AssignProcessToJobObject(self.job, process) } {
+                0 => Err(io::Error::last_os_error()),
+                _ => Ok(()),
+            }
+        }
+
+        /**
+        There's nothing to pass on to the job on Windows: it's still attached to the console, which sends it Ctrl+C.
+        */
+        pub fn forward_signals(&self) {}
+
+        /**
+        Kills everything in the job.
+        */
+        pub fn kill(&self) -> io::Result<()> {
+            match unsafe { winapi::um::jobapi2::TerminateJobObject(self.job, 1) } {
+                0 => Err(io::Error::last_os_error()),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    impl Drop for ProcessTree {
+        fn drop(&mut self) {
+            unsafe {
+                winapi::um::handleapi::CloseHandle(self.job);
+            }
+        }
+    }
+
+    unsafe extern "system" fn ignore_ctrl(ctrl_type: DWORD) -> BOOL {
+        use winapi::um::wincon::{CTRL_BREAK_EVENT, CTRL_C_EVENT};
+        match ctrl_type {
+            CTRL_C_EVENT | CTRL_BREAK_EVENT => TRUE,
+            _ => FALSE,
+        }
+    }
+
+    /**
+    While this is alive, Ctrl+C and Ctrl+Break don't kill `cargo-script`.  The script is attached to the same console, so it gets them anyway; this just means we're still around to see how it ended.
+    */
+    pub struct SignalGuard(());
+
+    impl SignalGuard {
+        pub fn install() -> SignalGuard {
+            if unsafe { winapi::um::consoleapi::SetConsoleCtrlHandler(Some(ignore_ctrl), TRUE) }
+                == 0
+            {
+                info!(
+                    "could not set console control handler: {}",
+                    io::Error::last_os_error()
+                );
+            }
+            SignalGuard(())
+        }
+
+        /**
+        The same as `install`, since a `ProcessTree` gets Ctrl+C from the console like anything else.
+        */
+        pub fn install_for_tree() -> SignalGuard {
+            SignalGuard::install()
+        }
+    }
+
+    impl Drop for SignalGuard {
+        fn drop(&mut self) {
+            unsafe {
+                winapi::um::consoleapi::SetConsoleCtrlHandler(Some(ignore_ctrl), FALSE);
+            }
+        }
+    }
+
+    /**
+    There's nothing to pass on to `child` on Windows: the console already sends it Ctrl+C.
+    */
+    pub fn forward_signals_to(_child: &std::process::Child) {}
+
+    /**
+    The exit code to pass on for a child which exited with `status`.
+    */
+    pub fn exit_code(status: std::process::ExitStatus) -> i32 {
+        status.code().unwrap_or(1)
+    }
 }
@@ -0,0 +1,293 @@
+/*
+Copyright ⓒ 2017 cargo-script contributors.
+
+Licensed under the MIT license (see LICENSE or <http://opensource.org
+/licenses/MIT>) or the Apache License, Version 2.0 (see LICENSE of
+<http://www.apache.org/licenses/LICENSE-2.0>), at your option. All
+files in the project carrying such notice may not be copied, modified,
+or distributed except according to those terms.
+*/
+/*!
+This module contains code for the `prebuild` subcommand, which builds a batch of scripts without running any of them, such as after a toolchain update.
+
+Each script is given to `run_script` with the same arguments as `cargo-script script --build-only`, so it goes through exactly the same steps, and ends up in exactly the same cache entry, as it would have if it had been run.  What Cargo prints goes to a file for each script, as with `--color-diagnostics-to-file`, so that one script's output doesn't get mixed up with another's; it's only shown if the script fails to build.
+*/
+use crate::error::{Blame, MainError, Result};
+use crate::util::Defer;
+use log::info;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub struct Args {
+    pub scripts: Vec<OsString>,
+    pub jobs: usize,
+    pub force: bool,
+}
+
+impl Args {
+    pub fn subcommand() -> clap::App<'static, 'static> {
+        use clap::{AppSettings, Arg, SubCommand};
+
+        SubCommand::with_name("prebuild")
+            .about("Build scripts without running them, so that they're ready to go.  Carries on past scripts which fail to build, and says how each one went at the end.")
+            .setting(AppSettings::AllowInvalidUtf8)
+            .arg(
+                Arg::with_name("scripts")
+                    .help("Scripts to build.  These can be patterns such as `bin/*.rs`, with `*` and `?` in the file name.")
+                    .index(1)
+                    .multiple(true)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("jobs")
+                    .help("Build up to N scripts at a time.  The scripts share a target directory, so Cargo may still make some of them wait their turn.")
+                    .long("jobs")
+                    .short("j")
+                    .takes_value(true)
+                    .value_name("N")
+                    .default_value("1")
+                    .validator(|v| match v.parse::<usize>() {
+                        Ok(n) if n > 0 => Ok(()),
+                        _ => Err(format!("`{}` is not a number of jobs", v)),
+                    }),
+            )
+            .arg(
+                Arg::with_name("force")
+                    .help("Rebuild scripts even if they look up to date, such as after a toolchain update.")
+                    .long("force")
+                    .short("f"),
+            )
+    }
+
+    pub fn parse(m: &clap::ArgMatches<'_>) -> Self {
+        Args {
+            scripts: m.values_of_os("scripts").unwrap().map(Into::into).collect(),
+            jobs: m.value_of("jobs").unwrap().parse().unwrap(),
+            force: m.is_present("force"),
+        }
+    }
+}
+
+/**
+How building one script went.
+*/
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Built,
+    Cached,
+    Failed,
+}
+
+impl Outcome {
+    fn name(&self) -> &'static str {
+        match *self {
+            Outcome::Built => "built",
+            Outcome::Cached => "cached",
+            Outcome::Failed => "failed",
+        }
+    }
+}
+
+pub fn try_main(args: Args) -> Result<i32> {
+    let scripts = expand_patterns(&args.scripts)?;
+    let output_dir = crate::util::make_temp_dir("cargo-script-prebuild")?;
+    let _defer_output_dir = {
+        let path = output_dir.clone();
+        Defer::<_, MainError>::defer(move || {
+            info!("removing {:?}", path);
+            fs::remove_dir_all(path)?;
+            Ok(())
+        })
+    };
+
+    let next = Arc::new(Mutex::new(0));
+    let outcomes = Arc::new(Mutex::new(vec![None; scripts.len()]));
+    let scripts = Arc::new(scripts);
+    let workers: Vec<_> = (0..args.jobs.min(scripts.len()))
+        .map(|_| {
+            let (next, outcomes, scripts) = (next.clone(), outcomes.clone(), scripts.clone());
+            let (output_dir, force) = (output_dir.clone(), args.force);
+            thread::spawn(move || loop {
+                let i = {
+                    let mut next = next.lock().unwrap();
+                    if *next == scripts.len() {
+                        break;
+                    }
+                    *next += 1;
+                    *next - 1
+                };
+                let output = output_dir.join(format!("{}.txt", i));
+                let outcome = build(&scripts[i], &output, force);
+                outcomes.lock().unwrap()[i] = Some(outcome);
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().map_err(|_| "a prebuild worker panicked")?;
+    }
+
+    let outcomes = outcomes.lock().unwrap();
+    let (mut built, mut cached, mut failed) = (0, 0, 0);
+    for (script, outcome) in scripts.iter().zip(outcomes.iter()) {
+        let outcome = outcome.unwrap_or(Outcome::Failed);
+        match outcome {
+            Outcome::Built => built += 1,
+            Outcome::Cached => cached += 1,
+            Outcome::Failed => failed += 1,
+        }
+        println!("{:<6}  {}", outcome.name(), script.display());
+    }
+    println!(
+        "{} script{}: {} built, {} cached, {} failed",
+        scripts.len(),
+        if scripts.len() == 1 { "" } else { "s" },
+        built,
+        cached,
+        failed
+    );
+    Ok(if failed == 0 { 0 } else { 1 })
+}
+
+/**
+Builds one script, writing what Cargo says to `output`, and showing it if the build fails.
+*/
+fn build(script: &Path, output: &Path, force: bool) -> Outcome {
+    let mut argv: Vec<OsString> = vec![
+        "cargo".into(),
+        "script".into(),
+        "--build-only".into(),
+        "--color-diagnostics-to-file".into(),
+        output.into(),
+    ];
+    if force {
+        argv.push("--force".into());
+    }
+    // Anything after the script would be taken as its arguments.
+    argv.push(script.into());
+    let args = match crate::parse_args_from(argv) {
+        crate::SubCommand::Script(args) => args,
+        _ => unreachable!("`script` was parsed as another subcommand"),
+    };
+    info!("prebuilding with {:?}", args);
+
+    let mut compiled = false;
+    match crate::run_script(args, Instant::now(), Duration::default(), &mut compiled) {
+        Ok(0) if compiled => Outcome::Built,
+        Ok(0) => Outcome::Cached,
+        Ok(code) => failed(script, &format!("exit status {}", code), output),
+        Err(err) => failed(script, &err, output),
+    }
+}
+
+/**
+Shows why `script` failed to build.  What Cargo said explains a failed build better than being told that it failed, so `err` is only shown if Cargo never got to say anything.
+*/
+fn failed(script: &Path, err: &dyn fmt::Display, output: &Path) -> Outcome {
+    let output = fs::read_to_string(output).unwrap_or_default();
+    let output = match atty::is(atty::Stream::Stderr) {
+        true => output.into(),
+        false => crate::hints::strip_colours(&output),
+    };
+    match output.trim_end() {
+        "" => eprintln!("error: could not build `{}`: {}", script.display(), err),
+        output => eprintln!("error: could not build `{}`:\n{}", script.display(), output),
+    }
+    Outcome::Failed
+}
+
+/**
+Expands any of `scripts` with `*` or `?` in their file names into the files which match, in order.  Anything else is left for `script` to find, just as if it had been run.
+*/
+fn expand_patterns(scripts: &[OsString]) -> Result<Vec<PathBuf>> {
+    let mut expanded = vec![];
+    for script in scripts {
+        let path = Path::new(script);
+        let pattern = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if name.contains(|c| c == '*' || c == '?') && !path.exists() => name,
+            _ => {
+                expanded.push(path.to_owned());
+                continue;
+            }
+        };
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+
+        let mut matches = vec![];
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let is_file = entry.file_type().map(|ft| !ft.is_dir()).unwrap_or(false);
+            if is_file
+                && name
+                    .to_str()
+                    .map_or(false, |name| matches_pattern(pattern, name))
+            {
+                matches.push(path.with_file_name(name));
+            }
+        }
+        if matches.is_empty() {
+            return Err(MainError::OtherOwned(
+                Blame::Human,
+                format!("`{}` doesn't match any scripts", path.display()),
+            ));
+        }
+        matches.sort();
+        expanded.extend(matches);
+    }
+    Ok(expanded)
+}
+
+/**
+Does `name` match `pattern`, where `*` stands for any run of characters, and `?` for any one?
+*/
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), name.chars().collect());
+    // Where to go back to if what followed the last `*` stops matching.
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((sp, sn)) => {
+                    star = Some((sp, sn + 1));
+                    p = sp + 1;
+                    n = sn + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[test]
+fn test_matches_pattern() {
+    assert!(matches_pattern("*.rs", "hello.rs"));
+    assert!(matches_pattern("*.rs", ".rs"));
+    assert!(!matches_pattern("*.rs", "hello.crs.bak"));
+    assert!(matches_pattern("*.*rs", "hello.crs"));
+    assert!(matches_pattern("h?llo.rs", "hallo.rs"));
+    assert!(!matches_pattern("h?llo.rs", "hllo.rs"));
+    assert!(matches_pattern("a*b*c", "aXbYbZc"));
+    assert!(!matches_pattern("a*b*c", "aXbYbZ"));
+    assert!(matches_pattern("*", "anything"));
+    assert!(matches_pattern("exact.rs", "exact.rs"));
+    assert!(!matches_pattern("exact.rs", "exact.rs~"));
+}
@@ -0,0 +1,243 @@
+/*
+Copyright ⓒ 2017 cargo-script contributors.
+
+Licensed under the MIT license (see LICENSE or <http://opensource.org
+/licenses/MIT>) or the Apache License, Version 2.0 (see LICENSE of
+<http://www.apache.org/licenses/LICENSE-2.0>), at your option. All
+files in the project carrying such notice may not be copied, modified,
+or distributed except according to those terms.
+*/
+/*!
+This module contains code for the `quote` subcommand, which turns the arguments it's given back into a command line for running `cargo script` with them, quoted for a particular shell.  This is for cron entries, systemd units, and other places where quoting an expression by hand is easy to get wrong.
+*/
+use crate::error::{Blame, MainError, Result};
+use std::ffi::OsString;
+
+#[derive(Debug)]
+pub struct Args {
+    pub shell: Shell,
+    pub args: Vec<OsString>,
+}
+
+impl Args {
+    pub fn subcommand() -> clap::App<'static, 'static> {
+        use clap::{AppSettings, Arg, SubCommand};
+
+        SubCommand::with_name("quote")
+            .about("Print the command line that runs `cargo script` with the arguments after `--`, quoted for a shell.")
+            .setting(AppSettings::AllowInvalidUtf8)
+            .arg(
+                Arg::with_name("shell")
+                    .help("The shell to quote for: sh (or any POSIX shell), powershell, or cmd.  Defaults to cmd on Windows, and sh everywhere else.")
+                    .long("shell")
+                    .takes_value(true)
+                    .possible_values(&["sh", "powershell", "cmd"])
+                    .default_value(if cfg!(windows) { "cmd" } else { "sh" }),
+            )
+            .arg(
+                Arg::with_name("args")
+                    .help("The arguments to give `cargo script`, exactly as it should get them.")
+                    .multiple(true)
+                    .last(true)
+                    .required(true),
+            )
+    }
+
+    pub fn parse(m: &clap::ArgMatches<'_>) -> Self {
+        Args {
+            shell: match m.value_of("shell").unwrap() {
+                "powershell" => Shell::Powershell,
+                "cmd" => Shell::Cmd,
+                _ => Shell::Sh,
+            },
+            args: m
+                .values_of_os("args")
+                .unwrap()
+                .map(|arg| arg.to_owned())
+                .collect(),
+        }
+    }
+}
+
+/**
+The shells `quote` knows how to quote for.
+*/
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Shell {
+    Sh,
+    Powershell,
+    Cmd,
+}
+
+pub fn try_main(args: Args) -> Result<i32> {
+    let Args { shell, args } = args;
+    let args = args
+        .into_iter()
+        .map(|arg| {
+            arg.into_string()
+                .map_err(|_| (Blame::Human, "arguments to quote must be valid UTF-8").into())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    println!("{}", command_line(shell, &args)?);
+    Ok(0)
+}
+
+/**
+Builds the command line which runs `cargo script` with exactly `args`, as run by `shell`.
+*/
+fn command_line(shell: Shell, args: &[String]) -> Result<String> {
+    let mut line = String::from("cargo script");
+    for arg in args {
+        line.push(' ');
+        line.push_str(&quote(shell, arg)?);
+    }
+    Ok(line)
+}
+
+/**
+Quotes a single argument.  Arguments with nothing special in them are left as they are, so the result stays readable.
+*/
+fn quote(shell: Shell, arg: &str) -> Result<String> {
+    let plain = |extra: &str| {
+        !arg.is_empty()
+            && arg
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_./:=+".contains(c) || extra.contains(c))
+    };
+    match shell {
+        Shell::Sh if plain("@%,") => Ok(arg.into()),
+        Shell::Sh => Ok(format!("'{}'", arg.replace('\'', r"'\''"))),
+        // PowerShell would take a bare `--` for itself.
+        Shell::Powershell if plain("") && arg != "--" => Ok(arg.into()),
+        Shell::Powershell => Ok(quote_powershell(arg)),
+        Shell::Cmd if arg.contains(|c| c == '\n' || c == '\r') => Err(MainError::OtherOwned(
+            Blame::Human,
+            format!(
+                "cmd has no way to pass a line break in an argument, as in {:?}",
+                arg
+            ),
+        )),
+        Shell::Cmd if plain("@,") => Ok(arg.into()),
+        Shell::Cmd => Ok(quote_cmd(arg)),
+    }
+}
+
+/**
+PowerShell takes anything in single quotes literally, except for the quotes themselves, which are doubled.  It counts the curly single quotes as quotes too, so those are doubled as well.
+
+This relies on PowerShell 7.3 or later passing arguments on as they are: older versions lose any double quotes in arguments to programs like `cargo`.
+*/
+fn quote_powershell(arg: &str) -> String {
+    let mut quoted = String::from("'");
+    for c in arg.chars() {
+        if let '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}' = c {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/**
+Quoting for cmd happens in two rounds.  First, the argument is quoted the way programs split their command line into arguments: in double quotes, with double quotes escaped by backslashes, and the backslashes before them (or before the closing quote) doubled.
+
+Then, every character cmd would do something with is escaped with `^`, *including* the double quotes.  That way cmd never thinks it's inside quotes, where `^` would be taken literally.  This also stops `%` from expanding variables, and `!`, if delayed expansion is on.  This is for cmd's own command line: a batch file still needs `%` doubled.
+*/
+fn quote_cmd(arg: &str) -> String {
+    let mut argv = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                argv.extend(std::iter::repeat('\\').take(backslashes * 2 + 1));
+                backslashes = 0;
+            }
+            _ => {
+                argv.extend(std::iter::repeat('\\').take(backslashes));
+                backslashes = 0;
+            }
+        }
+        if c != '\\' {
+            argv.push(c);
+        }
+    }
+    argv.extend(std::iter::repeat('\\').take(backslashes * 2));
+    argv.push('"');
+
+    let mut quoted = String::new();
+    for c in argv.chars() {
+        if "()%!^\"<>&|".contains(c) {
+            quoted.push('^');
+        }
+        quoted.push(c);
+    }
+    quoted
+}
+
+#[test]
+fn test_quote_sh() {
+    let q = |arg: &str| quote(Shell::Sh, arg).unwrap();
+    assert_eq!(q("--expr"), "--expr");
+    assert_eq!(q("a/b.rs"), "a/b.rs");
+    assert_eq!(q("50%"), "50%");
+    assert_eq!(q(""), "''");
+    assert_eq!(q("1 + 1"), "'1 + 1'");
+    assert_eq!(q("it's"), r"'it'\''s'");
+    assert_eq!(q(r#"println!("hi")"#), r#"'println!("hi")'"#);
+    assert_eq!(q("$HOME `id`"), "'$HOME `id`'");
+    assert_eq!(q("a\nb"), "'a\nb'");
+    assert_eq!(q("caf\u{e9}"), "'caf\u{e9}'");
+    assert_eq!(q(r"\"), r"'\'");
+}
+
+#[test]
+fn test_quote_powershell() {
+    let q = |arg: &str| quote(Shell::Powershell, arg).unwrap();
+    assert_eq!(q("--expr"), "--expr");
+    assert_eq!(q("--"), "'--'");
+    assert_eq!(q("--%"), "'--%'");
+    assert_eq!(q(""), "''");
+    assert_eq!(q("1 + 1"), "'1 + 1'");
+    assert_eq!(q("it's"), "'it''s'");
+    assert_eq!(q("it\u{2019}s"), "'it\u{2019}\u{2019}s'");
+    assert_eq!(q(r#"println!("hi")"#), r#"'println!("hi")'"#);
+    assert_eq!(q("$HOME `n"), "'$HOME `n'");
+    assert_eq!(q("50%"), "'50%'");
+    assert_eq!(q("a,b"), "'a,b'");
+    assert_eq!(q("@args"), "'@args'");
+    assert_eq!(q("a\nb"), "'a\nb'");
+    assert_eq!(q("caf\u{e9}"), "'caf\u{e9}'");
+}
+
+#[test]
+fn test_quote_cmd() {
+    let q = |arg: &str| quote(Shell::Cmd, arg).unwrap();
+    assert_eq!(q("--expr"), "--expr");
+    assert_eq!(q(""), r#"^"^""#);
+    assert_eq!(q("1 + 1"), r#"^"1 + 1^""#);
+    assert_eq!(q("it's"), r#"^"it's^""#);
+    assert_eq!(q(r#"println!("hi")"#), r#"^"println^!^(\^"hi\^"^)^""#);
+    assert_eq!(q("%PATH%"), r#"^"^%PATH^%^""#);
+    assert_eq!(q("a & b | c"), r#"^"a ^& b ^| c^""#);
+    assert_eq!(q(r"C:\dir\"), r#"^"C:\dir\\^""#);
+    assert_eq!(q(r#"\"x"#), r#"^"\\\^"x^""#);
+    assert_eq!(q(r"a\b c"), r#"^"a\b c^""#);
+    assert_eq!(q("$HOME"), r#"^"$HOME^""#);
+    assert_eq!(q("caf\u{e9}"), "^\"caf\u{e9}^\"");
+    assert!(quote(Shell::Cmd, "a\nb").is_err());
+}
+
+#[test]
+fn test_command_line() {
+    let args = |args: &[&str]| args.iter().map(|&arg| arg.to_owned()).collect::<Vec<_>>();
+    assert_eq!(
+        command_line(Shell::Sh, &args(&["-e", "1 + 1"])).unwrap(),
+        "cargo script -e '1 + 1'"
+    );
+    assert_eq!(
+        command_line(Shell::Cmd, &args(&["now.crs", "--", "a b"])).unwrap(),
+        r#"cargo script now.crs -- ^"a b^""#
+    );
+}
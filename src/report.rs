@@ -0,0 +1,79 @@
+/*
+Copyright ⓒ 2017 cargo-script contributors.
+
+Licensed under the MIT license (see LICENSE or <http://opensource.org
+/licenses/MIT>) or the Apache License, Version 2.0 (see LICENSE of
+<http://www.apache.org/licenses/LICENSE-2.0>), at your option. All
+files in the project carrying such notice may not be copied, modified,
+or distributed except according to those terms.
+*/
+/*!
+This module contains code for `--report`, which writes a summary of a run to a file as JSON, for CI dashboards and the like.
+*/
+use crate::error::Result;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/**
+What a run did, and how it went.
+*/
+#[derive(Debug, RustcEncodable)]
+pub struct Report {
+    /// The script, or `None` for an expression or loop.
+    pub script: Option<String>,
+
+    /// Was the script's executable already up to date?
+    pub cache_hit: bool,
+
+    /// How long Cargo took to build the script, in milliseconds, if it was built.
+    pub build_ms: Option<f64>,
+
+    /// How long the script took to run, in milliseconds, if it was run.
+    pub run_ms: Option<f64>,
+
+    /// The exit code cargo-script finished with.
+    pub exit_code: i32,
+
+    /// What went wrong, if cargo-script itself failed.
+    pub error: Option<String>,
+
+    /// The version of rustc that built it.
+    pub toolchain: Option<String>,
+
+    /// The edition the script was built with.
+    pub edition: String,
+
+    /// The size of the script's debug executable, in bytes, if it has one.
+    pub debug_exe_bytes: Option<u64>,
+
+    /// Likewise for the release executable.
+    pub release_exe_bytes: Option<u64>,
+}
+
+/**
+Converts a phase's duration to milliseconds, leaving out phases which took no time at all, since those didn't happen.
+*/
+pub fn millis(duration: Duration) -> Option<f64> {
+    match duration {
+        d if d == Duration::default() => None,
+        d => Some(d.as_secs() as f64 * 1000.0 + f64::from(d.subsec_nanos()) / 1_000_000.0),
+    }
+}
+
+/**
+Writes `report` to `path` as a JSON object on a line of its own.  With `append`, it's added to the end of the file, so that a file of reports has one per line; otherwise, the file is replaced.
+*/
+pub fn write(path: &Path, append: bool, report: &Report) -> Result<()> {
+    let json = rustc_serialize::json::encode(report).map_err(|err| err.to_string())?;
+    let mut file = crate::open_output(path, append)?;
+    writeln!(file, "{}", json)?;
+    Ok(())
+}
+
+#[test]
+fn test_millis() {
+    assert_eq!(millis(Duration::default()), None);
+    assert_eq!(millis(Duration::from_micros(1_500)), Some(1.5));
+    assert_eq!(millis(Duration::from_secs(2)), Some(2000.0));
+}
@@ -0,0 +1,185 @@
+/*
+Copyright ⓒ 2017 cargo-script contributors.
+
+Licensed under the MIT license (see LICENSE or <http://opensource.org
+/licenses/MIT>) or the Apache License, Version 2.0 (see LICENSE of
+<http://www.apache.org/licenses/LICENSE-2.0>), at your option. All
+files in the project carrying such notice may not be copied, modified,
+or distributed except according to those terms.
+*/
+/*!
+This module contains code for `--sandbox`, which runs a script's executable somewhere it can't reach the network, or write to anywhere but a directory of its own.
+
+cargo-script doesn't do the sandboxing itself.  On Linux, it uses [bubblewrap](https://github.com/containers/bubblewrap) unless it's told otherwise; anywhere else, it has to be given a sandbox command.
+*/
+use crate::error::{Blame, Result};
+use crate::util;
+use log::{error, info};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The sandbox command to use when `--sandbox` doesn't give one.
+pub const SANDBOX_VAR: &str = "CARGO_SCRIPT_SANDBOX";
+
+/// Tells a sandbox command which executable to run.  This is also its first argument.
+pub const SANDBOX_EXE_VAR: &str = "CARGO_SCRIPT_SANDBOX_EXE";
+
+/// Tells a sandbox command, and the script, which directory the script may write to.
+pub const SANDBOX_DIR_VAR: &str = "CARGO_SCRIPT_SANDBOX_DIR";
+
+/**
+A sandbox to run a script in, and the directory it's allowed to write to.
+
+The directory is removed once the sandbox is dropped.
+*/
+#[derive(Debug)]
+pub struct Sandbox {
+    command: Option<String>,
+    pub dir: PathBuf,
+    created: bool,
+}
+
+impl Sandbox {
+    /**
+    Works out which sandbox to use: `command` if `--sandbox` was given one, otherwise whatever's in `CARGO_SCRIPT_SANDBOX`, otherwise the built-in one.
+
+    The sandbox's directory is only created if `create` is set; there's no point for a dry run.
+    */
+    pub fn new(command: Option<String>, create: bool) -> Result<Self> {
+        let command = command
+            .or_else(|| env::var(SANDBOX_VAR).ok())
+            .filter(|command| !command.trim().is_empty());
+        let (dir, created) = match create {
+            true => (util::make_temp_dir("cargo-script-sandbox")?, true),
+            false => (env::temp_dir().join("cargo-script-sandbox"), false),
+        };
+        info!("sandbox: {:?} in {:?}", command, dir);
+        Ok(Sandbox {
+            command,
+            dir,
+            created,
+        })
+    }
+
+    /**
+    Builds the command which runs `exe_path` in the sandbox.  The script's own arguments are left for the caller to add.
+
+    The script sees the sandbox's directory as both `CARGO_SCRIPT_SANDBOX_DIR` and `TMPDIR`.
+    */
+    pub fn command(&self, exe_path: &Path) -> Result<Command> {
+        let mut cmd = match self.command {
+            Some(ref command) => {
+                let mut parts = command.split_whitespace();
+                let mut cmd = Command::new(parts.next().unwrap());
+                cmd.args(parts).arg(exe_path);
+                cmd
+            }
+            None => builtin_sandbox(exe_path, &self.dir)?,
+        };
+        cmd.env(SANDBOX_EXE_VAR, exe_path)
+            .env(SANDBOX_DIR_VAR, &self.dir)
+            .env("TMPDIR", &self.dir);
+        Ok(cmd)
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        if self.created {
+            if let Err(err) = fs::remove_dir_all(&self.dir) {
+                error!("could not remove sandbox directory {:?}: {}", self.dir, err);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn builtin_sandbox(exe_path: &Path, dir: &Path) -> Result<Command> {
+    if util::find_on_path("bwrap").is_none() {
+        return Err((
+            Blame::Human,
+            "`--sandbox` uses bubblewrap (`bwrap`), which isn't installed; \
+             install it, or give a sandbox command with `--sandbox=COMMAND`",
+        )
+            .into());
+    }
+    Ok(bubblewrap(exe_path, dir))
+}
+
+/**
+Runs `exe_path` under bubblewrap.  The whole filesystem is mounted read-only, except for `dir`; the network, processes and IPC are all cut off from the rest of the system.
+*/
+#[cfg(target_os = "linux")]
+fn bubblewrap(exe_path: &Path, dir: &Path) -> Command {
+    let mut cmd = Command::new("bwrap");
+    cmd.args(["--ro-bind", "/", "/"])
+        .args(["--dev", "/dev"])
+        .args(["--proc", "/proc"])
+        .arg("--bind")
+        .arg(dir)
+        .arg(dir)
+        .args([
+            "--unshare-net",
+            "--unshare-pid",
+            "--unshare-ipc",
+            "--new-session",
+            "--die-with-parent",
+            "--",
+        ])
+        .arg(exe_path);
+    cmd
+}
+
+#[cfg(not(target_os = "linux"))]
+fn builtin_sandbox(_exe_path: &Path, _dir: &Path) -> Result<Command> {
+    Err(crate::error::MainError::OtherOwned(
+        Blame::Human,
+        format!(
+            "`--sandbox` has no built-in sandbox on this platform; \
+             give a sandbox command with `--sandbox=COMMAND`, or set `{}`",
+            SANDBOX_VAR
+        ),
+    ))
+}
+
+#[test]
+fn test_sandbox_command() {
+    let args = |cmd: &Command| -> Vec<String> {
+        ::std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect()
+    };
+    let env = |cmd: &Command, k: &str| {
+        cmd.get_envs()
+            .find(|&(key, _)| key == k)
+            .and_then(|(_, v)| v)
+            .map(|v| v.to_string_lossy().into_owned())
+    };
+    let sandbox = |command: &str| Sandbox {
+        command: Some(command.into()),
+        dir: "/tmp/sb".into(),
+        created: false,
+    };
+    let exe = Path::new("/cache/s");
+
+    let cmd = sandbox("firejail  --net=none").command(exe).unwrap();
+    assert_eq!(args(&cmd), vec!["firejail", "--net=none", "/cache/s"]);
+    assert_eq!(env(&cmd, SANDBOX_EXE_VAR), Some("/cache/s".into()));
+    assert_eq!(env(&cmd, SANDBOX_DIR_VAR), Some("/tmp/sb".into()));
+    assert_eq!(env(&cmd, "TMPDIR"), Some("/tmp/sb".into()));
+
+    #[cfg(target_os = "linux")]
+    {
+        let args = args(&bubblewrap(exe, Path::new("/tmp/sb")));
+        assert_eq!(args[0], "bwrap");
+        assert!(args.windows(3).any(|w| w == ["--ro-bind", "/", "/"]));
+        assert!(args
+            .windows(3)
+            .any(|w| w == ["--bind", "/tmp/sb", "/tmp/sb"]));
+        assert!(args.contains(&"--unshare-net".into()));
+        assert_eq!(&args[args.len() - 2..], ["--", "/cache/s"]);
+    }
+}
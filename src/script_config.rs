@@ -0,0 +1,207 @@
+/*
+Copyright ⓒ 2017 cargo-script contributors.
+
+Licensed under the MIT license (see LICENSE or <http://opensource.org
+/licenses/MIT>) or the Apache License, Version 2.0 (see LICENSE of
+<http://www.apache.org/licenses/LICENSE-2.0>), at your option. All
+files in the project carrying such notice may not be copied, modified,
+or distributed except according to those terms.
+*/
+/*!
+This module contains code for finding the Cargo configuration that goes with a script: any `.cargo/config.toml` in the script's directory, or one above it.
+
+Scripts are built in the cache, where Cargo won't find these by itself.  Instead, they're passed to Cargo with `--config`, and their `[env]` tables are applied to the script when it runs, since the executable isn't run by Cargo.
+*/
+use crate::error::{Blame, MainError, Result, ResultExt};
+use crate::util::PathExt;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/**
+A Cargo configuration file which applies to a script.
+*/
+#[derive(Debug)]
+pub struct ConfigFile {
+    /// Where the file lives.
+    pub path: PathBuf,
+
+    /// The contents of the file.
+    pub content: String,
+
+    /// The contents of the file, parsed.
+    config: toml::Value,
+}
+
+impl ConfigFile {
+    /**
+    Does this set `build.target-dir`?  cargo-script has to know where the executable ends up, so this is overridden.
+    */
+    pub fn sets_target_dir(&self) -> bool {
+        self.config
+            .get("build")
+            .and_then(|build| build.get("target-dir"))
+            .is_some()
+    }
+}
+
+/**
+Finds the Cargo configuration files for the script at `script_path`, from the outermost to the innermost, which is the order Cargo should be given them in.
+
+This looks in the script's directory and each one above it, stopping before the home directory: the configuration in `~/.cargo` is Cargo's own, which it reads regardless.
+*/
+pub fn find(script_path: &Path) -> Result<Vec<ConfigFile>> {
+    let script_path = match script_path.canonicalize() {
+        Ok(path) => path,
+        Err(_) => return Ok(vec![]),
+    };
+    let home = env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .and_then(|home| Path::new(&home).canonicalize().ok());
+
+    let mut files = vec![];
+    for dir in script_path.ancestors().skip(1) {
+        if Some(dir) == home.as_deref() {
+            break;
+        }
+        // Cargo still reads `config` without the extension, in preference to `config.toml`.
+        let path = match ["config", "config.toml"]
+            .iter()
+            .map(|name| dir.join(".cargo").join(name))
+            .find(|path| path.is_file_polyfill())
+        {
+            Some(path) => path,
+            None => continue,
+        };
+        files.push(load(path)?);
+    }
+    files.reverse();
+    Ok(files)
+}
+
+fn load(path: PathBuf) -> Result<ConfigFile> {
+    let content = fs::read_to_string(&path)
+        .map_err(MainError::from)
+        .err_tag(format!(
+            "could not read Cargo configuration `{}`",
+            path.display()
+        ))
+        .shift_blame(Blame::Human)?;
+    let config = toml::from_str(&content).map_err(|err| {
+        MainError::OtherOwned(
+            Blame::Human,
+            format!(
+                "could not parse Cargo configuration `{}`: {}",
+                path.display(),
+                err
+            ),
+        )
+    })?;
+    Ok(ConfigFile {
+        path,
+        content,
+        config,
+    })
+}
+
+/**
+Works out the environment variables the `[env]` tables in `files` give the script, as Cargo would for anything it runs.
+
+Variables which are already set are left alone, unless they're marked `force`.  `relative` values are relative to the directory containing `.cargo`.  Where the files disagree, the innermost wins, which is also the last.
+*/
+pub fn env_vars<F>(files: &[ConfigFile], is_set: F) -> Result<Vec<(String, String)>>
+where
+    F: Fn(&str) -> bool,
+{
+    let mut vars = vec![];
+    for file in files {
+        let table = match file.config.get("env").and_then(toml::Value::as_table) {
+            Some(table) => table,
+            None => continue,
+        };
+        for (key, value) in table {
+            let (value, force, relative) = match *value {
+                toml::Value::String(ref value) => (Some(&**value), false, false),
+                toml::Value::Table(ref t) => (
+                    t.get("value").and_then(toml::Value::as_str),
+                    t.get("force").and_then(toml::Value::as_bool) == Some(true),
+                    t.get("relative").and_then(toml::Value::as_bool) == Some(true),
+                ),
+                _ => (None, false, false),
+            };
+            let value = value.ok_or_else(|| {
+                MainError::OtherOwned(
+                    Blame::Human,
+                    format!(
+                        "`env.{}` in `{}` must be a string, or a table with a string `value`",
+                        key,
+                        file.path.display()
+                    ),
+                )
+            })?;
+
+            if !force && is_set(key) {
+                continue;
+            }
+            let value = match file.path.parent().and_then(Path::parent) {
+                Some(base) if relative => base.join(value).to_string_lossy().into_owned(),
+                _ => value.into(),
+            };
+            vars.push((key.clone(), value));
+        }
+    }
+    Ok(vars)
+}
+
+#[test]
+fn test_env_vars() {
+    let file = |path: &str, content: &str| ConfigFile {
+        path: path.into(),
+        content: content.into(),
+        config: toml::from_str(content).unwrap(),
+    };
+    let files = [
+        file(
+            "/a/.cargo/config.toml",
+            r#"
+[env]
+OUTER = "outer"
+BOTH = "outer"
+SET = "config"
+FORCED = { value = "config", force = true }
+"#,
+        ),
+        file(
+            "/a/b/.cargo/config.toml",
+            r#"
+[build]
+target-dir = "elsewhere"
+
+[env]
+BOTH = "inner"
+DATA = { value = "data", relative = true }
+"#,
+        ),
+    ];
+    assert!(!files[0].sets_target_dir());
+    assert!(files[1].sets_target_dir());
+
+    let vars = env_vars(&files, |key| key == "SET" || key == "FORCED").unwrap();
+    let get = |k: &str| {
+        vars.iter()
+            .filter(|(key, _)| key == k)
+            .map(|(_, value)| &**value)
+            .last()
+    };
+    assert_eq!(get("OUTER"), Some("outer"));
+    assert_eq!(get("BOTH"), Some("inner"));
+    assert_eq!(get("SET"), None);
+    assert_eq!(get("FORCED"), Some("config"));
+    assert_eq!(
+        get("DATA").map(PathBuf::from),
+        Some(Path::new("/a/b").join("data"))
+    );
+
+    let bad = [file("/a/.cargo/config.toml", "[env]\nN = 1\n")];
+    assert!(env_vars(&bad, |_| false).is_err());
+}
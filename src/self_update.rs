@@ -0,0 +1,363 @@
+/*
+Copyright ⓒ 2017 cargo-script contributors.
+
+Licensed under the MIT license (see LICENSE or <http://opensource.org
+/licenses/MIT>) or the Apache License, Version 2.0 (see LICENSE of
+<http://www.apache.org/licenses/LICENSE-2.0>), at your option. All
+files in the project carrying such notice may not be copied, modified,
+or distributed except according to those terms.
+*/
+/*!
+This module contains code for the `self` subcommand, which checks whether there's a newer version of cargo-script on crates.io, and installs it.
+
+Nothing here talks to the network directly; versions are looked up in crates.io's sparse index using `curl`, and updates are left to `cargo install`.
+*/
+use crate::error::{Blame, MainError, Result};
+use crate::platform;
+use crate::util::write_file_atomic;
+use log::info;
+use rustc_serialize::json::Json;
+use semver::Version;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime};
+
+/// The name cargo-script is published under, which is what gets looked up and installed.
+const PKG_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// The version of cargo-script that's running.
+const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The sparse index to look cargo-script up in.
+const DEFAULT_INDEX_URL: &str = "https://index.crates.io";
+
+/// The sparse index to look cargo-script up in, in place of crates.io's.
+pub const INDEX_URL_VAR: &str = "CARGO_SCRIPT_INDEX_URL";
+
+/// Turns on the background check for updates when it's set to anything but `0` or nothing.
+pub const CHECK_FOR_UPDATES_VAR: &str = "CARGO_SCRIPT_CHECK_FOR_UPDATES";
+
+/// The file in the cache directory that records the last check for updates, and what it found.
+const CHECK_FILE_NAME: &str = "cargo-script-update-check";
+
+/// How long to wait between background checks for updates.
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug)]
+pub enum Args {
+    Version {
+        check: bool,
+        offline: bool,
+        background: bool,
+    },
+    Update,
+}
+
+impl Args {
+    pub fn subcommand() -> clap::App<'static, 'static> {
+        use clap::{AppSettings, Arg, SubCommand};
+
+        SubCommand::with_name("self")
+            .about("Check for a newer version of cargo-script, or update to it.")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("version")
+                .about("Show this version of cargo-script, and whether there's a newer one on crates.io.")
+                .arg(Arg::with_name("check")
+                    .help("Exit with a non-zero status if there's a newer version.")
+                    .long("check")
+                )
+                .arg(Arg::with_name("offline")
+                    .help("Don't check crates.io for a newer version.")
+                    .long("offline")
+                )
+                .arg(Arg::with_name("background")
+                    .help("Quietly check for a newer version, and record it for the next run.")
+                    .long("background")
+                    .hidden(true)
+                )
+            )
+            .subcommand(SubCommand::with_name("update")
+                .about("Install the latest version of cargo-script with `cargo install`.")
+            )
+    }
+
+    pub fn parse(m: &clap::ArgMatches<'_>) -> Self {
+        match m.subcommand() {
+            ("version", Some(m)) => Args::Version {
+                check: m.is_present("check"),
+                offline: m.is_present("offline"),
+                background: m.is_present("background"),
+            },
+            ("update", _) => Args::Update,
+            (name, _) => panic!("bad subcommand: {:?}", name),
+        }
+    }
+}
+
+pub fn try_main(args: Args) -> Result<i32> {
+    match args {
+        Args::Version {
+            background: true, ..
+        } => {
+            if let Err(err) = check_in_background() {
+                info!("background check for updates failed: {}", err);
+            }
+            Ok(0)
+        }
+        Args::Version { check, offline, .. } => version(check, offline || cargo_offline()),
+        Args::Update => update(),
+    }
+}
+
+fn version(check: bool, offline: bool) -> Result<i32> {
+    println!("{} {}", PKG_NAME, PKG_VERSION);
+    if offline {
+        warning!("not checking for a newer version while offline");
+        return Ok(0);
+    }
+
+    let latest = match latest_version() {
+        Ok(latest) => latest,
+        Err(err) => {
+            warning!("could not check for a newer version: {}", err);
+            return Ok(0);
+        }
+    };
+    if let Err(err) = record_check(&latest) {
+        info!("could not record check for updates: {}", err);
+    }
+
+    let current = current_version()?;
+    if latest > current {
+        println!(
+            "{} {} is available; run `cargo-script self update` to install it.",
+            PKG_NAME, latest
+        );
+        return Ok(if check { 1 } else { 0 });
+    }
+    if latest < current {
+        println!(
+            "This is newer than the latest published version, {}.",
+            latest
+        );
+    } else {
+        println!("This is the latest version.");
+    }
+    Ok(0)
+}
+
+fn update() -> Result<i32> {
+    let mut cmd = Command::new("cargo");
+    cmd.args(["install", PKG_NAME, "--force"]);
+    info!("running {:?}", cmd);
+    crate::logging::trace_command(&cmd);
+    let status = cmd.status().map_err(|err| {
+        MainError::OtherOwned(
+            Blame::Human,
+            format!("could not run `cargo install`: {}", err),
+        )
+    })?;
+    Ok(status.code().unwrap_or(1))
+}
+
+/**
+If the background check for updates is turned on, says whether the last one found a newer version, and starts another if the last one was long enough ago.
+
+This is run before scripts, so nothing here is allowed to fail; anything that goes wrong is only logged.
+*/
+pub fn notify_if_outdated() {
+    let enabled = env::var(CHECK_FOR_UPDATES_VAR)
+        .map(|v| !(v.is_empty() || v == "0"))
+        .unwrap_or(false);
+    if !enabled || cargo_offline() {
+        return;
+    }
+
+    let res = (|| -> Result<()> {
+        let path = check_file_path()?;
+        let (recorded, checked) = match fs::read_to_string(&path) {
+            Ok(recorded) => (recorded, fs::metadata(&path)?.modified()?),
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
+                (String::new(), SystemTime::UNIX_EPOCH)
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        if let Ok(latest) = Version::parse(recorded.trim()) {
+            if latest > current_version()? {
+                note!(
+                    "{} {} is available; run `cargo-script self update` to install it",
+                    PKG_NAME,
+                    latest
+                );
+            }
+        }
+
+        if is_due(checked, SystemTime::now()) {
+            // Rewriting the file marks the check as done, so other runs don't start one of their own in the meantime.
+            write_file_atomic(&path, recorded.as_bytes())?;
+            let mut cmd = Command::new(env::current_exe()?);
+            cmd.args(["self", "version", "--background"])
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null());
+            info!("checking for updates with {:?}", cmd);
+            crate::logging::trace_command(&cmd);
+            cmd.spawn()?;
+        }
+        Ok(())
+    })();
+    if let Err(err) = res {
+        info!("could not check for updates: {}", err);
+    }
+}
+
+fn check_in_background() -> Result<()> {
+    record_check(&latest_version()?)
+}
+
+/**
+Whether Cargo has been told to stay off the network with `CARGO_NET_OFFLINE`.
+*/
+fn cargo_offline() -> bool {
+    env::var("CARGO_NET_OFFLINE")
+        .map(|v| v == "true")
+        .unwrap_or(false)
+}
+
+fn current_version() -> Result<Version> {
+    Version::parse(PKG_VERSION).map_err(|err| {
+        MainError::OtherOwned(
+            Blame::Internal,
+            format!("bad version {:?}: {}", PKG_VERSION, err),
+        )
+    })
+}
+
+fn check_file_path() -> Result<PathBuf> {
+    Ok(platform::get_cache_dir()?.join(CHECK_FILE_NAME))
+}
+
+fn record_check(latest: &Version) -> Result<()> {
+    let path = check_file_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_file_atomic(&path, latest.to_string().as_bytes())?;
+    Ok(())
+}
+
+/**
+Whether a check for updates last done at `checked` is out of date by `now`.  A check from the future can't be trusted, so that counts as out of date too.
+*/
+fn is_due(checked: SystemTime, now: SystemTime) -> bool {
+    now.duration_since(checked)
+        .map(|age| age >= CHECK_INTERVAL)
+        .unwrap_or(true)
+}
+
+/**
+Looks up the latest version of cargo-script in the sparse index, using `curl`.
+*/
+fn latest_version() -> Result<Version> {
+    let index = env::var(INDEX_URL_VAR).unwrap_or_else(|_| DEFAULT_INDEX_URL.into());
+    let url = format!("{}/{}", index.trim_end_matches('/'), index_path(PKG_NAME));
+
+    let mut cmd = Command::new("curl");
+    cmd.args([
+        "--silent",
+        "--show-error",
+        "--fail",
+        "--location",
+        "--max-time",
+        "10",
+    ])
+    .arg(&url)
+    .stdin(Stdio::null());
+    info!("fetching {:?}", cmd);
+    crate::logging::trace_command(&cmd);
+    let out = cmd.output().map_err(|err| {
+        MainError::OtherOwned(Blame::Human, format!("could not run `curl`: {}", err))
+    })?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        return Err(MainError::OtherOwned(
+            Blame::Human,
+            format!("could not fetch {}: {}", url, stderr.trim()),
+        ));
+    }
+
+    latest_in_index(&String::from_utf8_lossy(&out.stdout)).ok_or_else(|| {
+        MainError::OtherOwned(
+            Blame::Human,
+            format!("{} doesn't list any releases of {}", url, PKG_NAME),
+        )
+    })
+}
+
+/**
+Works out where a package's entry lives in a sparse index, which depends on how long its name is.
+*/
+fn index_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => format!("1/{}", name),
+        2 => format!("2/{}", name),
+        3 => format!("3/{}/{}", &name[..1], name),
+        _ => format!("{}/{}/{}", &name[..2], &name[2..4], name),
+    }
+}
+
+/**
+Picks the latest release out of a package's index entry, which has one JSON object per line for each version.  Yanked versions and pre-releases are skipped.
+*/
+fn latest_in_index(entry: &str) -> Option<Version> {
+    entry
+        .lines()
+        .filter_map(|line| Json::from_str(line.trim()).ok())
+        .filter(|release| release.find("yanked").and_then(Json::as_boolean) != Some(true))
+        .filter_map(|release| {
+            release
+                .find("vers")
+                .and_then(Json::as_string)
+                .and_then(|v| Version::parse(v).ok())
+        })
+        .filter(|version| !version.is_prerelease())
+        .max()
+}
+
+#[test]
+fn test_index_path() {
+    assert_eq!(index_path("a"), "1/a");
+    assert_eq!(index_path("ab"), "2/ab");
+    assert_eq!(index_path("abc"), "3/a/abc");
+    assert_eq!(index_path("cargo-script"), "ca/rg/cargo-script");
+    assert_eq!(index_path("Serde"), "se/rd/serde");
+}
+
+#[test]
+fn test_latest_in_index() {
+    let entry = r#"{"name":"cargo-script","vers":"0.2.8","deps":[],"cksum":"","features":{},"yanked":false}
+{"name":"cargo-script","vers":"0.2.10","deps":[],"cksum":"","features":{},"yanked":false}
+{"name":"cargo-script","vers":"0.2.11","deps":[],"cksum":"","features":{},"yanked":true}
+{"name":"cargo-script","vers":"0.3.0-beta.1","deps":[],"cksum":"","features":{},"yanked":false}
+"#;
+    assert_eq!(
+        latest_in_index(entry),
+        Some(Version::parse("0.2.10").unwrap())
+    );
+    assert_eq!(latest_in_index(""), None);
+    assert_eq!(latest_in_index("<html>Not Found</html>"), None);
+}
+
+#[test]
+fn test_is_due() {
+    let now = SystemTime::UNIX_EPOCH + Duration::from_secs(10 * 24 * 60 * 60);
+    assert!(!is_due(now, now));
+    assert!(!is_due(now - Duration::from_secs(60 * 60), now));
+    assert!(is_due(now - CHECK_INTERVAL, now));
+    assert!(is_due(SystemTime::UNIX_EPOCH, now));
+    assert!(is_due(now + Duration::from_secs(60), now));
+}
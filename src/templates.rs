@@ -16,7 +16,7 @@ use crate::platform;
 use lazy_static::lazy_static;
 use regex::Regex;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::PathBuf;
 
@@ -50,7 +50,7 @@ impl Args {
             )
 
             .subcommand(SubCommand::with_name("list")
-                .about("List the available templates.")
+                .about("List the available templates, built-in and otherwise, and where each one comes from.")
             )
 
             .subcommand(SubCommand::with_name("show")
@@ -167,12 +167,55 @@ pub fn get_template(name: &str) -> Result<Cow<'static, str>> {
     Ok(text.into())
 }
 
+/**
+Returns a short identifier for the built-in templates.
+
+This changes whenever any of them, or anything else which goes into every generated package (like the default manifest), does.
+*/
+pub fn builtin_id() -> String {
+    use sha1::Sha1;
+
+    let mut hasher = Sha1::new();
+    for name in BUILTIN_NAMES {
+        hasher.update(name.as_bytes());
+        hasher.update(b"=");
+        hasher.update(builtin_template(name).unwrap().as_bytes());
+        hasher.update(b";");
+    }
+    hasher.update(b"manifest=");
+    hasher.update(consts::DEFAULT_MANIFEST.as_bytes());
+    hasher.update(b";test-module-main=");
+    hasher.update(consts::TEST_MODULE_MAIN.as_bytes());
+    hasher.update(b";");
+
+    let mut id = hasher.digest().to_string();
+    id.truncate(consts::ID_DIGEST_LEN_MAX);
+    id
+}
+
+const BUILTIN_NAMES: &[&str] = &[
+    "expr",
+    "file",
+    "loop",
+    "loop-count",
+    "loop-filter",
+    "loop-fold",
+    "new",
+    "new-expr",
+    "new-loop",
+];
+
 fn builtin_template(name: &str) -> Option<&'static str> {
     Some(match name {
         "expr" => consts::EXPR_TEMPLATE,
         "file" => consts::FILE_TEMPLATE,
         "loop" => consts::LOOP_TEMPLATE,
         "loop-count" => consts::LOOP_COUNT_TEMPLATE,
+        "loop-filter" => consts::LOOP_FILTER_TEMPLATE,
+        "loop-fold" => consts::LOOP_FOLD_TEMPLATE,
+        "new" => consts::NEW_SCRIPT_TEMPLATE,
+        "new-expr" => consts::NEW_EXPR_SCRIPT_TEMPLATE,
+        "new-loop" => consts::NEW_LOOP_SCRIPT_TEMPLATE,
         _ => return None,
     })
 }
@@ -183,20 +226,17 @@ fn dump(name: &str) -> Result<()> {
     Ok(())
 }
 
-fn list() -> Result<()> {
+/**
+Lists the templates which can be used, built-in and otherwise, along with where each comes from.
+
+Templates in the template folder take the place of any built-in template with the same name.
+*/
+pub fn list() -> Result<()> {
     use std::ffi::OsStr;
 
     let t_path = get_template_path()?;
 
-    if !t_path.exists() {
-        return Err(format!(
-            "cannot list template directory `{}`: it does not exist",
-            t_path.display()
-        )
-        .into());
-    }
-
-    if !t_path.is_dir() {
+    if t_path.exists() && !t_path.is_dir() {
         return Err(format!(
             "cannot list template directory `{}`: it is not a directory",
             t_path.display()
@@ -204,17 +244,38 @@ fn list() -> Result<()> {
         .into());
     }
 
-    for entry in fs::read_dir(&t_path)? {
-        let entry = entry?;
-        if !entry.file_type()?.is_file() {
-            continue;
-        }
-        let f_path = entry.path();
-        if f_path.extension() != Some(OsStr::new("rs")) {
-            continue;
+    let mut templates: BTreeMap<String, Option<PathBuf>> = BUILTIN_NAMES
+        .iter()
+        .map(|&name| (name.into(), None))
+        .collect();
+
+    if t_path.exists() {
+        for entry in fs::read_dir(&t_path)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let f_path = entry.path();
+            if f_path.extension() != Some(OsStr::new("rs")) {
+                continue;
+            }
+            if let Some(stem) = f_path.file_stem() {
+                templates.insert(stem.to_string_lossy().into_owned(), Some(f_path));
+            }
         }
-        if let Some(stem) = f_path.file_stem() {
-            println!("{}", stem.to_string_lossy());
+    }
+
+    let width = templates.keys().map(|name| name.len()).max().unwrap_or(0);
+    for (name, path) in &templates {
+        match path {
+            None => println!("{:width$}  built-in", name, width = width),
+            Some(path) if builtin_template(name).is_some() => println!(
+                "{:width$}  {} (overrides built-in)",
+                name,
+                path.display(),
+                width = width
+            ),
+            Some(path) => println!("{:width$}  {}", name, path.display(), width = width),
         }
     }
     Ok(())
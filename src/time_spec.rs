@@ -0,0 +1,146 @@
+/*
+Copyright ⓒ 2017 cargo-script contributors.
+
+Licensed under the MIT license (see LICENSE or <http://opensource.org
+/licenses/MIT>) or the Apache License, Version 2.0 (see LICENSE of
+<http://www.apache.org/licenses/LICENSE-2.0>), at your option. All
+files in the project carrying such notice may not be copied, modified,
+or distributed except according to those terms.
+*/
+/*!
+This module deals with parsing the points in time given to options like `--since` and `--until`, and the lengths of time given to ones like `--build-timeout`.
+*/
+use crate::error::{Blame, MainError, Result};
+use std::time::Duration;
+
+/**
+Formats which are accepted for absolute points in time.  All of these are interpreted as UTC.
+*/
+const DATE_FORMATS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%d",
+];
+
+/**
+Parses a point in time, returning it in milliseconds since the UNIX epoch.
+
+This can either be a duration relative to `now_ms` (*e.g.* `90m`, `12h`, `3d`, `2w`), meaning "that long ago", or a UTC date such as `2017-06-01` or `2017-06-01 14:30`.
+*/
+pub fn parse(s: &str, now_ms: u64) -> Result<u64> {
+    let s = s.trim();
+    if let Some(ms) = parse_duration_ms(s) {
+        return Ok(now_ms.saturating_sub(ms));
+    }
+
+    for fmt in DATE_FORMATS {
+        if let Ok(tm) = time::strptime(s, fmt) {
+            let ts = tm.to_timespec();
+            if ts.sec < 0 {
+                break;
+            }
+            return Ok(ts.sec as u64 * 1000);
+        }
+    }
+
+    Err(MainError::OtherOwned(
+        Blame::Human,
+        format!(
+            "invalid time {:?}: expected a duration such as `12h` or `3d`, or a date such as `2017-06-01`",
+            s
+        ),
+    ))
+}
+
+/**
+Parses a length of time, such as `90s` or `10m`.  A number on its own is taken as seconds.  Zero isn't allowed, since nothing could finish in that time.
+*/
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let ms = match s.parse::<u64>() {
+        Ok(secs) => secs.checked_mul(1000),
+        Err(_) => parse_duration_ms(s),
+    };
+    match ms {
+        Some(ms) if ms > 0 => Ok(Duration::from_millis(ms)),
+        _ => Err(MainError::OtherOwned(
+            Blame::Human,
+            format!(
+                "invalid duration {:?}: expected a length of time such as `90s`, `10m` or `1h`",
+                s
+            ),
+        )),
+    }
+}
+
+fn parse_duration_ms(s: &str) -> Option<u64> {
+    let split = s.find(|c: char| !c.is_ascii_digit())?;
+    let (num, unit) = s.split_at(split);
+    let num: u64 = num.parse().ok()?;
+    let unit_ms = match unit.trim() {
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        "w" => 7 * 24 * 60 * 60 * 1000,
+        _ => return None,
+    };
+    num.checked_mul(unit_ms)
+}
+
+#[test]
+fn test_parse() {
+    const NOW: u64 = 1_500_000_000_000;
+    const H: u64 = 60 * 60 * 1000;
+
+    macro_rules! p {
+        ($s:expr) => {
+            parse($s, NOW).map_err(|e| e.to_string())
+        };
+    }
+
+    assert_eq!(p!("30s"), Ok(NOW - 30 * 1000));
+    assert_eq!(p!("90m"), Ok(NOW - 90 * 60 * 1000));
+    assert_eq!(p!("12h"), Ok(NOW - 12 * H));
+    assert_eq!(p!("3 d"), Ok(NOW - 3 * 24 * H));
+    assert_eq!(p!("2w"), Ok(NOW - 2 * 7 * 24 * H));
+    assert_eq!(p!("100000w"), Ok(0));
+
+    assert_eq!(p!("2017-06-01"), Ok(1_496_275_200_000));
+    assert_eq!(
+        p!("2017-06-01 14:30"),
+        Ok(1_496_275_200_000 + 14 * H + 30 * 60 * 1000)
+    );
+    assert_eq!(
+        p!("2017-06-01T14:30:15"),
+        Ok(1_496_275_200_000 + 14 * H + 30 * 60 * 1000 + 15 * 1000)
+    );
+
+    assert!(p!("").is_err());
+    assert!(p!("3").is_err());
+    assert!(p!("3y").is_err());
+    assert!(p!("yesterday").is_err());
+    assert!(p!("2017-13-01").is_err());
+}
+
+#[test]
+fn test_parse_duration() {
+    macro_rules! p {
+        ($s:expr) => {
+            parse_duration($s).map_err(|e| e.to_string())
+        };
+    }
+
+    assert_eq!(p!("90"), Ok(Duration::from_secs(90)));
+    assert_eq!(p!("90s"), Ok(Duration::from_secs(90)));
+    assert_eq!(p!("10m"), Ok(Duration::from_secs(600)));
+    assert_eq!(p!(" 1 h "), Ok(Duration::from_secs(3600)));
+
+    assert!(p!("").is_err());
+    assert!(p!("0").is_err());
+    assert!(p!("0s").is_err());
+    assert!(p!("-5").is_err());
+    assert!(p!("5y").is_err());
+}
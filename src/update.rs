@@ -0,0 +1,226 @@
+/*
+Copyright ⓒ 2017 cargo-script contributors.
+
+Licensed under the MIT license (see LICENSE or <http://opensource.org
+/licenses/MIT>) or the Apache License, Version 2.0 (see LICENSE of
+<http://www.apache.org/licenses/LICENSE-2.0>), at your option. All
+files in the project carrying such notice may not be copied, modified,
+or distributed except according to those terms.
+*/
+/*!
+This module contains code related to updating the dependencies of scripts.
+*/
+use crate::error::{Blame, MainError, Result, ResultExt};
+use crate::manifest;
+use crate::{FileOverwrite, Input};
+use log::info;
+use semver::{Version, VersionReq};
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+#[derive(Debug)]
+pub struct Args {
+    pub script: OsString,
+    pub save: bool,
+    pub force: bool,
+}
+
+impl Args {
+    pub fn subcommand() -> clap::App<'static, 'static> {
+        use clap::{AppSettings, Arg, SubCommand};
+
+        SubCommand::with_name("update")
+            .about("Update the locked dependencies of a script.")
+            .setting(AppSettings::AllowInvalidUtf8)
+            .arg(
+                Arg::with_name("script")
+                    .help("Script file to update.")
+                    .index(1)
+                    .required(true),
+            )
+            .arg(
+                Arg::with_name("save")
+                    .help("Also rewrite the requirements in the script's embedded manifest to the versions which are now locked.")
+                    .long("save"),
+            )
+            .arg(
+                Arg::with_name("force")
+                    .help("Overwrite the script's generated package even if it has been changed by hand.")
+                    .long("force"),
+            )
+    }
+
+    pub fn parse(m: &clap::ArgMatches<'_>) -> Self {
+        Args {
+            script: m.value_of_os("script").unwrap().to_owned(),
+            save: m.is_present("save"),
+            force: m.is_present("force"),
+        }
+    }
+}
+
+pub fn try_main(args: Args) -> Result<i32> {
+    let script = crate::load_script(&args.script, None)?;
+    let input = script.input();
+
+    let deps_from = crate::env_deps_from()?;
+    let split = manifest::split_input(
+        &input,
+        &manifest::SplitOptions {
+            deps_from: deps_from.as_ref(),
+            ..Default::default()
+        },
+    )?;
+    for warning in &split.warnings {
+        warning!("{}", warning);
+    }
+
+    // Cargo needs the package to exist before it can update its lockfile.
+    let pkg_path = crate::script_pkg_path(&input, deps_from.as_ref())?;
+    info!("updating package in {:?}", pkg_path);
+    fs::create_dir_all(&pkg_path)?;
+    crate::mark_cache_dir(&crate::get_script_cache_path()?);
+    let (mut mani, mut files) = (split.manifest, split.files);
+    crate::add_bundle_files(&input, script.bundle.as_deref(), &mut mani, &mut files)?;
+    let mani_path = pkg_path.join("Cargo.toml");
+    let script_path = pkg_path.join(format!("{}.rs", input.safe_name()));
+
+    // Changes made to the package by hand are kept, just as they are by a run, unless `--force` says otherwise.
+    let old_meta = crate::get_pkg_metadata(&pkg_path).ok();
+    let written = match args.force {
+        true => None,
+        false => old_meta.as_ref(),
+    };
+    let mani_hash = written.map(|m| &*m.manifest_hash);
+    let script_hash = written.map(|m| &*m.script_hash);
+    crate::check_unmodified(&mani_path, &mani, mani_hash, mani_hash)?;
+    crate::check_unmodified(&script_path, &split.script, script_hash, script_hash)?;
+    let mani_written = crate::overwrite_file(&mani_path, &mani, mani_hash)?;
+    let script_written = crate::overwrite_file(&script_path, &split.script, script_hash)?;
+    let old_files = old_meta.as_ref().and_then(|m| m.files.as_deref());
+    crate::write_pkg_files(&pkg_path, &files, old_files.unwrap_or(&[]))?;
+
+    /*
+    The package might otherwise look up to date, and keep running against the old versions.  Forgetting what the script was built from is enough to have the next run build it again; what was written is remembered, so that changes made by hand are still noticed.
+    */
+    if let Some(mut meta) = old_meta {
+        if let FileOverwrite::Changed { new_hash } = mani_written {
+            meta.manifest_hash = new_hash;
+        }
+        if let FileOverwrite::Changed { new_hash } = script_written {
+            meta.script_hash = new_hash;
+        }
+        meta.content_hash = None;
+        crate::write_pkg_metadata(&pkg_path, &meta)?;
+    }
+
+    let mut cmd = Command::new("cargo");
+    cmd.arg("update").arg("--manifest-path").arg(&mani_path);
+    info!("running {:?}", cmd);
+    crate::logging::trace_command(&cmd);
+    let status = cmd.status()?;
+    if !status.success() {
+        return Ok(status.code().unwrap_or(1));
+    }
+
+    if args.save {
+        let locked = locked_versions(&pkg_path.join("Cargo.lock"))?;
+        let saved = manifest::rewrite_dep_reqs(&script.content, |name, req| {
+            let new = bump_req(req, &locked_for(&locked, name))?;
+            status!("Saving `{}` requirement {:?} -> {:?}", name, req, new);
+            Some(new)
+        });
+        if saved != script.content {
+            fs::write(&script.found, &saved)
+                .map_err(MainError::from)
+                .err_tag(format!("could not write `{}`", script.found.display()))
+                .shift_blame(Blame::Human)?;
+
+            // The script's contents are part of its cache ID, so take the lockfile along to its new package.
+            let input = Input::File(&script.name, &script.path, &saved, script.mtime);
+            let new_pkg_path = crate::script_pkg_path(&input, deps_from.as_ref())?;
+            fs::create_dir_all(&new_pkg_path)?;
+            fs::copy(pkg_path.join("Cargo.lock"), new_pkg_path.join("Cargo.lock"))?;
+        }
+    }
+
+    Ok(0)
+}
+
+/**
+Reads the name and version of every package in a lockfile which came from a registry.  Anything from a path or git repository has no business being written into a version requirement.
+*/
+pub fn locked_versions(lock_path: &Path) -> Result<Vec<(String, Version)>> {
+    use toml::Value;
+
+    let lock = fs::read_to_string(lock_path)?;
+    let lock: Value =
+        toml::from_str(&lock).map_err(|e| MainError::Other(Blame::Internal, Box::new(e)))?;
+
+    let pkgs = lock.get("package").and_then(Value::as_array);
+    Ok(pkgs
+        .into_iter()
+        .flatten()
+        .filter(|pkg| {
+            let source = pkg.get("source").and_then(Value::as_str).unwrap_or("");
+            source.starts_with("registry+") || source.starts_with("sparse+")
+        })
+        .filter_map(|pkg| {
+            let name = pkg.get("name")?.as_str()?;
+            let version = Version::parse(pkg.get("version")?.as_str()?).ok()?;
+            Some((name.to_owned(), version))
+        })
+        .collect())
+}
+
+pub fn locked_for(locked: &[(String, Version)], name: &str) -> Vec<Version> {
+    locked
+        .iter()
+        .filter(|(n, _)| n == name)
+        .map(|(_, v)| v.clone())
+        .collect()
+}
+
+/**
+Works out the new requirement for a dependency, given which versions of its package are locked.
+
+The requirement is pointed at the newest locked version it allows, keeping any `=`, `~` or `^` operator it had.  Requirements with more than one comparison, or which are already up to date, are left alone.
+*/
+fn bump_req(req: &str, locked: &[Version]) -> Option<String> {
+    let req = req.trim();
+    if req.contains(|c| c == ',' || c == '<' || c == '>') {
+        return None;
+    }
+    let parsed = VersionReq::parse(req).ok()?;
+    let version = locked.iter().filter(|v| parsed.matches(v)).max()?;
+
+    let op = match req.chars().next() {
+        Some(c) if "=~^".contains(c) => c.to_string(),
+        _ => String::new(),
+    };
+    let new = format!("{}{}", op, version);
+    match new == req {
+        true => None,
+        false => Some(new),
+    }
+}
+
+#[test]
+fn test_bump_req() {
+    let v = |s| Version::parse(s).unwrap();
+    let locked = [v("0.1.9"), v("0.2.5"), v("1.4.0")];
+    let br = |req| bump_req(req, &locked);
+
+    assert_eq!(br("*"), Some("1.4.0".into()));
+    assert_eq!(br("0.1"), Some("0.1.9".into()));
+    assert_eq!(br("0.2.0"), Some("0.2.5".into()));
+    assert_eq!(br("^1"), Some("^1.4.0".into()));
+    assert_eq!(br("~0.2.1"), Some("~0.2.5".into()));
+    assert_eq!(br("=0.1.9"), None);
+    assert_eq!(br("1.4.0"), None);
+    assert_eq!(br(">= 0.1, < 0.2"), None);
+    assert_eq!(br("2"), None);
+    assert_eq!(br("not a version"), None);
+}
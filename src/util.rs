@@ -10,9 +10,13 @@ or distributed except according to those terms.
 /*!
 This module just contains other random implementation stuff.
 */
-use log::error;
+use log::{error, trace};
 use std::error::Error;
+use std::fs;
+use std::io;
 use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /**
 A really, really hacky way of avoiding a variable binding.
@@ -76,6 +80,46 @@ where
     }
 }
 
+/**
+Looks for an executable on the `PATH`, the same way running it would.
+*/
+pub fn find_on_path(name: &str) -> Option<PathBuf> {
+    let name = format!("{}{}", name, std::env::consts::EXE_SUFFIX);
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(&name))
+            .find(|path| path.is_file())
+    })
+}
+
+/**
+Creates a new, empty directory in the system's temporary directory, with a name starting with `prefix`.  Removing it again is up to the caller.
+*/
+pub fn make_temp_dir(prefix: &str) -> io::Result<PathBuf> {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    for attempt in 0..100 {
+        let path = std::env::temp_dir().join(format!(
+            "{}-{}-{}-{}",
+            prefix,
+            std::process::id(),
+            nanos,
+            attempt
+        ));
+        match fs::create_dir(&path) {
+            Ok(()) => return Ok(path),
+            Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "could not find an unused name for a temporary directory",
+    ))
+}
+
 #[cfg(feature = "suppress-cargo-output")]
 pub use self::suppress_child_output::{suppress_child_output, ChildToken};
 
@@ -204,8 +248,94 @@ impl SubsliceOffset for str {
     }
 }
 
+use crate::error::{Blame, MainError};
 use std::path::Path;
 
+/**
+Gets a path as a `str`, for when it *has* to be UTF-8 (*e.g.* because it's going into a manifest).
+
+If it isn't valid UTF-8, the error names the first component at fault.
+*/
+pub fn path_to_utf8(path: &Path) -> Result<&str, MainError> {
+    path.to_str().ok_or_else(|| {
+        let component = path
+            .components()
+            .find(|c| c.as_os_str().to_str().is_none())
+            .map(|c| c.as_os_str())
+            .unwrap_or_else(|| path.as_os_str());
+        MainError::OtherOwned(
+            Blame::Human,
+            format!(
+                "path `{}` must be valid UTF-8, but {:?} is not",
+                path.display(),
+                component
+            ),
+        )
+    })
+}
+
+#[cfg(unix)]
+#[test]
+fn test_path_to_utf8() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    assert_eq!(path_to_utf8(Path::new("/a/b.rs")).ok(), Some("/a/b.rs"));
+
+    let path = Path::new(OsStr::from_bytes(b"/a/b\xffc/d.rs"));
+    assert_eq!(
+        path_to_utf8(path).map_err(|e| e.to_string()),
+        Err("path `/a/b\u{FFFD}c/d.rs` must be valid UTF-8, but \"b\\xFFc\" is not".into())
+    );
+}
+
+/**
+Works out a relative path from the directory `from` to `to`, written with `/` so the result is the same on every platform.  Both paths should be absolute and simplified.
+
+Returns `None` if there's no relative path between them (*e.g.* they're on different drives), or the result wouldn't be valid UTF-8.
+*/
+pub fn relative_path(from: &Path, to: &Path) -> Option<String> {
+    use std::path::Component;
+
+    if !from.is_absolute() || !to.is_absolute() {
+        return None;
+    }
+    let mut from = from.components().peekable();
+    let mut to = to.components().peekable();
+    match (from.peek(), to.peek()) {
+        (Some(Component::Prefix(a)), Some(Component::Prefix(b))) if a != b => return None,
+        _ => (),
+    }
+    while from.peek().is_some() && from.peek() == to.peek() {
+        from.next();
+        to.next();
+    }
+
+    let mut parts: Vec<&str> = from.map(|_| "..").collect();
+    for component in to {
+        parts.push(component.as_os_str().to_str()?);
+    }
+    match parts.is_empty() {
+        true => Some(".".into()),
+        false => Some(parts.join("/")),
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_relative_path() {
+    let rel = |from: &str, to: &str| relative_path(Path::new(from), Path::new(to));
+
+    assert_eq!(rel("/a/b/gen", "/a/b/dep"), Some("../dep".into()));
+    assert_eq!(
+        rel("/a/b/gen", "/a/b/gen/src/x.rs"),
+        Some("src/x.rs".into())
+    );
+    assert_eq!(rel("/a/b/gen", "/c/dep"), Some("../../../c/dep".into()));
+    assert_eq!(rel("/a/b", "/a/b"), Some(".".into()));
+    assert_eq!(rel("a/b", "/a/b"), None);
+}
+
 /**
 Stable replacement for unstable `std::fs::PathExt`.
 */
@@ -225,3 +355,273 @@ impl PathExt for Path {
             .unwrap_or(false)
     }
 }
+
+/**
+Checks that files can be created in `dir`, creating it first if need be.
+
+On Unix, this goes by the directory's permissions as well as actually trying it, since root can write to a directory that's been made read-only.
+*/
+pub fn check_writable(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    if cfg!(unix) && fs::metadata(dir)?.permissions().readonly() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "it's read-only",
+        ));
+    }
+    let probe = dir.join(format!(".write-check.{}", ::std::process::id()));
+    fs::File::create(&probe)?;
+    fs::remove_file(&probe)
+}
+
+/**
+Writes `content` to the file at `path`, such that the file either has its old contents, or all of the new contents, but never anything in between.
+
+This works by writing to a temporary file in the same directory, then renaming it over the top of the destination.  If anything goes wrong, the temporary file is removed and the destination is left untouched.
+*/
+pub fn write_file_atomic<P>(path: P, content: &[u8]) -> ::std::io::Result<()>
+where
+    P: AsRef<Path>,
+{
+    use std::fs;
+    use std::io::Write;
+
+    let path = path.as_ref();
+    trace!("writing {} bytes to {:?}", content.len(), path);
+    let file_name = path.file_name().ok_or_else(|| {
+        ::std::io::Error::new(
+            ::std::io::ErrorKind::InvalidInput,
+            format!("cannot write to {:?}: not a file path", path),
+        )
+    })?;
+    let mut temp_name = ::std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{}.tmp", ::std::process::id()));
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = fs::File::create(&temp_path)
+        .and_then(|mut f| {
+            f.write_all(content)?;
+            f.sync_all()
+        })
+        .and_then(|()| rename_over(&temp_path, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/**
+Renames `from` to `to`, replacing `to` if it already exists.
+
+On Windows, `rename` can refuse to replace an existing file (for example, if something else has it open without allowing deletion), so we remove the destination and try once more.
+*/
+fn rename_over(from: &Path, to: &Path) -> ::std::io::Result<()> {
+    match ::std::fs::rename(from, to) {
+        Err(ref err) if cfg!(windows) && to.is_file_polyfill() => {
+            error!(
+                "could not rename {:?} over {:?}: {}; retrying",
+                from, to, err
+            );
+            ::std::fs::remove_file(to)?;
+            ::std::fs::rename(from, to)
+        }
+        result => result,
+    }
+}
+
+#[test]
+fn test_write_file_atomic() {
+    use std::fs;
+
+    let dir = tempdir::TempDir::new("cargo-script-test-util").unwrap();
+    let path = dir.path().join("file.txt");
+    let entries = || {
+        let mut names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    };
+
+    write_file_atomic(&path, b"first").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "first");
+    assert_eq!(entries(), vec!["file.txt"]);
+
+    write_file_atomic(&path, b"second").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+    assert_eq!(entries(), vec!["file.txt"]);
+
+    // If the final rename can't happen, the temporary file must not be left behind.
+    let dir_path = dir.path().join("dir");
+    fs::create_dir(&dir_path).unwrap();
+    fs::write(dir_path.join("inner"), b"").unwrap();
+    assert!(write_file_atomic(&dir_path, b"third").is_err());
+    assert!(dir_path.is_dir());
+    assert_eq!(entries(), vec!["dir", "file.txt"]);
+}
+
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+/**
+Accumulates how long was spent in each of a fixed set of phases.
+
+Phases are timed with `scope`, which records the time until the returned guard is dropped.  Timing the same phase more than once adds the durations together.  A disabled `Timings` doesn't even look at the clock.
+*/
+pub struct Timings {
+    phases: Option<RefCell<Vec<(&'static str, Duration)>>>,
+}
+
+impl Timings {
+    /**
+    Creates a new set of timings for the given phases, in the order they should be reported.
+    */
+    pub fn new(enabled: bool, phases: &[&'static str]) -> Timings {
+        Timings {
+            phases: if enabled {
+                Some(RefCell::new(
+                    phases.iter().map(|&p| (p, Duration::default())).collect(),
+                ))
+            } else {
+                None
+            },
+        }
+    }
+
+    /**
+    Starts timing `phase`, stopping when the result is dropped.
+    */
+    pub fn scope(&self, phase: &'static str) -> TimingScope<'_> {
+        TimingScope {
+            timings: self,
+            phase,
+            start: self.phases.as_ref().map(|_| Instant::now()),
+        }
+    }
+
+    /**
+    Adds `duration` to the time spent in `phase`.
+    */
+    pub fn record(&self, phase: &'static str, duration: Duration) {
+        if let Some(ref phases) = self.phases {
+            match phases
+                .borrow_mut()
+                .iter_mut()
+                .find(|&&mut (p, _)| p == phase)
+            {
+                Some(&mut (_, ref mut total)) => *total += duration,
+                None => panic!("tried to record time for unknown phase {:?}", phase),
+            }
+        }
+    }
+
+    /**
+    How long has been spent in `phase` so far.  This is always zero if the timings are disabled.
+    */
+    pub fn get(&self, phase: &str) -> Duration {
+        self.phases
+            .as_ref()
+            .and_then(|phases| {
+                phases
+                    .borrow()
+                    .iter()
+                    .find(|&&(p, _)| p == phase)
+                    .map(|&(_, d)| d)
+            })
+            .unwrap_or_default()
+    }
+
+    /**
+    Formats the timings as a single line, *e.g.* `args 1.2ms, run 200.5ms, total 201.7ms`.
+    */
+    pub fn summary(&self, total: Duration) -> String {
+        self.entries(total)
+            .map(|(phase, ms)| format!("{} {:.1}ms", phase, ms))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /**
+    Formats the timings as a JSON object mapping each phase to its duration in milliseconds.
+    */
+    pub fn to_json(&self, total: Duration) -> String {
+        let fields: Vec<_> = self
+            .entries(total)
+            .map(|(phase, ms)| format!("{:?}:{:.3}", phase, ms))
+            .collect();
+        format!("{{{}}}", fields.join(","))
+    }
+
+    fn entries(&self, total: Duration) -> impl Iterator<Item = (&'static str, f64)> {
+        fn ms(d: Duration) -> f64 {
+            d.as_secs() as f64 * 1000.0 + f64::from(d.subsec_nanos()) / 1_000_000.0
+        }
+
+        let phases = match self.phases {
+            Some(ref phases) => phases.borrow().clone(),
+            None => vec![],
+        };
+        phases
+            .into_iter()
+            .chain(Some(("total", total)))
+            .map(|(p, d)| (p, ms(d)))
+    }
+}
+
+/**
+Records the time spent in a phase when dropped.  See `Timings::scope`.
+*/
+#[must_use]
+pub struct TimingScope<'a> {
+    timings: &'a Timings,
+    phase: &'static str,
+    start: Option<Instant>,
+}
+
+impl<'a> Drop for TimingScope<'a> {
+    fn drop(&mut self) {
+        if let Some(start) = self.start {
+            self.timings.record(self.phase, start.elapsed());
+        }
+    }
+}
+
+#[test]
+fn test_timings() {
+    let ms = Duration::from_millis;
+
+    let timings = Timings::new(true, &["a", "b"]);
+    timings.record("b", ms(2));
+    {
+        let _t = timings.scope("a");
+    }
+    timings.record("b", ms(3));
+    timings.record("a", ms(1));
+    let a = match timings.phases {
+        Some(ref phases) => phases.borrow()[0].1,
+        None => unreachable!(),
+    };
+    assert!(a >= ms(1) && a < ms(1000));
+
+    let timings = Timings::new(true, &["a", "b"]);
+    timings.record("a", Duration::new(1, 250_000));
+    timings.record("b", ms(5));
+    assert_eq!(
+        timings.summary(ms(1010)),
+        "a 1000.2ms, b 5.0ms, total 1010.0ms"
+    );
+    assert_eq!(
+        timings.to_json(ms(1010)),
+        r#"{"a":1000.250,"b":5.000,"total":1010.000}"#
+    );
+    assert_eq!(timings.get("b"), ms(5));
+
+    let timings = Timings::new(false, &["a"]);
+    assert!(timings.scope("a").start.is_none());
+    timings.record("nope", ms(1));
+    assert_eq!(timings.summary(ms(1)), "total 1.0ms");
+    assert_eq!(timings.get("a"), Duration::default());
+}
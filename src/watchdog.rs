@@ -0,0 +1,212 @@
+/*
+Copyright ⓒ 2017 cargo-script contributors.
+
+Licensed under the MIT license (see LICENSE or <http://opensource.org
+/licenses/MIT>) or the Apache License, Version 2.0 (see LICENSE of
+<http://www.apache.org/licenses/LICENSE-2.0>), at your option. All
+files in the project carrying such notice may not be copied, modified,
+or distributed except according to those terms.
+*/
+/*!
+This module contains code for `--build-timeout` and `--build-retries`, which stop a build from hanging forever, or failing for good, because of a flaky registry or mirror.
+*/
+use crate::consts;
+use crate::error::{Blame, MainError, Result};
+use crate::platform::{ProcessTree, SignalGuard};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use regex::Regex;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+lazy_static! {
+    /**
+    What Cargo says when it couldn't get something from the network.  Only failures like these are worth trying again; anything else will just fail the same way.
+    */
+    static ref NETWORK_FAILURES: Vec<Regex> = vec![
+        Regex::new(r"network failure seems to have happened").unwrap(),
+        Regex::new(r"spurious network error").unwrap(),
+        Regex::new(r"failed to download from `").unwrap(),
+        Regex::new(r"\[\d+\] (?:Couldn't resolve (?:host|proxy) name|Couldn't connect to server|Timeout was reached|SSL connect error|Failure when receiving data from the peer|Send failure)").unwrap(),
+        Regex::new(r"(?i)(?:connection (?:refused|reset|timed out)|operation timed out|temporary failure in name resolution)").unwrap(),
+    ];
+}
+
+/**
+How far a build is allowed to go.
+*/
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    /// How long each attempt at building can take before it's killed.
+    pub timeout: Option<Duration>,
+
+    /// How many times to try again after a failure which looks like it's down to the network.
+    pub retries: u32,
+}
+
+impl Limits {
+    /**
+    Does this limit anything at all?
+    */
+    pub fn any(&self) -> bool {
+        self.timeout.is_some() || self.retries > 0
+    }
+}
+
+/**
+Spawns `cmd`, killing it, and everything it has started, once `timeout` is up.  Without a timeout, this just spawns it.
+
+Since what's killed has to be kept out of the terminal's way, signals such as Ctrl+C are passed on to it until it exits, and then raised again for us, rather than leaving it behind when we go.
+*/
+pub fn spawn(cmd: &mut Command, timeout: Option<Duration>) -> Result<Watched> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => {
+            return Ok(Watched {
+                child: cmd.spawn()?,
+                timer: None,
+                _signals: None,
+            })
+        }
+    };
+
+    let mut tree = ProcessTree::prepare(cmd)?;
+    let signals = SignalGuard::install_for_tree();
+    let mut child = cmd.spawn()?;
+    if let Err(err) = tree.adopt(&child) {
+        let _ = child.kill();
+        return Err(err.into());
+    }
+    tree.forward_signals();
+
+    // The timer is stopped by dropping `stop`, which happens as soon as the child exits.
+    let (stop, stopped) = mpsc::channel::<()>();
+    let handle = thread::spawn(move || match stopped.recv_timeout(timeout) {
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            info!("killing process tree after {:?}", timeout);
+            if let Err(err) = tree.kill() {
+                warn!("could not kill process tree: {}", err);
+            }
+            true
+        }
+        _ => false,
+    });
+
+    Ok(Watched {
+        child,
+        timer: Some(Timer {
+            stop,
+            handle,
+            timeout,
+        }),
+        _signals: Some(signals),
+    })
+}
+
+/**
+A child process spawned by `spawn`.
+*/
+pub struct Watched {
+    pub child: Child,
+    timer: Option<Timer>,
+    _signals: Option<SignalGuard>,
+}
+
+struct Timer {
+    stop: mpsc::Sender<()>,
+    handle: thread::JoinHandle<bool>,
+    timeout: Duration,
+}
+
+impl Watched {
+    /**
+    Waits for the child to exit.  If it had to be killed for taking too long, this is a `MainError::TimedOut`.
+    */
+    pub fn wait(mut self) -> Result<ExitStatus> {
+        let status = self.child.wait()?;
+        if let Some(Timer {
+            stop,
+            handle,
+            timeout,
+        }) = self.timer.take()
+        {
+            drop(stop);
+            if handle.join().unwrap_or(false) {
+                return Err(MainError::TimedOut(
+                    Blame::Human,
+                    format!(
+                        "cargo took longer than {}, and was killed (--build-timeout)",
+                        describe(timeout)
+                    ),
+                ));
+            }
+        }
+        Ok(status)
+    }
+}
+
+/**
+Does what a failed build printed look like it couldn't reach the network?
+*/
+pub fn looks_like_network_failure(output: &str) -> bool {
+    NETWORK_FAILURES.iter().any(|re| re.is_match(output))
+}
+
+/**
+How long to wait before the given retry, counting from 1.
+*/
+pub fn retry_delay(retry: u32) -> Duration {
+    let doublings = retry.saturating_sub(1).min(31);
+    let ms = consts::BUILD_RETRY_DELAY_MS.saturating_mul(1 << doublings);
+    Duration::from_millis(ms.min(consts::BUILD_RETRY_DELAY_MAX_MS))
+}
+
+/// Formats a timeout the way it's most likely to have been given.
+fn describe(timeout: Duration) -> String {
+    match timeout.as_secs() {
+        _ if timeout.subsec_nanos() != 0 => format!("{:?}", timeout),
+        secs if secs % 3600 == 0 => format!("{}h", secs / 3600),
+        secs if secs % 60 == 0 => format!("{}m", secs / 60),
+        secs => format!("{}s", secs),
+    }
+}
+
+#[test]
+fn test_looks_like_network_failure() {
+    macro_rules! fixture {
+        ($name:expr) => {
+            looks_like_network_failure(include_str!(concat!(
+                "../tests/data/build-failures/",
+                $name,
+                ".txt"
+            )))
+        };
+    }
+
+    assert!(fixture!("network"));
+    assert!(fixture!("network-dns"));
+
+    // Being told to stay offline isn't something the network will fix.
+    assert!(!fixture!("offline"));
+    assert!(!fixture!("type-error"));
+    assert!(!fixture!("openssl"));
+}
+
+#[test]
+fn test_retry_delay() {
+    assert_eq!(retry_delay(1), Duration::from_secs(1));
+    assert_eq!(retry_delay(2), Duration::from_secs(2));
+    assert_eq!(retry_delay(3), Duration::from_secs(4));
+    assert_eq!(retry_delay(6), Duration::from_secs(30));
+    assert_eq!(retry_delay(100), Duration::from_secs(30));
+}
+
+#[test]
+fn test_describe() {
+    assert_eq!(describe(Duration::from_secs(90)), "90s");
+    assert_eq!(describe(Duration::from_secs(600)), "10m");
+    assert_eq!(describe(Duration::from_secs(7200)), "2h");
+    assert_eq!(describe(Duration::from_millis(1500)), "1.5s");
+}
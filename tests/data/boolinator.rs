@@ -0,0 +1,10 @@
+// cargo-deps: boolinator="=0.1.0"
+extern crate boolinator;
+use boolinator::Boolinator;
+use std::env;
+
+fn main() {
+    println!("--output--");
+    println!("{:?}", true.as_some(1));
+    println!("{}", env::var("CARGO_SCRIPT_PKG_NAME").unwrap());
+}
@@ -0,0 +1,23 @@
+//! Embeds its own build script to compile some C.
+//!
+//! ```cargo
+//! [package.metadata.cargo-script]
+//! include = ["shim.c"]
+//!
+//! [build-dependencies]
+//! cc = "1"
+//! ```
+//!
+//! ```build-script
+//! fn main() {
+//!     cc::Build::new().file("shim.c").compile("shim");
+//! }
+//! ```
+extern "C" {
+    fn shim_answer() -> i32;
+}
+
+fn main() {
+    println!("--output--");
+    println!("{}", unsafe { shim_answer() });
+}
@@ -0,0 +1,20 @@
+//! Uses the `build.rs` next to it to compile some C.
+//!
+//! ```cargo
+//! [package]
+//! build = "build.rs"
+//!
+//! [package.metadata.cargo-script]
+//! include = ["shim.c"]
+//!
+//! [build-dependencies]
+//! cc = "1"
+//! ```
+extern "C" {
+    fn shim_answer() -> i32;
+}
+
+fn main() {
+    println!("--output--");
+    println!("{}", unsafe { shim_answer() });
+}
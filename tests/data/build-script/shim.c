@@ -0,0 +1,3 @@
+int shim_answer(void) {
+    return 42;
+}
@@ -0,0 +1,3 @@
+pub fn greeting() -> String {
+    format!("hello, {}", include_str!("name.txt").trim())
+}
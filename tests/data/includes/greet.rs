@@ -0,0 +1,4 @@
+//# include shout.rs
+fn greet(name: &str) -> String {
+    shout(&format!("Hello, {}!", name))
+}
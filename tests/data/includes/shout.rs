@@ -0,0 +1,3 @@
+fn shout(s: &str) -> String {
+    s.to_uppercase()
+}
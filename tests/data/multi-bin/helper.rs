@@ -0,0 +1,4 @@
+fn main() {
+    println!("--output--");
+    println!("helper: {:?}", std::env::args().skip(1).collect::<Vec<_>>());
+}
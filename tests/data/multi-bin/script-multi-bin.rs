@@ -0,0 +1,11 @@
+//! Comes with a helper tool:
+//!
+//! ```cargo
+//! [[bin]]
+//! name = "helper"
+//! path = "helper.rs"
+//! ```
+fn main() {
+    println!("--output--");
+    println!("main: {:?}", std::env::args().skip(1).collect::<Vec<_>>());
+}
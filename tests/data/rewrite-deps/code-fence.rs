@@ -0,0 +1,29 @@
+#!/usr/bin/env run-cargo-script
+//! Prints the time, among other things.
+//!
+//! time = "0.1.25" isn't in the manifest, so this stays as it is.
+//!
+//! ```cargo
+//! [package]
+//! version = "0.1.0"
+//! edition = "2018"
+//!
+//! [dependencies]
+//! time   =   "0.1.25"   # Keep this comment.
+//! "boolinator" = "=0.1.0"
+//! re = { package = "regex", version = "1.0" }
+//! unknown = "3"
+//!
+//! [dependencies.serde]
+//! version = '1.0'
+//! features = ["derive"]
+//!
+//! [target.'cfg(unix)'.dependencies]
+//!     libc = "0.2"
+//!
+//! [features]
+//! time = []
+//! ```
+//!
+//! libc = "0.2" isn't either.
+fn main() {}
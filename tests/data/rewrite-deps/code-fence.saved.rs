@@ -0,0 +1,29 @@
+#!/usr/bin/env run-cargo-script
+//! Prints the time, among other things.
+//!
+//! time = "0.1.25" isn't in the manifest, so this stays as it is.
+//!
+//! ```cargo
+//! [package]
+//! version = "0.1.0"
+//! edition = "2018"
+//!
+//! [dependencies]
+//! time   =   "0.1.40"   # Keep this comment.
+//! "boolinator" = "=2.4.0"
+//! re = { package = "regex", version = "1.10.2" }
+//! unknown = "3"
+//!
+//! [dependencies.serde]
+//! version = '1.0.190'
+//! features = ["derive"]
+//!
+//! [target.'cfg(unix)'.dependencies]
+//!     libc = "0.2.150"
+//!
+//! [features]
+//! time = []
+//! ```
+//!
+//! libc = "0.2" isn't either.
+fn main() {}
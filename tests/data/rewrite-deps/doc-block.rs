@@ -0,0 +1,15 @@
+/*!
+ * Prints the time.
+ *
+ * ```cargo
+ * [dependencies]
+ * time = "0.1.25"
+ * boolinator = { version = "0.1", default-features = false }
+ * local = { path = "../local" }
+ *
+ * [dev-dependencies]
+ * libc = "*"
+ * ```
+ */
+/* time = "0.1.25" */
+fn main() {}
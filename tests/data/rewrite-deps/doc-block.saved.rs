@@ -0,0 +1,15 @@
+/*!
+ * Prints the time.
+ *
+ * ```cargo
+ * [dependencies]
+ * time = "0.1.40"
+ * boolinator = { version = "2.4.0", default-features = false }
+ * local = { path = "../local" }
+ *
+ * [dev-dependencies]
+ * libc = "0.2.150"
+ * ```
+ */
+/* time = "0.1.25" */
+fn main() {}
@@ -0,0 +1,6 @@
+#!/usr/bin/env run-cargo-script
+// cargo-deps: time="0.1.25",  boolinator ,libc = '0.2', serde={ version = "1", features = ["derive"] }, local={ path = "../local" }
+// The manifest is just the line above; this one isn't part of it, so time="0.1.25" stays.
+fn main() {
+    println!("{:?}", "time=\"0.1.25\"");
+}
@@ -0,0 +1,6 @@
+#!/usr/bin/env run-cargo-script
+// cargo-deps: time="0.1.40",  boolinator="2.4.0" ,libc = '0.2.150', serde={ version = "1.0.190", features = ["derive"] }, local={ path = "../local" }
+// The manifest is just the line above; this one isn't part of it, so time="0.1.25" stays.
+fn main() {
+    println!("{:?}", "time=\"0.1.25\"");
+}
@@ -0,0 +1,6 @@
+fn main() {
+    println!("--output--");
+    for (i, arg) in std::env::args_os().enumerate().skip(1) {
+        println!("{:>4}: {:?}", format!("[{}]", i), arg);
+    }
+}
@@ -0,0 +1,10 @@
+//! ```cargo
+//! [dependencies]
+//! shout = { path = 'deps-from\shout' }
+//! ```
+extern crate shout;
+
+fn main() {
+    println!("--output--");
+    println!("{}", shout::shout("hi"));
+}
@@ -0,0 +1,8 @@
+use std::panic;
+
+fn main() {
+    println!("--output--");
+    panic::set_hook(Box::new(|_| ()));
+    let caught = panic::catch_unwind(|| panic!("oh no")).is_err();
+    println!("caught: {}", caught);
+}
@@ -0,0 +1,5 @@
+fn main() {
+    println!("--output--");
+    println!("build: {:?}", option_env!("CS_TEST_CONFIG_ENV"));
+    println!("run: {:?}", std::env::var("CS_TEST_CONFIG_ENV").ok());
+}
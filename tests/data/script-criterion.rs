@@ -0,0 +1,30 @@
+//! ```cargo
+//! [package]
+//! edition = "2018"
+//!
+//! [dev-dependencies]
+//! criterion = { version = "0.5", default-features = false }
+//! # Newer versions of Criterion's dependencies need newer toolchains than the tests are run with.
+//! clap = "=4.4.18"
+//! half = "=2.4.1"
+//! ```
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::time::Duration;
+
+fn bench_sum(c: &mut Criterion) {
+    c.bench_function("sum", |b| b.iter(|| (1..100u64).sum::<u64>()));
+}
+
+fn bench_product(c: &mut Criterion) {
+    c.bench_function("product", |b| b.iter(|| (1..10u64).product::<u64>()));
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default()
+        .warm_up_time(Duration::from_millis(10))
+        .measurement_time(Duration::from_millis(100))
+        .sample_size(10);
+    targets = bench_sum, bench_product
+}
+criterion_main!(benches);
@@ -0,0 +1,12 @@
+#!/usr/bin/env run-cargo-script
+//! This script has Windows line endings.
+//!
+//! ```cargo
+//! [features]
+//! default = ["crlf"]
+//! crlf = []
+//! ```
+fn main() {
+    println!("--output--");
+    println!("crlf: {}", cfg!(feature = "crlf"));
+}
@@ -1,4 +1,5 @@
 use std::env;
+use std::path::Path;
 
 fn main() {
     println!("--output--");
@@ -8,5 +9,8 @@ fn main() {
     assert_eq!(env::var("CARGO_SCRIPT_PKG_NAME"), Ok("script-cs-env".into()));
     let base_path = env::var("CARGO_SCRIPT_BASE_PATH").expect("CSBP wasn't set");
     assert!(base_path.ends_with("data"));
+    let cache_path = env::var("CARGO_SCRIPT_CACHE_PATH").expect("CSCP wasn't set");
+    assert!(Path::new(&cache_path).is_absolute());
+    assert!(Path::new(&cache_path).join("Cargo.toml").is_file());
     println!("Ok");
 }
@@ -0,0 +1,15 @@
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    let dir = std::env::var("CARGO_SCRIPT_DATA_DIR").unwrap();
+    fs::create_dir_all(&dir).unwrap();
+    let counter = Path::new(&dir).join("counter");
+    let count = fs::read_to_string(&counter)
+        .map(|s| s.trim().parse::<u32>().unwrap())
+        .unwrap_or(0)
+        + 1;
+    fs::write(&counter, count.to_string()).unwrap();
+    println!("--output--");
+    println!("{}", count);
+}
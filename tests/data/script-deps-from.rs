@@ -0,0 +1,7 @@
+// cargo-deps: shout
+extern crate shout;
+
+fn main() {
+    println!("--output--");
+    println!("{}", shout::shout("hello"));
+}
@@ -0,0 +1,9 @@
+use std::env;
+
+fn main() {
+    println!("--output--");
+    assert_eq!(env::var("CS_TEST_PLAIN"), Ok("plain value".into()));
+    assert_eq!(env::var("CS_TEST_QUOTED"), Ok("quoted # value".into()));
+    assert_eq!(env::var("CS_TEST_OVERRIDE"), Ok("from flag".into()));
+    println!("Ok");
+}
@@ -0,0 +1,5 @@
+//# include includes/greet.rs
+fn main() {
+    println!("--output--");
+    println!("{}", greet("World"));
+}
@@ -0,0 +1,8 @@
+use std::io::IsTerminal;
+
+fn main() {
+    println!("--output--");
+    println!("stdin: {}", std::io::stdin().is_terminal());
+    println!("stdout: {}", std::io::stdout().is_terminal());
+    println!("stderr: {}", std::io::stderr().is_terminal());
+}
@@ -0,0 +1,13 @@
+//! ```cargo
+//! [features]
+//! dont-panic = []
+//!
+//! [package.metadata.cargo-script]
+//! debug = true
+//! features = ["dont-panic"]
+//! ```
+fn main() {
+    println!("--output--");
+    println!("debug: {}", cfg!(debug_assertions));
+    println!("dont-panic: {}", cfg!(feature = "dont-panic"));
+}
@@ -0,0 +1,8 @@
+//! ```cargo
+//! [package.metadata.cargo-script]
+//! release = true
+//! ```
+fn main() {
+    println!("--output--");
+    println!("debug: {}", cfg!(debug_assertions));
+}
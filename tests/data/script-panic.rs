@@ -0,0 +1,5 @@
+#!/usr/bin/env run-cargo-script
+fn main() {
+    eprintln!("about to panic");
+    panic!("oh no");
+}
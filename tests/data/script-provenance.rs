@@ -0,0 +1,6 @@
+fn main() {
+    println!("--output--");
+    println!("{}", env!("CARGO_SCRIPT_SOURCE_PATH"));
+    println!("{}", env!("CARGO_SCRIPT_SOURCE_DIGEST"));
+    println!("{}", env!("CARGO_SCRIPT_BUILD_TIME"));
+}
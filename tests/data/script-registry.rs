@@ -0,0 +1,10 @@
+//! ```cargo
+//! [dependencies]
+//! greet = { version = "0.1", registry = "company" }
+//! ```
+extern crate greet;
+
+fn main() {
+    println!("--output--");
+    println!("{}", greet::hello());
+}
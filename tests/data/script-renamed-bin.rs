@@ -0,0 +1,8 @@
+//! ```cargo
+//! [[bin]]
+//! name = "renamed"
+//! ```
+fn main() {
+    println!("--output--");
+    println!("Ok");
+}
@@ -0,0 +1,5 @@
+fn main() {
+    println!("--output--");
+    println!("runner: {}", std::env::var("CARGO_SCRIPT_TEST_RUNNER").unwrap_or_default());
+    println!("args: {:?}", std::env::args().skip(1).collect::<Vec<_>>());
+}
@@ -0,0 +1,13 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    println!("--output--");
+    let dir = env::var("CARGO_SCRIPT_SANDBOX_DIR").unwrap();
+    assert_eq!(env::var("TMPDIR").unwrap(), dir);
+    fs::write(Path::new(&dir).join("scratch"), "x").unwrap();
+    println!("dir: {}", dir);
+    println!("sandbox: {}", env::var("CARGO_SCRIPT_TEST_SANDBOX").unwrap_or_default());
+    println!("args: {:?}", env::args().skip(1).collect::<Vec<_>>());
+}
@@ -0,0 +1,28 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_term(_: i32) {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+extern "C" {
+    fn signal(sig: i32, handler: extern "C" fn(i32)) -> usize;
+}
+
+fn main() {
+    // `SIGTERM` is 15 everywhere this is run.
+    unsafe {
+        signal(15, on_term);
+    }
+    println!("ready");
+    std::io::stdout().flush().unwrap();
+    while !STOP.load(Ordering::SeqCst) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    println!("cleaned up");
+    std::process::exit(3);
+}
@@ -0,0 +1,5 @@
+fn main() {
+    std::thread::sleep(std::time::Duration::from_millis(200));
+    println!("--output--");
+    println!("Slept.");
+}
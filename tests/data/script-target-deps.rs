@@ -0,0 +1,15 @@
+//! Dependencies can be limited to some targets:
+//!
+//! ```cargo
+//! [target.'cfg(unix)'.dependencies]
+//! boolinator = "=0.1.0"
+//!
+//! [target.'cfg(windows)'.dependencies]
+//! boolinator = "=0.1.0"
+//! ```
+extern crate boolinator;
+use boolinator::Boolinator;
+fn main() {
+    println!("--output--");
+    println!("{:?}", true.as_some(1));
+}
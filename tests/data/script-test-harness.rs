@@ -0,0 +1,15 @@
+fn main() {}
+
+#[test]
+fn test_passes() {
+    println!("printed by test_passes");
+}
+
+#[test]
+fn test_fails() {
+    panic!("test_fails failed");
+}
+
+#[test]
+#[ignore]
+fn test_ignored() {}
@@ -0,0 +1,13 @@
+fn double(x: i32) -> i32 {
+    x * 2
+}
+
+#[test]
+fn test_double() {
+    assert_eq!(double(21), 42);
+}
+
+#[test]
+fn test_double_negative() {
+    assert_eq!(double(-1), -2);
+}
@@ -13,7 +13,15 @@ extern crate scan_rules;
 mod util;
 
 mod tests {
+    mod clippy;
+    mod export;
     mod expr;
+    mod fmt;
+    mod pin;
+    mod prebuild;
+    mod quote;
     mod script;
+    mod update;
     mod version;
+    mod which;
 }
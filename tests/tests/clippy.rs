@@ -0,0 +1,45 @@
+use std::fs;
+
+#[test]
+fn test_clippy() {
+    let dir = tempdir::TempDir::new("cargo-script-test-clippy").unwrap();
+    let script = dir.path().join("lint.rs");
+    fs::write(
+        &script,
+        "#!/usr/bin/env run-cargo-script\n// cargo-deps: boolinator=\"=0.1.0\"\nfn main() {\n    let x = 1;\n    if x == x {\n        println!(\"--output--\");\n    }\n}\n",
+    )
+    .unwrap();
+
+    // The lint should point at the script itself, hashbang and all.
+    let out = cargo_script!(
+        #[subcommand("clippy")]
+        &script
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("clippy::eq_op"));
+    assert!(out
+        .stderr
+        .contains(&format!("{}:5:8", script.canonicalize().unwrap().display())));
+
+    let out = cargo_script!(
+        #[subcommand("clippy")]
+        &script,
+        "--",
+        "-A",
+        "clippy::eq_op"
+    )
+    .unwrap();
+    assert!(out.success());
+}
+
+#[test]
+fn test_clippy_crs_modules() {
+    // rustc wouldn't find `.crs` modules next to the script, so this has to lint the generated package.
+    let out = cargo_script!(
+        #[subcommand("clippy")]
+        "tests/data/crs-tree/tree.crs"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+}
@@ -0,0 +1,152 @@
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn test_export() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let dest_dir = tempdir::TempDir::new("cargo-script-test-export").unwrap();
+    let dest = dest_dir.path().join("full-block");
+
+    // Run it first, so there's a `Cargo.lock` to export.
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "tests/data/script-full-block.rs"
+    )
+    .unwrap();
+    assert!(out.success());
+
+    let out = cargo_script!(
+        #[subcommand("export")]
+        #[env(CARGO_HOME = cargo_home.path())]
+        "tests/data/script-full-block.rs",
+        "--to",
+        &dest
+    )
+    .unwrap();
+    assert!(out.success());
+    assert!(dest.join("Cargo.lock").is_file());
+
+    let main_rs = fs::read_to_string(dest.join("src/main.rs")).unwrap();
+    assert!(main_rs.starts_with("//! This is merged into a default manifest"));
+    assert!(!main_rs.contains("```cargo"));
+    let cargo_toml = fs::read_to_string(dest.join("Cargo.toml")).unwrap();
+    assert!(cargo_toml.contains("boolinator = \"=0.1.0\""));
+
+    // It should build as-is, without any help from `cargo-script`.
+    let out = Command::new("cargo")
+        .arg("run")
+        .arg("--quiet")
+        .arg("--manifest-path")
+        .arg(dest.join("Cargo.toml"))
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    scan!(&stdout;
+        ("--output--", "Some(1)") => ()
+    )
+    .unwrap();
+
+    // The destination now has content, so a second export needs `--force`.
+    let out = cargo_script!(
+        #[subcommand("export")]
+        "tests/data/script-full-block.rs",
+        "--to",
+        &dest
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("--force"));
+
+    let out = cargo_script!(
+        #[subcommand("export")]
+        "tests/data/script-full-block.rs",
+        "--to",
+        &dest,
+        "--force"
+    )
+    .unwrap();
+    assert!(out.success());
+}
+
+#[test]
+fn test_export_crs_modules() {
+    let dest_dir = tempdir::TempDir::new("cargo-script-test-export").unwrap();
+    let dest = dest_dir.path().join("tree");
+
+    let out = cargo_script!(
+        #[subcommand("export")]
+        "tests/data/crs-tree/tree.crs",
+        "--to",
+        &dest
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(dest.join("src/shapes/square.rs").is_file());
+    assert!(dest.join("src/util/mod.rs").is_file());
+
+    let out = Command::new("cargo")
+        .arg("run")
+        .arg("--quiet")
+        .arg("--manifest-path")
+        .arg(dest.join("Cargo.toml"))
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    assert!(String::from_utf8(out.stdout)
+        .unwrap()
+        .contains("square of area 9"));
+}
+
+#[test]
+fn test_export_bundle() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let dest_dir = tempdir::TempDir::new("cargo-script-test-export").unwrap();
+    let dest = dest_dir.path().join("bundle");
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "tests/data/bundle"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+
+    // The bundle's other files go along with it, and so does the lockfile it ran with.
+    let out = cargo_script!(
+        #[subcommand("export")]
+        #[env(CARGO_HOME = cargo_home.path())]
+        "tests/data/bundle",
+        "--to",
+        &dest
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(dest.join("Cargo.lock").is_file());
+    assert!(dest.join("src/name.txt").is_file());
+
+    let out = Command::new("cargo")
+        .arg("run")
+        .arg("--quiet")
+        .arg("--manifest-path")
+        .arg(dest.join("Cargo.toml"))
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "{}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    scan!(&stdout;
+        ("--output--", "HELLO, BUNDLE!") => ()
+    )
+    .unwrap();
+}
@@ -91,6 +91,36 @@ fn test_expr_dep_extern() {
     assert!(!out.success());
 }
 
+#[test]
+fn test_expr_prelude_crate() {
+    let out = cargo_script!(
+        "--prelude-crate",
+        "tap=1.0.1",
+        "-e",
+        with_output_marker!("5.pipe(|x| x * 2)")
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("10") => ()
+    )
+    .unwrap();
+
+    // Asking for the same crate's `extern crate` again shouldn't declare it twice.
+    let out = cargo_script!(
+        "-D",
+        "tap=1.0.1",
+        "--prelude-crate",
+        "tap=1.0.1",
+        "-e",
+        with_output_marker!("5.pipe(|x| x * 2)")
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("10") => ()
+    )
+    .unwrap();
+}
+
 #[test]
 fn test_expr_panic() {
     let out = cargo_script!("-e", with_output_marker!("panic!()")).unwrap();
@@ -155,3 +185,494 @@ fn test_expr_template_override_expr() {
     )
     .unwrap();
 }
+
+#[test]
+fn test_expr_as() {
+    let out = cargo_script!("--expr-as", "i64", "-e", "1 << 40").unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(out.stdout.trim(), "1099511627776");
+
+    let out = cargo_script!("--expr-as", "Vec<u8>", "-e", "(1..4).collect()").unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(out.stdout.trim(), "[1, 2, 3]");
+
+    let out = cargo_script!("--expr-as", "i64 }", "-e", "1").unwrap();
+    assert!(!out.success());
+    assert!(
+        out.stderr.contains("doesn't look like a type"),
+        "{}",
+        out.stderr
+    );
+
+    // The overriding `expr` template doesn't know about `--expr-as`.
+    let template_dir = "tests/data/templates/override";
+    let out = cargo_script!(
+        #[env(CARGO_SCRIPT_DEBUG_TEMPLATE_PATH=template_dir)]
+        "--expr-as",
+        "bool",
+        "-e",
+        "true"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(
+        out.stderr.contains("can't be used with `--expr-as`"),
+        "{}",
+        out.stderr
+    );
+}
+
+#[test]
+fn test_list_templates() {
+    let template_dir = "tests/data/templates/override";
+    let out = cargo_script!(
+        #[subcommand("script")]
+        #[env(CARGO_SCRIPT_DEBUG_TEMPLATE_PATH=template_dir)]
+        "--list-templates"
+    )
+    .unwrap();
+    assert!(out.success());
+    let lines: Vec<_> = out
+        .stdout
+        .lines()
+        .map(|l| l.split_whitespace().collect::<Vec<_>>())
+        .collect();
+    assert!(lines.iter().any(|l| l[..] == ["loop-count", "built-in"]));
+    assert!(lines.iter().any(|l| l[0] == "expr"
+        && l[1].ends_with("expr.rs")
+        && l[2..] == ["(overrides", "built-in)"]));
+    assert_eq!(lines.iter().filter(|l| l[0] == "expr").count(), 1);
+
+    // A template folder which doesn't exist yet just means there are only the built-in templates.
+    let template_dir = "tests/data/templates/does-not-exist";
+    let out = cargo_script!(
+        #[subcommand("templates")]
+        #[env(CARGO_SCRIPT_DEBUG_TEMPLATE_PATH=template_dir)]
+        "list"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert!(out.stdout.lines().all(|l| l.ends_with("built-in")));
+}
+
+#[test]
+fn test_expr_check() {
+    let out = cargo_script!("--check", "-e", "swing begin").unwrap();
+    assert!(!out.success());
+
+    let out = cargo_script!("--check", "-e", with_output_marker!("0")).unwrap();
+    assert!(out.success());
+    assert!(!out.stdout.contains("--output--"));
+}
+
+#[test]
+fn test_expr_cache_path() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let expr = r#"{
+        let path = std::path::Path::new(&std::env::var("CARGO_SCRIPT_CACHE_PATH").unwrap()).join("state");
+        let runs = std::fs::read_to_string(&path).map(|s| s.len()).unwrap_or(0) + 1;
+        std::fs::write(&path, "x".repeat(runs)).unwrap();
+        println!("{}", path.display());
+        runs
+    }"#;
+    let run = || {
+        cargo_script!(
+            #[cargo_home(cargo_home.path())]
+            "-e",
+            expr
+        )
+        .unwrap()
+    };
+
+    let out = run();
+    assert!(out.success(), "{}", out.stderr);
+    let cache_path = cargo_home.path().join("script-cache");
+    assert!(out.stdout.starts_with(&*cache_path.to_string_lossy()));
+    assert!(out.stdout.ends_with("state\n1\n"));
+
+    // The script's own files are kept along with its package.
+    let out = run();
+    assert!(out.stdout.ends_with("state\n2\n"));
+}
+
+#[test]
+fn test_expr_interrupted() {
+    use std::fs;
+
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let run = || {
+        cargo_script!(
+            #[cargo_home(cargo_home.path())]
+            "-e",
+            with_output_marker!("1 + 1")
+        )
+        .unwrap()
+    };
+
+    let out = run();
+    scan!(out.stdout_output();
+        ("2") => ()
+    )
+    .unwrap();
+
+    let pkgs: Vec<_> = fs::read_dir(cargo_home.path().join("script-cache"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.is_dir())
+        .collect();
+    assert_eq!(pkgs.len(), 1);
+    let meta_path = pkgs[0].join("metadata.json");
+    let meta = fs::read_to_string(&meta_path).unwrap();
+    assert!(meta.contains(r#""state":"Ran""#));
+
+    // Pretend the last run was killed part-way through building the package.
+    fs::write(
+        &meta_path,
+        meta.replace(r#""state":"Ran""#, r#""state":"Generating""#),
+    )
+    .unwrap();
+    let leftover = pkgs[0].join("half-built");
+    fs::write(&leftover, "").unwrap();
+
+    let out = run();
+    scan!(out.stdout_output();
+        ("2") => ()
+    )
+    .unwrap();
+    assert!(out.stderr.contains("interrupted while being built"));
+    assert!(!leftover.exists());
+    let meta = fs::read_to_string(&meta_path).unwrap();
+    assert!(meta.contains(r#""state":"Ran""#));
+}
+
+#[test]
+fn test_expr_result() {
+    let out = cargo_script!("-e", with_output_marker!("\"42\".parse::<i32>()")).unwrap();
+    scan!(out.stdout_output();
+        ("42") => ()
+    )
+    .unwrap();
+
+    let out = cargo_script!("-e", "\"forty-two\".parse::<i32>()").unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("Error: invalid digit found in string"));
+    assert!(!out.stdout.contains("Err("));
+}
+
+#[test]
+fn test_expr_error_format() {
+    let out = cargo_script!(
+        "--error-format",
+        "plain",
+        "-e",
+        "\"forty-two\".parse::<i32>()"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("invalid digit found in string"));
+    assert!(!out.stderr.contains("Error: invalid digit"));
+
+    let out = cargo_script!(
+        "--error-format",
+        "prefixed",
+        "-e",
+        "\"forty-two\".parse::<i32>()"
+    )
+    .unwrap();
+    assert!(out.stderr.contains("Error: invalid digit found in string"));
+
+    // It only means anything for expressions.
+    let out = cargo_script!("--error-format", "plain", "tests/data/script-no-deps.rs").unwrap();
+    assert!(!out.success());
+}
+
+#[test]
+fn test_expr_fragments() {
+    let out = cargo_script!(
+        "-e",
+        "let x = 6",
+        "-e",
+        "let args: Vec<String> = std::env::args().skip(1).collect();",
+        "--expr",
+        "(x * 7, args)",
+        "an-arg",
+        "-e"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(out.stdout.trim(), r#"(42, ["an-arg", "-e"])"#);
+
+    let out = cargo_script!("-e", "let x = 6", "-e", "x *").unwrap();
+    assert!(!out.success());
+    assert!(out
+        .stderr
+        .contains("put together from 2 `--expr` fragments"));
+
+    let out = cargo_script!("-e", "let x = 6", "-e", "").unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("`--expr` fragment 2 is empty"));
+}
+
+#[test]
+fn test_loop_fold() {
+    // The tests don't give scripts any input, so this is all about what happens at the end of it.
+    let out = cargo_script!("--fold", "0", "-l", "|n, _| *n += 1").unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout.trim(), "0");
+
+    let out = cargo_script!(
+        "--fold",
+        "0",
+        "--finish",
+        "|n: u32| println!(\"{} lines\", n)",
+        "-l",
+        "|n, _| *n += 1"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout.trim(), "0 lines");
+}
+
+#[test]
+fn test_expr_statements() {
+    let out = cargo_script!("-e", with_output_marker!("let x = 5; x * 2 // doubled")).unwrap();
+    scan!(out.stdout_output();
+        ("10") => ()
+    )
+    .unwrap();
+
+    // A trailing semicolon makes the value `()`, which isn't shown.
+    let out = cargo_script!("-e", with_output_marker!("let x = 5; x * 2;")).unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(out.stdout_output().trim(), "");
+}
+
+#[test]
+fn test_expr_unbalanced_braces() {
+    let out = cargo_script!("-e", "1 }; fn main() { 2").unwrap();
+    assert!(!out.success());
+    assert!(
+        out.stderr
+            .contains("the expression has an unmatched `}` at line 1, column 3"),
+        "{}",
+        out.stderr
+    );
+
+    let out = cargo_script!("-e", "if true { 1").unwrap();
+    assert!(!out.success());
+    assert!(
+        out.stderr
+            .contains("the expression has an unclosed `{` at line 1, column 9"),
+        "{}",
+        out.stderr
+    );
+}
+
+#[test]
+fn test_expr_items() {
+    let out = cargo_script!(
+        "-e",
+        with_output_marker!(
+            prelude "fn fib(n: u64) -> u64 {{ if n < 2 {{ n }} else {{ fib(n - 1) + fib(n - 2) }} }}\n";
+            "fib(20)"
+        )
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("6765") => ()
+    )
+    .unwrap();
+
+    // A type defined by the expression can be its value, too.
+    let out = cargo_script!(
+        "-e",
+        with_output_marker!(
+            prelude "#[derive(Debug)] struct Point {{ x: i32, y: i32 }}\n\
+                     fn evens() -> impl Iterator<Item = i32> {{ (0..).step_by(2) }}\n";
+            "let mut e = evens().skip(1); Point {{ x: e.next().unwrap(), y: e.next().unwrap() }}"
+        )
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(out.stdout_output().trim(), "Point { x: 2, y: 4 }");
+}
+
+#[test]
+fn test_expr_items_after_value() {
+    let out = cargo_script!("-e", "fib(20) fn fib(n: u64) -> u64 { n }").unwrap();
+    assert!(!out.success());
+    assert!(
+        out.stderr.contains(
+            "the expression defines an item at line 1, column 9, after its final expression"
+        ),
+        "{}",
+        out.stderr
+    );
+}
+
+#[test]
+fn test_loop_filter() {
+    let out = cargo_script!(
+        #[stdin("apple\nbanana\ncherry\navocado")]
+        "--filter",
+        "-l",
+        "|l| l.starts_with('a')"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(out.stdout, "apple\navocado");
+
+    let out = cargo_script!(
+        #[stdin("apple\n")]
+        "--filter",
+        "-l",
+        "|l| l.len()"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(
+        out.stderr.contains("expected `bool`, found `usize`"),
+        "{}",
+        out.stderr
+    );
+}
+
+#[test]
+fn test_loop_output() {
+    let dir = tempdir::TempDir::new("cargo-script-test-output").unwrap();
+    let path = dir.path().join("sub").join("out.txt");
+    let read = || std::fs::read_to_string(&path).unwrap();
+
+    let out = cargo_script!(
+        #[stdin("a\nbb\n")]
+        "-o",
+        &path,
+        "--count",
+        "-l",
+        "|l, n| println!(\"{}: {}\", n, l.trim())"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(out.stdout, "");
+    assert_eq!(read(), "1: a\n2: bb\n");
+
+    let out = cargo_script!("--output", &path, "--append", "-e", "6 * 7").unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(read(), "1: a\n2: bb\n42\n");
+
+    let out = cargo_script!("--output", &path, "-e", "1 + 1").unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(read(), "2\n");
+}
+
+#[test]
+fn test_expr_file() {
+    use std::fs;
+
+    // Quotes of both kinds are what shells disagree about most; a file doesn't care.
+    let expr = r#"println!("--output--"); format!("it's {} \"quoted\"", 'x')"#;
+    let expected = r#""it's x \"quoted\"""#;
+
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let dir = tempdir::TempDir::new("cargo-script-test-expr").unwrap();
+    let path = dir.path().join("expr.txt");
+    fs::write(&path, format!("{}\n", expr)).unwrap();
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "-E",
+        &path
+    )
+    .unwrap();
+    assert_eq!(out.stdout_output().trim(), expected, "{}", out.stderr);
+
+    // It's the same expression as far as the cache is concerned.
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "-e",
+        expr
+    )
+    .unwrap();
+    assert_eq!(out.stdout_output().trim(), expected, "{}", out.stderr);
+    let pkgs: Vec<_> = fs::read_dir(cargo_home.path().join("script-cache"))
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("expr-"))
+        .collect();
+    assert_eq!(pkgs.len(), 1, "{:?}", pkgs);
+
+    // What Windows PowerShell writes with `>`: UTF-16 with a byte order mark, and CRLFs.
+    let mut utf16 = vec![0xff, 0xfe];
+    for unit in format!("{}\r\n", expr).encode_utf16() {
+        utf16.extend_from_slice(&unit.to_le_bytes());
+    }
+    fs::write(&path, utf16).unwrap();
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--expr-file",
+        &path
+    )
+    .unwrap();
+    assert_eq!(out.stdout_output().trim(), expected, "{}", out.stderr);
+
+    let out = cargo_script!(
+        #[stdin(expr)]
+        "-E",
+        "-"
+    )
+    .unwrap();
+    assert_eq!(out.stdout_output().trim(), expected, "{}", out.stderr);
+
+    fs::write(&path, " \n").unwrap();
+    let out = cargo_script!("-E", &path).unwrap();
+    assert!(!out.success());
+    assert!(
+        out.stderr.contains("expression file is empty"),
+        "{}",
+        out.stderr
+    );
+}
+
+#[test]
+fn test_expr_manifest_from_stdin() {
+    let out = cargo_script!(
+        #[stdin("[package]\nversion = \"1.2.3\"\n")]
+        "--manifest-from-stdin",
+        "-e",
+        with_output_marker!("env!(\"CARGO_PKG_VERSION\")")
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("\"1.2.3\"") => ()
+    )
+    .unwrap();
+
+    let out = cargo_script!(
+        #[stdin("[package")]
+        "--manifest-from-stdin",
+        "-e",
+        "1"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(
+        out.stderr
+            .contains("the manifest from stdin is not valid TOML"),
+        "{}",
+        out.stderr
+    );
+
+    let out = cargo_script!(
+        #[stdin("1")]
+        "--manifest-from-stdin",
+        "--expr-file",
+        "-"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(
+        out.stderr.contains("can't both read stdin"),
+        "{}",
+        out.stderr
+    );
+}
@@ -0,0 +1,52 @@
+use std::fs;
+
+#[test]
+fn test_fmt() {
+    let dir = tempdir::TempDir::new("cargo-script-test-fmt").unwrap();
+    let script = dir.path().join("messy.rs");
+    let header = r#"#!/usr/bin/env run-cargo-script
+//! ```cargo
+//! [dependencies]
+//! boolinator="=0.1.0"
+//! ```
+
+"#;
+    fs::write(
+        &script,
+        format!("{}fn main(){{\nprintln!(\"hi\");}}\n", header),
+    )
+    .unwrap();
+
+    let out = cargo_script!(
+        #[subcommand("fmt")]
+        &script,
+        "--check"
+    )
+    .unwrap();
+    assert!(!out.success());
+    // Depending on the version of rustfmt, this is either `PATH:7:` or `PATH at line 7:`.
+    let diff_in = format!("Diff in {}", script.display());
+    assert!(out.stdout.starts_with(&diff_in));
+    assert!(out.stdout.lines().next().unwrap().ends_with("7:"));
+    assert!(out.stdout.contains("+    println!(\"hi\");"));
+
+    let out = cargo_script!(
+        #[subcommand("fmt")]
+        &script
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(
+        fs::read_to_string(&script).unwrap(),
+        format!("{}fn main() {{\n    println!(\"hi\");\n}}\n", header)
+    );
+
+    let out = cargo_script!(
+        #[subcommand("fmt")]
+        &script,
+        "--check"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(out.stdout, "");
+}
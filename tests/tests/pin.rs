@@ -0,0 +1,156 @@
+use filetime::{set_file_mtime, FileTime};
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn test_pin() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let dir = tempdir::TempDir::new("cargo-script-test-pin").unwrap();
+    let script = |name: &str| {
+        let path = dir.path().join(name);
+        fs::copy("tests/data/script-no-deps.rs", &path).unwrap();
+        path
+    };
+    let (kept, swept, other) = (script("kept.rs"), script("swept.rs"), script("other.rs"));
+    let run = |script: &Path| {
+        let out = cargo_script!(
+            #[cargo_home(cargo_home.path())]
+            script
+        )
+        .unwrap();
+        assert!(out.success(), "{}", out.stderr);
+    };
+    let pin = |subcommand: &str| {
+        cargo_script!(
+            #[subcommand(subcommand)]
+            #[env(CARGO_HOME = cargo_home.path())]
+            &kept
+        )
+        .unwrap()
+    };
+
+    // There's nothing to pin until the script has been run.
+    let out = pin("pin");
+    assert!(!out.success());
+    assert!(
+        out.stderr.contains("has no cache entry to pin"),
+        "{}",
+        out.stderr
+    );
+
+    run(&kept);
+    run(&swept);
+    let out = pin("pin");
+    assert!(out.success(), "{}", out.stderr);
+    // Running it again rewrites the metadata, which mustn't lose the pin.
+    run(&kept);
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--list-cache"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    let listed = |name: &str| {
+        out.stdout
+            .lines()
+            .find(|line| line.contains(name))
+            .unwrap_or_else(|| panic!("{} isn't listed:\n{}", name, out.stdout))
+            .to_owned()
+    };
+    assert!(listed("kept.rs").ends_with("(pinned)"));
+    assert!(!listed("swept.rs").ends_with("(pinned)"));
+
+    // Make both look a few weeks old, then run something else to set off the clean-up.
+    let cache = cargo_home.path().join("script-cache");
+    let entries = || -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(&cache)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_dir())
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    };
+    for name in entries() {
+        let weeks_ago = FileTime::from_unix_time(1_000_000_000, 0);
+        set_file_mtime(cache.join(name).join("metadata.json"), weeks_ago).unwrap();
+    }
+    run(&other);
+    let left = entries();
+    assert!(
+        left.iter().any(|name| name.starts_with("file-kept-")),
+        "{:?}",
+        left
+    );
+    assert!(
+        !left.iter().any(|name| name.starts_with("file-swept-")),
+        "{:?}",
+        left
+    );
+
+    let out = pin("unpin");
+    assert!(out.success(), "{}", out.stderr);
+    let kept_dir = left
+        .iter()
+        .find(|name| name.starts_with("file-kept-"))
+        .unwrap();
+    let meta = fs::read_to_string(cache.join(kept_dir).join("metadata.json")).unwrap();
+    assert!(!meta.contains("\"pinned\":true"), "{}", meta);
+}
+
+#[test]
+fn test_pin_follows_script() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let dir = tempdir::TempDir::new("cargo-script-test-pin").unwrap();
+    let script = dir.path().join("moving.rs");
+    fs::copy("tests/data/script-no-deps.rs", &script).unwrap();
+    let listed = || -> Vec<String> {
+        let out = cargo_script!(
+            #[cargo_home(cargo_home.path())]
+            "--list-cache"
+        )
+        .unwrap();
+        assert!(out.success(), "{}", out.stderr);
+        out.stdout
+            .lines()
+            .filter(|line| line.contains("moving.rs"))
+            .map(Into::into)
+            .collect()
+    };
+
+    // Flags which change the cache ID don't hide the entry from `pin`.
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--rustflags=-Copt-level=1",
+        &script
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    let out = cargo_script!(
+        #[subcommand("pin")]
+        #[env(CARGO_HOME = cargo_home.path())]
+        &script
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    let lines = listed();
+    assert_eq!(lines.len(), 1, "{:?}", lines);
+    assert!(lines[0].ends_with("(pinned)"), "{:?}", lines);
+
+    // Once the script changes, its new entry takes the pin, and the old one can go.
+    let source = fs::read_to_string(&script).unwrap();
+    fs::write(&script, format!("{}// changed\n", source)).unwrap();
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        &script
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    let lines = listed();
+    assert_eq!(lines.len(), 2, "{:?}", lines);
+    let pinned: Vec<_> = lines.iter().filter(|l| l.ends_with("(pinned)")).collect();
+    assert_eq!(pinned.len(), 1, "{:?}", lines);
+    assert!(!pinned[0].contains("-rustflags-"), "{:?}", lines);
+}
@@ -0,0 +1,128 @@
+use std::fs;
+
+#[test]
+fn test_prebuild() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let dir = tempdir::TempDir::new("cargo-script-test-prebuild").unwrap();
+    for (name, source) in &[
+        ("good-a.rs", "tests/data/script-no-deps.rs"),
+        ("good-b.rs", "tests/data/script-no-deps.rs"),
+        ("broken.rs", "tests/data/script-type-error.rs"),
+    ] {
+        fs::copy(source, dir.path().join(name)).unwrap();
+    }
+    let (good_a, good_b) = (dir.path().join("good-a.rs"), dir.path().join("good-b.rs"));
+    let table = |stdout: &str| -> Vec<String> {
+        stdout
+            .lines()
+            .map(|line| match line.find("  ") {
+                // Only the file names are worth comparing; the directory is temporary.
+                Some(i) => format!(
+                    "{} {}",
+                    &line[..i].trim(),
+                    line[i..]
+                        .trim()
+                        .rsplit(|c| c == '/' || c == '\\')
+                        .next()
+                        .unwrap()
+                ),
+                None => line.into(),
+            })
+            .collect()
+    };
+
+    // A broken script doesn't stop the rest from being built.
+    let out = cargo_script!(
+        #[subcommand("prebuild")]
+        #[env(CARGO_HOME = cargo_home.path())]
+        "--jobs",
+        "2",
+        dir.path().join("*.rs")
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(
+        out.stderr.contains("could not build `") && out.stderr.contains("broken.rs"),
+        "{}",
+        out.stderr
+    );
+    assert_eq!(
+        table(&out.stdout),
+        vec![
+            "failed broken.rs",
+            "built good-a.rs",
+            "built good-b.rs",
+            "3 scripts: 2 built, 0 cached, 1 failed",
+        ]
+    );
+
+    // Now they're built, running them doesn't build them again.
+    let out = cargo_script!(
+        #[subcommand("prebuild")]
+        #[env(CARGO_HOME = cargo_home.path())]
+        &good_a,
+        &good_b
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(
+        table(&out.stdout),
+        vec![
+            "cached good-a.rs",
+            "cached good-b.rs",
+            "2 scripts: 0 built, 2 cached, 0 failed",
+        ]
+    );
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--dry-run",
+        &good_a
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(out.stdout.contains("cache: hit"), "{}", out.stdout);
+
+    let out = cargo_script!(
+        #[subcommand("prebuild")]
+        #[env(CARGO_HOME = cargo_home.path())]
+        "--force",
+        &good_a
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(
+        table(&out.stdout),
+        vec!["built good-a.rs", "1 script: 1 built, 0 cached, 0 failed"]
+    );
+
+    // Without anything from Cargo to show, what went wrong is shown instead.
+    let out = cargo_script!(
+        #[subcommand("prebuild")]
+        #[env(CARGO_HOME = cargo_home.path())]
+        dir.path().join("missing.rs")
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(
+        out.stderr.contains("missing.rs`: could not find script"),
+        "{}",
+        out.stderr
+    );
+    assert_eq!(
+        table(&out.stdout),
+        vec!["failed missing.rs", "1 script: 0 built, 0 cached, 1 failed"]
+    );
+
+    let out = cargo_script!(
+        #[subcommand("prebuild")]
+        #[env(CARGO_HOME = cargo_home.path())]
+        dir.path().join("*.crs")
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(
+        out.stderr.contains("doesn't match any scripts"),
+        "{}",
+        out.stderr
+    );
+}
@@ -0,0 +1,56 @@
+#[cfg(unix)]
+#[test]
+fn test_quote_sh() {
+    let args = [
+        "-e",
+        "println!(\"it's $HOME\")",
+        "--",
+        "",
+        "50%",
+        "two\nlines",
+    ];
+    let out = cargo_script!(
+        #[subcommand("quote")]
+        #[env()]
+        "--shell",
+        "sh",
+        "--",
+        args[0],
+        args[1],
+        args[2],
+        args[3],
+        args[4],
+        args[5]
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    let line = out.stdout.trim_end_matches('\n');
+    assert!(line.starts_with("cargo script -e "), "{}", line);
+
+    // Have sh unquote it again, by standing in for `cargo script`.
+    let echo = line.replacen("cargo script", "printf '[%s]'", 1);
+    let out = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(&echo)
+        .output()
+        .unwrap();
+    let expected = args
+        .iter()
+        .fold(String::new(), |s, arg| s + "[" + arg + "]");
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), expected, "{}", echo);
+}
+
+#[test]
+fn test_quote_cmd_line_break() {
+    let out = cargo_script!(
+        #[subcommand("quote")]
+        #[env()]
+        "--shell",
+        "cmd",
+        "--",
+        "two\nlines"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("line break"), "{}", out.stderr);
+}
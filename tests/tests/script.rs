@@ -28,6 +28,33 @@ fn test_script_full_block() {
     .unwrap()
 }
 
+#[test]
+fn test_script_target_deps() {
+    let out = cargo_script!("tests/data/script-target-deps.rs").unwrap();
+    scan!(out.stdout_output();
+        ("Some(1)") => ()
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_script_build_file() {
+    let out = cargo_script!("tests/data/build-script/script-build-file.rs").unwrap();
+    scan!(out.stdout_output();
+        ("42") => ()
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_script_build_block() {
+    let out = cargo_script!("tests/data/build-script/script-build-block.rs").unwrap();
+    scan!(out.stdout_output();
+        ("42") => ()
+    )
+    .unwrap()
+}
+
 #[test]
 fn test_script_full_line() {
     let out = cargo_script!("tests/data/script-full-line.rs").unwrap();
@@ -70,6 +97,62 @@ fn test_script_test() {
     assert!(out.success());
 }
 
+#[test]
+fn test_script_test_harness_args() {
+    let script = "tests/data/script-test-harness.rs";
+
+    let out = cargo_script!("--test", script).unwrap();
+    assert_eq!(out.status.code(), Some(101));
+    assert!(out.stdout.contains("test test_passes ... ok"));
+    assert!(out.stdout.contains("test test_fails ... FAILED"));
+    assert!(out.stdout.contains("test test_ignored ... ignored"));
+
+    let out = cargo_script!("--test", script, "test_passes", "--", "--nocapture").unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(out.stdout.contains("printed by test_passes"));
+    assert!(!out.stdout.contains("test_fails"));
+
+    // The first `--` only gets past `cargo-script`'s own options.
+    let out = cargo_script!("--test", script, "--", "--", "--ignored").unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(out.stdout.contains("test test_ignored ... ok"));
+    assert!(!out.stdout.contains("test_passes"));
+
+    let out = cargo_script!("--test", "--list", script).unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(out.stdout.contains("test_passes: test"));
+    assert!(out.stdout.contains("test_fails: test"));
+    assert!(!out.stdout.contains("... ok"));
+
+    let out = cargo_script!("--test", "--release", script, "test_passes").unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(out.stderr.contains("release"), "{}", out.stderr);
+
+    let out = cargo_script!("--list", script).unwrap();
+    assert!(!out.success());
+    assert!(out
+        .stderr
+        .contains("`--list` can only be used with `--test`"));
+}
+
+#[test]
+fn test_script_bench_criterion() {
+    let pkg_dir = tempdir::TempDir::new("cargo-script-test-criterion").unwrap();
+    let out = cargo_script!(
+        #[pkg_path(pkg_dir.path())]
+        "--bench",
+        "tests/data/script-criterion.rs",
+        "sum"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(out.stdout.contains("sum"));
+    assert!(out.stdout.contains("time:"));
+    assert!(!out.stdout.contains("product"));
+    // The results are kept with the script's package.
+    assert!(pkg_dir.path().join("criterion").join("sum").is_dir());
+}
+
 #[test]
 fn test_script_hyphens() {
     use scan_rules::scanner::QuotedString;
@@ -82,6 +165,29 @@ fn test_script_hyphens() {
     .unwrap()
 }
 
+#[test]
+fn test_script_args_separator() {
+    use scan_rules::scanner::QuotedString;
+
+    let out = cargo_script!("tests/data/script-args.rs", "--", "--", "-a").unwrap();
+    scan!(out.stdout_output();
+        ("[0]:", let _: QuotedString, "[1]:", let a1: QuotedString, "[2]:", let a2: QuotedString) => {
+            assert_eq!((&*a1, &*a2), ("--", "-a"));
+        }
+    )
+    .unwrap();
+
+    let out = cargo_script!("tests/data/script-args.rs", "sub", "--", "-a", "--").unwrap();
+    scan!(out.stdout_output();
+        ("[0]:", let _: QuotedString,
+            "[1]:", let a1: QuotedString, "[2]:", let a2: QuotedString,
+            "[3]:", let a3: QuotedString, "[4]:", let a4: QuotedString) => {
+            assert_eq!((&*a1, &*a2, &*a3, &*a4), ("sub", "--", "-a", "--"));
+        }
+    )
+    .unwrap()
+}
+
 #[test]
 fn test_script_has_weird_chars() {
     let out = cargo_script!("tests/data/script-has.weird§chars!.rs").unwrap();
@@ -121,3 +227,2454 @@ fn test_script_2018_edition() {
     )
     .unwrap()
 }
+
+#[test]
+fn test_script_manifest_key() {
+    // `use boolinator::..` only works from the 2018 edition on.
+    let out = cargo_script!(
+        "--manifest-key",
+        "package.edition=2015",
+        "tests/data/script-2018-edition.rs"
+    )
+    .unwrap();
+    assert!(!out.success());
+
+    let out = cargo_script!(
+        "--manifest-key",
+        "package.edition=2015",
+        "--manifest-key",
+        "package.edition=2021",
+        "tests/data/script-2018-edition.rs"
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("Some(1)") => ()
+    )
+    .unwrap();
+
+    let out = cargo_script!(
+        "--manifest-key",
+        "package=2015",
+        "tests/data/script-2018-edition.rs"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("only sections can go at the top level"));
+}
+
+#[test]
+fn test_script_multi_bin() {
+    let script = "tests/data/multi-bin/script-multi-bin.rs";
+    let out = cargo_script!(script, "x").unwrap();
+    scan!(out.stdout_output();
+        ("main: [\"x\"]") => ()
+    )
+    .unwrap();
+
+    let out = cargo_script!("--bin", "helper", script, "x").unwrap();
+    scan!(out.stdout_output();
+        ("helper: [\"x\"]") => ()
+    )
+    .unwrap();
+
+    let out = cargo_script!("--bin", "nope", script).unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains(
+        "there is no binary target named `nope`; the script has `script-multi-bin`, `helper`"
+    ));
+}
+
+#[test]
+fn test_script_color_diagnostics_to_file() {
+    use std::fs;
+
+    let temp_dir = tempdir::TempDir::new("cargo-script-test-diagnostics").unwrap();
+    let log_path = temp_dir.path().join("build.log");
+    let out = cargo_script!(
+        "--color-diagnostics-to-file",
+        &log_path,
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("Hello, World!") => ()
+    )
+    .unwrap();
+    assert!(!out.stderr.contains("Compiling"));
+    let log = fs::read_to_string(&log_path).unwrap();
+    assert!(log.contains("Compiling"));
+    assert!(log.contains('\x1b'));
+
+    let out = cargo_script!(
+        "--color-diagnostics-to-file",
+        &log_path,
+        "-e",
+        "swing begin"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("for what it said"));
+    assert!(!out.stderr.contains("swing"));
+    assert!(fs::read_to_string(&log_path).unwrap().contains("swing"));
+}
+
+#[test]
+fn test_script_env_file() {
+    let out = cargo_script!(
+        "--env-file",
+        "tests/data/script-env.env",
+        "--env",
+        "CS_TEST_OVERRIDE=from flag",
+        "tests/data/script-env.rs"
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("Ok") => ()
+    )
+    .unwrap();
+
+    let out = cargo_script!(
+        "--env-file",
+        "tests/data/missing.env",
+        "tests/data/script-env.rs"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("could not read environment file"));
+}
+
+#[test]
+fn test_script_force_child_color() {
+    let expr = with_output_marker!(r#"std::env::var("CLICOLOR_FORCE").ok()"#);
+    let out = cargo_script!("--force-child-color", "-e", &expr).unwrap();
+    assert_eq!(out.stdout_output().trim(), r#"Some("1")"#, "{}", out.stderr);
+
+    // An explicit `--env` still wins.
+    let out = cargo_script!(
+        "--force-child-color",
+        "--env",
+        "CLICOLOR_FORCE=0",
+        "-e",
+        &expr
+    )
+    .unwrap();
+    assert_eq!(out.stdout_output().trim(), r#"Some("0")"#, "{}", out.stderr);
+}
+
+#[test]
+fn test_script_named_after_dep() {
+    let out = cargo_script!("tests/data/boolinator.rs").unwrap();
+    scan!(out.stdout_output();
+        ("Some(1)", "boolinator-script") => ()
+    )
+    .unwrap();
+    assert!(out
+        .stderr
+        .contains("warning: package name `boolinator` collides"));
+
+    let out = cargo_script!("--name", "not-boolinator", "tests/data/boolinator.rs").unwrap();
+    scan!(out.stdout_output();
+        ("Some(1)", "not-boolinator") => ()
+    )
+    .unwrap();
+    assert!(!out.stderr.contains("warning:"));
+
+    let out = cargo_script!("--deny-warnings", "tests/data/boolinator.rs").unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("--deny-warnings"));
+}
+
+#[test]
+fn test_script_check() {
+    let out = cargo_script!("--check", "tests/data/script-no-deps.rs").unwrap();
+    assert!(out.success());
+    assert!(!out.stdout.contains("--output--"));
+}
+
+#[test]
+fn test_script_renamed_bin() {
+    let out = cargo_script!("tests/data/script-renamed-bin.rs").unwrap();
+    scan!(out.stdout_output();
+        ("Ok") => ()
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_script_marks_cache_dir() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let out = cargo_script!(
+        #[env(CARGO_HOME = cargo_home.path())]
+        "--use-shared-binary-cache=yes",
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(out.success());
+    let bincache_path = cargo_home.path().join("binary-cache");
+    assert!(bincache_path.join(".gitignore").is_file());
+    assert!(bincache_path.join("CACHEDIR.TAG").is_file());
+}
+
+#[test]
+fn test_script_run_as_test_module() {
+    let out = cargo_script!("--run-as-test-module", "tests/data/script-test-module.rs").unwrap();
+    assert!(out.success());
+    assert!(out.stdout.contains("test_double ... ok"));
+    assert!(out.stdout.contains("test_double_negative ... ok"));
+}
+
+#[test]
+fn test_script_suggest_test_module() {
+    let out = cargo_script!("tests/data/script-test-module.rs").unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("--run-as-test-module"));
+}
+
+#[test]
+fn test_script_in_workspace() {
+    use std::fs;
+
+    let ws_dir = tempdir::TempDir::new("cargo-script-test-ws").unwrap();
+    fs::write(
+        ws_dir.path().join("Cargo.toml"),
+        "[workspace]\nmembers = [\"member\"]\n",
+    )
+    .unwrap();
+    let script_dir = ws_dir.path().join("scripts");
+    fs::create_dir(&script_dir).unwrap();
+    let script_path = script_dir.join("ws-script.rs");
+    fs::copy("tests/data/script-no-deps.rs", &script_path).unwrap();
+
+    let out = cargo_script!(&script_path).unwrap();
+    scan!(out.stdout_output();
+        ("Hello, World!") => ()
+    )
+    .unwrap();
+    assert!(out.stderr.contains("will be built as a standalone package"));
+}
+
+#[test]
+fn test_script_cargo_config() {
+    use std::fs;
+
+    let dir = tempdir::TempDir::new("cargo-script-test-config").unwrap();
+    fs::create_dir(dir.path().join(".cargo")).unwrap();
+    fs::write(
+        dir.path().join(".cargo").join("config.toml"),
+        "[build]\ntarget-dir = \"elsewhere\"\n\n[env]\nCS_TEST_CONFIG_ENV = \"from config\"\n",
+    )
+    .unwrap();
+    let script_dir = dir.path().join("scripts");
+    fs::create_dir(&script_dir).unwrap();
+    let script_path = script_dir.join("config-env.rs");
+    fs::copy("tests/data/script-config-env.rs", &script_path).unwrap();
+
+    let pkg_dir = tempdir::TempDir::new("cargo-script-test-pkg").unwrap();
+    let out = cargo_script!(
+        #[pkg_path(pkg_dir.path())]
+        "--verbose",
+        &script_path
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(
+        out.stdout_output().trim(),
+        "build: Some(\"from config\")\nrun: Some(\"from config\")"
+    );
+    assert!(out.stderr.contains("note: using Cargo configuration from"));
+    assert!(out.stderr.contains("ignoring `build.target-dir`"));
+    assert!(pkg_dir.path().join("target").is_dir());
+    assert!(!dir.path().join("elsewhere").exists());
+}
+
+#[test]
+fn test_script_include() {
+    use std::fs;
+
+    let out = cargo_script!("tests/data/script-include.rs").unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(out.stdout_output().trim(), "HELLO, WORLD!");
+
+    // Changing an included file has to rebuild the script, even though the script itself is the same.
+    let dir = tempdir::TempDir::new("cargo-script-test-include").unwrap();
+    let script_path = dir.path().join("include.rs");
+    fs::write(&script_path, "//# include helper.rs\nfn main() {\n    println!(\"--output--\");\n    println!(\"{}\", helper());\n}\n").unwrap();
+    let helper_path = dir.path().join("helper.rs");
+    fs::write(&helper_path, "fn helper() -> &'static str { \"first\" }\n").unwrap();
+    let pkg_dir = tempdir::TempDir::new("cargo-script-test-pkg").unwrap();
+    let out = cargo_script!(
+        #[pkg_path(pkg_dir.path())]
+        &script_path
+    )
+    .unwrap();
+    assert_eq!(out.stdout_output().trim(), "first", "{}", out.stderr);
+    fs::write(&helper_path, "fn helper() -> &'static str { \"second\" }\n").unwrap();
+    let out = cargo_script!(
+        #[pkg_path(pkg_dir.path())]
+        "--verbose",
+        &script_path
+    )
+    .unwrap();
+    assert_eq!(out.stdout_output().trim(), "second", "{}", out.stderr);
+    assert!(
+        out.stderr
+            .contains("files pulled in with `//# include` changed"),
+        "{}",
+        out.stderr
+    );
+
+    fs::write(&helper_path, "//# include include.rs\n").unwrap();
+    let out = cargo_script!(&script_path).unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("`//# include` cycle"), "{}", out.stderr);
+}
+
+#[test]
+fn test_script_crs_modules() {
+    let out = cargo_script!("tests/data/crs-tree/tree").unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(out.stdout_output().trim(), "square of area 9");
+
+    let out = cargo_script!(
+        #[subcommand("which")]
+        "tests/data/crs-tree/tree"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(
+        out.stdout
+            .lines()
+            .next()
+            .unwrap()
+            .ends_with("tree.crs (exists)"),
+        "{}",
+        out.stdout
+    );
+}
+
+#[test]
+fn test_script_bundle() {
+    use std::fs;
+
+    let out = cargo_script!("tests/data/bundle").unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(out.stdout_output().trim(), "HELLO, BUNDLE!");
+
+    let out = cargo_script!(
+        #[subcommand("which")]
+        "tests/data/bundle/"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(
+        out.stdout
+            .lines()
+            .next()
+            .unwrap()
+            .ends_with("main.crs (exists)"),
+        "{}",
+        out.stdout
+    );
+
+    // Changing any file in the bundle has to rebuild it, not just changing the main file.
+    let dir = tempdir::TempDir::new("cargo-script-test-bundle").unwrap();
+    let bundle = dir.path().join("tool");
+    fs::create_dir(&bundle).unwrap();
+    fs::write(
+        bundle.join("tool.rs"),
+        "fn main() {\n    println!(\"--output--\");\n    println!(\"{}\", include_str!(\"data/name.txt\").trim());\n}\n",
+    )
+    .unwrap();
+    fs::create_dir(bundle.join("data")).unwrap();
+    fs::write(bundle.join("data/name.txt"), "first\n").unwrap();
+    let pkg_dir = tempdir::TempDir::new("cargo-script-test-pkg").unwrap();
+    let out = cargo_script!(
+        #[pkg_path(pkg_dir.path())]
+        &bundle
+    )
+    .unwrap();
+    assert_eq!(out.stdout_output().trim(), "first", "{}", out.stderr);
+    fs::write(bundle.join("data/name.txt"), "second\n").unwrap();
+    let out = cargo_script!(
+        #[pkg_path(pkg_dir.path())]
+        &bundle
+    )
+    .unwrap();
+    assert_eq!(out.stdout_output().trim(), "second", "{}", out.stderr);
+}
+
+#[test]
+fn test_script_corrupt_metadata() {
+    use std::fs;
+
+    let pkg_dir = tempdir::TempDir::new("cargo-script-test-pkg").unwrap();
+    let meta_path = pkg_dir.path().join("metadata.json");
+
+    let out = cargo_script!(
+        #[pkg_path(pkg_dir.path())]
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert!(meta_path.is_file());
+
+    let corruptions: &[&[u8]] = &[
+        b"",
+        b"{\"path\": ",
+        b"\xff\xfe\x00",
+        b"{\"path\": 42}",
+        b"[\"not\", \"metadata\"]",
+    ];
+    for corruption in corruptions {
+        fs::write(&meta_path, corruption).unwrap();
+        let out = cargo_script!(
+            #[pkg_path(pkg_dir.path())]
+            "tests/data/script-no-deps.rs"
+        )
+        .unwrap();
+        scan!(out.stdout_output();
+            ("Hello, World!") => ()
+        )
+        .unwrap();
+    }
+
+    // A package with good metadata but no source should be regenerated, too.
+    fs::remove_file(pkg_dir.path().join("script-no-deps.rs")).unwrap();
+    fs::remove_file(pkg_dir.path().join("target.exe_path")).unwrap();
+    let out = cargo_script!(
+        #[pkg_path(pkg_dir.path())]
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("Hello, World!") => ()
+    )
+    .unwrap();
+}
+
+#[cfg(windows)]
+#[test]
+fn test_script_long_path() {
+    use std::fs;
+
+    let temp_dir = tempdir::TempDir::new("cargo-script-test-long").unwrap();
+    let mut dir = temp_dir.path().canonicalize().unwrap();
+    while dir.as_os_str().len() < 300 {
+        dir.push("a-rather-long-directory-name");
+    }
+    fs::create_dir_all(&dir).unwrap();
+    let script_path = dir.join("long-path.rs");
+    fs::copy("tests/data/script-no-deps.rs", &script_path).unwrap();
+
+    let out = cargo_script!(&script_path).unwrap();
+    scan!(out.stdout_output();
+        ("Hello, World!") => ()
+    )
+    .unwrap();
+}
+
+#[cfg(windows)]
+#[test]
+fn test_script_unc_path() {
+    use std::fs;
+    use std::path::PathBuf;
+
+    let temp_dir = tempdir::TempDir::new("cargo-script-test-unc").unwrap();
+    let script_path = temp_dir.path().join("unc-path.rs");
+    fs::copy("tests/data/script-no-deps.rs", &script_path).unwrap();
+
+    // Reach the same file through the administrative share for its drive.
+    let script_path = script_path.to_str().unwrap();
+    let (drive, rest) = script_path.split_at(2);
+    let unc_path = PathBuf::from(format!(
+        r"\\localhost\{}${}",
+        drive.trim_end_matches(':'),
+        rest
+    ));
+    if !unc_path.exists() {
+        println!("skipping: {:?} is not accessible", unc_path);
+        return;
+    }
+
+    let out = cargo_script!(&unc_path).unwrap();
+    scan!(out.stdout_output();
+        ("Hello, World!") => ()
+    )
+    .unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_script_non_utf8() {
+    use std::ffi::OsStr;
+    use std::fs;
+    use std::os::unix::ffi::OsStrExt;
+
+    let temp_dir = tempdir::TempDir::new("cargo-script-test-non-utf8").unwrap();
+    let dir = temp_dir.path().join(OsStr::from_bytes(b"non-utf8-\xff"));
+    fs::create_dir(&dir).unwrap();
+    let script_path = dir.join("script-args-os.rs");
+    fs::copy("tests/data/script-args-os.rs", &script_path).unwrap();
+
+    let out = cargo_script!(&script_path, "caf\u{e9}", OsStr::from_bytes(b"caf\xe9")).unwrap();
+    let output = out.stdout_output();
+    assert!(output.contains("[1]: \"caf\u{e9}\""));
+    assert!(output.contains("[2]: \"caf\\xE9\""));
+}
+
+#[test]
+fn test_script_no_args() {
+    let out = cargo_script!().unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("<script>"));
+    assert!(out.stderr.contains("USAGE:\n    cargo script "));
+}
+
+#[test]
+fn test_script_mtime_skew() {
+    use filetime::{set_file_mtime, FileTime};
+    use std::fs;
+
+    let temp_dir = tempdir::TempDir::new("cargo-script-test-mtime").unwrap();
+    let pkg_dir = temp_dir.path().join("pkg");
+    let script_path = temp_dir.path().join("skewed.rs");
+    let write_script = |msg: &str| {
+        let src = format!(
+            "fn main() {{\n    println!(\"--output--\");\n    println!(\"{}\");\n}}\n",
+            msg
+        );
+        fs::write(&script_path, src).unwrap();
+    };
+
+    macro_rules! run {
+        ($output:expr, rebuild: $rebuild:expr) => {{
+            let out = cargo_script!(
+                #[pkg_path(&pkg_dir)]
+                #[env(RUST_LOG = "cargo_script=info")]
+                &script_path
+            )
+            .unwrap();
+            assert_eq!(out.stdout_output().trim(), $output);
+            assert_eq!(out.stderr.contains("recompiling because"), $rebuild);
+        }};
+    }
+
+    write_script("first");
+    run!("first", rebuild: true);
+    run!("first", rebuild: false);
+
+    // Neither a future-dated nor a zero timestamp should cause a rebuild, let alone one on every run.
+    set_file_mtime(&script_path, FileTime::from_unix_time(4_102_444_800, 0)).unwrap();
+    run!("first", rebuild: false);
+    run!("first", rebuild: false);
+    set_file_mtime(&script_path, FileTime::zero()).unwrap();
+    run!("first", rebuild: false);
+
+    // An edit has to be picked up, even if the timestamp doesn't move.
+    write_script("second");
+    set_file_mtime(&script_path, FileTime::zero()).unwrap();
+    run!("second", rebuild: true);
+    run!("second", rebuild: false);
+}
+
+#[test]
+fn test_script_build_file_changed() {
+    use std::fs;
+
+    let temp_dir = tempdir::TempDir::new("cargo-script-test-build").unwrap();
+    let pkg_dir = temp_dir.path().join("pkg");
+    let data_dir = std::path::Path::new("tests/data/build-script");
+    for name in &["script-build-file.rs", "build.rs"] {
+        fs::copy(data_dir.join(name), temp_dir.path().join(name)).unwrap();
+    }
+    let script_path = temp_dir.path().join("script-build-file.rs");
+    let write_shim = |answer: i32| {
+        let src = format!("int shim_answer(void) {{\n    return {};\n}}\n", answer);
+        fs::write(temp_dir.path().join("shim.c"), src).unwrap();
+    };
+
+    macro_rules! run {
+        ($output:expr, rebuild: $rebuild:expr) => {{
+            let out = cargo_script!(
+                #[pkg_path(&pkg_dir)]
+                #[env(RUST_LOG = "cargo_script=info")]
+                &script_path
+            )
+            .unwrap();
+            assert_eq!(out.stdout_output().trim(), $output);
+            assert_eq!(out.stderr.contains("recompiling because"), $rebuild);
+        }};
+    }
+
+    write_shim(1);
+    run!("1", rebuild: true);
+    run!("1", rebuild: false);
+
+    // The script hasn't changed, but a file its build script uses has.
+    write_shim(2);
+    run!("2", rebuild: true);
+    run!("2", rebuild: false);
+}
+
+#[test]
+fn test_script_timings() {
+    let out = cargo_script!("--timings", "tests/data/script-sleep.rs").unwrap();
+    scan!(out.stdout_output();
+        ("Slept.") => ()
+    )
+    .unwrap();
+
+    let summary = out
+        .stderr
+        .lines()
+        .find(|l| l.starts_with("timings: "))
+        .expect("no timings summary");
+    let phase_ms = |phase: &str| -> f64 {
+        let start = summary.find(&format!(" {} ", phase)).expect(phase) + phase.len() + 2;
+        let len = summary[start..].find("ms").unwrap();
+        summary[start..start + len].parse().unwrap()
+    };
+    for phase in &[
+        "args", "read", "cache", "manifest", "cargo", "run", "gc", "total",
+    ] {
+        phase_ms(phase);
+    }
+    let run = phase_ms("run");
+    assert!((200.0..2000.0).contains(&run), "run took {}ms", run);
+    assert!(run <= phase_ms("total"));
+
+    let out = cargo_script!(
+        "--timings",
+        "--message-format",
+        "json",
+        "tests/data/script-sleep.rs"
+    )
+    .unwrap();
+    assert!(out.success());
+    let json = out.stderr.lines().last().unwrap();
+    assert!(json.starts_with("{\"args\":"), "{:?}", json);
+    assert!(json.contains(",\"run\":"));
+    assert!(json.ends_with('}'));
+}
+
+#[test]
+fn test_script_content_addressed() {
+    use std::fs;
+
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let scripts = tempdir::TempDir::new("cargo-script-test-scripts").unwrap();
+    let mut paths = vec![];
+    for dir in &["a", "b"] {
+        let dir = scripts.path().join(dir);
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("same.rs");
+        fs::copy("tests/data/script-no-deps.rs", &path).unwrap();
+        paths.push(path);
+    }
+    let cache_entries = || {
+        let mut names: Vec<_> = fs::read_dir(cargo_home.path().join("script-cache"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|n| n.starts_with("content-") || n.starts_with("file-"))
+            .collect();
+        names.sort();
+        names
+    };
+
+    for path in &paths {
+        let out = cargo_script!(
+            #[cargo_home(cargo_home.path())]
+            #[env(RUST_LOG = "cargo_script=info")]
+            "--content-addressed",
+            path
+        )
+        .unwrap();
+        scan!(out.stdout_output();
+            ("Hello, World!") => ()
+        )
+        .unwrap();
+        // Only the first should have needed building.
+        assert_eq!(
+            out.stderr.contains("recompiling because"),
+            path == &paths[0]
+        );
+    }
+    let entries = cache_entries();
+    assert_eq!(entries.len(), 1, "{:?}", entries);
+    assert!(entries[0].starts_with("content-same-"));
+
+    // Different build flags mean a different package.
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--content-addressed",
+        "--debug",
+        &paths[1]
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(cache_entries().len(), 2);
+
+    // Without the flag, scripts are still cached by location.
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        &paths[0]
+    )
+    .unwrap();
+    assert!(out.success());
+    let entries = cache_entries();
+    assert_eq!(entries.len(), 3);
+    assert!(entries[2].starts_with("file-same-"));
+}
+
+#[test]
+fn test_script_manifest_defaults() {
+    let out = cargo_script!("tests/data/script-manifest-release.rs").unwrap();
+    scan!(out.stdout_output();
+        ("debug: false") => ()
+    )
+    .unwrap();
+
+    let out = cargo_script!("tests/data/script-manifest-debug.rs").unwrap();
+    scan!(out.stdout_output();
+        ("debug: true", "dont-panic: true") => ()
+    )
+    .unwrap();
+
+    // Anything given on the command line wins.
+    let out = cargo_script!("--debug", "tests/data/script-manifest-release.rs").unwrap();
+    scan!(out.stdout_output();
+        ("debug: true") => ()
+    )
+    .unwrap();
+
+    let out = cargo_script!(
+        "--release",
+        "--features",
+        "",
+        "tests/data/script-manifest-debug.rs"
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("debug: false", "dont-panic: false") => ()
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_script_registry() {
+    use std::fs;
+
+    let out = cargo_script!("tests/data/script-registry.rs").unwrap();
+    assert!(!out.success());
+
+    let out = cargo_script!(
+        "--verbose",
+        "--config",
+        "tests/data/registry/.cargo/config.toml",
+        "tests/data/script-registry.rs"
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("Hello from the company registry.") => ()
+    )
+    .unwrap();
+    assert!(out
+        .stderr
+        .contains("note: passing `--config tests/data/registry/.cargo/config.toml` on to Cargo"));
+
+    // Registries defined in the user's Cargo configuration work too, and survive overriding the dependency's version.
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let vendor = fs::canonicalize("tests/data/registry/vendor").unwrap();
+    let config = fs::read_to_string("tests/data/registry/.cargo/config.toml")
+        .unwrap()
+        .replace(
+            "directory = \"vendor\"",
+            &format!("directory = {:?}", vendor.to_str().unwrap()),
+        );
+    fs::write(cargo_home.path().join("config.toml"), config).unwrap();
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--dep",
+        "greet=0.1.0",
+        "tests/data/script-registry.rs"
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("Hello from the company registry.") => ()
+    )
+    .unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_script_runner() {
+    let out = cargo_script!(
+        "--runner",
+        "env CARGO_SCRIPT_TEST_RUNNER=yes",
+        "tests/data/script-runner.rs",
+        "a",
+        "--b"
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("runner: yes", "args: [\"a\", \"--b\"]") => ()
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_script_sandbox() {
+    let out = cargo_script!(
+        "--sandbox=env CARGO_SCRIPT_TEST_SANDBOX=yes",
+        "tests/data/script-sandbox.rs",
+        "a"
+    )
+    .unwrap();
+    let out = out.stdout_output();
+    let lines: Vec<&str> = out.trim().lines().collect();
+    assert_eq!(&lines[1..], ["sandbox: yes", "args: [\"a\"]"], "{}", out);
+
+    // The sandbox's directory shouldn't outlive the run.
+    let dir = lines[0].trim_start_matches("dir: ");
+    assert!(
+        !dir.is_empty() && !std::path::Path::new(dir).exists(),
+        "{}",
+        dir
+    );
+
+    let out = cargo_script!(
+        #[env(CARGO_SCRIPT_SANDBOX = "env CARGO_SCRIPT_TEST_SANDBOX=from-env")]
+        "--sandbox",
+        "tests/data/script-sandbox.rs"
+    )
+    .unwrap();
+    assert!(out.stdout_output().contains("sandbox: from-env"));
+}
+
+#[test]
+fn test_script_no_cache() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--no-cache",
+        "tests/data/script-runner.rs",
+        "a"
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("runner: ", "args: [\"a\"]") => ()
+    )
+    .unwrap();
+
+    let cache = cargo_home.path().join("script-cache");
+    let cached = std::fs::read_dir(&cache).map(|d| d.count()).unwrap_or(0);
+    assert_eq!(cached, 0);
+}
+
+#[test]
+fn test_script_ephemeral() {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    fn snapshot(dir: &Path, files: &mut Vec<(PathBuf, Vec<u8>)>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            match path.is_dir() {
+                true => snapshot(&path, files),
+                false => files.push((path.clone(), fs::read(&path).unwrap())),
+            }
+        }
+        files.sort();
+    }
+
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    let mut before = vec![];
+    snapshot(cargo_home.path(), &mut before);
+    assert!(!before.is_empty());
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--ephemeral",
+        "tests/data/script-runner.rs",
+        "a"
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("runner: ", "args: [\"a\"]") => ()
+    )
+    .unwrap();
+    let mut after = vec![];
+    snapshot(cargo_home.path(), &mut after);
+    assert!(before == after, "the cache changed");
+}
+
+#[test]
+fn test_script_report() {
+    use std::fs;
+
+    let dir = tempdir::TempDir::new("cargo-script-test-report").unwrap();
+    let report_path = dir.path().join("report.json");
+    let pkg_dir = dir.path().join("pkg");
+    let run = |append: bool| {
+        let append = if append {
+            "--append-report"
+        } else {
+            "--verbose"
+        };
+        cargo_script!(
+            #[pkg_path(&pkg_dir)]
+            "--report",
+            &report_path,
+            append,
+            "tests/data/script-no-deps.rs"
+        )
+        .unwrap()
+    };
+
+    let out = run(false);
+    assert!(out.success(), "{}", out.stderr);
+    assert!(
+        out.stderr.contains("note: release executable is "),
+        "{}",
+        out.stderr
+    );
+    let out = run(true);
+    assert!(out.success(), "{}", out.stderr);
+    let report = fs::read_to_string(&report_path).unwrap();
+    let lines: Vec<_> = report.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", report);
+    for line in &lines {
+        assert!(line.contains("script-no-deps.rs\""), "{}", line);
+        assert!(line.contains("\"exit_code\":0,\"error\":null,"), "{}", line);
+        assert!(line.contains("\"toolchain\":\"rustc "), "{}", line);
+        assert!(line.contains("\"edition\":\"2015\""), "{}", line);
+        assert!(!line.contains("\"run_ms\":null"), "{}", line);
+    }
+    assert!(lines[0].contains("\"cache_hit\":false"), "{}", lines[0]);
+    assert!(!lines[0].contains("\"build_ms\":null"), "{}", lines[0]);
+    assert!(
+        lines[1].contains("\"cache_hit\":true,\"build_ms\":null,"),
+        "{}",
+        lines[1]
+    );
+    for line in &lines {
+        assert!(line.contains("\"debug_exe_bytes\":null,"), "{}", line);
+        assert!(!line.contains("\"release_exe_bytes\":null"), "{}", line);
+    }
+
+    // Once there's a debug build as well, both sizes are given.
+    let out = cargo_script!(
+        #[pkg_path(&pkg_dir)]
+        "--report",
+        &report_path,
+        "--debug",
+        "--verbose",
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(
+        out.stderr.contains("note: debug executable is ")
+            && out.stderr.contains("note: release executable is "),
+        "{}",
+        out.stderr
+    );
+    let report = fs::read_to_string(&report_path).unwrap();
+    assert!(!report.contains("_exe_bytes\":null"), "{}", report);
+
+    // Without `--append-report`, the file is replaced, and failures are reported too.
+    let out = cargo_script!("--report", &report_path, "-e", "std::process::exit(3)").unwrap();
+    assert_eq!(out.status.code(), Some(3));
+    let report = fs::read_to_string(&report_path).unwrap();
+    assert_eq!(report.lines().count(), 1, "{}", report);
+    assert!(report.contains("\"script\":null,"), "{}", report);
+    assert!(report.contains("\"exit_code\":3,"), "{}", report);
+}
+
+#[test]
+fn test_script_trace() {
+    let dir = tempdir::TempDir::new("cargo-script-test-trace").unwrap();
+    let pkg_dir = dir.path().join("pkg");
+    let out = cargo_script!(
+        #[pkg_path(&pkg_dir)]
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+
+    let out = cargo_script!(
+        #[pkg_path(&pkg_dir)]
+        "--trace",
+        "--force",
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(out.stdout_output().trim(), "Hello, World!");
+    for expected in &[
+        "recompiling because: forced with `--force`",
+        r#"running "cargo" with arguments ["build""#,
+        "Cargo.toml",
+        "metadata.json",
+        "digest of ",
+    ] {
+        assert!(
+            out.stderr.contains(expected),
+            "{:?} not in {}",
+            expected,
+            out.stderr
+        );
+    }
+    assert!(!out.stdout.contains("recompiling"), "{}", out.stdout);
+
+    // Without `--trace`, none of that is shown.
+    let out = cargo_script!(
+        #[pkg_path(&pkg_dir)]
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(!out.stderr.contains("because"), "{}", out.stderr);
+
+    // Notes are shown by default, and `--quiet` silences them.
+    let out = cargo_script!("--keep-build-dir", "tests/data/script-no-deps.rs").unwrap();
+    assert!(
+        out.stderr.contains("note: kept the generated package"),
+        "{}",
+        out.stderr
+    );
+    let out = cargo_script!("-q", "--keep-build-dir", "tests/data/script-no-deps.rs").unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(!out.stderr.contains("note:"), "{}", out.stderr);
+    assert_eq!(out.stdout_output().trim(), "Hello, World!");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_script_read_only_cache() {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-read-only-cache").unwrap();
+    let cache = cargo_home.path().join("script-cache");
+    fs::create_dir(&cache).unwrap();
+    fs::set_permissions(&cache, fs::Permissions::from_mode(0o555)).unwrap();
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert_eq!(out.stdout_output().trim(), "Hello, World!");
+    assert!(out.stderr.contains("can't be written to"), "{}", out.stderr);
+    assert_eq!(fs::read_dir(&cache).unwrap().count(), 0);
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--require-cache",
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(
+        out.stderr.contains(&*cache.display().to_string()),
+        "{}",
+        out.stderr
+    );
+    assert!(out.stderr.contains("`CARGO_HOME`"), "{}", out.stderr);
+
+    fs::set_permissions(&cache, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+#[test]
+fn test_script_input_encoding() {
+    let out = cargo_script!("tests/data/script-latin1.crs").unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("is not valid UTF-8"), "{}", out.stderr);
+    assert!(out.stderr.contains("--input-encoding"), "{}", out.stderr);
+
+    let out = cargo_script!("--input-encoding", "latin1", "tests/data/script-latin1.crs").unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(out.stdout_output().trim(), "caf\u{e9} \u{80}");
+
+    let out = cargo_script!("--input-encoding", "cp1252", "tests/data/script-latin1.crs").unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(out.stdout_output().trim(), "caf\u{e9} \u{20ac}");
+
+    let out = cargo_script!("--input-encoding", "ebcdic", "tests/data/script-latin1.crs").unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("unknown encoding"), "{}", out.stderr);
+}
+
+#[test]
+fn test_script_no_net() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--dry-run",
+        "--no-net",
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(out.stdout.contains("--offline"), "{}", out.stdout);
+
+    // Nothing has been downloaded into this Cargo home, so there's no way to get the dependency.
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--no-net",
+        "tests/data/boolinator.rs"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("offline"), "{}", out.stderr);
+    assert!(out.stderr.contains("`--no-net`"), "{}", out.stderr);
+}
+
+#[test]
+fn test_script_target() {
+    use std::process::Command;
+
+    let host = Command::new("rustc").arg("-vV").output().unwrap();
+    let host = String::from_utf8(host.stdout).unwrap();
+    let host = host
+        .lines()
+        .find(|l| l.starts_with("host: "))
+        .map(|l| l["host: ".len()..].to_owned())
+        .unwrap();
+
+    let out = cargo_script!("--target", &host, "tests/data/script-runner.rs", "a").unwrap();
+    scan!(out.stdout_output();
+        ("runner: ", "args: [\"a\"]") => ()
+    )
+    .unwrap();
+
+    // This should fail before trying to build anything, so it doesn't need the target installed.
+    let out = cargo_script!("--target", "wasm32-wasi", "tests/data/script-runner.rs").unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("--runner wasmtime"));
+}
+
+#[test]
+fn test_script_crlf() {
+    let out = cargo_script!("tests/data/script-crlf.rs").unwrap();
+    scan!(out.stdout_output();
+        ("crlf: true") => ()
+    )
+    .unwrap();
+
+    let out = cargo_script!("tests/data/script-crlf-short.rs").unwrap();
+    scan!(out.stdout_output();
+        ("Some(1)") => ()
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_script_tree() {
+    let out = cargo_script!("--tree", "tests/data/script-full-block.rs").unwrap();
+    assert!(out.success());
+    assert!(out.stdout.contains("boolinator v0.1.0"));
+    assert!(
+        out.stdout.contains("script-full-block.rs)"),
+        "package should be shown as the script"
+    );
+    assert!(!out.stdout.contains("cargo-script-test"));
+
+    let out = cargo_script!(
+        "--tree",
+        "-d",
+        "boolinator=0.1.0",
+        "-e",
+        "1",
+        "--",
+        "--invert",
+        "boolinator"
+    )
+    .unwrap();
+    assert!(out.success());
+    let lines: Vec<_> = out.stdout.lines().collect();
+    assert_eq!(lines[0], "boolinator v0.1.0");
+    assert!(lines[1].ends_with(" (expression)"), "{:?}", lines);
+
+    let out = cargo_script!("--tree", "-d", "boolinator=0.1.0", "-l", "|l| l").unwrap();
+    assert!(out.success());
+    assert!(out.stdout.contains("(loop)"));
+    assert!(out.stdout.contains("boolinator v0.1.0"));
+}
+
+#[test]
+fn test_script_doc() {
+    use std::fs;
+
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let doc_path = cargo_home.path().join("binary-cache").join("doc");
+
+    // Don't go opening browsers in the middle of the tests.
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        #[env(BROWSER = "true")]
+        "--doc",
+        "tests/data/script-full-block.rs"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert!(out.stderr.contains("script_full_block/index.html"));
+    assert!(doc_path
+        .join("script_full_block")
+        .join("index.html")
+        .is_file());
+    assert!(!doc_path.join("boolinator").exists());
+
+    // The package should stick around, so the docs can be built again incrementally.
+    let pkgs: Vec<_> = fs::read_dir(cargo_home.path().join("script-cache"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.join("Cargo.toml").is_file())
+        .collect();
+    assert_eq!(pkgs.len(), 1);
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        #[env(BROWSER = "true")]
+        "--doc=deps",
+        "tests/data/script-full-block.rs"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert!(doc_path.join("boolinator").join("index.html").is_file());
+}
+
+#[test]
+fn test_script_keep_build_dir() {
+    use std::fs;
+
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let script_dir = tempdir::TempDir::new("cargo-script-test-keep").unwrap();
+    let script_path = script_dir.path().join("broken.rs");
+    fs::write(&script_path, "fn main() { let x: u8 = \"nope\"; }\n").unwrap();
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--keep-build-dir",
+        &script_path
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("kept the generated package"));
+
+    // Even though the build failed, the package should still be there to look at.
+    let pkgs: Vec<_> = fs::read_dir(cargo_home.path().join("script-cache"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.is_dir())
+        .collect();
+    assert_eq!(pkgs.len(), 1);
+    assert!(pkgs[0].join("Cargo.toml").is_file());
+    assert!(pkgs[0].join("broken.rs").is_file());
+}
+
+#[test]
+fn test_script_clean_panics() {
+    let out = cargo_script!(
+        #[env(RUST_BACKTRACE = "0")]
+        "--clean-panics",
+        "tests/data/script-panic.rs"
+    )
+    .unwrap();
+    assert_eq!(out.status.code(), Some(101));
+    assert!(out.stderr.contains("about to panic"));
+    assert!(out.stderr.contains("error: script panicked at "));
+    assert!(out.stderr.contains("script-panic.rs:4:5: oh no"));
+    assert!(!out.stderr.contains("thread 'main'"), "{}", out.stderr);
+
+    let out = cargo_script!(
+        #[env(RUST_BACKTRACE = "0")]
+        "--clean-panics",
+        "--verbose",
+        "tests/data/script-panic.rs"
+    )
+    .unwrap();
+    assert_eq!(out.status.code(), Some(101));
+    // Newer toolchains put the thread's ID between its name and "panicked".
+    assert!(
+        out.stderr.contains("thread 'main'") && out.stderr.contains(" panicked at "),
+        "{}",
+        out.stderr
+    );
+}
+
+#[test]
+fn test_script_dry_run() {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::time::SystemTime;
+
+    // Everything about every file under `dir` which a dry run might conceivably change.
+    fn snapshot(dir: &Path) -> Vec<(PathBuf, Vec<u8>, SystemTime)> {
+        let mut files = vec![];
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let meta = fs::metadata(&path).unwrap();
+            if meta.is_dir() {
+                files.extend(snapshot(&path));
+            } else {
+                let content = fs::read(&path).unwrap();
+                files.push((path, content, meta.modified().unwrap()));
+            }
+        }
+        files.sort();
+        files
+    }
+
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let script = "tests/data/script-no-deps.rs";
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--dry-run",
+        script
+    )
+    .unwrap();
+    assert!(out.success());
+    assert!(out.stdout.contains("input: file\n"));
+    assert!(out.stdout.contains("cache: miss (metadata missing)\n"));
+    assert!(out.stdout.contains("cargo build --manifest-path "));
+    assert!(!out.stdout.contains("Hello, World!"));
+    assert!(fs::read_dir(cargo_home.path()).unwrap().next().is_none());
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        script
+    )
+    .unwrap();
+    assert!(out.success());
+    let before = snapshot(cargo_home.path());
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--dry-run",
+        script
+    )
+    .unwrap();
+    assert!(out.stdout.contains("cache: hit\n"));
+    assert!(out.stdout.contains("build: (none)\n"));
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--dry-run",
+        "--message-format",
+        "json",
+        "--features",
+        "nope",
+        script
+    )
+    .unwrap();
+    assert!(out.stdout.contains(r#""cache_hit":false"#));
+    assert!(out.stdout.contains(r#""reason":"features differ""#));
+    assert!(out.stdout.contains("--features nope"));
+
+    assert!(snapshot(cargo_home.path()) == before);
+
+    // Not even a broken package gets cleaned up.
+    let pkg_path = fs::read_dir(cargo_home.path().join("script-cache"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| p.is_dir())
+        .unwrap();
+    fs::write(pkg_path.join("metadata.json"), "not json").unwrap();
+    let before = snapshot(cargo_home.path());
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--dry-run",
+        script
+    )
+    .unwrap();
+    assert!(out.stdout.contains("cache: miss (package is broken"));
+    assert!(snapshot(cargo_home.path()) == before);
+}
+
+#[test]
+fn test_script_rustflags() {
+    use std::fs;
+
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let scripts = tempdir::TempDir::new("cargo-script-test-scripts").unwrap();
+    let script = scripts.path().join("flagged.rs");
+    fs::write(
+        &script,
+        "fn main() { println!(\"a={} b={}\", cfg!(flag_a), cfg!(flag_b)); }\n",
+    )
+    .unwrap();
+
+    // Each set of flags should get its own build, and keep it.
+    for round in 0..2 {
+        for &(flags, expected) in &[
+            ("--cfg flag_a", "a=true b=false"),
+            ("--cfg flag_b", "a=false b=true"),
+        ] {
+            let out = cargo_script!(
+                #[cargo_home(cargo_home.path())]
+                #[env(RUST_LOG = "cargo_script=info")]
+                "--rustflags",
+                flags,
+                &script
+            )
+            .unwrap();
+            assert!(out.success(), "{}", out.stderr);
+            assert_eq!(out.stdout.trim(), expected);
+            assert_eq!(out.stderr.contains("recompiling because"), round == 0);
+        }
+    }
+    let flagged_dirs = fs::read_dir(cargo_home.path().join("binary-cache"))
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .filter(|n| n.starts_with("rustflags-"))
+        .count();
+    assert_eq!(flagged_dirs, 2);
+
+    // Flags from the environment aren't kept apart, but changing them should say why everything is rebuilt.
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        #[env(RUSTFLAGS = "--cfg flag_a")]
+        &script
+    )
+    .unwrap();
+    assert_eq!(out.stdout.trim(), "a=true b=false");
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        #[env(RUSTFLAGS = "--cfg flag_b")]
+        "-v",
+        &script
+    )
+    .unwrap();
+    assert_eq!(out.stdout.trim(), "a=false b=true");
+    assert!(out
+        .stderr
+        .contains("note: compiling because: RUSTFLAGS in the environment differ"));
+}
+
+#[test]
+fn test_script_lint_levels() {
+    use std::fs;
+
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let scripts = tempdir::TempDir::new("cargo-script-test-scripts").unwrap();
+    let script = scripts.path().join("unsafe.rs");
+    fs::write(
+        &script,
+        "fn main() { let x = 1; println!(\"{}\", unsafe { *(&x as *const i32) }); }\n",
+    )
+    .unwrap();
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--deny",
+        "unsafe_code",
+        &script
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(
+        out.stderr.contains("usage of an `unsafe` block"),
+        "{}",
+        out.stderr
+    );
+
+    // The last level given for a lint is the one that counts.
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--deny",
+        "unsafe_code",
+        "--allow",
+        "unsafe_code",
+        &script
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(out.stdout.trim(), "1");
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--deny",
+        "unsafe code",
+        &script
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(
+        out.stderr.contains("`unsafe code` is not a lint name"),
+        "{}",
+        out.stderr
+    );
+}
+
+#[test]
+fn test_script_build_hints() {
+    use std::fs;
+
+    let scripts = tempdir::TempDir::new("cargo-script-test-scripts").unwrap();
+    let script = scripts.path().join("old-edition.rs");
+    fs::write(&script, "async fn answer() -> i32 { 42 }\nfn main() {}\n").unwrap();
+
+    let out = cargo_script!(&script).unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("E0670"), "{}", out.stderr);
+    assert!(
+        out.stderr.contains("hint: scripts are built as Rust 2015"),
+        "{}",
+        out.stderr
+    );
+
+    let out = cargo_script!("--no-hints", &script).unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("E0670"), "{}", out.stderr);
+    assert!(!out.stderr.contains("hint:"), "{}", out.stderr);
+}
+
+#[test]
+fn test_script_reproducible_pkg() {
+    use std::fs;
+
+    // Two "machines", with the same project checked out in different places.
+    let machines: Vec<_> = (0..2)
+        .map(|_| tempdir::TempDir::new("cargo-script-test-machine").unwrap())
+        .collect();
+    let generate = |machine: &tempdir::TempDir| {
+        let proj = machine.path().join("proj");
+        let dep = proj.join("slow-build");
+        fs::create_dir_all(&dep).unwrap();
+        for name in &["Cargo.toml", "slow-build-build.rs", "slow-build-lib.rs"] {
+            fs::copy(
+                std::path::Path::new("tests/data/slow-build").join(name),
+                dep.join(name),
+            )
+            .unwrap();
+        }
+        let script = proj.join("script-slow-output.rs");
+        fs::copy("tests/data/script-slow-output.rs", &script).unwrap();
+
+        let pkg_path = proj.join("gen");
+        let out = cargo_script!(
+            #[pkg_path(&pkg_path)]
+            #[env(CARGO_HOME = machine.path().join("cargo"), HOME = machine.path())]
+            "--gen-pkg-only",
+            &script
+        )
+        .unwrap();
+        assert!(out.success(), "{}", out.stderr);
+        let read = |name: &str| fs::read(pkg_path.join(name)).unwrap();
+        (read("Cargo.toml"), read("script-slow-output.rs"))
+    };
+
+    let first = generate(&machines[0]);
+    assert_eq!(generate(&machines[0]), first);
+    assert_eq!(generate(&machines[1]), first);
+
+    let mani = String::from_utf8(first.0).unwrap();
+    assert!(mani.contains(r#"path = "../slow-build""#), "{}", mani);
+}
+
+#[test]
+fn test_script_panic_strategy() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "tests/data/script-catch-panic.rs"
+    )
+    .unwrap();
+    assert_eq!(out.stdout_output().trim(), "caught: true");
+
+    // With `abort`, there's nothing to catch; the script just dies.
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--panic",
+        "abort",
+        "tests/data/script-catch-panic.rs"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(!out.stdout.contains("caught"), "{}", out.stdout);
+
+    // Each strategy gets a package of its own, rather than rebuilding over the others.
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--panic",
+        "unwind",
+        "tests/data/script-catch-panic.rs"
+    )
+    .unwrap();
+    assert_eq!(out.stdout_output().trim(), "caught: true");
+    let packages = std::fs::read_dir(cargo_home.path().join("script-cache"))
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with("file-"))
+        .collect::<Vec<_>>();
+    assert_eq!(packages.len(), 3, "{:?}", packages);
+    assert!(packages.iter().any(|p| p.ends_with("-panic-abort")));
+
+    // The executables are kept apart, too, so the unwinding one is still there.
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "tests/data/script-catch-panic.rs"
+    )
+    .unwrap();
+    assert_eq!(out.stdout_output().trim(), "caught: true");
+
+    let out = cargo_script!("--panic", "abort", "--test", "tests/data/script-test.rs").unwrap();
+    assert!(!out.success());
+    assert!(
+        out.stderr
+            .contains("`--panic abort` can't be used with `--test` or `--bench`"),
+        "{}",
+        out.stderr
+    );
+}
+
+#[test]
+fn test_script_strip() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let binary = |strip: &str| {
+        let out = cargo_script!(
+            #[cargo_home(cargo_home.path())]
+            "--build-only",
+            "--strip",
+            strip,
+            "tests/data/script-no-deps.rs"
+        )
+        .unwrap();
+        assert!(out.success(), "{}", out.stderr);
+
+        let out = cargo_script!(
+            #[subcommand("which")]
+            #[env(CARGO_HOME = cargo_home.path())]
+            "--strip",
+            strip,
+            "tests/data/script-no-deps.rs"
+        )
+        .unwrap();
+        assert!(out.success(), "{}", out.stderr);
+        let line = out
+            .stdout
+            .lines()
+            .find(|l| l.starts_with("binary: "))
+            .unwrap()
+            .to_owned();
+        std::path::PathBuf::from(line["binary: ".len()..].trim_end_matches(" (exists)"))
+    };
+
+    let unstripped = binary("none");
+    let stripped = binary("symbols");
+    assert_ne!(unstripped, stripped);
+    let size = |p: &std::path::Path| std::fs::metadata(p).unwrap().len();
+    assert!(
+        size(&stripped) < size(&unstripped),
+        "{:?} isn't smaller than {:?}",
+        stripped,
+        unstripped
+    );
+}
+
+#[test]
+fn test_script_gui() {
+    let source = |target: &str| {
+        let pkg_path = tempdir::TempDir::new("cargo-script-test-pkg").unwrap();
+        let out = cargo_script!(
+            #[pkg_path(pkg_path.path())]
+            "--gen-pkg-only",
+            "--gui",
+            "--target",
+            target,
+            "tests/data/script-no-deps.rs"
+        )
+        .unwrap();
+        assert!(out.success(), "{}", out.stderr);
+        std::fs::read_to_string(pkg_path.path().join("script-no-deps.rs")).unwrap()
+    };
+    let attr = "#![windows_subsystem = \"windows\"] ";
+
+    // Anywhere else, `--gui` doesn't change anything.
+    let linux = source("x86_64-unknown-linux-gnu");
+    assert!(!linux.contains("windows_subsystem"), "{}", linux);
+
+    let windows = source("x86_64-pc-windows-msvc");
+    assert!(windows.starts_with(attr), "{}", windows);
+    assert_eq!(windows[attr.len()..], linux);
+}
+
+#[test]
+fn test_script_gen_pkg_local_changes() {
+    use std::fs;
+
+    let dir = tempdir::TempDir::new("cargo-script-test-edit").unwrap();
+    let script_path = dir.path().join("edit.rs");
+    fs::copy("tests/data/script-no-deps.rs", &script_path).unwrap();
+    let pkg_dir = tempdir::TempDir::new("cargo-script-test-pkg").unwrap();
+    let generated = pkg_dir.path().join("edit.rs");
+    let generate = || {
+        cargo_script!(
+            #[pkg_path(pkg_dir.path())]
+            "--gen-pkg-only",
+            &script_path
+        )
+        .unwrap()
+    };
+
+    let out = generate();
+    assert!(out.success(), "{}", out.stderr);
+    let poked = fs::read_to_string(&generated).unwrap() + "// poked\n";
+    fs::write(&generated, &poked).unwrap();
+
+    // Nothing needs regenerating, so the change is left alone.
+    let out = generate();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(fs::read_to_string(&generated).unwrap(), poked);
+
+    let script = fs::read_to_string(&script_path).unwrap();
+    fs::write(&script_path, script + "// changed\n").unwrap();
+    let out = generate();
+    assert!(!out.success());
+    assert!(
+        out.stderr.contains("local modifications were detected"),
+        "{}",
+        out.stderr
+    );
+    assert_eq!(fs::read_to_string(&generated).unwrap(), poked);
+
+    let out = cargo_script!(
+        #[pkg_path(pkg_dir.path())]
+        "--gen-pkg-only",
+        "--force",
+        &script_path
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    let regenerated = fs::read_to_string(&generated).unwrap();
+    assert!(regenerated.contains("// changed"), "{}", regenerated);
+    assert!(!regenerated.contains("// poked"), "{}", regenerated);
+}
+
+#[test]
+fn test_script_before_after() {
+    let out = cargo_script!(
+        "--before",
+        "echo before",
+        "--after",
+        "echo after",
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    let before = out.stdout.find("before").unwrap();
+    let output = out.stdout.find("--output--").unwrap();
+    let after = out.stdout.rfind("after").unwrap();
+    assert!(before < output && output < after, "{}", out.stdout);
+
+    // `--after` is run even when the script fails, and the script's exit status is kept.
+    let out = cargo_script!("--after", "echo after", "tests/data/script-panic.rs").unwrap();
+    assert!(!out.success());
+    assert!(out.stdout.contains("after"), "{}", out.stdout);
+
+    // If `--before` fails, nothing after it is run, not even `--after`.
+    let out = cargo_script!(
+        "--before",
+        "exit 3",
+        "--after",
+        "echo after",
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert_eq!(out.status.code(), Some(3));
+    assert!(!out.stdout.contains("--output--"), "{}", out.stdout);
+    assert!(!out.stdout.contains("after"), "{}", out.stdout);
+    assert!(out.stderr.contains("the `--before` command failed"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_script_before_after_env() {
+    let out = cargo_script!(
+        "--env",
+        "CS_TEST_HOOK=hooked",
+        "--before",
+        "echo \"before: $CS_TEST_HOOK\"",
+        "--after",
+        "echo \"after: $CARGO_SCRIPT_SAFE_NAME\"",
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(out.stdout.starts_with("before: hooked\n"), "{}", out.stdout);
+    assert!(
+        out.stdout.ends_with("after: script-no-deps\n"),
+        "{}",
+        out.stdout
+    );
+}
+
+#[test]
+fn test_script_edition_too_new() {
+    let out = cargo_script!(
+        "--manifest-key",
+        "package.edition=2024",
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+
+    let cargo = std::process::Command::new("cargo")
+        .arg("-V")
+        .output()
+        .unwrap();
+    let cargo = String::from_utf8_lossy(&cargo.stdout);
+    let minor: u64 = cargo.split('.').nth(1).unwrap().parse().unwrap();
+    if minor >= 85 {
+        assert!(out.success(), "{}", out.stderr);
+        return;
+    }
+    assert!(!out.success());
+    assert!(
+        out.stderr
+            .contains("the script uses edition 2024, which needs Cargo 1.85 or later"),
+        "{}",
+        out.stderr
+    );
+    assert!(!out.stderr.contains("feature `edition2024` is required"));
+}
+
+#[test]
+fn test_script_provenance() {
+    use std::fs;
+
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "tests/data/script-provenance.rs"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    let lines: Vec<String> = out.stdout_output().trim().lines().map(Into::into).collect();
+    assert_eq!(lines.len(), 3, "{:?}", lines);
+    let (path, digest, built) = (&lines[0], &lines[1], &lines[2]);
+
+    let pkgs: Vec<_> = fs::read_dir(cargo_home.path().join("script-cache"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.is_dir())
+        .collect();
+    assert_eq!(pkgs.len(), 1);
+    let meta = fs::read_to_string(pkgs[0].join("metadata.json")).unwrap();
+    assert!(
+        meta.contains(&format!(r#""content_hash":"{}""#, digest)),
+        "{}",
+        meta
+    );
+    assert!(path.ends_with("script-provenance.rs"), "{}", path);
+    assert!(meta.contains(&format!(r#""path":{:?}"#, path)), "{}", meta);
+    assert_eq!(built.len(), "2017-09-17T10:38:58Z".len(), "{}", built);
+    assert!(built.ends_with('Z'), "{}", built);
+
+    // Running it again uses the same executable, so nothing changes.
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "tests/data/script-provenance.rs"
+    )
+    .unwrap();
+    assert_eq!(
+        out.stdout_output().trim().lines().collect::<Vec<_>>(),
+        lines
+    );
+}
+
+#[test]
+fn test_script_jobs() {
+    let out = cargo_script!("--dry-run", "-j", "2", "tests/data/script-no-deps.rs").unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    let build = out
+        .stdout
+        .lines()
+        .find(|line| line.starts_with("build: "))
+        .unwrap();
+    assert!(build.ends_with(" --jobs 2"), "{}", build);
+
+    // Running the script isn't affected.
+    let out = cargo_script!("--jobs", "1", "tests/data/script-no-deps.rs").unwrap();
+    scan!(out.stdout_output();
+        ("Hello, World!") => ()
+    )
+    .unwrap();
+
+    let out = cargo_script!("-j", "0", "tests/data/script-no-deps.rs").unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("expected a non-zero number of jobs"));
+}
+
+/**
+Writes a `cargo` to `dir` which runs `build` (as `sh`) in place of `cargo build`, and passes anything else on to the real one, and returns a `PATH` that finds it first.
+*/
+#[cfg(unix)]
+fn fake_cargo(dir: &std::path::Path, build: &str) -> std::ffi::OsString {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    let shim = dir.join("cargo");
+    fs::write(
+        &shim,
+        format!(
+            "#!/bin/sh\nif [ \"$1\" != build ]; then\n    PATH=\"${{PATH#*:}}\" exec cargo \"$@\"\nfi\n{}\n",
+            build
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&shim, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let mut path = dir.as_os_str().to_owned();
+    path.push(":");
+    path.push(std::env::var_os("PATH").unwrap_or_default());
+    path
+}
+
+#[cfg(unix)]
+#[test]
+fn test_script_build_timeout() {
+    use std::time::{Duration, Instant};
+
+    let dir = tempdir::TempDir::new("cargo-script-test-fake-cargo").unwrap();
+    let grandchild = dir.path().join("grandchild");
+    // The `sleep` stands in for something Cargo started, like rustc or a build script.
+    let path = fake_cargo(
+        dir.path(),
+        &format!(
+            "echo '    Updating crates.io index' >&2\nsleep 60 &\necho $! > '{}'\nwait",
+            grandchild.display()
+        ),
+    );
+
+    let start = Instant::now();
+    let out = cargo_script!(
+        #[env(PATH = &path)]
+        "--build-timeout",
+        "2s",
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(start.elapsed() < Duration::from_secs(30));
+    assert_eq!(out.status.code(), Some(124), "{}", out.stderr);
+    assert!(out.stderr.contains("--build-timeout"), "{}", out.stderr);
+    assert!(
+        out.stderr.contains("Updating crates.io index"),
+        "{}",
+        out.stderr
+    );
+
+    /*
+    Killing Cargo has to take what it started with it.  Something which has been killed can hang about as a zombie until it's reaped, which may never happen in a container whose init doesn't, so that counts as dead too.  It can also take a moment to go, so this gives it a few seconds.
+    */
+    let pid = std::fs::read_to_string(&grandchild).unwrap();
+    assert_goes(pid.trim());
+
+    let out = cargo_script!("--build-timeout", "soon", "tests/data/script-no-deps.rs").unwrap();
+    assert!(!out.success());
+    assert!(out.stderr.contains("invalid duration"), "{}", out.stderr);
+}
+
+/**
+Asserts that the process `pid` is gone, or goes within a few seconds.  A zombie counts as gone.
+*/
+#[cfg(unix)]
+fn assert_goes(pid: &str) {
+    use std::time::{Duration, Instant};
+
+    let is_alive = || {
+        let out = std::process::Command::new("ps")
+            .args(["-o", "stat=", "-p", pid])
+            .output()
+            .unwrap();
+        let stat = String::from_utf8_lossy(&out.stdout);
+        out.status.success() && !stat.trim().is_empty() && !stat.trim().starts_with('Z')
+    };
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while is_alive() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert!(!is_alive(), "process {} is still running", pid);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_script_build_timeout_forwards_signals() {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::Command;
+    use std::time::{Duration, Instant};
+
+    let dir = tempdir::TempDir::new("cargo-script-test-fake-cargo").unwrap();
+    let grandchild = dir.path().join("grandchild");
+    let path = fake_cargo(
+        dir.path(),
+        &format!(
+            "sleep 60 &\necho $! > '{0}.tmp'\nmv '{0}.tmp' '{0}'\nwait",
+            grandchild.display()
+        ),
+    );
+
+    let _cargo_lock = crate::util::CARGO_MUTEX.lock().unwrap();
+    let target_dir = std::env::var("CARGO_TARGET_DIR").unwrap_or_else(|_| String::from("target"));
+    let mut child = Command::new(format!("{}/debug/cargo-script", target_dir))
+        .args([
+            "script",
+            "--build-timeout",
+            "10m",
+            "tests/data/script-no-deps.rs",
+        ])
+        .env_remove("CARGO_TARGET_DIR")
+        .env("PATH", &path)
+        .spawn()
+        .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while !grandchild.exists() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    let pid = std::fs::read_to_string(&grandchild).unwrap();
+
+    // The build is in a process group of its own, but going shouldn't leave it behind.
+    assert_eq!(
+        unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) },
+        0
+    );
+    let status = child.wait().unwrap();
+    assert_eq!(status.signal(), Some(libc::SIGTERM), "{:?}", status);
+    assert_goes(pid.trim());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_script_build_retries() {
+    let dir = tempdir::TempDir::new("cargo-script-test-fake-cargo").unwrap();
+    let attempts = dir.path().join("attempts");
+    // The first build fails to download anything; after that, the real Cargo takes over.
+    let path = fake_cargo(
+        dir.path(),
+        &format!(
+            "echo x >> '{0}'\nif [ $(wc -l < '{0}') -eq 1 ]; then\n    echo 'error: failed to download from `https://static.crates.io/crates/x/1.0.0/download`' >&2\n    exit 101\nfi\nPATH=\"${{PATH#*:}}\" exec cargo \"$@\"",
+            attempts.display()
+        ),
+    );
+    let count = || {
+        std::fs::read_to_string(&attempts)
+            .map(|s| s.lines().count())
+            .unwrap_or(0)
+    };
+
+    let out = cargo_script!(
+        #[env(PATH = &path)]
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert_eq!(count(), 1);
+
+    std::fs::remove_file(&attempts).unwrap();
+    let out = cargo_script!(
+        #[env(PATH = &path)]
+        "--build-retries",
+        "2",
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("Hello, World!") => ()
+    )
+    .unwrap();
+    assert!(
+        out.stderr
+            .contains("couldn't reach the network; trying again"),
+        "{}",
+        out.stderr
+    );
+    // One failure, the build that worked, and then the one for finding the executable.
+    assert!(count() >= 2);
+
+    // Something that isn't down to the network isn't worth another go.
+    std::fs::remove_file(&attempts).unwrap();
+    let path = fake_cargo(
+        dir.path(),
+        &format!(
+            "echo x >> '{}'\necho 'error[E0308]: mismatched types' >&2\nexit 101",
+            attempts.display()
+        ),
+    );
+    let out = cargo_script!(
+        #[env(PATH = &path)]
+        "--build-retries",
+        "2",
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(!out.stderr.contains("trying again"), "{}", out.stderr);
+    assert_eq!(count(), 1);
+}
+
+#[test]
+fn test_script_data_dir() {
+    use std::fs;
+
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-data-dir").unwrap();
+    let script_dir = tempdir::TempDir::new("cargo-script-test-data-dir-script").unwrap();
+    let script = script_dir.path().join("counter.rs");
+    let source = fs::read_to_string("tests/data/script-data-dir.rs").unwrap();
+    fs::write(&script, &source).unwrap();
+
+    macro_rules! count {
+        ($($args:expr),*) => {{
+            let out = cargo_script!(
+                #[cargo_home(cargo_home.path())]
+                $($args,)* &script
+            )
+            .unwrap();
+            out.stdout_output().trim().to_owned()
+        }};
+    }
+
+    // Scripts which don't keep anything don't get a directory made for them.
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(!cargo_home.path().join("script-data").exists());
+
+    assert_eq!(count!(), "1");
+    assert_eq!(count!(), "2");
+    // Neither rebuilding the script nor changing it loses its data.
+    assert_eq!(count!("--force"), "3");
+    fs::write(&script, source.replace("+ 1;", "+ 1 + 0;")).unwrap();
+    assert_eq!(count!(), "4");
+    // Nor does clearing the cache.
+    assert_eq!(count!("--clear-cache"), "5");
+    // A run which doesn't use the cache starts from nothing, and leaves the real data alone.
+    assert_eq!(count!("--ephemeral"), "1");
+    assert_eq!(count!(), "6");
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--list-data"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    let lines: Vec<_> = out.stdout.lines().collect();
+    assert_eq!(lines.len(), 1, "{}", out.stdout);
+    assert!(lines[0].contains("counter.rs  "), "{}", out.stdout);
+    assert!(lines[0].contains("script-data"), "{}", out.stdout);
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--clear-data"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(!cargo_home.path().join("script-data").exists());
+    assert_eq!(count!(), "1");
+
+    // Expressions don't get one.
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "-e",
+        with_output_marker!("std::env::var_os(\"CARGO_SCRIPT_DATA_DIR\").is_none()")
+    )
+    .unwrap();
+    assert_eq!(out.stdout_output().trim(), "true");
+}
+
+#[test]
+fn test_script_stdin_passthrough() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-stdin").unwrap();
+    let input: String = (0..300_000).map(|i| format!("line {}\n", i)).collect();
+    let hash = input.bytes().fold(0xcbf29ce484222325u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+    let expected = format!("{} {:016x}", input.len(), hash);
+
+    // The first run has to build the script, the second doesn't; neither may touch its input.
+    for _ in 0..2 {
+        let out = cargo_script!(
+            #[cargo_home(cargo_home.path())]
+            #[stdin(&input)]
+            "tests/data/script-stdin-checksum.rs"
+        )
+        .unwrap();
+        assert!(out.success(), "{}", out.stderr);
+        assert_eq!(out.stdout_output().trim(), expected);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_script_terminal() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-terminal").unwrap();
+    let cargo_home = cargo_home.path().to_str().unwrap();
+    let script = "tests/data/script-isatty.rs";
+
+    // Piped, nothing is a terminal; this also gets the build out of the way.
+    let out = cargo_script!(
+        #[cargo_home(cargo_home)]
+        #[stdin("")]
+        script
+    )
+    .unwrap();
+    assert_eq!(
+        out.stdout_output().trim(),
+        "stdin: false\nstdout: false\nstderr: false"
+    );
+
+    let out = crate::util::cargo_script_in_pty(&[("CARGO_HOME", cargo_home)], &[script]);
+    let out = out.unwrap_or_else(|err| panic!("could not run in a terminal: {}", err));
+    assert!(out.success(), "{}", out.stdout);
+    assert_eq!(
+        out.stdout_output().trim(),
+        "stdin: true\nstdout: true\nstderr: true"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_script_forwards_signals() {
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-signals").unwrap();
+    let _cargo_lock = crate::util::CARGO_MUTEX.lock().unwrap();
+    let target_dir = std::env::var("CARGO_TARGET_DIR").unwrap_or_else(|_| String::from("target"));
+    let mut child = Command::new(format!("{}/debug/cargo-script", target_dir))
+        .args(&["script", "tests/data/script-sigterm.rs"])
+        .env_remove("CARGO_TARGET_DIR")
+        .env("CARGO_HOME", cargo_home.path())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // The script says when it's ready, which is once it has been built.
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(line.trim(), "ready");
+
+    // Only cargo-script is sent this; it's up to it to pass it on, and wait for the script to finish up.
+    assert_eq!(
+        unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) },
+        0
+    );
+    let status = child.wait().unwrap();
+    line.clear();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(line.trim(), "cleaned up");
+    assert_eq!(status.code(), Some(3));
+}
+
+#[test]
+fn test_script_deps_from() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let member = "tests/data/deps-from/member/Cargo.toml";
+
+    // `shout` has no version in the script; the member inherits it from the workspace, as a path dependency.
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--verbose",
+        "--deps-from",
+        member,
+        "tests/data/script-deps-from.rs"
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("HELLO!") => ()
+    )
+    .unwrap();
+    assert!(
+        out.stderr.contains("taking dependency versions from `")
+            && out.stderr.contains("member/Cargo.toml`"),
+        "{}",
+        out.stderr
+    );
+
+    // What the project has goes into which package the script gets.
+    let hash = |deps_from: &str| {
+        let out = cargo_script!(
+            #[cargo_home(cargo_home.path())]
+            #[env(CARGO_SCRIPT_DEPS_FROM = deps_from)]
+            "--hash-only",
+            "tests/data/script-deps-from.rs"
+        )
+        .unwrap();
+        assert!(out.success(), "{}", out.stderr);
+        out.stdout.trim().to_owned()
+    };
+    assert_eq!(hash(member), hash(member));
+    assert_ne!(hash(member), hash("tests/data/deps-from/Cargo.toml"));
+
+    // Anything the project doesn't have can be any version, as it would have been anyway.
+    let pkg_dir = tempdir::TempDir::new("cargo-script-test-pkg").unwrap();
+    let out = cargo_script!(
+        #[pkg_path(pkg_dir.path())]
+        "--gen-pkg-only",
+        "--deps-from",
+        member,
+        "--dep",
+        "not_in_the_project",
+        "tests/data/script-deps-from.rs"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(
+        out.stderr
+            .contains("dependency `not_in_the_project` can be any version"),
+        "{}",
+        out.stderr
+    );
+    let mani = std::fs::read_to_string(pkg_dir.path().join("Cargo.toml")).unwrap();
+    assert!(mani.contains("not_in_the_project = \"*\""), "{}", mani);
+    assert!(mani.contains("[dependencies.shout]"), "{}", mani);
+}
+
+#[test]
+fn test_script_wildcard_deps() {
+    use std::fs;
+
+    // A package which has been built before has a lockfile to suggest versions from.
+    let pkg_dir = tempdir::TempDir::new("cargo-script-test-pkg").unwrap();
+    fs::write(
+        pkg_dir.path().join("Cargo.lock"),
+        "[[package]]\nname = \"rand\"\nversion = \"0.8.5\"\n\
+         source = \"registry+https://github.com/rust-lang/crates.io-index\"\n",
+    )
+    .unwrap();
+
+    let out = cargo_script!(
+        #[pkg_path(pkg_dir.path())]
+        "--gen-pkg-only",
+        "--dep",
+        "rand",
+        "--dep",
+        "serde",
+        "--dep",
+        "regex=1",
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    let warnings: Vec<_> = out
+        .stderr
+        .lines()
+        .filter(|l| l.contains("can be any version"))
+        .collect();
+    assert_eq!(warnings.len(), 2, "{}", out.stderr);
+    assert!(
+        warnings[0].starts_with("warning: dependency `rand` can be any version")
+            && warnings[0].contains("`rand=\"0.8.5\"`, the version it's locked to now"),
+        "{}",
+        warnings[0]
+    );
+    assert!(
+        warnings[1].contains("`serde=\"VERSION\"`"),
+        "{}",
+        warnings[1]
+    );
+
+    // For CI, it can be an error instead, naming every one of them.
+    let out = cargo_script!(
+        #[pkg_path(pkg_dir.path())]
+        "--gen-pkg-only",
+        "--deny-wildcard-deps",
+        "--dep",
+        "rand",
+        "--dep",
+        "serde",
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert_eq!(out.status.code(), Some(1), "{}", out.stderr);
+    assert!(
+        out.stderr
+            .contains("(--deny-wildcard-deps): `rand`, `serde`;"),
+        "{}",
+        out.stderr
+    );
+
+    // Or it can be kept quiet, by flag or from the environment.
+    let out = cargo_script!(
+        #[pkg_path(pkg_dir.path())]
+        "--gen-pkg-only",
+        "--allow-wildcard-deps",
+        "--dep",
+        "rand",
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(!out.stderr.contains("any version"), "{}", out.stderr);
+
+    let out = cargo_script!(
+        #[pkg_path(pkg_dir.path())]
+        #[env(CARGO_SCRIPT_ALLOW_WILDCARD_DEPS = "1")]
+        "--gen-pkg-only",
+        "--dep",
+        "rand",
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(!out.stderr.contains("any version"), "{}", out.stderr);
+}
+
+#[cfg(windows)]
+#[test]
+fn test_script_windows_separators() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+
+    // However the separators are mixed, it's the same script, with the same package.
+    let id = |script: &str| {
+        let out = cargo_script!(
+            #[cargo_home(cargo_home.path())]
+            "--hash-only",
+            script
+        )
+        .unwrap();
+        assert!(out.success(), "{}", out.stderr);
+        out.stdout.trim().to_owned()
+    };
+    let first = id(r"tests\data\script-no-deps.rs");
+    assert_eq!(id("tests/data/script-no-deps.rs"), first);
+    assert_eq!(id(r"tests/data\script-no-deps.rs"), first);
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        r"tests\data\script-backslash-path-dep.rs"
+    )
+    .unwrap();
+    scan!(out.stdout_output();
+        ("HI!") => ()
+    )
+    .unwrap();
+
+    // Cargo gets forward slashes, whichever the script used.
+    let pkg_dir = tempdir::TempDir::new("cargo-script-test-pkg").unwrap();
+    let out = cargo_script!(
+        #[pkg_path(pkg_dir.path())]
+        "--gen-pkg-only",
+        r"tests/data\script-backslash-path-dep.rs"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    let mani = std::fs::read_to_string(pkg_dir.path().join("Cargo.toml")).unwrap();
+    assert!(mani.contains("[dependencies.shout]"), "{}", mani);
+    assert!(!mani.contains('\\'), "{}", mani);
+}
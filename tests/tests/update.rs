@@ -0,0 +1,155 @@
+use std::fs;
+
+#[test]
+fn test_update() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let dir = tempdir::TempDir::new("cargo-script-test-update").unwrap();
+    let script = dir.path().join("stale.rs");
+    fs::write(
+        &script,
+        "// cargo-deps: boolinator = \"0.1\"\nfn main() {\n    println!(\"--output--\");\n}\n",
+    )
+    .unwrap();
+
+    // Without `--save`, the script is left alone.
+    let out = cargo_script!(
+        #[subcommand("update")]
+        #[env(CARGO_HOME = cargo_home.path())]
+        &script
+    )
+    .unwrap();
+    assert!(out.success());
+    assert!(out.stderr.contains("boolinator v0.1.0"));
+    assert!(fs::read_to_string(&script)
+        .unwrap()
+        .starts_with("// cargo-deps: boolinator = \"0.1\"\n"));
+
+    let out = cargo_script!(
+        #[subcommand("update")]
+        #[env(CARGO_HOME = cargo_home.path())]
+        &script,
+        "--save"
+    )
+    .unwrap();
+    assert!(out.success());
+    assert_eq!(
+        fs::read_to_string(&script).unwrap(),
+        "// cargo-deps: boolinator = \"0.1.0\"\nfn main() {\n    println!(\"--output--\");\n}\n"
+    );
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        &script
+    )
+    .unwrap();
+    assert!(out.success());
+}
+
+#[test]
+fn test_update_bundle() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let dir = tempdir::TempDir::new("cargo-script-test-update").unwrap();
+    let bundle = dir.path().join("mytool");
+    fs::create_dir(&bundle).unwrap();
+    fs::write(
+        bundle.join("main.rs"),
+        "// cargo-deps: boolinator = \"0.1\"\nfn main() {\n    println!(\"--output--\");\n}\n",
+    )
+    .unwrap();
+    let entries = || -> Vec<String> {
+        fs::read_dir(cargo_home.path().join("script-cache"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("file-"))
+            .collect()
+    };
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        &bundle
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    let ran = entries();
+    assert_eq!(ran.len(), 1);
+    assert!(ran[0].starts_with("file-mytool-"), "{:?}", ran);
+
+    // A bundle's package is named after its directory, so `update` has to find it the same way a run does.
+    let out = cargo_script!(
+        #[subcommand("update")]
+        #[env(CARGO_HOME = cargo_home.path())]
+        &bundle
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(entries(), ran);
+}
+
+#[test]
+fn test_update_local_modifications() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let dir = tempdir::TempDir::new("cargo-script-test-update").unwrap();
+    let script = dir.path().join("poked.rs");
+    fs::write(
+        &script,
+        "// cargo-deps: boolinator = \"0.1\"\nfn main() {\n    println!(\"--output--\");\n}\n",
+    )
+    .unwrap();
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        &script
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    let pkg = fs::read_dir(cargo_home.path().join("script-cache"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| {
+            p.file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .starts_with("file-poked-")
+        })
+        .unwrap();
+    let manifest = pkg.join("Cargo.toml");
+    // Changed by hand, which `update` shouldn't undo unless it's told to.
+    let poked = fs::read_to_string(&manifest).unwrap() + "# poked\n";
+    fs::write(&manifest, &poked).unwrap();
+
+    let out = cargo_script!(
+        #[subcommand("update")]
+        #[env(CARGO_HOME = cargo_home.path())]
+        &script
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(fs::read_to_string(&manifest).unwrap(), poked);
+
+    let out = cargo_script!(
+        #[subcommand("update")]
+        #[env(CARGO_HOME = cargo_home.path())]
+        &script,
+        "--force"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(!fs::read_to_string(&manifest).unwrap().contains("# poked"));
+
+    // What was written is still known, but the script has to be built again against what's now locked.
+    assert!(pkg.join("metadata.json").exists());
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "--verbose",
+        &script
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(
+        out.stderr
+            .contains("compiling because: dependencies were updated"),
+        "{}",
+        out.stderr
+    );
+}
@@ -6,4 +6,81 @@ fn test_version() {
         ("cargo-script", &::std::env::var("CARGO_PKG_VERSION").unwrap(), .._) => ()
     )
     .unwrap();
+
+    let lines: Vec<_> = out.stdout.lines().collect();
+    assert_eq!(lines.len(), 4, "{:?}", lines);
+    assert!(lines[1].starts_with("cargo "));
+    assert!(lines[2].starts_with("rustc "));
+    assert!(lines[3].starts_with("templates "));
+}
+
+#[test]
+fn test_self_version() {
+    use std::fs;
+
+    let home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+    let index = tempdir::TempDir::new("cargo-script-test-index").unwrap();
+    let index_url = format!(
+        "file:///{}",
+        index
+            .path()
+            .display()
+            .to_string()
+            .replace('\\', "/")
+            .trim_start_matches('/')
+    );
+    let current = ::std::env::var("CARGO_PKG_VERSION").unwrap();
+    let publish = |versions: &[&str]| {
+        let entry = index.path().join("ca").join("rg");
+        fs::create_dir_all(&entry).unwrap();
+        let lines: Vec<_> = versions
+            .iter()
+            .map(|v| format!(r#"{{"name":"cargo-script","vers":"{}","yanked":false}}"#, v))
+            .collect();
+        fs::write(entry.join("cargo-script"), lines.join("\n")).unwrap();
+    };
+    macro_rules! self_version {
+        ($($args:expr),*) => {
+            cargo_script!(
+                #[subcommand("self")]
+                #[env(CARGO_HOME=home.path(), CARGO_SCRIPT_INDEX_URL=&index_url)]
+                "version", $($args),*
+            )
+            .unwrap()
+        };
+    }
+
+    publish(&["0.1.0", &current]);
+    let out = self_version!("--check");
+    assert!(out.success(), "{}", out.stderr);
+    assert!(out
+        .stdout
+        .starts_with(&format!("cargo-script {}\n", current)));
+    assert!(out.stdout.contains("This is the latest version."));
+
+    publish(&["0.1.0", &current, "999.0.0"]);
+    let out = self_version!("--check");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(out.stdout.contains("cargo-script 999.0.0 is available"));
+    assert_eq!(
+        fs::read_to_string(home.path().join("cargo-script-update-check")).unwrap(),
+        "999.0.0"
+    );
+
+    // Without `--check`, being out of date isn't a failure.
+    assert!(self_version!().success());
+
+    // Neither is not being able to find out.
+    fs::remove_dir_all(index.path().join("ca")).unwrap();
+    let out = self_version!("--check");
+    assert!(out.success());
+    assert!(out
+        .stderr
+        .contains("warning: could not check for a newer version"));
+
+    let out = self_version!("--check", "--offline");
+    assert!(out.success());
+    assert!(out
+        .stderr
+        .contains("not checking for a newer version while offline"));
 }
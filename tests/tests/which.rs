@@ -0,0 +1,275 @@
+use std::path::Path;
+
+fn locations(stdout: &str) -> Vec<(String, String)> {
+    stdout
+        .lines()
+        .map(|line| {
+            let i = line.find(": ").expect("expected `key: value`");
+            (line[..i].to_owned(), line[i + 2..].to_owned())
+        })
+        .collect()
+}
+
+#[test]
+fn test_which() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+
+    let out = cargo_script!(
+        #[subcommand("which")]
+        #[env(CARGO_HOME = cargo_home.path())]
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(out.success());
+    let before = locations(&out.stdout);
+    let keys: Vec<_> = before.iter().map(|(k, _)| &**k).collect();
+    assert_eq!(keys, ["script", "id", "package", "binary"]);
+    assert!(before[0].1.ends_with("script-no-deps.rs (exists)"));
+    assert!(before[2].1.ends_with(" (missing)"));
+    assert!(before[3].1.ends_with(" (missing)"));
+
+    // Asking mustn't create anything.
+    let pkg_path = before[2].1.trim_end_matches(" (missing)");
+    assert!(!Path::new(pkg_path).exists());
+    assert!(pkg_path.ends_with(&before[1].1));
+
+    let out = cargo_script!(
+        #[cargo_home(cargo_home.path())]
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(out.success());
+
+    // Now that it's been built, it should all be where `which` said it would be.
+    let out = cargo_script!(
+        #[subcommand("which")]
+        #[env(CARGO_HOME = cargo_home.path())]
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(out.success());
+    let after = locations(&out.stdout);
+    assert_eq!(after[0], before[0]);
+    assert_eq!(after[1], before[1]);
+    assert_eq!(after[2].1, format!("{} (exists)", pkg_path));
+    let binary = before[3].1.trim_end_matches(" (missing)");
+    assert_eq!(after[3].1, format!("{} (exists)", binary));
+    assert!(Path::new(pkg_path).join("Cargo.toml").is_file());
+    assert!(Path::new(binary).is_file());
+
+    let out = cargo_script!(
+        #[subcommand("which")]
+        #[env(CARGO_HOME = cargo_home.path())]
+        "--json",
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert!(out.success());
+    let binary_json = format!("\"binary\":{:?}", binary);
+    assert!(out.stdout.contains(&binary_json), "{}", out.stdout);
+    assert!(out.stdout.contains("\"binary_exists\":true"));
+
+    let out = cargo_script!(
+        #[subcommand("which")]
+        #[env(CARGO_HOME = cargo_home.path())]
+        "-e",
+        "1 + 1"
+    )
+    .unwrap();
+    assert!(out.success());
+    let expr = locations(&out.stdout);
+    assert_eq!(expr[0].1, "(expression)");
+    assert!(expr[1].1.starts_with("expr-"));
+}
+
+#[test]
+fn test_which_all() {
+    let dir = tempdir::TempDir::new("cargo-script-test-which").unwrap();
+    let dir_path = dir.path().canonicalize().unwrap();
+    for name in &["both.crs", "both.rs", "only.rs"] {
+        std::fs::write(dir_path.join(name), "fn main() {}\n").unwrap();
+    }
+
+    let out = cargo_script!(
+        #[subcommand("which")]
+        "--all",
+        dir_path.join("both")
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    let found: Vec<_> = out.stdout.lines().collect();
+    assert_eq!(
+        found,
+        [
+            dir_path.join("both.crs").display().to_string(),
+            dir_path.join("both.rs").display().to_string(),
+        ]
+    );
+
+    // With an extension, no others are tried.
+    let out = cargo_script!(
+        #[subcommand("which")]
+        "--all",
+        dir_path.join("only.rs")
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert_eq!(
+        out.stdout.trim_end(),
+        dir_path.join("only.rs").display().to_string()
+    );
+
+    let out = cargo_script!(
+        #[subcommand("which")]
+        "--all",
+        dir_path.join("missing")
+    )
+    .unwrap();
+    assert!(!out.success());
+    assert!(out.stdout.is_empty());
+}
+
+#[test]
+fn test_which_search_path() {
+    let dir = tempdir::TempDir::new("cargo-script-test-which").unwrap();
+    let dir_path = dir.path().canonicalize().unwrap();
+    let (first, second) = (dir_path.join("first"), dir_path.join("second"));
+    for dir in &[&first, &second] {
+        std::fs::create_dir(dir).unwrap();
+    }
+    std::fs::write(first.join("mytool.rs"), "fn main() {}\n").unwrap();
+    std::fs::write(second.join("mytool.crs"), "fn main() {}\n").unwrap();
+    let search = std::env::join_paths(&[&first, &second]).unwrap();
+
+    let out = cargo_script!(
+        #[subcommand("which")]
+        #[env(CARGO_SCRIPT_PATH_DIRS = &search)]
+        "--all",
+        "mytool"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    let found: Vec<_> = out.stdout.lines().collect();
+    assert_eq!(
+        found,
+        [
+            first.join("mytool.rs").display().to_string(),
+            second.join("mytool.crs").display().to_string(),
+        ]
+    );
+
+    let out = cargo_script!(
+        #[subcommand("which")]
+        #[env(CARGO_SCRIPT_PATH_DIRS = &search)]
+        "mytool"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    let script = locations(&out.stdout).remove(0).1;
+    assert_eq!(
+        script,
+        format!("{} (exists)", first.join("mytool.rs").display())
+    );
+
+    // Only bare names are searched for.
+    let out = cargo_script!(
+        #[subcommand("which")]
+        #[env(CARGO_SCRIPT_PATH_DIRS = &search)]
+        "--all",
+        "./mytool"
+    )
+    .unwrap();
+    assert!(!out.success());
+}
+
+#[test]
+fn test_hash_only() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+
+    macro_rules! hash {
+        ($($args:expr),*) => {{
+            let out = cargo_script!(
+                #[cargo_home(cargo_home.path())]
+                "--hash-only",
+                $($args,)*
+                "tests/data/script-no-deps.rs"
+            )
+            .unwrap();
+            assert!(out.success(), "{}", out.stderr);
+            out.stdout.trim().to_owned()
+        }};
+    }
+
+    let id = hash!();
+    assert!(id.starts_with("file-script-no-deps-"), "{}", id);
+    assert_eq!(hash!(), id);
+    // Nothing should have been built, or even looked for.
+    assert!(!cargo_home.path().join("script-cache").exists());
+
+    let out = cargo_script!(
+        #[subcommand("which")]
+        #[env(CARGO_HOME = cargo_home.path())]
+        "tests/data/script-no-deps.rs"
+    )
+    .unwrap();
+    assert_eq!(locations(&out.stdout)[1].1, id);
+
+    // Builds that wouldn't share an executable don't share an ID.
+    assert_ne!(hash!("--panic", "abort"), id);
+
+    let content = hash!("--content-addressed");
+    assert!(
+        content.starts_with("content-script-no-deps-"),
+        "{}",
+        content
+    );
+    assert_ne!(
+        hash!("--content-addressed", "--dep", "boolinator=2.4.0"),
+        content
+    );
+    assert_ne!(hash!("--content-addressed", "--debug"), content);
+}
+
+#[test]
+fn test_env() {
+    let cargo_home = tempdir::TempDir::new("cargo-script-test-home").unwrap();
+
+    let out = cargo_script!(
+        #[subcommand("env")]
+        #[env(CARGO_HOME = cargo_home.path(), CARGO_SCRIPT_TEST_ENV = "yes")]
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    let report = locations(&out.stdout);
+    let get = |key: &str| {
+        report
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| &**v)
+            .unwrap_or_else(|| panic!("no `{}` in {:?}", key, report))
+    };
+    let home = cargo_home.path().display().to_string();
+    assert_eq!(get("cache"), home);
+    assert_eq!(
+        get("script cache"),
+        Path::new(&home).join("script-cache").display().to_string()
+    );
+    assert!(get("edition").starts_with("2015"));
+    assert!(get("rustc").contains("rustc "), "{}", get("rustc"));
+    assert!(report.contains(&("env".into(), "CARGO_SCRIPT_TEST_ENV=yes".into())));
+    assert!(report.contains(&("env".into(), format!("CARGO_HOME={}", home))));
+
+    let out = cargo_script!(
+        #[subcommand("env")]
+        #[env(CARGO_HOME = cargo_home.path())]
+        "--json"
+    )
+    .unwrap();
+    assert!(out.success(), "{}", out.stderr);
+    assert!(out.stdout.starts_with('{'), "{}", out.stdout);
+    assert!(
+        out.stdout.contains("\"edition\":\"2015\""),
+        "{}",
+        out.stdout
+    );
+}
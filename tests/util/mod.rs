@@ -2,6 +2,8 @@ use std::sync::Mutex;
 
 macro_rules! cargo_script {
     (
+        @run $subcommand:expr, [$($pre_args:expr),*]
+        $(#[stdin($stdin:expr)])?
         #[env($($env_k:ident=$env_v:expr),* $(,)*)]
         $($args:expr),* $(,)*
     ) => {
@@ -10,14 +12,15 @@ macro_rules! cargo_script {
 
             let cargo_lock = crate::util::CARGO_MUTEX.lock().expect("could not acquire Cargo mutext");
 
-            let temp_dir = tempdir::TempDir::new("cargo-script-test").unwrap();
             let cmd_str;
             let out = {
                 let target_dir = ::std::env::var("CARGO_TARGET_DIR")
                     .unwrap_or_else(|_| String::from("target"));
                 let mut cmd = Command::new(format!("{}/debug/cargo-script", target_dir));
-                cmd.arg("script");
-                cmd.arg("--pkg-path").arg(temp_dir.path());
+                cmd.arg($subcommand);
+                $(
+                    cmd.arg($pre_args);
+                )*
                 $(
                     cmd.arg($args);
                 )*
@@ -27,7 +30,7 @@ macro_rules! cargo_script {
 
                 cmd_str = format!("{:?}", cmd);
 
-                cmd.output()
+                crate::util::output_with_stdin(&mut cmd, None $(.or(Some($stdin)))?)
                     .map(crate::util::Output::from)
             };
 
@@ -43,13 +46,107 @@ macro_rules! cargo_script {
                 println!("-----");
             }
 
-            drop(temp_dir);
             drop(cargo_lock);
 
             out
         }
     };
 
+    (
+        #[pkg_path($pkg_path:expr)]
+        #[env($($env_k:ident=$env_v:expr),* $(,)*)]
+        $($args:expr),* $(,)*
+    ) => {
+        cargo_script!(@run "script", ["--pkg-path", $pkg_path] #[env($($env_k=$env_v),*)] $($args),*)
+    };
+
+    /*
+    Runs with the real cache, rather than a temporary package directory.  This needs its own `CARGO_HOME` so tests don't interfere with each other, or the user's cache.
+    */
+    (
+        #[cargo_home($cargo_home:expr)]
+        #[env($($env_k:ident=$env_v:expr),* $(,)*)]
+        $($args:expr),* $(,)*
+    ) => {
+        cargo_script!(@run "script", [] #[env(CARGO_HOME=$cargo_home, $($env_k=$env_v),*)] $($args),*)
+    };
+
+    (
+        #[cargo_home($cargo_home:expr)]
+        #[stdin($stdin:expr)]
+        $($args:expr),* $(,)*
+    ) => {
+        cargo_script!(@run "script", [] #[stdin($stdin)] #[env(CARGO_HOME=$cargo_home)] $($args),*)
+    };
+
+    (
+        #[cargo_home($cargo_home:expr)]
+        $($args:expr),* $(,)*
+    ) => {
+        cargo_script!(#[cargo_home($cargo_home)] #[env()] $($args),*)
+    };
+
+    /*
+    Runs one of `cargo-script`'s other subcommands, such as `new` or `export`, rather than a script.
+    */
+    (
+        #[subcommand($subcommand:expr)]
+        #[env($($env_k:ident=$env_v:expr),* $(,)*)]
+        $($args:expr),* $(,)*
+    ) => {
+        cargo_script!(@run $subcommand, [] #[env($($env_k=$env_v),*)] $($args),*)
+    };
+
+    (
+        #[subcommand($subcommand:expr)]
+        $($args:expr),* $(,)*
+    ) => {
+        cargo_script!(#[subcommand($subcommand)] #[env()] $($args),*)
+    };
+
+    /*
+    Runs a script with `stdin` piped to it, such as for `--loop`.
+    */
+    (
+        #[stdin($stdin:expr)]
+        $($args:expr),* $(,)*
+    ) => {
+        {
+            let temp_dir = tempdir::TempDir::new("cargo-script-test").unwrap();
+            let out = cargo_script!(
+                @run "script", ["--pkg-path", temp_dir.path()]
+                #[stdin($stdin)]
+                #[env()]
+                $($args),*
+            );
+            drop(temp_dir);
+            out
+        }
+    };
+
+    (
+        #[pkg_path($pkg_path:expr)]
+        $($args:expr),* $(,)*
+    ) => {
+        cargo_script!(#[pkg_path($pkg_path)] #[env()] $($args),*)
+    };
+
+    (
+        #[env($($env_k:ident=$env_v:expr),* $(,)*)]
+        $($args:expr),* $(,)*
+    ) => {
+        {
+            let temp_dir = tempdir::TempDir::new("cargo-script-test").unwrap();
+            let out = cargo_script!(
+                #[pkg_path(temp_dir.path())]
+                #[env($($env_k=$env_v),*)]
+                $($args),*
+            );
+            drop(temp_dir);
+            out
+        }
+    };
+
     ($($args:expr),* $(,)*) => {
         cargo_script!(#[env()] $($args),*)
     };
@@ -118,6 +215,116 @@ impl From<::std::process::Output> for Output {
     }
 }
 
+/**
+Runs a command to completion, like `Command::output`, but with `stdin` piped to it if it's given, rather than nothing.
+
+The input is written from another thread, so a child which doesn't read it until after it has written a lot of output can't deadlock with us.
+*/
+pub fn output_with_stdin(
+    cmd: &mut ::std::process::Command,
+    stdin: Option<&str>,
+) -> ::std::io::Result<::std::process::Output> {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let stdin = match stdin {
+        Some(stdin) => stdin,
+        None => return cmd.output(),
+    };
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut pipe = child.stdin.take().unwrap();
+    let stdin = stdin.to_owned();
+    let writer = ::std::thread::spawn(move || pipe.write_all(stdin.as_bytes()));
+    let out = child.wait_with_output()?;
+    match writer.join().unwrap() {
+        Err(ref err) if err.kind() != ::std::io::ErrorKind::BrokenPipe => {
+            Err(::std::io::Error::new(err.kind(), err.to_string()))
+        }
+        _ => Ok(out),
+    }
+}
+
+/**
+Runs `cargo-script` with the given environment variables and arguments, and with a pseudo-terminal for its stdin, stdout, and stderr, as if it had been run by hand.
+
+Everything written to the terminal ends up in `stdout`, with the terminal's `\r\n` line endings turned back into `\n`.
+*/
+#[cfg(unix)]
+pub fn cargo_script_in_pty(env: &[(&str, &str)], args: &[&str]) -> ::std::io::Result<Output> {
+    use std::ffi::CStr;
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Read};
+    use std::os::unix::io::FromRawFd;
+    use std::process::{Command, Stdio};
+
+    let cargo_lock = CARGO_MUTEX.lock().expect("could not acquire Cargo mutext");
+
+    let (mut master, pts) = unsafe {
+        let fd = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = File::from_raw_fd(fd);
+        if libc::grantpt(fd) != 0 || libc::unlockpt(fd) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let name = libc::ptsname(fd);
+        if name.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        (master, CStr::from_ptr(name).to_string_lossy().into_owned())
+    };
+    let slave = OpenOptions::new().read(true).write(true).open(pts)?;
+
+    let target_dir = ::std::env::var("CARGO_TARGET_DIR").unwrap_or_else(|_| String::from("target"));
+    let mut cmd = Command::new(format!("{}/debug/cargo-script", target_dir));
+    cmd.arg("script")
+        .args(args)
+        .env_remove("CARGO_TARGET_DIR")
+        .envs(env.iter().cloned())
+        .stdin(slave.try_clone()?)
+        .stdout(slave.try_clone()?)
+        .stderr(slave);
+    println!("cargo-script cmd (in a terminal): {:?}", cmd);
+    let mut child = cmd.spawn()?;
+    // Reading the terminal only stops once every copy of the other end is closed, including the ones `cmd` is holding on to.
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    drop(cmd);
+
+    let reader = ::std::thread::spawn(move || {
+        let mut output = vec![];
+        let mut buf = [0; 4096];
+        // Once the other end is closed, this is an error (`EIO`) on Linux, rather than the end of the file.
+        while let Ok(n) = master.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            output.extend_from_slice(&buf[..n]);
+        }
+        output
+    });
+    let status = child.wait()?;
+    let output = reader.join().unwrap();
+    drop(cargo_lock);
+
+    let out = Output {
+        status,
+        stdout: String::from_utf8_lossy(&output).replace("\r\n", "\n"),
+        stderr: String::new(),
+    };
+    println!("cargo-script terminal output:");
+    println!("-----");
+    println!("{}", out.stdout);
+    println!("-----");
+    Ok(out)
+}
+
 fn subslice_offset(outer: &str, inner: &str) -> Option<usize> {
     let outer_beg = outer.as_ptr() as usize;
     let inner = inner.as_ptr() as usize;